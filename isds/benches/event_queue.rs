@@ -0,0 +1,122 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use isds::{Event, EventQueue, HeapEventQueue, OrderedFloat, SimSeconds, World};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Exp};
+
+/// So that we can run the same benchmarks for both implementations.
+trait Queue: Default {
+    fn push(&mut self, time_due: SimSeconds, event: Event);
+    fn pop(&mut self) -> Option<(SimSeconds, Event)>;
+}
+impl Queue for EventQueue {
+    fn push(&mut self, time_due: SimSeconds, event: Event) {
+        EventQueue::push(self, time_due, event)
+    }
+    fn pop(&mut self) -> Option<(SimSeconds, Event)> {
+        EventQueue::pop(self)
+    }
+}
+impl Queue for HeapEventQueue {
+    fn push(&mut self, time_due: SimSeconds, event: Event) {
+        HeapEventQueue::push(self, time_due, event)
+    }
+    fn pop(&mut self) -> Option<(SimSeconds, Event)> {
+        HeapEventQueue::pop(self)
+    }
+}
+
+fn prefilled<Q: Queue>(queue_size: usize) -> Q {
+    let mut rng = SmallRng::seed_from_u64(23);
+    let interval_distribution = Exp::new(1.).unwrap();
+    let event = Event::Generic(World::new().spawn((0,)));
+
+    let mut queue = Q::default();
+    for _ in 0..queue_size {
+        queue.push(OrderedFloat(interval_distribution.sample(&mut rng)), event);
+    }
+    queue
+}
+
+/// The classic "hold" model: a queue of constant size where each popped event causes a new one
+/// to be pushed a random interval later. Roughly what a flooding simulation does.
+fn hold<Q: Queue>(mut queue: Q, operations: usize) {
+    let mut rng = SmallRng::seed_from_u64(42);
+    let interval_distribution = Exp::new(1.).unwrap();
+
+    for _ in 0..operations {
+        let (now, event) = queue.pop().unwrap();
+        // messages that are sent "in bulk" are scheduled at the same time
+        let interval = if rng.gen_bool(0.1) {
+            0.
+        } else {
+            interval_distribution.sample(&mut rng)
+        };
+        queue.push(now + interval, event);
+    }
+}
+
+/// Fill the queue up completely, then drain it.
+fn fill_and_drain<Q: Queue>(queue_size: usize) {
+    let mut rng = SmallRng::seed_from_u64(42);
+    let event = Event::Generic(World::new().spawn((0,)));
+
+    let mut queue = Q::default();
+    for _ in 0..queue_size {
+        queue.push(OrderedFloat(rng.gen_range(0. ..1000.)), event);
+    }
+    while queue.pop().is_some() {}
+}
+
+fn bench_hold(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hold");
+    for queue_size in [100, 10_000, 1_000_000] {
+        group.bench_with_input(
+            BenchmarkId::new("calendar", queue_size),
+            &queue_size,
+            |b, &n| {
+                b.iter_batched(
+                    || prefilled::<EventQueue>(n),
+                    |queue| hold(queue, 100_000),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("binary_heap", queue_size),
+            &queue_size,
+            |b, &n| {
+                b.iter_batched(
+                    || prefilled::<HeapEventQueue>(n),
+                    |queue| hold(queue, 100_000),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_fill_and_drain(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill_and_drain");
+    for queue_size in [100, 10_000, 1_000_000] {
+        group.bench_with_input(
+            BenchmarkId::new("calendar", queue_size),
+            &queue_size,
+            |b, &n| b.iter(|| fill_and_drain::<EventQueue>(n)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("binary_heap", queue_size),
+            &queue_size,
+            |b, &n| b.iter(|| fill_and_drain::<HeapEventQueue>(n)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_hold, bench_fill_and_drain
+}
+criterion_main!(benches);
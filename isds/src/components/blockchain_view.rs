@@ -1,13 +1,20 @@
 use super::*;
-use blockchain_types::{BlockContents, Transaction};
+use block_hashes::HashedHeader;
+use blockchain_types::{BlockContents, OutPoint, Transaction};
 use common::PseudorandomColors;
 use nakamoto_consensus::NakamotoNodeState;
+use std::collections::{HashMap, HashSet};
 
 pub struct BlockchainView {
     sim: SharedSimulation,
     highlight: Highlight,
     colors: PseudorandomColors,
     cache: Cache,
+    /// Transactions that conflict with another transaction, we show them in red.
+    double_spends: HashSet<Entity>,
+    /// Visible blocks whose hash doesn't match their data anymore, if the simulation uses real
+    /// block hashes. We show their links in red.
+    broken_hash_links: HashSet<Entity>,
     _context_handle: yew::context::ContextHandle<IsdsContext>,
 }
 
@@ -69,6 +76,8 @@ impl Component for BlockchainView {
             highlight,
             colors,
             cache: Default::default(), // will be set on first render
+            double_spends: HashSet::new(),
+            broken_hash_links: HashSet::new(),
             _context_handle,
         }
     }
@@ -127,13 +136,27 @@ impl Component for BlockchainView {
                 let state = get_node_state(ctx.props().viewing_node, &sim);
 
                 // only recalculate the view if the blockchain tip or the highlight changed
-                let tip_changed = if let Some(state) = state {
-                    self.cache.update(&state)
+                let tip_changed = if let Some(state) = &state {
+                    self.cache.update(state)
                 } else {
                     false
                 };
+                if tip_changed {
+                    self.double_spends = double_spends(&sim);
+                }
+                // tampering doesn't change the tip, so we have to check every time
+                let broken_hash_links = match (sim.block_hashes(), state) {
+                    (Some(_), Some(state)) => {
+                        let mut chain = visible_chain(&state, ctx.props().max_visible_blocks);
+                        chain.reverse();
+                        sim.broken_hash_links(&chain)
+                    }
+                    _ => HashSet::new(),
+                };
+                let hash_links_changed = broken_hash_links != self.broken_hash_links;
+                self.broken_hash_links = broken_hash_links;
                 let hightlight_changed = self.highlight.update();
-                tip_changed || hightlight_changed
+                tip_changed || hash_links_changed || hightlight_changed
             }
         }
     }
@@ -234,6 +257,11 @@ impl BlockchainView {
             ..
         } = ctx.props();
         let color = self.colors.get(block_id.id()).to_string();
+        let link_color = if self.broken_hash_links.contains(&block_id) {
+            "crimson".to_string()
+        } else {
+            color.clone()
+        };
 
         html! {
             <g>
@@ -246,7 +274,7 @@ impl BlockchainView {
                     stroke={ color.clone() }
                     stroke-width={ stroke_width.to_string() }
                 />
-                { self.view_link(block_x, color.clone(), ctx) }
+                { self.view_link(block_x, link_color, ctx) }
                 { self.view_block_text(block_x, transactions_shortform, "black".to_string(), ctx) }
             </g>
         }
@@ -375,6 +403,11 @@ impl BlockchainView {
                                         ),
                                     )
                                 }
+                                fill={
+                                    entity
+                                        .filter(|e| self.double_spends.contains(e))
+                                        .map(|_| "crimson")
+                                }
                                 { onmouseover }
                                 { onmouseout }
                                 { onclick }
@@ -413,7 +446,11 @@ fn last_blocks_in_longest_chain(
     let mut block_id = state.tip();
     for _ in 0..max_blocks {
         // The block with id `None` will be counted as the genesis block
-        chain.push((block_id, get_transactions_shortform(block_id, sim)));
+        let mut lines = get_transactions_shortform(block_id, sim);
+        if let Some(header) = block_id.and_then(|id| sim.world.get::<HashedHeader>(id).ok()) {
+            lines.insert(0, (None, format!("#{}", header.hash.short())));
+        }
+        chain.push((block_id, lines));
         if block_id == None {
             break;
         }
@@ -422,6 +459,17 @@ fn last_blocks_in_longest_chain(
     chain
 }
 
+/// The last `max_blocks` blocks of the node's chain, newest first.
+fn visible_chain(state: &NakamotoNodeState, max_blocks: usize) -> Vec<Entity> {
+    let mut chain = vec![];
+    let mut block_id = state.tip();
+    while let Some(id) = block_id.filter(|_| chain.len() < max_blocks) {
+        chain.push(id);
+        block_id = state.block_header(id).and_then(|header| header.id_prev);
+    }
+    chain
+}
+
 fn get_node_state(
     node_id: Option<Entity>,
     sim: &Simulation,
@@ -434,34 +482,73 @@ fn get_transactions_shortform(
     sim: &Simulation,
 ) -> Vec<(Option<Entity>, String)> {
     if let Some(block_id) = block_id {
-        let block_contents = sim.world.get::<BlockContents>(block_id).unwrap();
-        block_contents
-            .iter()
-            .map(|&txid| (txid, sim.world.get::<Transaction>(txid).unwrap()))
-            .map(|(txid, tx)| (Some(txid), transaction_shortform(&tx)))
-            .collect()
+        if let Ok(block_contents) = sim.world.get::<BlockContents>(block_id) {
+            block_contents
+                .iter()
+                .map(|&txid| (txid, sim.world.get::<Transaction>(txid).unwrap()))
+                .map(|(txid, tx)| (Some(txid), transaction_shortform(&tx)))
+                .collect()
+        } else {
+            // contents of deeply buried blocks might have been garbage-collected
+            vec![(None, "(pruned)".to_string())]
+        }
     } else {
         vec![]
     }
 }
 
+/// In the order in which a miner would put them into blocks.
 fn get_unconfirmed_transactions_shortform(
     state: &NakamotoNodeState,
     sim: &Simulation,
 ) -> Vec<(Option<Entity>, String)> {
-    state
+    let txes: Vec<(Entity, hecs::Ref<Transaction>)> = state
         .txes_unconfirmed()
         .iter()
         .map(|&txid| (txid, sim.world.get::<Transaction>(txid).unwrap()))
-        .map(|(txid, tx)| (Some(txid), transaction_shortform(&tx)))
+        .collect();
+    let shortforms: HashMap<Entity, String> = txes
+        .iter()
+        .map(|(txid, tx)| (*txid, transaction_shortform(tx)))
+        .collect();
+    nakamoto_consensus::template_order(txes.iter().map(|(txid, tx)| (*txid, &**tx)))
+        .into_iter()
+        .map(|txid| (Some(txid), shortforms[&txid].clone()))
+        .collect()
+}
+/// All transactions that spend the same coins as some other transaction.
+fn double_spends(sim: &Simulation) -> HashSet<Entity> {
+    let mut spenders: HashMap<OutPoint, Vec<Entity>> = HashMap::new();
+    for (tx_id, tx) in sim.world.query::<&Transaction>().iter() {
+        for &input in tx.inputs.iter() {
+            spenders.entry(input).or_default().push(tx_id);
+        }
+    }
+    spenders
+        .into_values()
+        .filter(|tx_ids| tx_ids.len() > 1)
+        .flatten()
         .collect()
 }
-fn transaction_shortform(tx: &Transaction) -> String {
+
+/// E.g., `[A->B: 1.5+0.1]` for 1.5 coins from Alice to Bob with a fee of 0.1 coins, or `[*->M: 50]`
+/// for the coinbase transaction that pays miner Mallory.
+pub(super) fn transaction_shortform(tx: &Transaction) -> String {
+    let fee = if tx.fee > 0 {
+        format!("+{}", blockchain_types::coins_from(tx.fee as i64))
+    } else {
+        String::new()
+    };
     format!(
-        "[{}->{}: {}]",
-        tx.from.chars().next().unwrap_or('_'),
-        tx.to.chars().next().unwrap_or('_'),
-        blockchain_types::coins_from(tx.value as i64),
+        "[{}->{}: {}{}]",
+        if tx.is_coinbase() {
+            '*'
+        } else {
+            tx.from.chars().next().unwrap_or('_')
+        },
+        tx.to().chars().next().unwrap_or('_'),
+        blockchain_types::coins_from(tx.value() as i64),
+        fee,
     )
 }
 
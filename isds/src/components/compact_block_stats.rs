@@ -0,0 +1,48 @@
+use super::*;
+use nakamoto_consensus::CompactBlockStats;
+
+/// Shows how well compact block relay works across all nodes, see
+/// `nakamoto_consensus::Relay::CompactBlocks`.
+#[function_component(CompactBlockStatsView)]
+pub fn compact_block_stats_view() -> Html {
+    let stats = CompactBlockStats::total(&get_isds_context!().sim.borrow());
+    let reconstruction_rate = stats
+        .reconstruction_rate()
+        .map_or("-".to_string(), |rate| format!("{:.0}%", rate * 100.));
+    let bytes_saved = if stats.bytes_of_full_blocks > 0 {
+        let saved = 1. - stats.bytes_received as f64 / stats.bytes_of_full_blocks as f64;
+        format!("{:.0}%", saved * 100.)
+    } else {
+        "-".to_string()
+    };
+    html! {
+        <div class="level is-mobile is-size-6-tablet is-size-7-mobile">
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Compact blocks received" }</p>
+                    <p>{ stats.blocks_received }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Reconstructed from mempool" }</p>
+                    <p>{ reconstruction_rate }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Transactions requested" }</p>
+                    <p>{ stats.txes_requested }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Bytes saved" }</p>
+                    <p title={ format!("{} bytes instead of {}", stats.bytes_received, stats.bytes_of_full_blocks) }>
+                        { bytes_saved }
+                    </p>
+                </div>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,106 @@
+use super::*;
+use difficulty::RetargetingRule;
+use mining::Hashrate;
+use nakamoto_consensus::NakamotoNodeState;
+
+#[derive(Properties, PartialEq)]
+pub struct DifficultyViewProps {
+    pub viewing_node: Entity,
+    /// Over how many blocks the average block interval is taken.
+    #[prop_or(10)]
+    pub recent_blocks: usize,
+}
+
+/// Shows the difficulty of the next block on `viewing_node`'s longest chain and how fast blocks
+/// were found recently, compared to what the difficulty adjustment targets.
+#[function_component(DifficultyView)]
+pub fn difficulty_view(props: &DifficultyViewProps) -> Html {
+    let sim = get_isds_context!().sim;
+    let sim = sim.borrow();
+    let total_hashrate: f64 = sim
+        .world
+        .query::<&Hashrate>()
+        .iter()
+        .map(|(_, hashrate)| hashrate.0)
+        .sum();
+    let state = match sim.world.get::<NakamotoNodeState>(props.viewing_node) {
+        Ok(state) => state,
+        Err(_) => return html! {},
+    };
+    let adjustment = *state.difficulty_adjustment();
+    let difficulty = state.next_difficulty();
+
+    let tip = state.tip().and_then(|tip| state.block_header(tip));
+    let tip_height = tip.map_or(0, |tip| tip.height);
+    let mut earlier_block = tip;
+    let mut intervals = 0;
+    while intervals < props.recent_blocks {
+        match earlier_block
+            .and_then(|b| b.id_prev)
+            .and_then(|id| state.block_header(id))
+        {
+            Some(header) => earlier_block = Some(header),
+            None => break,
+        }
+        intervals += 1;
+    }
+    let average_interval = match (tip, earlier_block) {
+        (Some(tip), Some(earlier_block)) if intervals > 0 => format!(
+            "{:.0}s",
+            (tip.time - earlier_block.time).into_inner() / intervals as f64
+        ),
+        _ => "-".to_string(),
+    };
+    let expected_interval = if total_hashrate > 0. {
+        format!("{:.0}s", difficulty / total_hashrate)
+    } else {
+        "∞".to_string()
+    };
+    let next_retarget = match adjustment.rule {
+        RetargetingRule::Fixed => "never".to_string(),
+        RetargetingRule::Window { window: 0, .. } => "never".to_string(),
+        RetargetingRule::Window { window, .. } => {
+            format!("in {} of {} blocks", window - tip_height % window, window)
+        }
+        RetargetingRule::PerBlock { .. } => "every block".to_string(),
+    };
+
+    html! {
+        <div class="level is-mobile is-size-6-tablet is-size-7-mobile">
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Difficulty" }</p>
+                    <p title={ "Expected number of hashes needed to find the next block" }>
+                        { format!("{:.0}", difficulty) }
+                    </p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Next adjustment" }</p>
+                    <p>{ next_retarget }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Target interval" }</p>
+                    <p>{ format!("{:.0}s", adjustment.target_block_interval.into_inner()) }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Expected interval" }</p>
+                    <p title={ format!("Total hashrate: {:.1}", total_hashrate) }>
+                        { expected_interval }
+                    </p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ format!("Last {} intervals", props.recent_blocks) }</p>
+                    <p>{ average_interval }</p>
+                </div>
+            </div>
+        </div>
+    }
+}
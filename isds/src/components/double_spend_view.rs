@@ -0,0 +1,79 @@
+use super::*;
+use double_spending::{confirmations, DoubleSpendOutcome, DoubleSpenderState};
+use nakamoto_consensus::NakamotoNodeState;
+
+#[derive(Properties, PartialEq)]
+pub struct DoubleSpendViewProps {
+    pub attacker: Entity,
+    /// The node that the merchant's wallet is connected to.
+    pub merchant_node: Entity,
+}
+
+/// Shows how the last double-spend attack of `attacker` is going, see
+/// `double_spending::DoubleSpending`.
+#[function_component(DoubleSpendView)]
+pub fn double_spend_view(props: &DoubleSpendViewProps) -> Html {
+    let sim = get_isds_context!().sim;
+    let sim = sim.borrow();
+    let attacker_state = match sim.world.get::<DoubleSpenderState>(props.attacker) {
+        Ok(attacker_state) => (*attacker_state).clone(),
+        Err(_) => return html! { <p class="has-text-centered">{ "No attack yet." }</p> },
+    };
+    let status = match attacker_state.outcome() {
+        DoubleSpendOutcome::Ongoing => "Mining in secret",
+        DoubleSpendOutcome::Succeeded => "Payment reversed!",
+        DoubleSpendOutcome::GaveUp => "Gave up",
+    };
+    let merchant_confirmations = attacker_state.payment().map_or(0, |payment| {
+        confirmations(&sim, props.merchant_node, payment)
+    });
+    let merchant_height = sim
+        .world
+        .get::<NakamotoNodeState>(props.merchant_node)
+        .map_or(0, |state| state.tip_height());
+    let (private_height, withheld_blocks) = sim
+        .world
+        .get::<NakamotoNodeState>(props.attacker)
+        .map_or((0, 0), |state| {
+            (
+                state.height(state.mining_tip()),
+                state.withheld_blocks().len(),
+            )
+        });
+    let lead = if attacker_state.outcome() == DoubleSpendOutcome::Ongoing {
+        format!("{:+}", private_height as i64 - merchant_height as i64)
+    } else {
+        "-".to_string()
+    };
+
+    html! {
+        <div class="level is-mobile is-size-6-tablet is-size-7-mobile">
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Attack" }</p>
+                    <p>{ status }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Merchant's confirmations" }</p>
+                    <p>{ merchant_confirmations }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Secret blocks" }</p>
+                    <p>{ withheld_blocks }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Attacker's lead" }</p>
+                    <p title="Height of the attacker's chain compared to the merchant's">
+                        { lead }
+                    </p>
+                </div>
+            </div>
+        </div>
+    }
+}
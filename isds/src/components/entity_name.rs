@@ -53,8 +53,9 @@ pub fn entity_name(props: &Props) -> Html {
             onmouseover={ on_mouse_over }
             onmouseout={ on_mouse_out }
             onclick={ on_click }
+            title={ entity.map(|id| sim.borrow().describe_node(id)) }
         >
-            { entity.map_or("NONE".to_string(), |id| sim.borrow().name(id)) }
+            { entity.map_or("NONE".to_string(), |id| sim.borrow().label(id)) }
         </span>
     }
 }
@@ -0,0 +1,58 @@
+use super::*;
+use light_client::{LightClientState, LightClientStats};
+
+#[derive(Properties, PartialEq)]
+pub struct LightClientStatsProps {
+    pub light_client: Option<Entity>,
+}
+
+/// Shows how little a light client downloaded compared to a full node, see `light_client`.
+#[function_component(LightClientStatsView)]
+pub fn light_client_stats_view(props: &LightClientStatsProps) -> Html {
+    let sim = get_isds_context!().sim;
+    let sim = sim.borrow();
+    let (headers, stats) = match props.light_client {
+        Some(node) => (
+            sim.world
+                .get::<LightClientState>(node)
+                .map_or(0, |state| state.headers_count()),
+            sim.world
+                .get::<LightClientStats>(node)
+                .map_or(LightClientStats::default(), |stats| *stats),
+        ),
+        None => (0, LightClientStats::default()),
+    };
+    let kilobytes = |bytes: usize| format!("{:.1} kB", bytes as f64 / 1000.);
+    html! {
+        <div class="level is-mobile is-size-6-tablet is-size-7-mobile">
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Block headers" }</p>
+                    <p>{ headers }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Proven transactions" }</p>
+                    <p title={ format!("{} invalid proofs", stats.invalid_proofs) }>
+                        { stats.transactions_verified }
+                    </p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Downloaded" }</p>
+                    <p>{ kilobytes(stats.bytes_received) }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Full blocks" }</p>
+                    <p title="What a full node downloads for the same blocks">
+                        { kilobytes(stats.bytes_of_full_blocks) }
+                    </p>
+                </div>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,202 @@
+use super::*;
+use blockchain_types::{merkle_tree, BlockContents, Hash256, MerkleProof, Transaction};
+use nakamoto_consensus::NakamotoNodeState;
+
+#[derive(Properties, PartialEq)]
+pub struct MerkleTreeProps {
+    /// We show the Merkle tree of this node's tip.
+    pub viewing_node: Entity,
+    #[prop_or_default()]
+    pub highlight_class: Classes,
+    #[prop_or(110.)]
+    pub leaf_width: f32,
+    #[prop_or(50.)]
+    pub level_height: f32,
+    #[prop_or(12.)]
+    pub font_size: f32,
+}
+
+/// Draws the Merkle tree over the transactions of a block. For a highlighted transaction, it
+/// highlights the path up to the root and marks the hashes that make up its `MerkleProof`. It can
+/// also show what happens to the tree if someone alters the highlighted transaction.
+#[function_component(MerkleTree)]
+pub fn merkle_tree_view(props: &MerkleTreeProps) -> Html {
+    let IsdsContext { sim, highlight, .. } = get_isds_context!();
+    let is_altered = use_state(|| false);
+    let sim = sim.borrow();
+    let &MerkleTreeProps {
+        viewing_node,
+        leaf_width,
+        level_height,
+        font_size,
+        ..
+    } = props;
+
+    let tip = sim
+        .world
+        .get::<NakamotoNodeState>(viewing_node)
+        .ok()
+        .and_then(|state| state.tip());
+    let (tx_ids, tx_hashes) = match tip.and_then(|tip| {
+        let tx_ids: Vec<Entity> = sim
+            .world
+            .get::<BlockContents>(tip)
+            .ok()?
+            .iter()
+            .copied()
+            .collect();
+        Some((tx_ids, sim.transaction_hashes(tip)?))
+    }) {
+        Some((tx_ids, tx_hashes)) if !tx_ids.is_empty() => (tx_ids, tx_hashes),
+        _ => return html! { <p class="has-text-centered">{ "No transactions, no tree..." }</p> },
+    };
+    let selected = tx_ids.iter().position(|&tx_id| highlight.is(tx_id));
+    let proof = selected.and_then(|index| MerkleProof::new(&tx_hashes, index));
+
+    let levels = merkle_tree(&tx_hashes);
+    let mut altered_hashes = tx_hashes.clone();
+    let altered = match (selected, *is_altered) {
+        (Some(index), true) => {
+            let mut tx = (*sim.world.get::<Transaction>(tx_ids[index]).unwrap()).clone();
+            if let Some(output) = tx.outputs.first_mut() {
+                output.value += blockchain_types::TOSHIS_PER_COIN;
+            }
+            altered_hashes[index] = tx.hash();
+            Some(merkle_tree(&altered_hashes))
+        }
+        _ => None,
+    };
+    let shown_levels = altered.as_ref().unwrap_or(&levels);
+
+    let n_leaves = tx_hashes.len();
+    let height = levels.len();
+    // centered above the leaves that the node covers
+    let position = |depth: usize, index: usize| {
+        let first_leaf = index << depth;
+        let last_leaf = (((index + 1) << depth) - 1).min(n_leaves - 1);
+        let x = (first_leaf + last_leaf + 1) as f32 / 2. * leaf_width;
+        let y = (height - 1 - depth) as f32 * level_height + font_size;
+        (x, y)
+    };
+    let is_on_path = |depth: usize, index: usize| selected.is_some_and(|s| s >> depth == index);
+    let is_in_proof = |depth: usize, index: usize| {
+        selected.is_some_and(|s| depth + 1 < height && (s >> depth) ^ 1 == index)
+    };
+    let view_node = |depth: usize, index: usize, hash: &Hash256| {
+        let (x, y) = position(depth, index);
+        let changed = hash != &levels[depth][index];
+        let class = classes!(
+            is_on_path(depth, index).then_some(props.highlight_class.clone()),
+            is_in_proof(depth, index).then_some("has-text-weight-bold"),
+        );
+        let fill = if changed {
+            "crimson"
+        } else if is_in_proof(depth, index) {
+            "darkorange"
+        } else {
+            "black"
+        };
+        let leaf = (depth == 0).then(|| tx_ids[index]);
+        let (onmouseover, onmouseout, onclick) = match leaf {
+            Some(tx_id) => (
+                highlight.set_hover_callback(tx_id),
+                highlight.reset_hover_callback(),
+                highlight.toggle_select_callback(tx_id),
+            ),
+            None => (Callback::noop(), Callback::noop(), Callback::noop()),
+        };
+        html! {
+            <text
+                x={ x.to_string() }
+                y={ y.to_string() }
+                text-anchor="middle"
+                font-size={ font_size.to_string() }
+                font-family="monospace"
+                class={ classes!(class, leaf.map(|_| "is-clickable")) }
+                { fill }
+                { onmouseover }
+                { onmouseout }
+                { onclick }
+            >
+                { format!("#{}", hash.short()) }
+                if let Some(tx_id) = leaf {
+                    <tspan x={ x.to_string() } dy="1.2em">
+                        {
+                            sim.world
+                                .get::<Transaction>(tx_id)
+                                .map_or(String::new(), |tx| blockchain_view::transaction_shortform(&tx))
+                        }
+                    </tspan>
+                }
+            </text>
+        }
+    };
+    let view_edges = |depth: usize, index: usize| {
+        let (x, y) = position(depth, index);
+        [2 * index, 2 * index + 1]
+            .into_iter()
+            .filter(|&child| child < levels[depth - 1].len())
+            .map(|child| {
+                let (child_x, child_y) = position(depth - 1, child);
+                html! {
+                    <line
+                        x1={ x.to_string() }
+                        y1={ (y + 0.3 * font_size).to_string() }
+                        x2={ child_x.to_string() }
+                        y2={ (child_y - font_size).to_string() }
+                        stroke="gray"
+                    />
+                }
+            })
+            .collect::<Html>()
+    };
+
+    let onclick = {
+        let is_altered = is_altered.clone();
+        Callback::from(move |_| is_altered.set(!*is_altered))
+    };
+    html! {
+        <div>
+            <svg viewBox={ format!("0 0 {} {}",
+                n_leaves as f32 * leaf_width,
+                height as f32 * level_height + font_size,
+            ) }>
+                {
+                    shown_levels.iter().enumerate().flat_map(|(depth, level)| {
+                        level.iter().enumerate().map(move |(index, hash)| html! {
+                            <g>
+                                if depth > 0 {
+                                    { view_edges(depth, index) }
+                                }
+                                { view_node(depth, index, hash) }
+                            </g>
+                        })
+                    }).collect::<Html>()
+                }
+            </svg>
+            <div class="has-text-centered">
+                if let Some(proof) = proof {
+                    <p class="is-size-7 mb-2">
+                        {
+                            format!(
+                                "Proving that the highlighted transaction is part of the block \
+                                takes {} hashes instead of all {} transactions.",
+                                proof.siblings.len(),
+                                n_leaves,
+                            )
+                        }
+                    </p>
+                    <button class="button is-small" { onclick }>
+                        if *is_altered {
+                            { "Restore the transaction" }
+                        } else {
+                            { "Alter the transaction" }
+                        }
+                    </button>
+                } else {
+                    <p class="is-size-7">{ "Click on a transaction to see its Merkle proof." }</p>
+                }
+            </div>
+        </div>
+    }
+}
@@ -6,6 +6,15 @@ pub use common::Highlight;
 mod blockchain_view;
 pub use blockchain_view::BlockchainView;
 
+mod compact_block_stats;
+pub use compact_block_stats::CompactBlockStatsView;
+
+mod difficulty_view;
+pub use difficulty_view::DifficultyView;
+
+mod double_spend_view;
+pub use double_spend_view::DoubleSpendView;
+
 mod entity_name;
 pub use entity_name::EntityName;
 
@@ -15,9 +24,18 @@ pub use fps_counter::FpsCounter;
 mod hash_box;
 pub use hash_box::HashBox;
 
+mod light_client_stats;
+pub use light_client_stats::LightClientStatsView;
+
+mod merkle_tree;
+pub use merkle_tree::MerkleTree;
+
 mod net_view;
 pub use net_view::NetView;
 
+mod selfish_mining_revenue;
+pub use selfish_mining_revenue::SelfishMiningRevenueView;
+
 mod spinner;
 pub use spinner::Spinner;
 
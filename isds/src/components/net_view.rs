@@ -85,6 +85,7 @@ impl Component for NetView {
                     { self.view_edges(ctx) }
                     { self.view_nodes(ctx) }
                     { self.view_messages(ctx) }
+                    { self.view_pbft_messages() }
                 </svg>
             </>
         }
@@ -151,14 +152,52 @@ impl NetView {
     fn view_nodes(&self, ctx: &Context<NetView>) -> Html {
         let r = 5.0;
         let link = ctx.link();
-        self.sim
-            .borrow()
+        let sim = self.sim.borrow();
+        let nodes = sim
             .world
-            .query::<(&UnderlayPosition, &nakamoto_consensus::NakamotoNodeState)>()
+            .query::<(
+                &UnderlayPosition,
+                Option<&nakamoto_consensus::NakamotoNodeState>,
+                Option<&pbft::PbftReplicaState>,
+                Option<&NodeLabel>,
+            )>()
             .into_iter()
-            .map(|(node, (pos, node_state))| {
+            .map(|(node, (pos, node_state, replica_state, label))| {
+                let eclipsed = eclipse_attack::is_eclipsed(&sim, node);
                 html! {
                     <g>
+                        <title>{ sim.describe_node(node) }</title>
+                        if eclipsed {
+                            <circle
+                                cx={ pos.x.to_string() }
+                                cy={ pos.y.to_string() }
+                                r={ (r + 3.).to_string() }
+                                fill="none"
+                                stroke="crimson"
+                                stroke-width="2"
+                            />
+                            <text
+                                class="is-unselectable"
+                                x={ pos.x.to_string() }
+                                y={ (pos.y - r - 6.).to_string() }
+                                text-anchor="middle"
+                                font-size="8"
+                                fill="crimson"
+                            >
+                                { format!("{} blocks behind", eclipse_attack::blocks_behind(&sim, node)) }
+                            </text>
+                        }
+                        if let Some(label) = label {
+                            <text
+                                class="is-unselectable"
+                                x={ pos.x.to_string() }
+                                y={ (pos.y + r + 8.).to_string() }
+                                text-anchor="middle"
+                                font-size="8"
+                            >
+                                { &label.0 }
+                            </text>
+                        }
                         <circle
                             class={
                                 classes!(
@@ -178,18 +217,56 @@ impl NetView {
                             onmouseover={ link.callback(move |_| Msg::NodeMouseOver(node)) }
                             onmouseout={ link.callback(|_| Msg::NodeMouseOut) }
                         />
-                        { self.view_blocks(node_state, pos.x + 8., pos.y - 8.) }
+                        if let Some(node_state) = node_state {
+                            { self.view_blocks(node_state, pos.x + 8., pos.y - 8.) }
+                        }
+                        if let Some(replica_state) = replica_state {
+                            <text
+                                class="is-unselectable"
+                                x={ (pos.x + r + 3.).to_string() }
+                                y={ (pos.y - r).to_string() }
+                                font-size="8"
+                                fill={ if replica_state.is_crashed() { "crimson" } else { "black" } }
+                            >
+                                {
+                                    if replica_state.is_crashed() {
+                                        "crashed".to_string()
+                                    } else if replica_state.is_view_changing() {
+                                        format!("view {}?", replica_state.view())
+                                    } else {
+                                        format!("view {}", replica_state.view())
+                                    }
+                                }
+                            </text>
+                        }
                     </g>
                 }
             })
-            .collect()
+            .collect();
+        nodes
     }
     fn view_edges(&self, ctx: &Context<NetView>) -> Html {
         let link = ctx.link();
+        let sim = self.sim.borrow();
+        let eclipsed: Vec<Entity> = sim
+            .world
+            .query::<&PeerSet>()
+            .iter()
+            .map(|(node, _)| node)
+            .filter(|&node| eclipse_attack::is_eclipsed(&sim, node))
+            .collect();
         self.edges
             .edges
             .iter()
             .map(|(&edge_endpoints, &(edge_type, line))| {
+                // the connections that the attackers monopolize
+                let stroke = if eclipsed.contains(&edge_endpoints.left())
+                    || eclipsed.contains(&edge_endpoints.right())
+                {
+                    "crimson"
+                } else {
+                    "gray"
+                };
                 html! {
                     <g
                         onclick={ link.callback(move |_| Msg::LinkClick(
@@ -216,7 +293,7 @@ impl NetView {
                                     y1={ line.start.y.to_string() }
                                     x2={ line.end.x.to_string() }
                                     y2={ line.end.y.to_string() }
-                                    stroke="gray"
+                                    stroke={ stroke }
                                     class={
                                         classes!(
                                             ctx.props().toggle_edges_on_click.then_some("is-clickable")
@@ -254,13 +331,29 @@ impl NetView {
             .query::<(
                 &UnderlayLine,
                 &TimeSpan,
-                &simple_flooding::SimpleFloodingMessage<nakamoto_consensus::InventoryItem>,
+                &nakamoto_consensus::NakamotoMessage,
             )>()
             .into_iter()
             .map(|(_, (trajectory, time_span, message))| {
                 let (x, y) = message_position(trajectory, time_span, time_now);
-                match message.0 {
-                    nakamoto_consensus::InventoryItem::Transaction(txid) => {
+                let item = match *message {
+                    nakamoto_consensus::NakamotoMessage::Flood(item) => Some(item),
+                    nakamoto_consensus::NakamotoMessage::Block(block_id)
+                    | nakamoto_consensus::NakamotoMessage::CmpctBlock(
+                        blockchain_types::BlockHeader { id: block_id, .. },
+                        _,
+                    )
+                    | nakamoto_consensus::NakamotoMessage::MerkleBlock(
+                        blockchain_types::BlockHeader { id: block_id, .. },
+                        _,
+                    ) => Some(nakamoto_consensus::InventoryItem::Block(block_id)),
+                    nakamoto_consensus::NakamotoMessage::Tx(tx_id) => {
+                        Some(nakamoto_consensus::InventoryItem::Transaction(tx_id))
+                    }
+                    _ => None,
+                };
+                match item {
+                    Some(nakamoto_consensus::InventoryItem::Transaction(txid)) => {
                         html! {
                             <circle
                                 class={
@@ -276,7 +369,7 @@ impl NetView {
                             />
                         }
                     }
-                    nakamoto_consensus::InventoryItem::Block(block_id) => {
+                    Some(nakamoto_consensus::InventoryItem::Block(block_id)) => {
                         html! {
                             <circle
                                 cx={ x.to_string() }
@@ -286,6 +379,49 @@ impl NetView {
                             />
                         }
                     }
+                    // announcements and requests
+                    None => {
+                        html! {
+                            <circle
+                                cx={ x.to_string() }
+                                cy={ y.to_string() }
+                                r=1
+                                fill="gray"
+                            >
+                                <title>{ message.command() }</title>
+                            </circle>
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+    fn view_pbft_messages(&self) -> Html {
+        let time_now = self.sim.borrow().time.now();
+        self.sim
+            .borrow()
+            .world
+            .query::<(&UnderlayLine, &TimeSpan, &pbft::PbftMessage)>()
+            .into_iter()
+            .map(|(_, (trajectory, time_span, message))| {
+                let (x, y) = message_position(trajectory, time_span, time_now);
+                let color = match message {
+                    pbft::PbftMessage::Request(_) => "dimgray",
+                    pbft::PbftMessage::PrePrepare { .. } => "royalblue",
+                    pbft::PbftMessage::Prepare { .. } => "darkorange",
+                    pbft::PbftMessage::Commit { .. } => "seagreen",
+                    pbft::PbftMessage::Reply { .. } => "mediumpurple",
+                    _ => "crimson",
+                };
+                html! {
+                    <circle
+                        cx={ x.to_string() }
+                        cy={ y.to_string() }
+                        r=2
+                        fill={ color }
+                    >
+                        <title>{ message.phase() }</title>
+                    </circle>
                 }
             })
             .collect()
@@ -0,0 +1,77 @@
+use super::*;
+use selfish_mining::{expected_selfish_share, SelfishMinerState, SelfishMiningRevenue};
+
+#[derive(Properties, PartialEq)]
+pub struct SelfishMiningRevenueViewProps {
+    pub viewing_node: Entity,
+}
+
+/// Compares what selfish miners earn on `viewing_node`'s longest chain with what they would have
+/// earned by mining honestly, see `selfish_mining::SelfishMining`.
+#[function_component(SelfishMiningRevenueView)]
+pub fn selfish_mining_revenue_view(props: &SelfishMiningRevenueViewProps) -> Html {
+    let sim = get_isds_context!().sim;
+    let sim = sim.borrow();
+    let revenue = SelfishMiningRevenue::of(&sim, props.viewing_node);
+    let gamma = sim
+        .world
+        .query::<&SelfishMinerState>()
+        .iter()
+        .map(|(_, selfish_state)| selfish_state.gamma())
+        .next();
+    let alpha = revenue.selfish_hashrate_share;
+    let percent = |share: f64| format!("{:.1}%", share * 100.);
+    let actual_share = revenue.selfish_share().map_or("-".to_string(), percent);
+    let expected_share = gamma.map_or("-".to_string(), |gamma| {
+        percent(expected_selfish_share(alpha, gamma))
+    });
+    // blocks per unit of hashrate share, relative to mining honestly
+    let relative_revenue = |blocks: usize, hashrate_share: f64| match revenue.selfish_share() {
+        Some(_) if hashrate_share > 0. => {
+            let total_blocks = (revenue.selfish_blocks + revenue.honest_blocks) as f64;
+            percent(blocks as f64 / total_blocks / hashrate_share)
+        }
+        _ => "-".to_string(),
+    };
+
+    html! {
+        <div class="level is-mobile is-size-6-tablet is-size-7-mobile">
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Selfish hashrate" }</p>
+                    <p>{ percent(alpha) }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Selfish blocks" }</p>
+                    <p title={ format!("{} selfish and {} honest blocks", revenue.selfish_blocks, revenue.honest_blocks) }>
+                        { actual_share }
+                    </p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Expected (Eyal & Sirer)" }</p>
+                    <p>{ expected_share }</p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Selfish revenue" }</p>
+                    <p title="Compared to mining honestly">
+                        { relative_revenue(revenue.selfish_blocks, alpha) }
+                    </p>
+                </div>
+            </div>
+            <div class="level-item has-text-centered">
+                <div>
+                    <p class="heading">{ "Honest revenue" }</p>
+                    <p title="Compared to everyone mining honestly">
+                        { relative_revenue(revenue.honest_blocks, 1. - alpha) }
+                    </p>
+                </div>
+            </div>
+        </div>
+    }
+}
@@ -1,4 +1,5 @@
 use super::*;
+use web_sys::HtmlSelectElement;
 
 #[derive(Properties, PartialEq)]
 pub struct TimeUiProps {
@@ -6,6 +7,12 @@ pub struct TimeUiProps {
     pub show_fps: bool,
     #[prop_or_default]
     pub slowdown_handler_index: Option<usize>,
+    #[prop_or_default]
+    pub breakpoints_handler_index: Option<usize>,
+    #[prop_or_default]
+    pub speed_controller_index: Option<usize>,
+    #[prop_or_default]
+    pub show_step_controls: bool,
 }
 
 #[function_component(TimeUi)]
@@ -18,22 +25,49 @@ pub fn time_ui(props: &TimeUiProps) -> Html {
                         <TimeControls/>
                     </div>
                 </div>
+                if props.show_step_controls {
+                    <div class="level-item">
+                        <div class="buttons are-small">
+                            <StepControls/>
+                        </div>
+                    </div>
+                }
                 <div class="level-item">
                     <TimeDisplay/>
                 </div>
+                if props.show_step_controls {
+                    <div class="level-item">
+                        <StepDisplay/>
+                    </div>
+                }
                 if props.show_fps {
                     <div class="level-item">
                         { "FPS: " } <FpsCounter />
                     </div>
                 }
+                if props.breakpoints_handler_index.is_some() {
+                    <div class="level-item">
+                        <BreakpointNotice handler_index={ props.breakpoints_handler_index }/>
+                    </div>
+                }
             </div>
+            <div class="level-right">
+                if props.speed_controller_index.is_some() {
+                    <div class="level-item">
+                        <SpeedPolicySelect handler_index={ props.speed_controller_index }/>
+                    </div>
+                }
+                if props.breakpoints_handler_index.is_some() {
+                    <div class="level-item">
+                        <BreakpointsCheckbox handler_index={ props.breakpoints_handler_index }/>
+                    </div>
+                }
                 if props.slowdown_handler_index.is_some() {
-                    <div class="level-right">
-                        <div class="level-item">
-                            <SlowdownCheckbox handler_index={ props.slowdown_handler_index }/>
-                        </div>
+                    <div class="level-item">
+                        <SlowdownCheckbox handler_index={ props.slowdown_handler_index }/>
                     </div>
                 }
+            </div>
         </div>
     }
 }
@@ -92,6 +126,75 @@ pub fn time_controls() -> Html {
     }
 }
 
+/// For going through the simulation event by event. Pauses the simulation first.
+#[function_component(StepControls)]
+pub fn step_controls() -> Html {
+    let context = get_isds_context!();
+
+    let step_callback = |step: fn(&mut Simulation) -> Option<ProcessedEvent>| {
+        let sim = context.sim.clone();
+        Callback::from(move |_| {
+            let mut sim = sim.borrow_mut();
+            sim.time.pause();
+            step(&mut sim);
+        })
+    };
+    let on_next_event = step_callback(Simulation::step);
+    let on_next_delivery = step_callback(Simulation::step_to_next_message_delivery);
+    let on_next_command = step_callback(Simulation::step_to_next_command);
+
+    html! {
+        <>
+            <button class="button" onclick={ on_next_event } title="Next event">
+                <span class="icon">
+                    <i class="fas fa-step-forward"></i>
+                </span>
+            </button>
+            <button class="button" onclick={ on_next_delivery } title="Next message delivery">
+                <span class="icon">
+                    <i class="fas fa-step-forward"></i>
+                </span>
+                <span class="icon">
+                    <i class="fas fa-envelope"></i>
+                </span>
+            </button>
+            <button class="button" onclick={ on_next_command } title="Next command">
+                <span class="icon">
+                    <i class="fas fa-step-forward"></i>
+                </span>
+                <span class="icon">
+                    <i class="fas fa-terminal"></i>
+                </span>
+            </button>
+        </>
+    }
+}
+
+/// Shows what happened in the last step, as long as the simulation stays paused there.
+#[function_component(StepDisplay)]
+pub fn step_display() -> Html {
+    let context = get_isds_context!();
+    let sim = context.sim.borrow();
+
+    html! {
+        if let Some(step) = sim.last_step.as_ref().filter(|_| sim.last_step_is_current()) {
+            <div title="Last processed event and who reacted to it">
+                <i class="fas fa-search"></i>
+                <span class="ml-1">{ &step.description }</span>
+                <span class="ml-1 has-text-grey">
+                    {
+                        if step.reactions.is_empty() {
+                            "(nobody reacted)".to_string()
+                        } else {
+                            format!("⇨ {}", step.reactions.join(", "))
+                        }
+                    }
+                </span>
+            </div>
+        }
+    }
+}
+
 #[function_component(TimeDisplay)]
 pub fn time_display() -> Html {
     let context = get_isds_context!();
@@ -190,3 +293,147 @@ pub fn slowdown_checkbox(props: &SlowdownCheckboxProps) -> Html {
         </>
     }
 }
+
+#[derive(Properties, PartialEq)]
+pub struct BreakpointsProps {
+    pub handler_index: Option<usize>,
+}
+
+/// Tells the user why the simulation paused, if it was one of our breakpoints.
+#[function_component(BreakpointNotice)]
+pub fn breakpoint_notice(props: &BreakpointsProps) -> Html {
+    let context = get_isds_context!();
+    let sim = context.sim.borrow();
+
+    let last_hit = props.handler_index.and_then(|i| {
+        sim.additional_event_handlers()
+            .borrow()
+            .get::<Breakpoints>(i)
+            .and_then(|h| h.last_hit().cloned())
+    });
+
+    html! {
+        if let Some(hit) = last_hit.filter(|hit| sim.time.paused() && hit.time == sim.time.now()) {
+            <div class="has-text-warning-dark" title="Paused by breakpoint">
+                <i class="fas fa-hand-paper"></i>
+                <span class="ml-1">{ hit.reason }</span>
+            </div>
+        }
+    }
+}
+
+#[function_component(BreakpointsCheckbox)]
+pub fn breakpoints_checkbox(props: &BreakpointsProps) -> Html {
+    let context = get_isds_context!();
+
+    let config_ok = props.handler_index.is_some();
+
+    let breakpoints_are_active = props
+        .handler_index
+        .and_then(|i| {
+            context
+                .sim
+                .borrow()
+                .additional_event_handlers()
+                .borrow()
+                .get::<Breakpoints>(i)
+                .map(|h| h.is_enabled())
+        })
+        .unwrap_or(false);
+
+    let toggle_breakpoints = {
+        if let Some(handler_index) = props.handler_index {
+            Callback::from(move |_| {
+                if let Some(breakpoints) = context
+                    .sim
+                    .borrow()
+                    .additional_event_handlers()
+                    .borrow_mut()
+                    .get_mut::<Breakpoints>(handler_index)
+                {
+                    breakpoints.toggle_enabled();
+                }
+            })
+        } else {
+            Callback::noop()
+        }
+    };
+
+    html! {
+        <button
+            class={ classes!("button", "is-small", breakpoints_are_active.then_some("is-warning")) }
+            onclick={ toggle_breakpoints }
+            disabled={ !config_ok }
+            title={
+                if breakpoints_are_active {
+                    "Don't pause when something interesting happens"
+                } else {
+                    "Pause when something interesting happens"
+                }
+            }
+        >
+            <span class="icon">
+                <i class="fas fa-hand-paper"></i>
+            </span>
+        </button>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct SpeedPolicySelectProps {
+    pub handler_index: Option<usize>,
+}
+#[function_component(SpeedPolicySelect)]
+pub fn speed_policy_select(props: &SpeedPolicySelectProps) -> Html {
+    let context = get_isds_context!();
+
+    let (policy_names, selected): (Vec<String>, usize) = props
+        .handler_index
+        .and_then(|i| {
+            context
+                .sim
+                .borrow()
+                .additional_event_handlers()
+                .borrow()
+                .get::<SpeedController>(i)
+                .map(|h| {
+                    let names = h.policy_names().into_iter().map(String::from).collect();
+                    (names, h.selected())
+                })
+        })
+        .unwrap_or_default();
+
+    let on_change = {
+        if let Some(handler_index) = props.handler_index {
+            Callback::from(move |e: yew::Event| {
+                let selected_index = e
+                    .target_unchecked_into::<HtmlSelectElement>()
+                    .selected_index();
+                let mut sim = context.sim.borrow_mut();
+                if let Some(speed_controller) = sim
+                    .additional_event_handlers()
+                    .borrow_mut()
+                    .get_mut::<SpeedController>(handler_index)
+                {
+                    speed_controller.select(&mut sim, selected_index as usize);
+                }
+            })
+        } else {
+            Callback::noop()
+        }
+    };
+
+    html! {
+        <div class="select is-small" title="How the speed of time is controlled">
+            <select onchange={ on_change } disabled={ policy_names.is_empty() }>
+                {
+                    policy_names.into_iter().enumerate().map(|(i, name)| {
+                        html! {
+                            <option selected={ i == selected }>{ name }</option>
+                        }
+                    }).collect::<Html>()
+                }
+            </select>
+        </div>
+    }
+}
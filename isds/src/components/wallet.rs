@@ -1,5 +1,6 @@
 use super::*;
 use blockchain_types::{coins_from, Address, BlockContents, BlockHeader, Transaction};
+use light_client::LightClientState;
 use nakamoto_consensus::NakamotoNodeState;
 use std::collections::{BTreeSet, VecDeque};
 
@@ -10,6 +11,8 @@ pub struct Wallet {
     highlight: Highlight,
     cache: TransactionsCache,
     send_modal: SendModalState,
+    /// Why the full node refused to build the last transaction sent from the whitelist UI.
+    send_error: Option<String>,
     _context_handle: yew::context::ContextHandle<IsdsContext>,
 }
 
@@ -19,13 +22,15 @@ struct SendModalState {
     error_message: Option<String>,
     to_field_ref: NodeRef,
     value_field_ref: NodeRef,
+    fee_field_ref: NodeRef,
 }
 
 #[derive(Debug, Clone)]
 pub enum Msg {
     Rendered(RealSeconds),
     ToggleSendModal,
-    BroadcastNewTransactionFromWhitelist(u64, String),
+    /// Amount, recipient, and fee.
+    BroadcastNewTransactionFromWhitelist(u64, String, u64),
     BroadcastNewTransactionFromModal,
     TxClick(Entity),
     TxMouseOver(Entity),
@@ -34,6 +39,8 @@ pub enum Msg {
 
 #[derive(Properties, PartialEq)]
 pub struct Props {
+    /// The node that the wallet gets its transactions from and sends new ones to. Either a full
+    /// node or a light client (see `light_client`), which needs to watch `address`.
     pub full_node: Option<Entity>,
     pub address: Address,
     /// If no send amounts are set, a send button with arbitrary amounts is enabled.
@@ -45,18 +52,28 @@ pub struct Props {
 pub struct SendWhitelist {
     pub amounts: Vec<u64>,
     pub recipients: Vec<Address>,
+    /// If there are several, users can choose how much fee they pay. Otherwise, they pay the only
+    /// one (or none).
+    pub fees: Vec<u64>,
 }
 impl SendWhitelist {
     pub fn new(recipients: Vec<String>, coin_amounts: Vec<f64>) -> Self {
-        let amounts = coin_amounts
-            .into_iter()
-            .map(|c| blockchain_types::toshis_from(c) as u64)
-            .collect();
         Self {
-            amounts,
+            amounts: toshis_from_coins(coin_amounts),
             recipients,
+            fees: vec![],
         }
     }
+    pub fn with_fees(mut self, coin_fees: Vec<f64>) -> Self {
+        self.fees = toshis_from_coins(coin_fees);
+        self
+    }
+}
+fn toshis_from_coins(coins: Vec<f64>) -> Vec<u64> {
+    coins
+        .into_iter()
+        .map(|c| blockchain_types::toshis_from(c) as u64)
+        .collect()
 }
 
 impl Component for Wallet {
@@ -77,6 +94,7 @@ impl Component for Wallet {
             highlight,
             cache,
             send_modal: Default::default(),
+            send_error: None,
             _context_handle,
         }
     }
@@ -107,23 +125,33 @@ impl Component for Wallet {
                 self.send_modal.reset_fields();
                 true
             }
-            Msg::BroadcastNewTransactionFromWhitelist(amount, recipient) => {
+            Msg::BroadcastNewTransactionFromWhitelist(amount, recipient, fee) => {
                 let sender = ctx.props().address.clone();
-                self.sim.borrow_mut().do_now(ForSpecific(
-                    ctx.props().full_node.unwrap(), // buttons wouldn't have been clickable if it was `None`
-                    nakamoto_consensus::BuildAndBroadcastTransaction::new(
-                        sender, recipient, amount,
-                    ),
-                ));
+                self.send_error = self
+                    .build_and_broadcast(
+                        ctx.props().full_node.unwrap(), // buttons wouldn't have been clickable if it was `None`
+                        sender,
+                        recipient,
+                        amount,
+                        fee,
+                    )
+                    .err();
                 true
             }
             Msg::BroadcastNewTransactionFromModal => {
-                match self.parse_send_form(ctx) {
-                    Ok((from, to, value)) => {
-                        self.sim.borrow_mut().do_now(ForSpecific(
+                let result = self
+                    .parse_send_form(ctx)
+                    .and_then(|(from, to, value, fee)| {
+                        self.build_and_broadcast(
                             ctx.props().full_node.unwrap(), // modal couldn't have been opened if it was `None`
-                            nakamoto_consensus::BuildAndBroadcastTransaction::new(from, to, value),
-                        ));
+                            from,
+                            to,
+                            value,
+                            fee,
+                        )
+                    });
+                match result {
+                    Ok(()) => {
                         self.send_modal.toggle();
                         self.send_modal.reset_fields();
                     }
@@ -156,6 +184,21 @@ impl Component for Wallet {
 }
 
 impl Wallet {
+    /// Lets the full node build the transaction right away, so that we can tell the user if it
+    /// refuses to, e.g., because the wallet doesn't have enough coins.
+    fn build_and_broadcast(
+        &self,
+        full_node: Entity,
+        from: Address,
+        to: Address,
+        value: u64,
+        fee: u64,
+    ) -> Result<(), String> {
+        nakamoto_consensus::BuildAndBroadcastTransaction::new(from, to, value)
+            .with_fee(fee)
+            .execute_for(&mut self.sim.borrow_mut(), full_node)
+            .map_err(|error| error.to_string())
+    }
     fn view_top_infos(&self) -> Html {
         html! {
             <>
@@ -185,6 +228,47 @@ impl Wallet {
         html! {
             <table class="table is-fullwidth mb-1">
                 <tbody>
+                    {
+                        self.cache.iter_reverted_transactions().map(|(txid, tx)| {
+                            let counterpart = if *tx.to() == self.cache.monitored_address {
+                                &tx.from
+                            } else {
+                                tx.to()
+                            };
+                            html! {
+                                <tr
+                                    class={
+                                        classes!(
+                                            "is-clickable",
+                                            self.highlight
+                                                .is(txid)
+                                                .then_some("has-background-info"),
+                                        )
+                                    }
+                                    title={ "Was on the blockchain, but a longer chain without it replaced it" }
+                                    onclick={ link.callback(move |_| Msg::TxClick(txid)) }
+                                    onmouseover={ link.callback(move |_| Msg::TxMouseOver(txid)) }
+                                    onmouseout={ link.callback(|_| Msg::TxMouseOut) }
+                                >
+                                    <td>
+                                        <span class="icon is-size-6 has-text-danger">
+                                            <i class="fas fa-exclamation-triangle"></i>
+                                        </span>
+                                    </td>
+                                    <td>
+                                        <span class="has-text-grey-light is-family-code">
+                                            { counterpart }
+                                        </span>
+                                    </td>
+                                    <td>
+                                        <s class="has-text-grey-light">
+                                            { format!("{:+}", coins_from(self.cache.value_of(tx))) }
+                                        </s>
+                                    </td>
+                                </tr>
+                            }
+                        }).collect::<Html>()
+                    }
                     {
                         visible_transactions.map(|(confirmations, txid, tx)| {
                             let coin_value = coins_from(self.cache.value_of(tx));
@@ -204,6 +288,14 @@ impl Wallet {
                             };
                             let tooltip = if confirmations < 1 {
                                 "Not on the blockchain".to_string()
+                            } else if tx.is_coinbase()
+                                && confirmations < self.cache.coinbase_maturity
+                            {
+                                format!(
+                                    "Newly mined coins, on the blockchain since {} of the {} \
+                                    blocks needed to spend them",
+                                    confirmations, self.cache.coinbase_maturity
+                                )
                             } else {
                                 format!(
                                     "On the blockchain since {} block{}{}",
@@ -214,10 +306,10 @@ impl Wallet {
                                     } else { "" }
                                 )
                             };
-                            let counterpart = if tx.to == self.cache.monitored_address {
+                            let counterpart = if *tx.to() == self.cache.monitored_address {
                                 &tx.from
                             } else {
-                                &tx.to
+                                tx.to()
                             };
                             html! {
                                 <tr
@@ -291,67 +383,95 @@ impl Wallet {
         let balance =
             (self.cache.total_value_confirmed() + self.cache.total_value_unconfirmed()) as u64;
         let select_ref = NodeRef::default();
+        let fee_select_ref = NodeRef::default();
         let onclick = |amount: u64| {
             let select_ref_clone = select_ref.clone();
-            let recipients_clone = ctx
-                .props()
-                .send_whitelist
-                .as_ref()
-                .unwrap()
-                .recipients
-                .clone();
+            let fee_select_ref_clone = fee_select_ref.clone();
+            let send_whitelist = ctx.props().send_whitelist.as_ref().unwrap();
+            let recipients_clone = send_whitelist.recipients.clone();
+            let fees_clone = send_whitelist.fees.clone();
             ctx.link().callback(move |_| {
-                let recipient = get_selected_recipient(&recipients_clone, &select_ref_clone)
+                let recipient = get_selected(&recipients_clone, &select_ref_clone)
                     .unwrap_or_else(|| "???".to_string());
-                Msg::BroadcastNewTransactionFromWhitelist(amount, recipient)
+                let fee = get_selected(&fees_clone, &fee_select_ref_clone)
+                    .or_else(|| fees_clone.first().copied())
+                    .unwrap_or(0);
+                Msg::BroadcastNewTransactionFromWhitelist(amount, recipient, fee)
             })
         };
         if let Some(send_whitelist) = ctx.props().send_whitelist.as_ref() {
             html! {
-                <div class="is-flex is-flex-wrap-wrap is-align-items-center">
-                    <div class="mr-2">
-                        <span class="icon">
-                            <i class="fas fa-paper-plane" />
-                        </span>
-                        <span>
-                            { "Send" }
-                        </span>
-                    </div>
-                    <div class="buttons has-addons my-1">
-                        {
-                            send_whitelist.amounts.iter().map(|amount| {
-                                let disabled = ctx.props().full_node.is_some() && *amount > balance;
-                                let amount_in_coins = blockchain_types::coins_from(*amount as i64);
-                                html! {
-                                    <button
-                                        class="button mb-0"
-                                        onclick={ onclick(*amount) }
-                                        disabled={ disabled }
-                                        title={ format!("Send {amount_in_coins} coins now") }
-                                    >
-                                        { amount_in_coins }
-                                    </button>
-                                }
-                            }).collect::<Html>()
-                        }
-                    </div>
-                    <div class="mx-2">
-                        <span>
-                            { "coins to" }
-                        </span>
-                    </div>
-                    <div class="select my-1">
-                        <select ref={ select_ref.clone() }>
+                <>
+                    <div class="is-flex is-flex-wrap-wrap is-align-items-center">
+                        <div class="mr-2">
+                            <span class="icon">
+                                <i class="fas fa-paper-plane" />
+                            </span>
+                            <span>
+                                { "Send" }
+                            </span>
+                        </div>
+                        <div class="buttons has-addons my-1">
                             {
-                                send_whitelist.recipients.iter().enumerate().map(|(i, recipient)| {
+                                send_whitelist.amounts.iter().map(|amount| {
+                                    let disabled = ctx.props().full_node.is_some() && *amount > balance;
+                                    let amount_in_coins = blockchain_types::coins_from(*amount as i64);
                                     html! {
-                                        <option selected={ i == 0 }> { recipient } </option>
+                                        <button
+                                            class="button mb-0"
+                                            onclick={ onclick(*amount) }
+                                            disabled={ disabled }
+                                            title={ format!("Send {amount_in_coins} coins now") }
+                                        >
+                                            { amount_in_coins }
+                                        </button>
                                     }
                                 }).collect::<Html>()
                             }
-                        </select>
+                        </div>
+                        <div class="mx-2">
+                            <span>
+                                { "coins to" }
+                            </span>
+                        </div>
+                        <div class="select my-1">
+                            <select ref={ select_ref.clone() }>
+                                {
+                                    send_whitelist.recipients.iter().enumerate().map(|(i, recipient)| {
+                                        html! {
+                                            <option selected={ i == 0 }> { recipient } </option>
+                                        }
+                                    }).collect::<Html>()
+                                }
+                            </select>
+                        </div>
+                        if send_whitelist.fees.len() > 1 {
+                            <div class="mx-2">
+                                <span>
+                                    { "with a fee of" }
+                                </span>
+                            </div>
+                            <div class="select my-1">
+                                <select ref={ fee_select_ref.clone() }>
+                                    {
+                                        send_whitelist.fees.iter().enumerate().map(|(i, fee)| {
+                                            html! {
+                                                <option selected={ i == 0 }>
+                                                    { format!("{} coins", coins_from(*fee as i64)) }
+                                                </option>
+                                            }
+                                        }).collect::<Html>()
+                                    }
+                                </select>
+                            </div>
+                        }
                     </div>
-                </div>
+                    if let Some(send_error) = self.send_error.as_ref() {
+                        <p class="help is-danger">
+                            { send_error }
+                        </p>
+                    }
+                </>
             }
         } else {
             html! {
@@ -465,6 +585,26 @@ impl Wallet {
                                 </div>
                             </div>
                         </div>
+                        <div class="field is-horizontal">
+                            <div class="field-label is-normal">
+                                <label class="label">{ "Fee" }</label>
+                            </div>
+                            <div class="field-body">
+                                <div class="field has-addons">
+                                    <div class="control">
+                                        <input ref={self.send_modal.fee_field_ref.clone()} class="input" type="number" />
+                                    </div>
+                                    <div class="control">
+                                        <a class="button is-static">
+                                            { "coins" }
+                                        </a>
+                                    </div>
+                                </div>
+                                <p class="help">
+                                    { "Miners prefer transactions with higher fees when blocks are full." }
+                                </p>
+                            </div>
+                        </div>
                     </section>
                     <footer class="modal-card-foot">
                         <button class="button is-success" onclick={onclick_broadcast}>{ "Broadcast Transaction" }</button>
@@ -474,7 +614,7 @@ impl Wallet {
             </div>
         }
     }
-    fn parse_send_form(&self, ctx: &Context<Self>) -> Result<(Address, Address, u64), String> {
+    fn parse_send_form(&self, ctx: &Context<Self>) -> Result<(Address, Address, u64, u64), String> {
         let from = ctx.props().address.clone();
 
         let to = self
@@ -492,10 +632,26 @@ impl Wallet {
             .map(|ie| ie.value())
             .and_then(|v| v.parse::<f64>().ok())
             .and_then(|v| blockchain_types::toshis_from(v).try_into().ok())
-            .and_then(|v| (v > 0).then(|| v))
+            .and_then(|v| (v > 0).then_some(v))
             .ok_or("Invalid \"value\".")?;
 
-        Ok((from, to, value))
+        let fee = self
+            .send_modal
+            .fee_field_ref
+            .cast::<HtmlInputElement>()
+            .map_or(Some(0), |ie| {
+                let fee = ie.value();
+                if fee.is_empty() {
+                    Some(0)
+                } else {
+                    fee.parse::<f64>()
+                        .ok()
+                        .and_then(|v| blockchain_types::toshis_from(v).try_into().ok())
+                }
+            })
+            .ok_or("Invalid \"fee\".")?;
+
+        Ok((from, to, value, fee))
     }
 }
 
@@ -511,6 +667,9 @@ impl SendModalState {
         if let Some(value_field) = self.value_field_ref.cast::<HtmlInputElement>() {
             value_field.set_value("0");
         }
+        if let Some(fee_field) = self.fee_field_ref.cast::<HtmlInputElement>() {
+            fee_field.set_value("0");
+        }
     }
 }
 
@@ -522,6 +681,11 @@ struct TransactionsCache {
     txes_confirmed: VecDeque<(usize, Entity, Transaction)>,
     txids_unconfirmed: BTreeSet<Entity>,
     txes_unconfirmed: VecDeque<(Entity, Transaction)>,
+    /// Transactions that were on the blockchain, but aren't anymore (and aren't unconfirmed
+    /// either), e.g., because of a double spend. Newest first.
+    txes_reverted: VecDeque<(Entity, Transaction)>,
+    /// How many blocks need to confirm newly mined coins before they can be spent.
+    coinbase_maturity: usize,
 }
 impl TransactionsCache {
     fn new(monitored_address: Address) -> Self {
@@ -567,10 +731,18 @@ impl TransactionsCache {
                     .map(|(height, id, tx)| (self.tip_height() - height + 1, *id, tx)),
             )
     }
+    fn iter_reverted_transactions(&self) -> impl Iterator<Item = (Entity, &Transaction)> {
+        self.txes_reverted
+            .iter()
+            .filter(|(id, _)| !self.txids_unconfirmed.contains(id))
+            .map(|(id, tx)| (*id, tx))
+    }
     fn update(&mut self, sim: &Simulation) -> bool {
         if let Some(full_node) = self.full_node {
             if let Some(state) = get_state(full_node, sim) {
                 self.update_confirmed(&state, sim) || self.update_unconfirmed(&state, sim)
+            } else if let Ok(state) = sim.world.get::<LightClientState>(full_node) {
+                self.update_from_light_client(&state)
             } else {
                 self.reset()
             }
@@ -579,6 +751,7 @@ impl TransactionsCache {
         }
     }
     fn update_confirmed(&mut self, state: &NakamotoNodeState, sim: &Simulation) -> bool {
+        self.coinbase_maturity = state.monetary_policy().coinbase_maturity;
         let new_tip = state
             .tip()
             .map(|block_id| state.block_header(block_id).unwrap());
@@ -632,11 +805,77 @@ impl TransactionsCache {
             true
         }
     }
+    /// Light clients only know the transactions that they have proofs for and those that they
+    /// built themselves, so we simply take over what they know.
+    fn update_from_light_client(&mut self, state: &LightClientState) -> bool {
+        let tip = state.tip().and_then(|tip| state.block_header(tip));
+        let txes_confirmed: VecDeque<(usize, Entity, Transaction)> = state
+            .confirmed_transactions()
+            .into_iter()
+            .rev()
+            .filter(|(_, _, tx)| self.is_relevant(tx))
+            .map(|(header, tx_id, tx)| (header.height, tx_id, tx.clone()))
+            .collect();
+        let mut txes_unconfirmed: VecDeque<(Entity, Transaction)> = state
+            .unconfirmed_transactions()
+            .filter(|(_, tx)| self.is_relevant(tx))
+            .map(|(tx_id, tx)| (tx_id, tx.clone()))
+            .collect();
+        txes_unconfirmed.make_contiguous().reverse();
+        if self.tip == tip
+            && self.txes_confirmed == txes_confirmed
+            && self.txes_unconfirmed == txes_unconfirmed
+        {
+            return false;
+        }
+        for (_, tx_id, tx) in self.txes_confirmed.iter() {
+            if !txes_confirmed.iter().any(|(_, id, _)| id == tx_id) {
+                self.txes_reverted.push_front((*tx_id, tx.clone()));
+            }
+        }
+        self.txes_reverted
+            .retain(|(tx_id, _)| !txes_confirmed.iter().any(|(_, id, _)| id == tx_id));
+        self.tip = tip;
+        self.txes_confirmed = txes_confirmed;
+        self.txids_unconfirmed = txes_unconfirmed.iter().map(|(tx_id, _)| *tx_id).collect();
+        self.txes_unconfirmed = txes_unconfirmed;
+        true
+    }
     fn rebuild_confirmed_from_tip(&mut self, sim: &Simulation, block_id: Entity) {
-        self.txes_confirmed.clear();
+        let mut blocks = vec![];
         let mut next_block = Some(block_id);
         while let Some(block_id) = next_block {
-            next_block = self.update_confirmed_by_one_block(sim, block_id)
+            // The contents of deeply buried blocks might have been garbage-collected - we keep
+            // whatever we already know about these.
+            if sim.world.get::<BlockContents>(block_id).is_err() {
+                break;
+            }
+            blocks.push(block_id);
+            next_block = get_block_header_unchecked(block_id, sim).id_prev;
+        }
+        let lowest_rebuilt_height = blocks.last().map_or(usize::MAX, |&block_id| {
+            get_block_header_unchecked(block_id, sim).height
+        });
+        let mut removed_txes = vec![];
+        self.txes_confirmed.retain(|(height, id, tx)| {
+            if *height < lowest_rebuilt_height {
+                true
+            } else {
+                removed_txes.push((*id, tx.clone()));
+                false
+            }
+        });
+        for block_id in blocks.into_iter().rev() {
+            self.update_confirmed_by_one_block(sim, block_id);
+        }
+        for (id, tx) in removed_txes {
+            if !self
+                .txes_confirmed
+                .iter()
+                .any(|&(_, other_id, _)| other_id == id)
+            {
+                self.txes_reverted.push_front((id, tx));
+            }
         }
     }
     fn update_confirmed_by_one_block(
@@ -653,18 +892,19 @@ impl TransactionsCache {
             if self.is_relevant(&tx) {
                 self.txes_confirmed
                     .push_front((block_height, *txid, (*tx).clone()));
+                self.txes_reverted.retain(|(id, _)| id != txid);
             }
         }
         block_header.id_prev
     }
     fn is_relevant(&self, tx: &Transaction) -> bool {
-        tx.from == self.monitored_address || tx.to == self.monitored_address
+        tx.from == self.monitored_address || *tx.to() == self.monitored_address
     }
     fn value_of(&self, tx: &Transaction) -> i64 {
         if tx.from == self.monitored_address {
-            -(tx.value as i64)
-        } else if tx.to == self.monitored_address {
-            tx.value as i64
+            -((tx.value() + tx.fee) as i64)
+        } else if *tx.to() == self.monitored_address {
+            tx.value() as i64
         } else {
             0
         }
@@ -697,11 +937,11 @@ fn get_state(node_id: Entity, sim: &Simulation) -> Option<hecs::Ref<NakamotoNode
     sim.world.get::<NakamotoNodeState>(node_id).ok()
 }
 
-fn get_selected_recipient(recipients: &[String], select_ref: &NodeRef) -> Option<String> {
+fn get_selected<T: Clone>(options: &[T], select_ref: &NodeRef) -> Option<T> {
     select_ref
         .cast::<HtmlSelectElement>()
         .map(|el| el.selected_index() as usize)
-        .and_then(|selected_index| recipients.get(selected_index))
+        .and_then(|selected_index| options.get(selected_index))
         .cloned()
 }
 
@@ -729,7 +969,7 @@ mod tests {
         sim.do_now(ForSpecific(
             miner_node,
             nakamoto_consensus::BuildAndBroadcastTransaction::from(
-                "CoinBroker25",
+                "Faucet",
                 "Bob",
                 blockchain_types::toshis_from(1.) as u64,
             ),
@@ -768,4 +1008,48 @@ mod tests {
             "Cache didn't calculate wallet value correctly?"
         );
     }
+
+    #[wasm_bindgen_test]
+    fn cache_notices_reverted_payments() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            nakamoto_consensus::NakamotoConsensus::default(),
+        ));
+        sim.add_event_handler(double_spending::DoubleSpending);
+
+        let attacker = sim.spawn_random_node();
+        let merchant_node = sim.spawn_random_node();
+        sim.add_peer(attacker, merchant_node);
+        sim.add_peer(merchant_node, attacker);
+        sim.do_now(ForSpecific(
+            merchant_node,
+            nakamoto_consensus::BuildAndBroadcastTransaction::from("Faucet", "Mallory", 100),
+        ));
+        sim.do_now(ForSpecific(merchant_node, nakamoto_consensus::MineBlock));
+        sim.work_until(SimSeconds::from(10.));
+        sim.do_now(ForSpecific(
+            attacker,
+            double_spending::StartDoubleSpendAttack::new("Mallory", "Merchant", 100)
+                .with_confirmations(1),
+        ));
+        sim.work_until(SimSeconds::from(20.));
+        sim.do_now(ForSpecific(merchant_node, nakamoto_consensus::MineBlock));
+        sim.work_until(SimSeconds::from(30.));
+
+        let mut cache = TransactionsCache::new("Merchant".to_string());
+        cache.full_node = Some(merchant_node);
+        cache.update(&sim);
+        assert_eq!(cache.total_value_confirmed(), 100);
+        assert_eq!(cache.iter_reverted_transactions().count(), 0);
+
+        sim.do_now(MultipleTimes::new(
+            ForSpecific(attacker, nakamoto_consensus::MineBlock),
+            2,
+        ));
+        sim.work_until(SimSeconds::from(40.));
+        cache.update(&sim);
+        assert_eq!(cache.tip_height(), 3);
+        assert_eq!(cache.total_value_confirmed(), 0);
+        assert_eq!(cache.iter_reverted_transactions().count(), 1);
+    }
 }
@@ -14,6 +14,8 @@ pub use protocols::*;
 
 mod simulation;
 pub use simulation::*;
+// disambiguate from `yew::Event`
+pub use simulation::Event;
 
 pub struct Isds {
     pub sim: SharedSimulation,
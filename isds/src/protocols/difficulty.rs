@@ -0,0 +1,205 @@
+use super::*;
+use blockchain_types::*;
+
+/// Bitcoin adjusts its difficulty every 2016 blocks, i.e., about every two weeks.
+pub const BITCOIN_RETARGET_WINDOW: usize = 2016;
+/// Bitcoin never changes its difficulty by more than this factor at once.
+pub const BITCOIN_MAX_ADJUSTMENT_FACTOR: f64 = 4.;
+
+/// How the difficulty of new blocks is derived from the blocks before them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetargetingRule {
+    /// The difficulty never changes.
+    Fixed,
+    /// Bitcoin's rule: Every `window` blocks, the difficulty is scaled by how much faster (or
+    /// slower) than targeted the blocks of the last window were found, but by no more than
+    /// `max_factor` in either direction. Like Bitcoin, we measure the time between the first and
+    /// the last block of the window, which spans `window - 1` block intervals.
+    Window { window: usize, max_factor: f64 },
+    /// Adjusts the difficulty with every block, exponentially in how much the last block interval
+    /// deviated from the target. Blocks that are `half_life` too slow halve the difficulty.
+    /// Similar to Bitcoin Cash's ASERT.
+    PerBlock { half_life: SimSeconds },
+}
+impl Default for RetargetingRule {
+    fn default() -> Self {
+        Self::Window {
+            window: BITCOIN_RETARGET_WINDOW,
+            max_factor: BITCOIN_MAX_ADJUSTMENT_FACTOR,
+        }
+    }
+}
+
+/// The consensus rules for the difficulty that block headers need to carry. The difficulty is the
+/// expected number of hashes needed to find a block, so a node with a `Hashrate` of `h` finds
+/// blocks at a rate of `h / difficulty`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DifficultyAdjustment {
+    /// Difficulty of the first block.
+    pub initial_difficulty: f64,
+    pub target_block_interval: SimSeconds,
+    pub rule: RetargetingRule,
+}
+impl Default for DifficultyAdjustment {
+    /// Bitcoin's rules, for a total hashrate of 1.
+    fn default() -> Self {
+        Self::for_block_interval(SimSeconds::from(600.), 1.)
+    }
+}
+impl DifficultyAdjustment {
+    /// Chooses the initial difficulty such that blocks are found every `target_block_interval` on
+    /// average if the nodes have `total_hashrate` together. Retargets like Bitcoin.
+    pub fn for_block_interval(target_block_interval: SimSeconds, total_hashrate: f64) -> Self {
+        Self {
+            initial_difficulty: target_block_interval.into_inner() * total_hashrate,
+            target_block_interval,
+            rule: RetargetingRule::default(),
+        }
+    }
+    pub fn with_rule(mut self, rule: RetargetingRule) -> Self {
+        self.rule = rule;
+        self
+    }
+    /// Is the block at `height` one where the difficulty may change?
+    pub fn is_retarget_height(&self, height: usize) -> bool {
+        match self.rule {
+            RetargetingRule::Fixed => false,
+            RetargetingRule::Window { window, .. } => window > 0 && height.is_multiple_of(window),
+            RetargetingRule::PerBlock { .. } => height > 1,
+        }
+    }
+    /// The difficulty that a block building on `parent` must have. `get_header` needs to know the
+    /// headers of the blocks before `parent`, at least as far back as the retargeting window goes.
+    pub fn next_difficulty(
+        &self,
+        parent: Option<BlockHeader>,
+        get_header: impl Fn(Entity) -> Option<BlockHeader>,
+    ) -> f64 {
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return self.initial_difficulty,
+        };
+        let difficulty = parent.difficulty.into_inner();
+        if !self.is_retarget_height(parent.height + 1) {
+            return difficulty;
+        }
+        let target = self.target_block_interval.into_inner();
+        match self.rule {
+            RetargetingRule::Fixed => difficulty,
+            RetargetingRule::Window { window, max_factor } => {
+                let mut first = parent;
+                while first.height + window > parent.height + 1 {
+                    match first.id_prev.and_then(&get_header) {
+                        Some(header) => first = header,
+                        None => break,
+                    }
+                }
+                let intervals = parent.height - first.height;
+                let timespan = (parent.time - first.time).into_inner();
+                if intervals == 0 || timespan <= 0. {
+                    return difficulty * max_factor;
+                }
+                let factor =
+                    (target * intervals as f64 / timespan).clamp(1. / max_factor, max_factor);
+                difficulty * factor
+            }
+            RetargetingRule::PerBlock { half_life } => match parent.id_prev.and_then(&get_header) {
+                Some(grandparent) => {
+                    let interval = (parent.time - grandparent.time).into_inner();
+                    difficulty * ((target - interval) / half_life.into_inner()).exp2()
+                }
+                None => difficulty,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Spawns a chain of blocks that were found `interval` apart, following `adjustment`.
+    fn spawn_chain(
+        sim: &mut Simulation,
+        adjustment: &DifficultyAdjustment,
+        length: usize,
+        interval: f64,
+    ) -> HashMap<Entity, BlockHeader> {
+        let miner = sim.spawn_random_node();
+        let mut headers = HashMap::new();
+        let mut tip: Option<BlockHeader> = None;
+        for i in 0..length {
+            let difficulty = adjustment.next_difficulty(tip, |id| headers.get(&id).copied());
+            sim.work_until(SimSeconds::from(interval * (i + 1) as f64));
+            let header = sim.node_interface(miner).spawn_block_with_difficulty(
+                tip.map(|h| h.id),
+                [],
+                difficulty,
+            );
+            headers.insert(header.id, header);
+            tip = Some(header);
+        }
+        headers
+    }
+
+    fn difficulty_at(headers: &HashMap<Entity, BlockHeader>, height: usize) -> f64 {
+        headers
+            .values()
+            .find(|h| h.height == height)
+            .unwrap()
+            .difficulty
+            .into_inner()
+    }
+
+    #[wasm_bindgen_test]
+    fn difficulty_only_changes_at_the_end_of_a_window() {
+        let mut sim = Simulation::new();
+        let adjustment = DifficultyAdjustment::for_block_interval(SimSeconds::from(10.), 1.)
+            .with_rule(RetargetingRule::Window {
+                window: 10,
+                max_factor: 4.,
+            });
+        // blocks come twice as fast as they should
+        let headers = spawn_chain(&mut sim, &adjustment, 25, 5.);
+
+        assert_eq!(10., difficulty_at(&headers, 1));
+        assert_eq!(10., difficulty_at(&headers, 9));
+        assert_eq!(20., difficulty_at(&headers, 10));
+        assert_eq!(20., difficulty_at(&headers, 19));
+        assert_eq!(40., difficulty_at(&headers, 20));
+    }
+
+    #[wasm_bindgen_test]
+    fn adjustments_are_limited_by_max_factor() {
+        let mut sim = Simulation::new();
+        let adjustment = DifficultyAdjustment::for_block_interval(SimSeconds::from(600.), 1.)
+            .with_rule(RetargetingRule::Window {
+                window: 5,
+                max_factor: 4.,
+            });
+        let headers = spawn_chain(&mut sim, &adjustment, 6, 1.);
+        assert_eq!(2400., difficulty_at(&headers, 5));
+
+        let headers = spawn_chain(&mut sim, &adjustment, 6, 100_000.);
+        assert_eq!(150., difficulty_at(&headers, 5));
+    }
+
+    #[wasm_bindgen_test]
+    fn per_block_rule_halves_difficulty_for_blocks_that_are_a_half_life_too_slow() {
+        let mut sim = Simulation::new();
+        let adjustment = DifficultyAdjustment::for_block_interval(SimSeconds::from(10.), 1.)
+            .with_rule(RetargetingRule::PerBlock {
+                half_life: SimSeconds::from(20.),
+            });
+        let headers = spawn_chain(&mut sim, &adjustment, 4, 30.);
+
+        assert_eq!(10., difficulty_at(&headers, 1));
+        assert_eq!(10., difficulty_at(&headers, 2));
+        assert_eq!(5., difficulty_at(&headers, 3));
+        assert_eq!(2.5, difficulty_at(&headers, 4));
+    }
+}
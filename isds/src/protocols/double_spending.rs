@@ -0,0 +1,484 @@
+use super::*;
+use blockchain_types::*;
+use nakamoto_consensus::*;
+
+/// Starts a double-spend attack by a node: It pays `value` from `from` to `merchant` and
+/// broadcasts that payment as usual. At the same time, it secretly mines a chain that contains a
+/// conflicting transaction, which spends the same coins but pays `double_spend_to` instead (by
+/// default, `from` itself). See `DoubleSpending` for what happens next.
+///
+/// Both transactions spend the same unspent output of `from` on the node's longest chain, which
+/// must be worth at least `value`. Anything above `value` goes back to `from` as change.
+#[derive(Debug, Clone)]
+pub struct StartDoubleSpendAttack {
+    pub from: Address,
+    pub merchant: Address,
+    pub double_spend_to: Address,
+    pub value: u64,
+    /// How many confirmations the merchant waits for before handing out the goods.
+    pub confirmations: usize,
+    /// How many blocks the attacker may fall behind the honest chain before it gives up.
+    pub max_deficit: usize,
+}
+impl StartDoubleSpendAttack {
+    pub fn new(from: &str, merchant: &str, value: u64) -> Self {
+        Self {
+            from: from.to_string(),
+            merchant: merchant.to_string(),
+            double_spend_to: from.to_string(),
+            value,
+            confirmations: 3,
+            max_deficit: 3,
+        }
+    }
+    pub fn with_double_spend_to(mut self, double_spend_to: &str) -> Self {
+        self.double_spend_to = double_spend_to.to_string();
+        self
+    }
+    pub fn with_confirmations(mut self, confirmations: usize) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+    pub fn with_max_deficit(mut self, max_deficit: usize) -> Self {
+        self.max_deficit = max_deficit;
+        self
+    }
+}
+impl StartDoubleSpendAttack {
+    fn spend(&self, (out_point, output): (OutPoint, TxOutput), to: &Address) -> Transaction {
+        let mut outputs = vec![TxOutput {
+            to: to.clone(),
+            value: self.value,
+        }];
+        if output.value > self.value {
+            outputs.push(TxOutput {
+                to: self.from.clone(),
+                value: output.value - self.value,
+            });
+        }
+        Transaction {
+            from: self.from.clone(),
+            inputs: vec![out_point],
+            outputs,
+            fee: 0,
+        }
+    }
+}
+impl EntityAction for StartDoubleSpendAttack {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        if sim
+            .world
+            .get::<DoubleSpenderState>(entity)
+            .is_ok_and(|attacker_state| attacker_state.outcome == DoubleSpendOutcome::Ongoing)
+        {
+            return Err("The node is carrying out another double-spend attack already!".into());
+        }
+        let coins = unspent_coins(sim, entity, &self.from, self.value)
+            .ok_or_else(|| format!("{} has no confirmed coins to double-spend!", self.from))?;
+        sim.add_node_role(entity, NodeRole::Attacker)?;
+        let mut node = sim.node_interface(entity);
+        let payment = node.spawn_transaction(self.spend(coins.clone(), &self.merchant));
+        let double_spend = node.spawn_transaction(self.spend(coins, &self.double_spend_to));
+        let double_spend_tx = node.get_transaction(double_spend).unwrap().clone();
+        let state = node.get::<NakamotoNodeState>();
+        let fork_point = state.tip();
+        state.register_transaction(double_spend, &double_spend_tx);
+        state.set_private_tip(fork_point);
+        state.set_withholds_blocks(true);
+        *node.get::<DoubleSpenderState>() = DoubleSpenderState {
+            payment: Some(payment),
+            double_spend: Some(double_spend),
+            fork_point,
+            confirmations: self.confirmations,
+            max_deficit: self.max_deficit,
+            outcome: DoubleSpendOutcome::Ongoing,
+        };
+        node.log(&format!(
+            "Paying {} to {}, but secretly mining a chain that pays {} instead.",
+            self.value, self.merchant, self.double_spend_to
+        ));
+        NakamotoConsensus::broadcast_transaction(&mut node, payment);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DoubleSpendOutcome {
+    #[default]
+    Ongoing,
+    /// The attacker's chain replaced the one with the payment to the merchant.
+    Succeeded,
+    /// The honest chain got too far ahead.
+    GaveUp,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DoubleSpenderState {
+    payment: Option<Entity>,
+    double_spend: Option<Entity>,
+    /// The last block that the attacker's private chain shares with the honest chain.
+    fork_point: Option<Entity>,
+    confirmations: usize,
+    max_deficit: usize,
+    outcome: DoubleSpendOutcome,
+}
+impl DoubleSpenderState {
+    /// The transaction that pays the merchant.
+    pub fn payment(&self) -> Option<Entity> {
+        self.payment
+    }
+    /// The transaction that spends the same coins on the attacker's chain.
+    pub fn double_spend(&self) -> Option<Entity> {
+        self.double_spend
+    }
+    pub fn outcome(&self) -> DoubleSpendOutcome {
+        self.outcome
+    }
+}
+
+/// Carries out the double-spend attacks started with `StartDoubleSpendAttack`. The attacker mines
+/// on its private chain (see `NakamotoNodeState::set_private_tip`), no matter how far the honest
+/// chain gets ahead. As soon as the merchant's payment has enough confirmations on the honest chain
+/// *and* the private chain is longer, the attacker publishes it: Honest nodes switch to the longer
+/// chain, and the payment disappears from the blockchain because it conflicts with the double
+/// spend. If the honest chain gets more than `max_deficit` blocks ahead, the attacker gives up.
+///
+/// With less than half of the hashrate, the attacker's chances shrink exponentially with every
+/// confirmation that the merchant waits for. With more than half of it, the attack succeeds
+/// eventually - that's a *51% attack*.
+///
+/// Add it after `NakamotoConsensus` and before `Mining`.
+#[derive(Debug, Clone, Default)]
+pub struct DoubleSpending;
+impl DoubleSpending {
+    fn update_attacker(&self, sim: &mut Simulation, node: Entity) {
+        let attacker_state = match sim.world.get::<DoubleSpenderState>(node) {
+            Ok(attacker_state) if attacker_state.outcome == DoubleSpendOutcome::Ongoing => {
+                (*attacker_state).clone()
+            }
+            _ => return,
+        };
+        let (private_height, public_tip, public_height) =
+            match sim.world.get::<NakamotoNodeState>(node) {
+                Ok(state) => {
+                    let public_tip = public_tip(&state);
+                    let public_height = state
+                        .height(public_tip)
+                        .max(state.height(attacker_state.fork_point));
+                    (state.height(state.mining_tip()), public_tip, public_height)
+                }
+                Err(_) => return,
+            };
+        let confirmations = attacker_state.payment.map_or(0, |payment| {
+            confirmations_after_fork_point(sim, node, public_tip, payment)
+        });
+
+        let mut node_interface = sim.node_interface(node);
+        let outcome = if confirmations >= attacker_state.confirmations
+            && private_height > public_height
+        {
+            node_interface.log(&format!(
+                "The merchant has seen {} confirmations, time to publish my longer chain!",
+                confirmations
+            ));
+            NakamotoConsensus::publish_withheld_blocks(&mut node_interface, usize::MAX);
+            DoubleSpendOutcome::Succeeded
+        } else if public_height > private_height + attacker_state.max_deficit {
+            node_interface.log("The honest chain is too far ahead, giving up on my double spend.");
+            node_interface
+                .get::<NakamotoNodeState>()
+                .discard_withheld_blocks();
+            DoubleSpendOutcome::GaveUp
+        } else {
+            DoubleSpendOutcome::Ongoing
+        };
+        if outcome != DoubleSpendOutcome::Ongoing {
+            let state = node_interface.get::<NakamotoNodeState>();
+            state.set_private_tip(None);
+            state.set_withholds_blocks(false);
+            node_interface.get::<DoubleSpenderState>().outcome = outcome;
+        }
+    }
+}
+impl EventHandler for DoubleSpending {
+    fn handle_event(&mut self, sim: &mut Simulation, event: Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            Event::Node(node, _) => self.update_attacker(sim, node),
+            // attackers might have mined a block
+            Event::Command(_) => {
+                let attackers: Vec<Entity> = sim
+                    .world
+                    .query::<&DoubleSpenderState>()
+                    .iter()
+                    .map(|(node, _)| node)
+                    .collect();
+                for node in attackers {
+                    self.update_attacker(sim, node);
+                }
+            }
+            Event::Generic(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// A mature output of `address` that is worth at least `value` and that is unspent as far as
+/// `node` knows, i.e., neither on its longest chain nor in its mempool.
+fn unspent_coins(
+    sim: &Simulation,
+    node: Entity,
+    address: &Address,
+    value: u64,
+) -> Option<(OutPoint, TxOutput)> {
+    let state = sim.world.get::<NakamotoNodeState>(node).ok()?;
+    let coins = state
+        .utxos()
+        .owned_by(address)
+        .into_iter()
+        .find(|&(out_point, output)| {
+            state.spent_in_mempool(out_point).is_none()
+                && state.is_mature(out_point)
+                && output.value >= value
+        })
+        .map(|(out_point, output)| (out_point, output.clone()));
+    coins
+}
+
+/// The tip of the longest chain that doesn't contain any of our withheld blocks, if there is one.
+fn public_tip(state: &NakamotoNodeState) -> Option<Entity> {
+    state
+        .tip()
+        .iter()
+        .chain(state.fork_tips().iter())
+        .copied()
+        .filter(|block_id| !state.withheld_blocks().contains(block_id))
+        .max_by_key(|&block_id| state.height(Some(block_id)))
+}
+
+/// How many blocks of `node`'s longest chain confirm `tx_id`, i.e., the block that contains it
+/// and all the blocks after it. Zero if it isn't on that chain (or if the contents of the block
+/// that contains it have been garbage-collected).
+pub fn confirmations(sim: &Simulation, node: Entity, tx_id: Entity) -> usize {
+    match sim.world.get::<NakamotoNodeState>(node) {
+        Ok(state) => confirmations_on_chain(sim, &state, state.tip(), tx_id, 0),
+        Err(_) => 0,
+    }
+}
+
+/// How deep `tx_id` is buried on the chain ending in `tip`, counting only blocks after the
+/// attacker's fork point.
+fn confirmations_after_fork_point(
+    sim: &Simulation,
+    node: Entity,
+    tip: Option<Entity>,
+    tx_id: Entity,
+) -> usize {
+    match (
+        sim.world.get::<NakamotoNodeState>(node),
+        sim.world.get::<DoubleSpenderState>(node),
+    ) {
+        (Ok(state), Ok(attacker_state)) => {
+            let fork_point_height = state.height(attacker_state.fork_point);
+            confirmations_on_chain(sim, &state, tip, tx_id, fork_point_height)
+        }
+        _ => 0,
+    }
+}
+
+/// Looks for `tx_id` in the blocks of the chain ending in `tip` that are higher than
+/// `min_height`.
+fn confirmations_on_chain(
+    sim: &Simulation,
+    state: &NakamotoNodeState,
+    tip: Option<Entity>,
+    tx_id: Entity,
+    min_height: usize,
+) -> usize {
+    let tip_height = state.height(tip);
+    let mut next_block = tip;
+    while let Some(header) = next_block.and_then(|block_id| state.block_header(block_id)) {
+        if header.height <= min_height {
+            break;
+        }
+        let contents = match sim.world.get::<BlockContents>(header.id) {
+            Ok(contents) => contents,
+            Err(_) => break,
+        };
+        if contents.iter().any(|&id| id == tx_id) {
+            return tip_height - header.height + 1;
+        }
+        next_block = header.id_prev;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    fn get_state(sim: &Simulation, node: Entity) -> NakamotoNodeState {
+        (*sim.world.get::<NakamotoNodeState>(node).unwrap()).clone()
+    }
+
+    fn is_on_longest_chain(sim: &Simulation, node: Entity, tx_id: Entity) -> bool {
+        confirmations(sim, node, tx_id) > 0
+    }
+
+    /// An attacker, an honest miner and the merchant's node, all connected. Mallory has 100 toshis
+    /// on the blockchain already.
+    fn init_simulation() -> (Simulation, Entity, Entity, Entity) {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        sim.add_event_handler(DoubleSpending);
+
+        let attacker = sim.spawn_random_node();
+        let honest_miner = sim.spawn_random_node();
+        let merchant_node = sim.spawn_random_node();
+        for (a, b) in [
+            (attacker, honest_miner),
+            (honest_miner, merchant_node),
+            (merchant_node, attacker),
+        ] {
+            sim.add_peer(a, b);
+            sim.add_peer(b, a);
+        }
+        sim.do_now(ForSpecific(
+            honest_miner,
+            BuildAndBroadcastTransaction::from("Faucet", "Mallory", 100),
+        ));
+        sim.work_until(SimSeconds::from(10.));
+        sim.do_now(ForSpecific(honest_miner, MineBlock));
+        sim.work_until(SimSeconds::from(20.));
+        (sim, attacker, honest_miner, merchant_node)
+    }
+
+    fn mine(sim: &mut Simulation, node: Entity) {
+        sim.do_now(ForSpecific(node, MineBlock));
+        let until = sim.time.now() + SimSeconds::from(10.);
+        sim.work_until(until);
+    }
+
+    #[wasm_bindgen_test]
+    fn payment_disappears_after_merchant_saw_enough_confirmations() {
+        let (mut sim, attacker, honest_miner, merchant_node) = init_simulation();
+        sim.do_now(ForSpecific(
+            attacker,
+            StartDoubleSpendAttack::new("Mallory", "Merchant", 100).with_confirmations(2),
+        ));
+        sim.work_until(SimSeconds::from(30.));
+        let attacker_state = (*sim.world.get::<DoubleSpenderState>(attacker).unwrap()).clone();
+        let payment = attacker_state.payment().unwrap();
+        let double_spend = attacker_state.double_spend().unwrap();
+        assert!(get_state(&sim, merchant_node)
+            .txes_unconfirmed()
+            .contains(&payment));
+        assert!(!get_state(&sim, attacker)
+            .txes_unconfirmed()
+            .contains(&payment));
+
+        mine(&mut sim, attacker);
+        mine(&mut sim, honest_miner);
+        mine(&mut sim, honest_miner);
+        mine(&mut sim, attacker);
+        // the merchant saw two confirmations, but the attacker's chain isn't longer yet
+        assert!(is_on_longest_chain(&sim, merchant_node, payment));
+        assert_eq!(2, get_state(&sim, attacker).withheld_blocks().len());
+
+        mine(&mut sim, attacker);
+        let attacker_state = (*sim.world.get::<DoubleSpenderState>(attacker).unwrap()).clone();
+        assert_eq!(DoubleSpendOutcome::Succeeded, attacker_state.outcome());
+        for node in [attacker, honest_miner, merchant_node] {
+            assert_eq!(4, get_state(&sim, node).tip_height());
+            assert!(!is_on_longest_chain(&sim, node, payment));
+            assert!(is_on_longest_chain(&sim, node, double_spend));
+            assert!(!get_state(&sim, node).txes_unconfirmed().contains(&payment));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn attacker_gives_up_when_too_far_behind() {
+        let (mut sim, attacker, honest_miner, merchant_node) = init_simulation();
+        sim.do_now(ForSpecific(
+            attacker,
+            StartDoubleSpendAttack::new("Mallory", "Merchant", 100).with_max_deficit(2),
+        ));
+        sim.work_until(SimSeconds::from(30.));
+        let payment = sim
+            .world
+            .get::<DoubleSpenderState>(attacker)
+            .unwrap()
+            .payment()
+            .unwrap();
+
+        mine(&mut sim, attacker);
+        for _ in 0..3 {
+            mine(&mut sim, honest_miner);
+        }
+        let attacker_state = (*sim.world.get::<DoubleSpenderState>(attacker).unwrap()).clone();
+        assert_eq!(DoubleSpendOutcome::Ongoing, attacker_state.outcome());
+
+        mine(&mut sim, honest_miner);
+        let attacker_state = (*sim.world.get::<DoubleSpenderState>(attacker).unwrap()).clone();
+        assert_eq!(DoubleSpendOutcome::GaveUp, attacker_state.outcome());
+        // the attacker is back to mining on the honest chain
+        mine(&mut sim, attacker);
+        let state = get_state(&sim, attacker);
+        assert_eq!(None, state.private_tip());
+        assert_eq!(6, state.tip_height());
+        assert_eq!(state.tip(), get_state(&sim, merchant_node).tip());
+        assert!(is_on_longest_chain(&sim, merchant_node, payment));
+    }
+
+    #[wasm_bindgen_test]
+    fn immature_coinbase_outputs_arent_double_spent() {
+        let (mut sim, _, honest_miner, _) = init_simulation();
+        let miner = sim.label(honest_miner);
+        assert!(get_state(&sim, honest_miner).utxos().balance(&miner) > 0);
+
+        let result =
+            StartDoubleSpendAttack::new(&miner, "Merchant", 1).execute_for(&mut sim, honest_miner);
+        assert!(result.is_err());
+        assert!(sim.world.get::<DoubleSpenderState>(honest_miner).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn conflicting_transactions_are_rejected() {
+        let (mut sim, _, honest_miner, merchant_node) = init_simulation();
+        let state = get_state(&sim, honest_miner);
+        let coins = OutPoint {
+            tx_id: *sim
+                .world
+                .get::<BlockContents>(state.tip().unwrap())
+                .unwrap()
+                .iter()
+                .next()
+                .unwrap(),
+            index: 0,
+        };
+        let spend_to = |to: &str| Transaction {
+            from: "Mallory".into(),
+            inputs: vec![coins],
+            outputs: vec![TxOutput {
+                to: to.into(),
+                value: 100,
+            }],
+            fee: 0,
+        };
+        let mut node = sim.node_interface(honest_miner);
+        let first = node.spawn_transaction(spend_to("Alice"));
+        let second = node.spawn_transaction(spend_to("Bob"));
+        NakamotoConsensus::broadcast_transaction(&mut node, first);
+        sim.work_until(SimSeconds::from(30.));
+        let mut node = sim.node_interface(honest_miner);
+        NakamotoConsensus::broadcast_transaction(&mut node, second);
+        sim.work_until(SimSeconds::from(40.));
+
+        let state = get_state(&sim, merchant_node);
+        assert!(state.txes_unconfirmed().contains(&first));
+        assert!(!state.txes_unconfirmed().contains(&second));
+        assert_eq!(Some(first), state.spent_in_mempool(coins));
+    }
+}
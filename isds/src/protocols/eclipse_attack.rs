@@ -0,0 +1,232 @@
+use super::*;
+use nakamoto_consensus::NakamotoNodeState;
+use peer_discovery::{AddressBook, DiscoveryMessage, Reconnect};
+
+/// How long the victim of `EclipseMethod::PoisonAddressBook` takes until it reconnects.
+pub const RECONNECT_DELAY: f64 = 60.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EclipseMethod {
+    /// The attackers take over all of the victim's connections right away - as if they had kept
+    /// opening connections to it until its honest peers got pushed out.
+    FillPeerSet,
+    /// The attackers connect to the victim and flood its `AddressBook` with their own addresses.
+    /// Once the victim reconnects, e.g., after a restart, it only finds attackers. Needs
+    /// `PeerDiscovery`.
+    PoisonAddressBook,
+}
+
+/// Lets `attackers` monopolize the connections of `victim`. The attackers cut their connections
+/// to the honest network, so the victim only learns about blocks that the attackers mine: Without
+/// any hashrate, its chain gets stale. With some, it follows a chain that the attackers control.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EclipseNode {
+    pub victim: Entity,
+    pub attackers: Vec<Entity>,
+    pub method: EclipseMethod,
+}
+impl Command for EclipseNode {
+    fn execute(&self, sim: &mut Simulation) -> Result<(), Box<dyn Error>> {
+        if self.attackers.is_empty() {
+            return Err("An eclipse attack needs at least one attacker!".into());
+        }
+        if self.attackers.contains(&self.victim) {
+            return Err("The victim of an eclipse attack can't be one of the attackers!".into());
+        }
+        for &attacker in self.attackers.iter() {
+            sim.add_node_role(attacker, NodeRole::Attacker)?;
+        }
+        for (i, &attacker) in self.attackers.iter().enumerate() {
+            let peers: Vec<Entity> = sim.peers_mut(attacker).iter().copied().collect();
+            let mut node = sim.node_interface(attacker);
+            for peer in peers {
+                if !self.attackers.contains(&peer) {
+                    node.disconnect(peer);
+                }
+            }
+            for &other_attacker in self.attackers.iter().skip(i + 1) {
+                node.connect(other_attacker);
+            }
+        }
+
+        let honest_peers: Vec<Entity> = sim.peers_mut(self.victim).iter().copied().collect();
+        match self.method {
+            EclipseMethod::FillPeerSet => {
+                let mut node = sim.node_interface(self.victim);
+                for peer in honest_peers {
+                    node.disconnect(peer);
+                }
+                for &attacker in self.attackers.iter() {
+                    node.connect(attacker);
+                }
+            }
+            EclipseMethod::PoisonAddressBook => {
+                for &attacker in self.attackers.iter() {
+                    let mut node = sim.node_interface(attacker);
+                    node.get::<AddressBook>()
+                        .set_advertised(Some(self.attackers.clone()));
+                    node.connect(self.victim);
+                    // unsolicited, like Bitcoin nodes announcing themselves
+                    node.send_message(self.victim, DiscoveryMessage::Addr(self.attackers.clone()));
+                }
+                sim.do_in(
+                    SimSeconds::from(RECONNECT_DELAY),
+                    ForSpecific(self.victim, Reconnect(honest_peers.len().max(1))),
+                );
+            }
+        }
+        let victim_name = sim.name(self.victim);
+        sim.log(format!(
+            "{} attackers are eclipsing {}.",
+            self.attackers.len(),
+            victim_name
+        ));
+        Ok(())
+    }
+}
+
+/// Whether `node` is honest, but all of its peers are attackers.
+pub fn is_eclipsed(sim: &Simulation, node: Entity) -> bool {
+    !sim.has_role(node, NodeRole::Attacker)
+        && sim.world.get::<PeerSet>(node).is_ok_and(|peers| {
+            !peers.is_empty()
+                && peers
+                    .iter()
+                    .all(|&peer| sim.has_role(peer, NodeRole::Attacker))
+        })
+}
+
+/// How many blocks of the longest chain among the honest, non-eclipsed nodes `node` doesn't know
+/// about.
+pub fn blocks_behind(sim: &Simulation, node: Entity) -> usize {
+    let state = match sim.world.get::<NakamotoNodeState>(node) {
+        Ok(state) => state,
+        Err(_) => return 0,
+    };
+    let honest_node = sim
+        .world
+        .query::<&NakamotoNodeState>()
+        .iter()
+        .filter(|&(honest_node, _)| {
+            !sim.has_role(honest_node, NodeRole::Attacker) && !is_eclipsed(sim, honest_node)
+        })
+        .max_by_key(|(_, honest_state)| honest_state.tip_height())
+        .map(|(honest_node, _)| honest_node);
+    let honest_state = match honest_node {
+        Some(honest_node) => sim.world.get::<NakamotoNodeState>(honest_node).unwrap(),
+        None => return 0,
+    };
+    let mut behind = 0;
+    let mut next_block = honest_state.tip();
+    while let Some(header) = next_block.and_then(|block_id| honest_state.block_header(block_id)) {
+        if state.block_header(header.id).is_some() {
+            break;
+        }
+        behind += 1;
+        next_block = header.id_prev;
+    }
+    behind
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_consensus::{MineBlock, NakamotoConsensus};
+    use peer_discovery::PeerDiscovery;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Six honest nodes in a ring (the first one is the victim), plus two attackers that are
+    /// connected to honest nodes other than the victim.
+    fn init_simulation() -> (Simulation, Vec<Entity>, Vec<Entity>) {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        sim.add_event_handler(InvokeProtocolForAllNodes(PeerDiscovery));
+        let honest: Vec<Entity> = (0..6).map(|_| sim.spawn_random_node()).collect();
+        let attackers: Vec<Entity> = (0..2).map(|_| sim.spawn_random_node()).collect();
+        for i in 0..honest.len() {
+            sim.node_interface(honest[i])
+                .connect(honest[(i + 1) % honest.len()]);
+        }
+        sim.node_interface(attackers[0]).connect(honest[2]);
+        sim.node_interface(attackers[1]).connect(honest[3]);
+        sim.work_until(SimSeconds::from(30.));
+        (sim, honest, attackers)
+    }
+
+    fn mine(sim: &mut Simulation, node: Entity) {
+        sim.do_now(ForSpecific(node, MineBlock));
+        let until = sim.time.now() + SimSeconds::from(10.);
+        sim.work_until(until);
+    }
+
+    fn tip_height(sim: &Simulation, node: Entity) -> usize {
+        sim.world
+            .get::<NakamotoNodeState>(node)
+            .unwrap()
+            .tip_height()
+    }
+
+    #[wasm_bindgen_test]
+    fn filling_the_peer_set_leaves_victim_behind() {
+        let (mut sim, honest, attackers) = init_simulation();
+        let victim = honest[0];
+        mine(&mut sim, honest[3]);
+        assert_eq!(1, tip_height(&sim, victim));
+
+        sim.do_now(EclipseNode {
+            victim,
+            attackers: attackers.clone(),
+            method: EclipseMethod::FillPeerSet,
+        });
+        sim.work_until(SimSeconds::from(50.));
+        assert!(is_eclipsed(&sim, victim));
+        assert!(!is_eclipsed(&sim, honest[1]));
+        for _ in 0..3 {
+            mine(&mut sim, honest[3]);
+        }
+        assert_eq!(1, tip_height(&sim, victim));
+        assert_eq!(3, blocks_behind(&sim, victim));
+        assert_eq!(0, blocks_behind(&sim, honest[1]));
+
+        // the attackers feed the victim their own chain
+        mine(&mut sim, attackers[0]);
+        assert_eq!(2, tip_height(&sim, victim));
+        assert_eq!(3, blocks_behind(&sim, victim));
+        assert_eq!(4, tip_height(&sim, honest[1]));
+    }
+
+    #[wasm_bindgen_test]
+    fn poisoned_address_book_eclipses_victim_after_reconnect() {
+        let (mut sim, honest, attackers) = init_simulation();
+        let victim = honest[0];
+        sim.do_now(EclipseNode {
+            victim,
+            attackers: attackers.clone(),
+            method: EclipseMethod::PoisonAddressBook,
+        });
+        sim.work_until(SimSeconds::from(50.));
+        // the victim still has its honest peers until it reconnects
+        assert!(!is_eclipsed(&sim, victim));
+        assert!(sim
+            .world
+            .get::<PeerSet>(victim)
+            .unwrap()
+            .contains(&honest[1]));
+
+        sim.work_until(SimSeconds::from(30. + RECONNECT_DELAY + 10.));
+        assert!(is_eclipsed(&sim, victim));
+        let peers: Vec<Entity> = sim
+            .world
+            .get::<PeerSet>(victim)
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(attackers, peers);
+        mine(&mut sim, honest[3]);
+        assert_eq!(0, tip_height(&sim, victim));
+        assert_eq!(1, blocks_behind(&sim, victim));
+    }
+}
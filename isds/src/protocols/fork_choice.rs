@@ -0,0 +1,240 @@
+use super::*;
+use blockchain_types::*;
+use dyn_clone::DynClone;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// How a node decides which of the chains that it knows is *the* blockchain. Nodes follow the
+/// chain whose tip compares greatest. If several compare equal, they stick with the one they
+/// saw first.
+pub trait ForkChoiceRule: DynClone + std::fmt::Debug + Sync + Send {
+    fn name(&self) -> &str;
+    /// Compares the chains that end in `a` and `b`.
+    fn compare(&self, blocks: &BlockTree, a: Entity, b: Entity) -> Ordering;
+    /// Whether `compare` only looks at the blocks of the two chains. If so, a new block can only
+    /// ever make its own chain win, and nodes don't need to reconsider all the other forks.
+    fn looks_at_chains_only(&self) -> bool {
+        true
+    }
+}
+dyn_clone::clone_trait_object!(ForkChoiceRule);
+
+impl Default for Box<dyn ForkChoiceRule> {
+    fn default() -> Self {
+        Box::new(LongestChain)
+    }
+}
+
+/// The blocks that a node knows, as far as fork-choice rules are concerned.
+pub struct BlockTree<'a> {
+    headers: &'a HashMap<Entity, BlockHeader>,
+    /// Differs between nodes, see `RandomTieBreaking`.
+    salt: u64,
+}
+impl<'a> BlockTree<'a> {
+    pub fn new(headers: &'a HashMap<Entity, BlockHeader>, salt: u64) -> Self {
+        Self { headers, salt }
+    }
+    pub fn header(&self, block_id: Entity) -> Option<&BlockHeader> {
+        self.headers.get(&block_id)
+    }
+    pub fn height(&self, block_id: Entity) -> usize {
+        self.header(block_id).map_or(0, |header| header.height)
+    }
+    /// The blocks of the chains ending in `a` and `b` that come after the last block that both
+    /// chains have in common, newest first.
+    pub fn diverging_blocks(&self, a: Entity, b: Entity) -> (Vec<Entity>, Vec<Entity>) {
+        let (mut a, mut b) = (Some(a), Some(b));
+        let (mut only_a, mut only_b) = (vec![], vec![]);
+        while a != b {
+            let a_height = a.map_or(0, |a| self.height(a));
+            let b_height = b.map_or(0, |b| self.height(b));
+            if a_height >= b_height {
+                only_a.extend(a);
+                a = a
+                    .and_then(|a| self.header(a))
+                    .and_then(|header| header.id_prev);
+            }
+            if b_height >= a_height {
+                only_b.extend(b);
+                b = b
+                    .and_then(|b| self.header(b))
+                    .and_then(|header| header.id_prev);
+            }
+        }
+        (only_a, only_b)
+    }
+    /// How many blocks build on `block_id`, directly or indirectly, counting `block_id` itself.
+    pub fn subtree_size(&self, block_id: Entity) -> usize {
+        let mut children: HashMap<Entity, Vec<Entity>> = HashMap::new();
+        for header in self.headers.values() {
+            if let Some(id_prev) = header.id_prev {
+                children.entry(id_prev).or_default().push(header.id);
+            }
+        }
+        let mut size = 0;
+        let mut pending = vec![block_id];
+        while let Some(block_id) = pending.pop() {
+            size += 1;
+            pending.extend(children.get(&block_id).into_iter().flatten());
+        }
+        size
+    }
+}
+
+/// Bitcoin's rule, more or less: The chain with the most blocks wins.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LongestChain;
+impl ForkChoiceRule for LongestChain {
+    fn name(&self) -> &str {
+        "Longest chain"
+    }
+    fn compare(&self, blocks: &BlockTree, a: Entity, b: Entity) -> Ordering {
+        blocks.height(a).cmp(&blocks.height(b))
+    }
+}
+
+/// Bitcoin's actual rule: The chain with the most work wins, i.e., the one whose blocks took the
+/// most hashes to find, as measured by their difficulty. The same as `LongestChain` as long as
+/// the difficulty doesn't change.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeaviestChain;
+impl ForkChoiceRule for HeaviestChain {
+    fn name(&self) -> &str {
+        "Most work"
+    }
+    fn compare(&self, blocks: &BlockTree, a: Entity, b: Entity) -> Ordering {
+        // the blocks that both chains share add the same work to both
+        let (only_a, only_b) = blocks.diverging_blocks(a, b);
+        let work = |chain: Vec<Entity>| -> OrderedFloat<f64> {
+            chain
+                .into_iter()
+                .filter_map(|block_id| blocks.header(block_id))
+                .map(|header| header.difficulty)
+                .sum()
+        };
+        work(only_a).cmp(&work(only_b))
+    }
+}
+
+/// GHOST (Greedy Heaviest Observed SubTree), as proposed by Sompolinsky and Zohar: Starting from
+/// the first block, always follow the child with the most blocks building on it, including blocks
+/// of stale forks. Unlike with `LongestChain`, blocks that lose a race still count for the chain
+/// they build on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ghost;
+impl ForkChoiceRule for Ghost {
+    fn name(&self) -> &str {
+        "GHOST"
+    }
+    fn looks_at_chains_only(&self) -> bool {
+        false
+    }
+    fn compare(&self, blocks: &BlockTree, a: Entity, b: Entity) -> Ordering {
+        // what matters is where the chains part ways
+        let (only_a, only_b) = blocks.diverging_blocks(a, b);
+        match (only_a.last(), only_b.last()) {
+            (Some(&fork_a), Some(&fork_b)) => blocks
+                .subtree_size(fork_a)
+                .cmp(&blocks.subtree_size(fork_b)),
+            // one chain contains the other
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        }
+    }
+}
+
+/// Breaks the ties of another rule randomly instead of by which chain came first, which makes
+/// selfish mining less attractive (see `selfish_mining`). Each node picks its own random winner,
+/// but sticks with it.
+#[derive(Debug, Clone)]
+pub struct RandomTieBreaking<R: ForkChoiceRule + Clone> {
+    rule: R,
+    name: String,
+}
+impl<R: ForkChoiceRule + Clone> RandomTieBreaking<R> {
+    pub fn new(rule: R) -> Self {
+        let name = format!("{}, random tie-breaking", rule.name());
+        Self { rule, name }
+    }
+    fn lottery_ticket(blocks: &BlockTree, block_id: Entity) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (blocks.salt, block_id).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+impl<R: ForkChoiceRule + Clone + 'static> ForkChoiceRule for RandomTieBreaking<R> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn looks_at_chains_only(&self) -> bool {
+        self.rule.looks_at_chains_only()
+    }
+    fn compare(&self, blocks: &BlockTree, a: Entity, b: Entity) -> Ordering {
+        self.rule
+            .compare(blocks, a, b)
+            .then_with(|| Self::lottery_ticket(blocks, a).cmp(&Self::lottery_ticket(blocks, b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Builds a main chain of three blocks with a fork of two blocks and a fork of one block
+    /// branching off from the first block. Returns the known headers and the three tips.
+    fn forked_tree() -> (HashMap<Entity, BlockHeader>, [Entity; 3]) {
+        let mut sim = Simulation::new();
+        let node = sim.spawn_random_node();
+        let mut node = sim.node_interface(node);
+        let genesis = node.spawn_block(None, []);
+        let main1 = node.spawn_block(Some(genesis.id), []);
+        let main2 = node.spawn_block(Some(main1.id), []);
+        let main3 = node.spawn_block(Some(main2.id), []);
+        let fork1 = node.spawn_block(Some(genesis.id), []);
+        let fork2 = node.spawn_block_with_difficulty(Some(fork1.id), [], 10.);
+        let uncle = node.spawn_block(Some(fork1.id), []);
+        let headers = [genesis, main1, main2, main3, fork1, fork2, uncle]
+            .into_iter()
+            .map(|header| (header.id, header))
+            .collect();
+        (headers, [main3.id, fork2.id, uncle.id])
+    }
+
+    #[wasm_bindgen_test]
+    fn rules_disagree_on_forked_tree() {
+        let (headers, [main, heavy_fork, uncle]) = forked_tree();
+        let blocks = BlockTree::new(&headers, 0);
+        assert_eq!(
+            Ordering::Greater,
+            LongestChain.compare(&blocks, main, heavy_fork)
+        );
+        assert_eq!(
+            Ordering::Less,
+            HeaviestChain.compare(&blocks, main, heavy_fork)
+        );
+        // the fork's subtree has three blocks, just like the main chain's, thanks to the uncle
+        assert_eq!(Ordering::Equal, Ghost.compare(&blocks, main, heavy_fork));
+        assert_eq!(Ordering::Equal, Ghost.compare(&blocks, uncle, heavy_fork));
+        assert_eq!(
+            Ordering::Equal,
+            LongestChain.compare(&blocks, uncle, heavy_fork)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn random_tie_breaking_depends_on_the_node() {
+        let (headers, [_, heavy_fork, uncle]) = forked_tree();
+        let rule = RandomTieBreaking::new(LongestChain);
+        let winners: Vec<Ordering> = (0..64)
+            .map(|salt| rule.compare(&BlockTree::new(&headers, salt), uncle, heavy_fork))
+            .collect();
+        assert!(winners.iter().all(|&o| o != Ordering::Equal));
+        assert!(winners.contains(&Ordering::Less));
+        assert!(winners.contains(&Ordering::Greater));
+    }
+}
@@ -0,0 +1,556 @@
+use super::*;
+use blockchain_types::*;
+use nakamoto_consensus::{
+    block_locator, message_size, send, send_all, InventoryItem, NakamotoMessage, MAX_HEADERS,
+};
+use std::collections::{BTreeSet, HashMap};
+
+/// Lets nodes with the `NodeRole::LightClient` role follow the blockchain like Bitcoin's SPV
+/// (simplified payment verification) clients: They only download the headers of the longest
+/// chain and, for the addresses that they watch, transactions with a `MerkleProof` that they are
+/// part of a block. That's a lot less data than full nodes need, but light clients can't check
+/// whether transactions are valid - they trust that the longest chain only contains valid ones,
+/// and that their peers don't hide any transactions from them.
+///
+/// Runs next to `NakamotoConsensus` (which leaves light clients alone) and needs full nodes as
+/// peers.
+#[derive(Debug, Default)]
+pub struct LightClient;
+
+/// Makes a light client ask its peers for the transactions of `address`, including those that
+/// are already on the blockchain.
+#[derive(Debug, Clone)]
+pub struct WatchAddress(pub Address);
+impl EntityAction for WatchAddress {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let mut node = sim.node_interface(entity);
+        if !node.has_role(NodeRole::LightClient) {
+            return Err("Only light clients need to watch addresses.".into());
+        }
+        let state = node.get::<LightClientState>();
+        state.addresses.insert(self.0.clone());
+        let filter: Vec<Address> = state.addresses.iter().cloned().collect();
+        let main_chain = state.main_chain();
+        let peers = node.get::<PeerSet>().clone();
+        for (i, peer) in peers.into_iter().enumerate() {
+            let mut messages = vec![NakamotoMessage::FilterLoad(filter.clone())];
+            // one peer is enough to rescan the blocks we already know
+            if i == 0 && !main_chain.is_empty() {
+                messages.push(NakamotoMessage::GetMerkleBlocks(main_chain.clone()));
+            }
+            send_all(&mut node, peer, messages);
+        }
+        Ok(())
+    }
+}
+
+impl LightClient {
+    /// Like `NakamotoConsensus::handle_new_transaction`, but only spends coins that we have proofs
+    /// for (or that come from our own unconfirmed transactions), and sends the transaction to all
+    /// our peers.
+    pub(super) fn handle_new_transaction(
+        node: &mut NodeInterface,
+        from: Address,
+        to: Address,
+        value: u64,
+        fee: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let tx = if from == FAUCET {
+            Transaction {
+                fee,
+                ..Transaction::from_faucet(to, value)
+            }
+        } else {
+            let needed = value + fee;
+            let mut inputs = vec![];
+            let mut input_value = 0;
+            for (out_point, output) in node.get::<LightClientState>().spendable_outputs(&from) {
+                if input_value >= needed {
+                    break;
+                }
+                inputs.push(out_point);
+                input_value += output.value;
+            }
+            if input_value < needed {
+                return Err(TransactionError::InsufficientFunds {
+                    available: input_value,
+                    needed,
+                }
+                .into());
+            }
+            let mut outputs = vec![TxOutput { to, value }];
+            if input_value > needed {
+                outputs.push(TxOutput {
+                    to: from.clone(),
+                    value: input_value - needed,
+                });
+            }
+            Transaction {
+                from,
+                inputs,
+                outputs,
+                fee,
+            }
+        };
+        node.log(&format!(
+            "Building new transaction: {} toshis from {} to {}.",
+            tx.value(),
+            tx.from,
+            tx.to()
+        ));
+        let tx_id = node.spawn_transaction(tx.clone());
+        let state = node.get::<LightClientState>();
+        state.transactions.insert(tx_id, tx);
+        state.built.insert(tx_id);
+        let peers = node.get::<PeerSet>().clone();
+        for peer in peers.into_iter() {
+            send(
+                node,
+                peer,
+                NakamotoMessage::Flood(InventoryItem::Transaction(tx_id)),
+            );
+        }
+        Ok(())
+    }
+    /// Unlike `NakamotoConsensus::handle_mining_success`, this always fails.
+    pub(super) fn handle_mining_success() -> Result<(), Box<dyn Error>> {
+        Err("Light clients can't mine, they don't know which transactions are valid.".into())
+    }
+    fn request_headers(node: &mut NodeInterface, peer: Entity) {
+        let locator = block_locator(&node.get::<LightClientState>().main_chain());
+        send(node, peer, NakamotoMessage::GetHeaders(locator));
+    }
+    /// Returns `false` if the header doesn't connect to the ones we know.
+    fn add_header(node: &mut NodeInterface, header: BlockHeader) -> bool {
+        let state = node.get::<LightClientState>();
+        if state.headers.contains_key(&header.id) {
+            return true;
+        }
+        if !state.add_header(header) {
+            return false;
+        }
+        // what a full node would have downloaded instead
+        let bytes_of_full_block = message_size(node, &NakamotoMessage::Block(header.id));
+        node.get::<LightClientStats>().bytes_of_full_blocks += bytes_of_full_block;
+        true
+    }
+    fn handle_headers(node: &mut NodeInterface, peer: Entity, headers: Vec<BlockHeader>) {
+        let has_more = headers.len() == MAX_HEADERS;
+        let mut new_blocks = vec![];
+        for header in headers.iter() {
+            let is_new = node
+                .get::<LightClientState>()
+                .block_header(header.id)
+                .is_none();
+            if !Self::add_header(node, *header) {
+                Self::request_headers(node, peer);
+                return;
+            }
+            if is_new {
+                new_blocks.push(header.id);
+            }
+        }
+        if !new_blocks.is_empty() {
+            send(node, peer, NakamotoMessage::GetMerkleBlocks(new_blocks));
+        }
+        if has_more {
+            Self::request_headers(node, peer);
+        }
+    }
+    fn handle_merkle_block(
+        node: &mut NodeInterface,
+        peer: Entity,
+        header: BlockHeader,
+        proofs: Vec<(Entity, MerkleProof)>,
+    ) {
+        if !Self::add_header(node, header) {
+            // we'll ask for the Merkle block again once we have the headers leading up to it
+            Self::request_headers(node, peer);
+            return;
+        }
+        // only the Merkle root of the header that we already know counts
+        let state = node.get::<LightClientState>();
+        let merkle_root = state.headers[&header.id].merkle_root;
+        let addresses = state.addresses.clone();
+        node.get::<LightClientStats>().merkle_blocks_received += 1;
+        for (tx_id, proof) in proofs {
+            let tx = match node.get_transaction(tx_id) {
+                Some(tx) => tx.clone(),
+                None => continue,
+            };
+            if !addresses.iter().any(|address| tx.involves(address)) {
+                continue;
+            }
+            if proof.verify(tx.hash(), merkle_root) {
+                let state = node.get::<LightClientState>();
+                state.transactions.insert(tx_id, tx);
+                if state.proven.entry(header.id).or_default().insert(tx_id) {
+                    node.get::<LightClientStats>().transactions_verified += 1;
+                }
+            } else {
+                node.get::<LightClientStats>().invalid_proofs += 1;
+                node.log(&format!(
+                    "Rejecting a transaction of {} toshis from {} to {}: Its Merkle proof doesn't \
+                    match block {}.",
+                    tx.value(),
+                    tx.from,
+                    tx.to(),
+                    header.height
+                ));
+            }
+        }
+    }
+}
+
+impl Protocol for LightClient {
+    type MessagePayload = NakamotoMessage;
+
+    fn handle_message(
+        &self,
+        mut node: NodeInterface,
+        underlay_message: UnderlayMessage,
+        message_payload: Self::MessagePayload,
+    ) -> Result<(), Box<dyn Error>> {
+        if !node.has_role(NodeRole::LightClient) {
+            return Ok(()); // see `NakamotoConsensus`
+        }
+        let bytes_received = message_size(&mut node, &message_payload);
+        node.get::<LightClientStats>().bytes_received += bytes_received;
+        let peer = underlay_message.source;
+        match message_payload {
+            NakamotoMessage::Headers(headers) => Self::handle_headers(&mut node, peer, headers),
+            NakamotoMessage::MerkleBlock(header, proofs) => {
+                Self::handle_merkle_block(&mut node, peer, header, proofs)
+            }
+            // we don't validate anything, so we don't relay anything either
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_poke(&self, mut node: NodeInterface) -> Result<(), Box<dyn Error>> {
+        if node.has_role(NodeRole::LightClient) {
+            node.log("Light clients don't mine.");
+        }
+        Ok(())
+    }
+
+    fn handle_peer_set_update(
+        &self,
+        mut node: NodeInterface,
+        update: PeerSetUpdate,
+    ) -> Result<(), Box<dyn Error>> {
+        if !node.has_role(NodeRole::LightClient) {
+            return Ok(());
+        }
+        if let PeerSetUpdate::PeerAdded(peer) = update {
+            let state = node.get::<LightClientState>();
+            let filter = state.addresses.iter().cloned().collect();
+            let locator = block_locator(&state.main_chain());
+            send_all(
+                &mut node,
+                peer,
+                [
+                    NakamotoMessage::FilterLoad(filter),
+                    NakamotoMessage::GetHeaders(locator),
+                ],
+            );
+        }
+        Ok(())
+    }
+}
+
+/// What a light client knows about the blockchain.
+#[derive(Debug, Clone, Default)]
+pub struct LightClientState {
+    /// The addresses whose transactions we ask our peers for, see `WatchAddress`.
+    addresses: BTreeSet<Address>,
+    headers: HashMap<Entity, BlockHeader>,
+    /// The highest header that we know.
+    tip: Option<Entity>,
+    /// The transactions of our addresses that we know, whether they are in a block or not.
+    transactions: HashMap<Entity, Transaction>,
+    /// Which of our transactions a block provably contains.
+    proven: HashMap<Entity, BTreeSet<Entity>>,
+    /// The transactions that we built ourselves.
+    built: BTreeSet<Entity>,
+}
+impl LightClientState {
+    /// Returns `false` if we don't know the header's predecessor.
+    fn add_header(&mut self, header: BlockHeader) -> bool {
+        if header
+            .id_prev
+            .is_some_and(|id_prev| !self.headers.contains_key(&id_prev))
+        {
+            return false;
+        }
+        self.headers.insert(header.id, header);
+        if header.height > self.tip_height() {
+            self.tip = Some(header.id);
+        }
+        true
+    }
+    /// The blocks of the longest chain that we know the headers of, starting with the first one.
+    fn main_chain(&self) -> Vec<Entity> {
+        let mut main_chain = vec![];
+        let mut next_block = self.tip;
+        while let Some(block_id) = next_block {
+            main_chain.push(block_id);
+            next_block = self.headers[&block_id].id_prev;
+        }
+        main_chain.reverse();
+        main_chain
+    }
+    /// Outputs to `address` of the transactions that we know, unless one of them spends them.
+    fn spendable_outputs(&self, address: &str) -> Vec<(OutPoint, TxOutput)> {
+        let known: Vec<(Entity, &Transaction)> = self
+            .confirmed_transactions()
+            .into_iter()
+            .map(|(_, tx_id, tx)| (tx_id, tx))
+            .chain(self.unconfirmed_transactions())
+            .collect();
+        let spent: BTreeSet<OutPoint> = known
+            .iter()
+            .flat_map(|(_, tx)| tx.inputs.iter().copied())
+            .collect();
+        known
+            .iter()
+            .flat_map(|&(tx_id, tx)| {
+                tx.outputs
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, output)| (OutPoint { tx_id, index }, output))
+            })
+            .filter(|(out_point, output)| output.to == address && !spent.contains(out_point))
+            .map(|(out_point, output)| (out_point, output.clone()))
+            .collect()
+    }
+    pub fn addresses(&self) -> &BTreeSet<Address> {
+        &self.addresses
+    }
+    pub fn block_header(&self, block_id: Entity) -> Option<BlockHeader> {
+        self.headers.get(&block_id).copied()
+    }
+    pub fn tip(&self) -> Option<Entity> {
+        self.tip
+    }
+    pub fn tip_height(&self) -> usize {
+        self.tip.map_or(0, |tip| self.headers[&tip].height)
+    }
+    /// How many block headers we store, forks included.
+    pub fn headers_count(&self) -> usize {
+        self.headers.len()
+    }
+    /// The transactions that blocks of the longest chain provably contain, with the headers of
+    /// these blocks, oldest first.
+    pub fn confirmed_transactions(&self) -> Vec<(BlockHeader, Entity, &Transaction)> {
+        self.main_chain()
+            .into_iter()
+            .flat_map(|block_id| {
+                self.proven
+                    .get(&block_id)
+                    .into_iter()
+                    .flatten()
+                    .map(move |tx_id| (self.headers[&block_id], *tx_id, &self.transactions[tx_id]))
+            })
+            .collect()
+    }
+    /// The transactions that we built but that aren't on the longest chain (yet).
+    pub fn unconfirmed_transactions(&self) -> impl Iterator<Item = (Entity, &Transaction)> {
+        let confirmed: BTreeSet<Entity> = self
+            .confirmed_transactions()
+            .into_iter()
+            .map(|(_, tx_id, _)| tx_id)
+            .collect();
+        self.built
+            .iter()
+            .filter(move |tx_id| !confirmed.contains(tx_id))
+            .map(|tx_id| (*tx_id, &self.transactions[tx_id]))
+    }
+}
+
+/// How much a light client downloaded, compared to a full node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LightClientStats {
+    pub bytes_received: usize,
+    /// The size of the blocks that we have headers of, i.e., what a full node would have needed
+    /// to download.
+    pub bytes_of_full_blocks: usize,
+    pub merkle_blocks_received: usize,
+    pub transactions_verified: usize,
+    /// Transactions that our peers claimed were in a block, but whose proofs didn't check out.
+    pub invalid_proofs: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use block_hashes::TamperWithTransaction;
+    use nakamoto_consensus::{BuildAndBroadcastTransaction, MineBlock, NakamotoConsensus};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    fn light_client_sim() -> (Simulation, Entity, Entity) {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        sim.add_event_handler(InvokeProtocolForAllNodes(LightClient));
+        let full_node = sim.spawn_random_node();
+        let light_client = sim
+            .spawn_node(NodeIdentity::new().with_role(NodeRole::LightClient))
+            .unwrap();
+        (sim, full_node, light_client)
+    }
+
+    #[wasm_bindgen_test]
+    fn light_clients_sync_headers_and_verify_their_transactions() {
+        let (mut sim, full_node, light_client) = light_client_sim();
+        for (to, value) in [("Bob", 100), ("Alice", 50)] {
+            ForSpecific(
+                full_node,
+                BuildAndBroadcastTransaction::from(FAUCET, to, value),
+            )
+            .execute(&mut sim)
+            .unwrap();
+        }
+        for _ in 0..3 {
+            ForSpecific(full_node, MineBlock).execute(&mut sim).unwrap();
+        }
+        ForSpecific(light_client, WatchAddress("Bob".into()))
+            .execute(&mut sim)
+            .unwrap();
+        sim.node_interface(light_client).connect(full_node);
+        sim.catch_up(100.);
+
+        let full_tip = sim
+            .world
+            .get::<nakamoto_consensus::NakamotoNodeState>(full_node)
+            .unwrap()
+            .tip();
+        let state = sim.world.get::<LightClientState>(light_client).unwrap();
+        assert_eq!(full_tip, state.tip());
+        assert_eq!(3, state.headers_count());
+        let confirmed = state.confirmed_transactions();
+        assert_eq!(1, confirmed.len());
+        assert_eq!(
+            ("Bob", 100),
+            (confirmed[0].2.to().as_str(), confirmed[0].2.value())
+        );
+
+        let stats = *sim.world.get::<LightClientStats>(light_client).unwrap();
+        assert_eq!(1, stats.transactions_verified);
+        assert_eq!(0, stats.invalid_proofs);
+        assert!(stats.bytes_received < stats.bytes_of_full_blocks);
+        assert!(sim
+            .world
+            .get::<nakamoto_consensus::NakamotoNodeState>(light_client)
+            .is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn light_clients_reject_transactions_that_dont_match_the_merkle_root() {
+        let (mut sim, full_node, light_client) = light_client_sim();
+        ForSpecific(
+            full_node,
+            BuildAndBroadcastTransaction::from(FAUCET, "Bob", 100),
+        )
+        .execute(&mut sim)
+        .unwrap();
+        ForSpecific(full_node, MineBlock).execute(&mut sim).unwrap();
+        let tip = sim
+            .world
+            .get::<nakamoto_consensus::NakamotoNodeState>(full_node)
+            .unwrap()
+            .tip()
+            .unwrap();
+        let tx_id = *sim
+            .world
+            .get::<BlockContents>(tip)
+            .unwrap()
+            .iter()
+            .find(|&&tx_id| !sim.world.get::<Transaction>(tx_id).unwrap().is_coinbase())
+            .unwrap();
+        // the full node now claims that Bob got much more than he did
+        ForSpecific(tx_id, TamperWithTransaction(1000))
+            .execute(&mut sim)
+            .unwrap();
+        ForSpecific(light_client, WatchAddress("Bob".into()))
+            .execute(&mut sim)
+            .unwrap();
+        sim.node_interface(light_client).connect(full_node);
+        sim.catch_up(100.);
+
+        let state = sim.world.get::<LightClientState>(light_client).unwrap();
+        assert_eq!(Some(tip), state.tip());
+        assert!(state.confirmed_transactions().is_empty());
+        let stats = *sim.world.get::<LightClientStats>(light_client).unwrap();
+        assert_eq!(1, stats.invalid_proofs);
+    }
+
+    #[wasm_bindgen_test]
+    fn light_clients_spend_verified_coins() {
+        let (mut sim, full_node, light_client) = light_client_sim();
+        ForSpecific(light_client, WatchAddress("Bob".into()))
+            .execute(&mut sim)
+            .unwrap();
+        sim.node_interface(light_client).connect(full_node);
+        ForSpecific(
+            full_node,
+            BuildAndBroadcastTransaction::from(FAUCET, "Bob", 100),
+        )
+        .execute(&mut sim)
+        .unwrap();
+        ForSpecific(full_node, MineBlock).execute(&mut sim).unwrap();
+        sim.catch_up(100.);
+
+        assert!(ForSpecific(
+            light_client,
+            BuildAndBroadcastTransaction::from("Bob", "Alice", 200)
+        )
+        .execute(&mut sim)
+        .is_err());
+        ForSpecific(
+            light_client,
+            BuildAndBroadcastTransaction::from("Bob", "Alice", 60),
+        )
+        .execute(&mut sim)
+        .unwrap();
+        sim.catch_up(100.);
+        let mempool_size = sim
+            .world
+            .get::<nakamoto_consensus::NakamotoNodeState>(full_node)
+            .unwrap()
+            .txes_unconfirmed()
+            .len();
+        assert_eq!(1, mempool_size);
+        {
+            let state = sim.world.get::<LightClientState>(light_client).unwrap();
+            assert_eq!(1, state.unconfirmed_transactions().count());
+        }
+
+        ForSpecific(full_node, MineBlock).execute(&mut sim).unwrap();
+        sim.catch_up(100.);
+        {
+            let state = sim.world.get::<LightClientState>(light_client).unwrap();
+            assert_eq!(0, state.unconfirmed_transactions().count());
+            assert_eq!(2, state.confirmed_transactions().len());
+        }
+        assert!(ForSpecific(light_client, MineBlock)
+            .execute(&mut sim)
+            .is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn light_clients_dont_take_part_in_nakamoto_consensus() {
+        let (mut sim, full_node, light_client) = light_client_sim();
+        sim.node_interface(light_client).connect(full_node);
+        ForSpecific(light_client, PokeNode)
+            .execute(&mut sim)
+            .unwrap();
+        sim.catch_up(10.);
+        assert!(sim
+            .world
+            .get::<nakamoto_consensus::NakamotoNodeState>(light_client)
+            .is_err());
+    }
+}
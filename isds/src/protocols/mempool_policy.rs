@@ -0,0 +1,61 @@
+use super::*;
+
+/// Bitcoin Core forgets unconfirmed transactions after two weeks.
+pub const BITCOIN_MEMPOOL_EXPIRY: SimSeconds = OrderedFloat(14. * 24. * 60. * 60.);
+
+/// Rules that each node chooses for itself (unlike consensus rules) about which unconfirmed
+/// transactions it keeps in its mempool. By default, nodes keep everything forever and the first
+/// of two conflicting transactions wins.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MempoolPolicy {
+    /// How many transactions the mempool holds at most. If there are more, the ones with the
+    /// lowest fee rate (and whatever spends their outputs) have to go. All our transactions have
+    /// the same size, so that's simply the lowest fee.
+    pub max_transactions: Option<usize>,
+    /// How long a transaction may stay in the mempool without getting confirmed.
+    pub expiry: Option<SimSeconds>,
+    /// Whether a transaction that conflicts with transactions in the mempool replaces them if it
+    /// pays more fees than all of them together (and whatever spends their outputs). Without this,
+    /// the transaction that arrived first wins. Bitcoin Core calls this *full RBF*.
+    pub replace_by_fee: bool,
+}
+impl MempoolPolicy {
+    pub fn with_max_transactions(mut self, max_transactions: usize) -> Self {
+        self.max_transactions = Some(max_transactions);
+        self
+    }
+    pub fn with_expiry(mut self, expiry: SimSeconds) -> Self {
+        self.expiry = Some(expiry);
+        self
+    }
+    pub fn with_replace_by_fee(mut self) -> Self {
+        self.replace_by_fee = true;
+        self
+    }
+    /// Whether a transaction that entered the mempool at `entry_time` has to leave it at `now`.
+    pub fn is_expired(&self, entry_time: SimSeconds, now: SimSeconds) -> bool {
+        self.expiry.is_some_and(|expiry| now - entry_time >= expiry)
+    }
+}
+
+/// What nodes removed from their mempools, and why.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MempoolStats {
+    /// Transactions that had to make room for others because the mempool was full.
+    pub evicted: usize,
+    pub expired: usize,
+    /// Transactions that conflicting transactions with higher fees replaced.
+    pub replaced: usize,
+}
+impl MempoolStats {
+    pub fn total(sim: &Simulation) -> Self {
+        sim.world
+            .query::<&MempoolStats>()
+            .iter()
+            .fold(Self::default(), |total, (_, stats)| Self {
+                evicted: total.evicted + stats.evicted,
+                expired: total.expired + stats.expired,
+                replaced: total.replaced + stats.replaced,
+            })
+    }
+}
@@ -0,0 +1,257 @@
+use super::*;
+use blockchain_types::*;
+use difficulty::*;
+use nakamoto_consensus::*;
+use rand_distr::{Distribution, Exp};
+use std::collections::HashMap;
+
+/// How many hashes per second a node computes while mining. Nodes without hashrate don't mine
+/// (unless told to with `MineBlock`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Hashrate(pub f64);
+
+#[derive(Debug, Clone, Copy)]
+pub struct SetHashrate(pub f64);
+impl EntityAction for SetHashrate {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        sim.node_interface(entity).get::<Hashrate>().0 = self.0;
+        Ok(())
+    }
+}
+
+/// What a node is currently mining on.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MiningAttempt {
+    pub id: usize,
+    pub tip: Option<Entity>,
+    /// Expected blocks per second.
+    pub rate: f64,
+}
+
+/// Lets all nodes with a `Hashrate` mine on the tip of their longest chain (or on their private
+/// tip, see `NakamotoNodeState::mining_tip`). The time until a node
+/// finds a block is exponentially distributed with a rate of `hashrate / difficulty`, where the
+/// difficulty of the next block follows from the node's `DifficultyAdjustment` (see
+/// `NakamotoConsensus::with_difficulty_adjustment`). Whenever the tip, the hashrate or the
+/// difficulty changes, the node starts a new attempt; because the exponential distribution is
+/// memoryless, this is the same as continuing to mine.
+///
+/// Add it *after* `NakamotoConsensus` so that it sees tip changes right away.
+#[derive(Debug, Clone)]
+pub struct Mining {
+    block_limit: Option<usize>,
+    next_attempt_id: usize,
+}
+impl Default for Mining {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Mining {
+    pub fn new() -> Self {
+        Self {
+            block_limit: None,
+            next_attempt_id: 1,
+        }
+    }
+    pub fn with_block_limit(mut self, block_limit: usize) -> Self {
+        self.block_limit = Some(block_limit);
+        self
+    }
+    fn update_attempt(&mut self, sim: &mut Simulation, node: Entity) {
+        let hashrate = match sim.world.get::<Hashrate>(node) {
+            Ok(hashrate) => hashrate.0,
+            Err(_) => return,
+        };
+        let (tip, difficulty) = match sim.world.get::<NakamotoNodeState>(node) {
+            Ok(state) => (state.mining_tip(), state.next_difficulty()),
+            Err(_) => (None, DifficultyAdjustment::default().initial_difficulty),
+        };
+        let rate = hashrate / difficulty;
+        let attempt = sim.world.get::<MiningAttempt>(node).ok().map(|a| *a);
+        if attempt.is_some_and(|attempt| attempt.tip == tip && attempt.rate == rate) {
+            return;
+        }
+        let attempt = MiningAttempt {
+            id: self.next_attempt_id,
+            tip,
+            rate,
+        };
+        self.next_attempt_id += 1;
+        let mut node_interface = sim.node_interface(node);
+        *node_interface.get::<MiningAttempt>() = attempt;
+        if let Ok(distribution) = Exp::new(rate) {
+            let duration = distribution.sample(node_interface.rng());
+            if duration.is_finite() {
+                let block_found = BlockFound {
+                    attempt_id: attempt.id,
+                    block_limit: self.block_limit,
+                };
+                sim.do_in(SimSeconds::from(duration), ForSpecific(node, block_found));
+            }
+        }
+    }
+}
+impl EventHandler for Mining {
+    fn handle_event(&mut self, sim: &mut Simulation, event: Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            Event::Node(node, _) => self.update_attempt(sim, node),
+            // commands might change anything, e.g., let nodes mine or change their hashrate
+            Event::Command(_) => {
+                let miners: Vec<Entity> = sim
+                    .world
+                    .query::<&Hashrate>()
+                    .iter()
+                    .map(|(node, _)| node)
+                    .collect();
+                for node in miners {
+                    self.update_attempt(sim, node);
+                }
+            }
+            Event::Generic(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Scheduled by `Mining`. Does nothing if the node has started another attempt in the meantime.
+#[derive(Debug, Clone)]
+struct BlockFound {
+    attempt_id: usize,
+    block_limit: Option<usize>,
+}
+impl EntityAction for BlockFound {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let is_current = sim
+            .world
+            .get::<MiningAttempt>(entity)
+            .is_ok_and(|attempt| attempt.id == self.attempt_id);
+        match (is_current, self.block_limit) {
+            (false, _) => Ok(()),
+            (true, Some(block_limit)) => MineBlockWithLimit(block_limit).execute_for(sim, entity),
+            (true, None) => MineBlock.execute_for(sim, entity),
+        }
+    }
+}
+
+/// Counts who mined the blocks of `viewing_node`'s longest chain.
+pub fn blocks_on_longest_chain_by_miner(
+    sim: &Simulation,
+    viewing_node: Entity,
+) -> HashMap<Entity, usize> {
+    let mut blocks_by_miner = HashMap::new();
+    if let Ok(state) = sim.world.get::<NakamotoNodeState>(viewing_node) {
+        let mut next_block = state.tip();
+        while let Some(block_id) = next_block {
+            if let Ok(miner) = sim.world.get::<MinedBy>(block_id) {
+                *blocks_by_miner.entry(miner.0).or_default() += 1;
+            }
+            next_block = state.block_header(block_id).and_then(|h| h.id_prev);
+        }
+    }
+    blocks_by_miner
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn share_of_blocks_is_proportional_to_hashrate() {
+        let mut sim = Simulation::new();
+        sim.seed_rng(42);
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_difficulty_adjustment(
+                DifficultyAdjustment::for_block_interval(SimSeconds::from(600.), 10.)
+                    .with_rule(RetargetingRule::Fixed),
+            ),
+        ));
+        sim.add_event_handler(Mining::new());
+
+        let big_miner = sim.spawn_random_node();
+        sim.do_now(SpawnRandomNodes(7));
+        sim.do_now(MakeDelaunayNetwork);
+        sim.do_now(ForEachNode(SetHashrate(1.)));
+        sim.do_now(ForSpecific(big_miner, SetHashrate(3.)));
+        sim.work_until(SimSeconds::from(600. * 500.));
+
+        let blocks_by_miner = blocks_on_longest_chain_by_miner(&sim, big_miner);
+        let total_blocks: usize = blocks_by_miner.values().sum();
+        let share = blocks_by_miner[&big_miner] as f64 / total_blocks as f64;
+        assert!(total_blocks > 450 && total_blocks < 550);
+        assert!(share > 0.25 && share < 0.35);
+    }
+
+    #[wasm_bindgen_test]
+    fn block_intervals_converge_to_target_after_hashrate_changes() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_difficulty_adjustment(
+                DifficultyAdjustment::for_block_interval(SimSeconds::from(600.), 4.).with_rule(
+                    RetargetingRule::Window {
+                        window: 50,
+                        max_factor: 4.,
+                    },
+                ),
+            ),
+        ));
+        sim.add_event_handler(Mining::new());
+
+        let node = sim.spawn_random_node();
+        sim.do_now(SpawnRandomNodes(3));
+        sim.do_now(MakeDelaunayNetwork);
+        sim.do_now(ForEachNode(SetHashrate(1.)));
+        sim.work_until(SimSeconds::from(600. * 100.));
+        sim.do_now(ForEachNode(SetHashrate(4.)));
+        sim.work_until(SimSeconds::from(600. * 500.));
+
+        let state = sim.world.get::<NakamotoNodeState>(node).unwrap();
+        let tip = state.block_header(state.tip().unwrap()).unwrap();
+        let mut earlier_block = tip;
+        for _ in 0..100 {
+            earlier_block = state.block_header(earlier_block.id_prev.unwrap()).unwrap();
+        }
+        let average_interval = (tip.time - earlier_block.time).into_inner() / 100.;
+        assert!(average_interval > 400. && average_interval < 900.);
+        let difficulty = tip.difficulty.into_inner();
+        assert!(difficulty > 600. * 16. * 0.6 && difficulty < 600. * 16. * 1.6);
+    }
+
+    #[wasm_bindgen_test]
+    fn mining_restarts_on_new_tip() {
+        let mut sim = Simulation::new();
+        // a block every 1000 years
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_difficulty_adjustment(
+                DifficultyAdjustment::for_block_interval(SimSeconds::from(3e10), 30.),
+            ),
+        ));
+        sim.add_event_handler(Mining::new());
+
+        let miner = sim.spawn_random_node();
+        let other_node = sim.spawn_random_node();
+        sim.add_peer(miner, other_node);
+        sim.add_peer(other_node, miner);
+        sim.do_now(ForSpecific(miner, SetHashrate(30.)));
+        sim.work_until(SimSeconds::from(1.));
+        let first_attempt = *sim.world.get::<MiningAttempt>(miner).unwrap();
+        assert_eq!(None, first_attempt.tip);
+
+        sim.do_now(ForSpecific(other_node, MineBlock));
+        sim.work_until(SimSeconds::from(2.));
+        let second_attempt = *sim.world.get::<MiningAttempt>(miner).unwrap();
+        assert_ne!(first_attempt.id, second_attempt.id);
+        assert!(second_attempt.tip.is_some());
+        assert_eq!(
+            sim.world
+                .get::<NakamotoNodeState>(other_node)
+                .unwrap()
+                .tip(),
+            second_attempt.tip
+        );
+        assert!(sim.world.get::<MiningAttempt>(other_node).is_err());
+    }
+}
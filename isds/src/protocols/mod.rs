@@ -1,5 +1,18 @@
 use super::*;
 
+pub mod difficulty;
+pub mod double_spending;
+pub mod eclipse_attack;
+pub mod fork_choice;
+pub mod light_client;
+pub mod mempool_policy;
+pub mod mining;
+pub mod monetary_policy;
 pub mod nakamoto_consensus;
+pub mod pbft;
+pub mod peer_discovery;
+pub mod proof_of_stake;
 pub mod random_walks;
+pub mod selfish_mining;
 pub mod simple_flooding;
+pub mod sybil_resistance;
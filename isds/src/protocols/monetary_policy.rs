@@ -0,0 +1,106 @@
+use super::*;
+use blockchain_types::*;
+
+/// Bitcoin's first blocks created 50 coins each.
+pub const BITCOIN_INITIAL_SUBSIDY: u64 = 50 * TOSHIS_PER_COIN;
+/// Bitcoin halves its block subsidy every 210,000 blocks, i.e., about every four years.
+pub const BITCOIN_HALVING_INTERVAL: usize = 210_000;
+/// Bitcoin's newly mined coins can't be spent until their block is 100 blocks deep.
+pub const BITCOIN_COINBASE_MATURITY: usize = 100;
+
+/// The consensus rules for how many new coins each block creates. The miner of a block gets them,
+/// plus the fees of the block's transactions, in the block's *coinbase* transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonetaryPolicy {
+    /// The subsidy of the first block, in toshis.
+    pub initial_subsidy: u64,
+    /// After how many blocks the subsidy halves. Zero means never.
+    pub halving_interval: usize,
+    /// How many blocks (counting the one that contains it) need to confirm a coinbase transaction
+    /// before its coins can be spent. Reorgs can make coinbase transactions disappear for good,
+    /// unlike other transactions, which usually make it into the new chain as well.
+    pub coinbase_maturity: usize,
+}
+impl Default for MonetaryPolicy {
+    /// Bitcoin's rules.
+    fn default() -> Self {
+        Self {
+            initial_subsidy: BITCOIN_INITIAL_SUBSIDY,
+            halving_interval: BITCOIN_HALVING_INTERVAL,
+            coinbase_maturity: BITCOIN_COINBASE_MATURITY,
+        }
+    }
+}
+impl MonetaryPolicy {
+    pub fn with_halving_interval(mut self, halving_interval: usize) -> Self {
+        self.halving_interval = halving_interval;
+        self
+    }
+    pub fn with_coinbase_maturity(mut self, coinbase_maturity: usize) -> Self {
+        self.coinbase_maturity = coinbase_maturity;
+        self
+    }
+    /// The new coins that the block at `height` creates. The first block has height 1.
+    pub fn subsidy(&self, height: usize) -> u64 {
+        let halvings = match self.halving_interval {
+            0 => 0,
+            halving_interval => height.saturating_sub(1) / halving_interval,
+        };
+        self.initial_subsidy
+            .checked_shr(halvings as u32)
+            .unwrap_or(0)
+    }
+    /// All the coins that the blocks up to (and including) `height` have created. Since the
+    /// subsidy keeps halving, this never exceeds `2 * initial_subsidy * halving_interval` - for
+    /// Bitcoin, that's just under 21 million coins.
+    pub fn total_supply(&self, height: usize) -> u64 {
+        if self.halving_interval == 0 {
+            return self.initial_subsidy * height as u64;
+        }
+        let mut supply = 0;
+        let mut first_height = 1;
+        while first_height <= height {
+            let subsidy = self.subsidy(first_height);
+            if subsidy == 0 {
+                break;
+            }
+            let last_height = (first_height + self.halving_interval - 1).min(height);
+            supply += subsidy * (last_height - first_height + 1) as u64;
+            first_height = last_height + 1;
+        }
+        supply
+    }
+    /// Whether the coins of a coinbase transaction at `coinbase_height` can go into a block at
+    /// `height`.
+    pub fn is_mature(&self, coinbase_height: usize, height: usize) -> bool {
+        height >= coinbase_height + self.coinbase_maturity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn subsidy_halves_and_supply_is_capped() {
+        let policy = MonetaryPolicy::default();
+        assert_eq!(50 * TOSHIS_PER_COIN, policy.subsidy(1));
+        assert_eq!(50 * TOSHIS_PER_COIN, policy.subsidy(210_000));
+        assert_eq!(25 * TOSHIS_PER_COIN, policy.subsidy(210_001));
+        assert_eq!(0, policy.subsidy(64 * 210_000 + 1));
+        assert_eq!(150 * TOSHIS_PER_COIN, policy.total_supply(3));
+        let max_supply = policy.total_supply(100 * 210_000);
+        assert!(max_supply < 21_000_000 * TOSHIS_PER_COIN);
+        assert!(max_supply > 20_999_999 * TOSHIS_PER_COIN);
+    }
+
+    #[wasm_bindgen_test]
+    fn coinbase_matures_after_enough_blocks() {
+        let policy = MonetaryPolicy::default().with_coinbase_maturity(3);
+        assert!(!policy.is_mature(5, 7));
+        assert!(policy.is_mature(5, 8));
+    }
+}
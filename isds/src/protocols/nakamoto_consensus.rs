@@ -1,6 +1,11 @@
 use super::*;
+use difficulty::*;
+use fork_choice::*;
+use mempool_policy::*;
+use monetary_policy::*;
 use simple_flooding::*;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use sybil_resistance::*;
 
 use blockchain_types::*;
 
@@ -9,25 +14,43 @@ pub struct BuildAndBroadcastTransaction {
     from: Address,
     to: Address,
     value: u64,
+    fee: u64,
 }
 impl BuildAndBroadcastTransaction {
     pub fn new(from: String, to: String, value: u64) -> Self {
-        Self { from, to, value }
+        Self {
+            from,
+            to,
+            value,
+            fee: 0,
+        }
     }
     pub fn from(from: &str, to: &str, value: u64) -> Self {
-        let from = from.to_string();
-        let to = to.to_string();
-        Self { from, to, value }
+        Self::new(from.to_string(), to.to_string(), value)
+    }
+    pub fn with_fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
     }
 }
 impl EntityAction for BuildAndBroadcastTransaction {
     fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
         let mut node = sim.node_interface(entity);
+        if NakamotoConsensus::is_light_client(&node) {
+            return light_client::LightClient::handle_new_transaction(
+                &mut node,
+                self.from.clone(),
+                self.to.clone(),
+                self.value,
+                self.fee,
+            );
+        }
         NakamotoConsensus::handle_new_transaction(
             &mut node,
             self.from.clone(),
             self.to.clone(),
             self.value,
+            self.fee,
         )
     }
 }
@@ -38,8 +61,7 @@ impl EntityAction for BuildAndBroadcastTransaction {
 pub struct MineBlock;
 impl EntityAction for MineBlock {
     fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
-        let mut node = sim.node_interface(entity);
-        NakamotoConsensus::handle_mining_success(&mut node, None)
+        mine_block(sim, entity, None)
     }
 }
 
@@ -47,271 +69,3147 @@ impl EntityAction for MineBlock {
 pub struct MineBlockWithLimit(pub usize);
 impl EntityAction for MineBlockWithLimit {
     fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
-        let block_limit = self.0;
-        let mut node = sim.node_interface(entity);
-        NakamotoConsensus::handle_mining_success(&mut node, Some(block_limit))
+        mine_block(sim, entity, Some(self.0))
+    }
+}
+
+fn mine_block(
+    sim: &mut Simulation,
+    entity: Entity,
+    block_limit: Option<usize>,
+) -> Result<(), Box<dyn Error>> {
+    let mut node = sim.node_interface(entity);
+    if NakamotoConsensus::is_light_client(&node) {
+        return light_client::LightClient::handle_mining_success();
+    }
+    NakamotoConsensus::handle_mining_success(&mut node, block_limit)
+}
+
+/// Removes things from the world (and from node states) that are no longer needed so that
+/// long-running simulations don't keep growing. This means stale forks and the contents (including
+/// transactions) of blocks that every node that knows them sees buried under at least
+/// `finality_depth` blocks. Block headers of the longest chain are kept so that newly connected
+/// nodes can still sync. Should be scheduled periodically, e.g., using `AtStaticIntervals`.
+///
+/// Choose a `finality_depth` that is larger than the number of blocks you show in `NetView` and
+/// `BlockchainView`!
+#[derive(Debug, Clone)]
+pub struct CollectGarbage {
+    pub finality_depth: usize,
+}
+impl CollectGarbage {
+    pub fn new(finality_depth: usize) -> Self {
+        Self { finality_depth }
+    }
+}
+impl Command for CollectGarbage {
+    fn execute(&self, sim: &mut Simulation) -> Result<(), Box<dyn Error>> {
+        collect_garbage(sim, self.finality_depth)
+    }
+}
+
+fn collect_garbage(sim: &mut Simulation, finality_depth: usize) -> Result<(), Box<dyn Error>> {
+    let nodes: Vec<Entity> = sim
+        .world
+        .query::<&NakamotoNodeState>()
+        .iter()
+        .map(|(node, _)| node)
+        .collect();
+
+    // Which blocks are still in use, and how?
+    let mut known_blocks = HashSet::new();
+    let mut shallow_blocks = HashSet::new();
+    let mut live_txes = HashSet::new();
+    for &node in nodes.iter() {
+        let mut node = sim.node_interface(node);
+        let state = node.get::<NakamotoNodeState>();
+        state.prune_stale_forks(finality_depth);
+        let tip_height = state.tip_height();
+        for (&block_id, header) in state.known_blocks.iter() {
+            known_blocks.insert(block_id);
+            if header.height + finality_depth > tip_height {
+                shallow_blocks.insert(block_id);
+            }
+        }
+        for &block_id in state.orphans.keys() {
+            known_blocks.insert(block_id);
+            shallow_blocks.insert(block_id);
+        }
+        live_txes.extend(state.txes_unconfirmed.iter().copied());
+    }
+    for (_, message) in sim.world.query::<&NakamotoMessage>().iter() {
+        for item in message.items() {
+            match item {
+                InventoryItem::Block(block_id) => {
+                    known_blocks.insert(block_id);
+                    shallow_blocks.insert(block_id);
+                }
+                InventoryItem::Transaction(tx_id) => {
+                    live_txes.insert(tx_id);
+                }
+            }
+        }
+    }
+
+    let mut despawned_blocks = vec![];
+    let mut pruned_blocks = vec![];
+    let mut pruned_txes = HashSet::new();
+    for (block_id, (_, contents)) in sim
+        .world
+        .query::<(&BlockHeader, Option<&BlockContents>)>()
+        .iter()
+    {
+        if !known_blocks.contains(&block_id) {
+            despawned_blocks.push(block_id);
+            pruned_txes.extend(contents.into_iter().flat_map(|c| c.iter().copied()));
+        } else if let Some(contents) = contents {
+            if shallow_blocks.contains(&block_id) {
+                live_txes.extend(contents.iter().copied());
+            } else {
+                pruned_blocks.push(block_id);
+                pruned_txes.extend(contents.iter().copied());
+            }
+        }
+    }
+    pruned_txes.retain(|tx_id| !live_txes.contains(tx_id));
+
+    // nodes that sync later can't build their UTXO sets from pruned blocks
+    let mut snapshot_blocks = HashSet::new();
+    for &node in nodes.iter() {
+        snapshot_blocks.extend(snapshot_utxos(sim, node, &shallow_blocks)?);
+    }
+    let outdated_snapshots: Vec<Entity> = sim
+        .world
+        .query::<&UtxoSnapshot>()
+        .iter()
+        .map(|(block_id, _)| block_id)
+        .filter(|block_id| !snapshot_blocks.contains(block_id))
+        .collect();
+    for block_id in outdated_snapshots {
+        sim.remove_utxo_snapshot(block_id)?;
+    }
+
+    for &block_id in despawned_blocks.iter() {
+        sim.despawn_block(block_id)?;
+    }
+    for &block_id in pruned_blocks.iter() {
+        sim.prune_block_contents(block_id)?;
+    }
+    for &tx_id in pruned_txes.iter() {
+        sim.despawn_transaction(tx_id)?;
+    }
+
+    let is_forgotten = |item: &InventoryItem| match item {
+        InventoryItem::Block(block_id) => !known_blocks.contains(block_id),
+        InventoryItem::Transaction(tx_id) => pruned_txes.contains(tx_id),
+    };
+    for &node in nodes.iter() {
+        let mut node = sim.node_interface(node);
+        let state = node.get::<NakamotoNodeState>();
+        state
+            .txes_confirmed
+            .retain(|tx_id| !pruned_txes.contains(tx_id));
+        state
+            .mempool_spent
+            .retain(|_, tx_id| !pruned_txes.contains(tx_id));
+        let (tip_height, monetary_policy) = (state.tip_height(), state.monetary_policy);
+        state
+            .coinbase_heights
+            .retain(|_, &mut height| !monetary_policy.is_mature(height, tip_height));
+        state.utxos.forget_undo_data(|block_id| {
+            despawned_blocks.contains(&block_id) || pruned_blocks.contains(&block_id)
+        });
+        node.get::<SimpleFloodingState<InventoryItem>>()
+            .forget(is_forgotten);
+    }
+
+    if !despawned_blocks.is_empty() || !pruned_blocks.is_empty() || !pruned_txes.is_empty() {
+        sim.log(format!(
+            "Collected garbage: {} blocks removed, {} more pruned, {} transactions removed.",
+            despawned_blocks.len(),
+            pruned_blocks.len(),
+            pruned_txes.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Saves a `UtxoSnapshot` of the newest block on `node`'s chain that isn't in `shallow_blocks`,
+/// unless there is one already, and returns that block.
+fn snapshot_utxos(
+    sim: &mut Simulation,
+    node: Entity,
+    shallow_blocks: &HashSet<Entity>,
+) -> Result<Option<Entity>, Box<dyn Error>> {
+    let mut node = sim.node_interface(node);
+    NakamotoConsensus::sync_utxos(&mut node);
+    let state = node.get::<NakamotoNodeState>();
+    if state.utxo_tip != state.mining_tip() {
+        return Ok(None); // still waiting for a snapshot itself
+    }
+    let mut shallow_part = vec![];
+    let mut next_block = state.utxo_tip;
+    while let Some(block_id) = next_block.filter(|block_id| shallow_blocks.contains(block_id)) {
+        shallow_part.push(block_id);
+        next_block = state.known_blocks[&block_id].id_prev;
+    }
+    let block_id = match next_block {
+        Some(block_id) => block_id,
+        None => return Ok(None),
+    };
+    if node.get_utxo_snapshot(block_id).is_some() {
+        return Ok(Some(block_id));
+    }
+    let state = node.get::<NakamotoNodeState>();
+    let height = state.height(Some(block_id));
+    let mut snapshot = UtxoSnapshot {
+        utxos: state.utxos.clone(),
+        coinbase_heights: state.coinbase_heights.clone(),
+    };
+    for shallow_block in shallow_part {
+        let txes = NakamotoConsensus::block_transactions(&mut node, shallow_block);
+        if !snapshot.utxos.disconnect_block(shallow_block, &txes) {
+            return Ok(None);
+        }
+    }
+    snapshot.utxos.forget_undo_data(|_| true);
+    snapshot
+        .coinbase_heights
+        .retain(|_, &mut coinbase_height| coinbase_height <= height);
+    sim.save_utxo_snapshot(block_id, snapshot)?;
+    Ok(Some(block_id))
+}
+
+/// Fires when some node learns about a block that competes with another block building on the
+/// same predecessor. Each fork is reported only once, no matter how many nodes see it. Forks that
+/// are buried under more than `finality_depth` blocks on every node's longest chain are ignored, so
+/// that the blocks we remember don't pile up.
+#[derive(Debug, Clone)]
+pub struct ForkOccurred {
+    finality_depth: usize,
+    /// Blocks that we have looked at already, with their heights.
+    seen_blocks: HashMap<Entity, usize>,
+    first_children: HashMap<Entity, Entity>,
+    /// Blocks below this height aren't looked at (anymore).
+    min_height: usize,
+}
+impl ForkOccurred {
+    pub const DEFAULT_FINALITY_DEPTH: usize = 24;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_finality_depth(mut self, finality_depth: usize) -> Self {
+        self.finality_depth = finality_depth;
+        self
+    }
+    fn forget_buried_blocks(&mut self, lowest_tip_height: usize) {
+        let min_height = lowest_tip_height.saturating_sub(self.finality_depth);
+        if min_height <= self.min_height {
+            return;
+        }
+        self.min_height = min_height;
+        self.seen_blocks
+            .retain(|_, &mut height| height >= min_height);
+        let seen_blocks = &self.seen_blocks;
+        self.first_children
+            .retain(|_, first_child| seen_blocks.contains_key(first_child));
+    }
+}
+impl Default for ForkOccurred {
+    fn default() -> Self {
+        Self {
+            finality_depth: Self::DEFAULT_FINALITY_DEPTH,
+            seen_blocks: HashMap::new(),
+            first_children: HashMap::new(),
+            min_height: 0,
+        }
+    }
+}
+impl BreakCondition for ForkOccurred {
+    fn check(&mut self, sim: &Simulation, _: Event) -> Option<String> {
+        let mut reason = None;
+        for (node, state) in sim.world.query::<&NakamotoNodeState>().iter() {
+            for &tip in state.tip.iter().chain(state.fork_tips.iter()) {
+                // every block is looked at only once, so this is cheap most of the time
+                let mut next_block = Some(tip);
+                while let Some(block_id) =
+                    next_block.filter(|block_id| !self.seen_blocks.contains_key(block_id))
+                {
+                    let header = match state.block_header(block_id) {
+                        Some(header) if header.height >= self.min_height => header,
+                        _ => break,
+                    };
+                    self.seen_blocks.insert(block_id, header.height);
+                    if let Some(id_prev) = header.id_prev {
+                        let first_child = *self.first_children.entry(id_prev).or_insert(block_id);
+                        if first_child != block_id {
+                            reason = Some(format!(
+                                "A fork occurred at height {}, first seen by {}.",
+                                header.height,
+                                sim.name(node)
+                            ));
+                        }
+                    }
+                    next_block = header.id_prev;
+                }
+            }
+        }
+        let lowest_tip_height = sim
+            .world
+            .query::<&NakamotoNodeState>()
+            .iter()
+            .map(|(_, state)| state.tip_height())
+            .min();
+        if let Some(lowest_tip_height) = lowest_tip_height {
+            self.forget_buried_blocks(lowest_tip_height);
+        }
+        reason
+    }
+}
+
+/// Fires whenever the tip of `node`'s longest chain changes.
+#[derive(Debug, Clone)]
+pub struct TipOfNodeChanged {
+    node: Entity,
+}
+impl TipOfNodeChanged {
+    pub fn new(node: Entity) -> Self {
+        Self { node }
+    }
+}
+impl BreakCondition for TipOfNodeChanged {
+    fn check(&mut self, sim: &Simulation, event: Event) -> Option<String> {
+        let tip_changed = sim
+            .notification::<TipChanged>(event)
+            .filter(|tip_changed| tip_changed.node == self.node)?;
+        let height = tip_changed
+            .new_tip
+            .and_then(|tip| sim.world.get::<BlockHeader>(tip).ok().map(|h| h.height))
+            .unwrap_or(0);
+        Some(if tip_changed.is_reorg() {
+            format!(
+                "{} switched to another chain, replacing {} blocks.",
+                sim.name(self.node),
+                tip_changed.disconnected_blocks.len()
+            )
+        } else {
+            format!(
+                "The tip of {} changed to a block of height {}.",
+                sim.name(self.node),
+                height
+            )
+        })
+    }
+}
+
+/// Tells everyone who is interested (see `Simulation::notification`) that the tip of a node's
+/// longest chain changed. If the node switched to another chain, some blocks got disconnected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TipChanged {
+    pub node: Entity,
+    pub old_tip: Option<Entity>,
+    pub new_tip: Option<Entity>,
+    /// Blocks that are no longer part of the node's longest chain, newest first.
+    pub disconnected_blocks: Vec<Entity>,
+    /// Blocks that are now part of the node's longest chain, oldest first.
+    pub connected_blocks: Vec<Entity>,
+}
+impl TipChanged {
+    pub fn is_reorg(&self) -> bool {
+        !self.disconnected_blocks.is_empty()
+    }
+}
+
+/// How nodes tell each other about new blocks and transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Relay {
+    /// Every node pushes every new item to all of its peers that don't have it yet, and newly
+    /// connected peers get pushed all known blocks.
+    #[default]
+    Flooding,
+    /// Like Bitcoin: Nodes announce new items with `inv` messages and peers request the ones that
+    /// they are missing with `getdata`. Newly connected peers sync headers first.
+    Inventory,
+    /// Like `Inventory`, but new blocks are pushed right away as compact blocks (BIP152 in
+    /// high-bandwidth mode): only the header and short ids of the transactions, which the receiver
+    /// looks up in its own mempool. Missing transactions are requested with `getblocktxn`.
+    CompactBlocks,
+}
+
+/// Approximate sizes in bytes of (parts of) Bitcoin's P2P messages.
+const MESSAGE_HEADER_SIZE: usize = 24;
+const INVENTORY_VECTOR_SIZE: usize = 36;
+const HASH_SIZE: usize = 32;
+pub const BLOCK_HEADER_SIZE: usize = 80;
+pub const TRANSACTION_SIZE: usize = 250;
+const SHORT_TRANSACTION_ID_SIZE: usize = 6;
+/// Maximum number of headers in a `headers` message, as in Bitcoin.
+pub(super) const MAX_HEADERS: usize = 2000;
+/// Bloom filters need about this many bytes per address to rarely match other addresses.
+const FILTER_BYTES_PER_ADDRESS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NakamotoMessage {
+    /// An item pushed without asking first (`Relay::Flooding`).
+    Flood(InventoryItem),
+    /// Announces items that the sender has.
+    Inv(Vec<InventoryItem>),
+    /// Requests items that the receiver announced.
+    GetData(Vec<InventoryItem>),
+    /// Tells the requester that the sender doesn't have these items (anymore), e.g., because it
+    /// garbage-collected them.
+    NotFound(Vec<InventoryItem>),
+    Block(Entity),
+    Tx(Entity),
+    /// Requests the headers of the receiver's longest chain that follow the first block of the
+    /// locator that the receiver knows, or all of them if it knows none.
+    GetHeaders(Vec<Entity>),
+    Headers(Vec<BlockHeader>),
+    /// A block's header and (short) ids of the transactions in it.
+    CmpctBlock(BlockHeader, Vec<Entity>),
+    /// Requests those transactions of a compact block that the sender didn't have.
+    GetBlockTxn(Entity, Vec<Entity>),
+    BlockTxn(Entity, Vec<Entity>),
+    /// Tells a full node which addresses a light client is interested in. Stands in for the Bloom
+    /// filters of BIP37.
+    FilterLoad(Vec<Address>),
+    /// Requests the receiver's filtered versions of some blocks, see `MerkleBlock`.
+    GetMerkleBlocks(Vec<Entity>),
+    /// A block's header and those of its transactions that match the receiver's filter, each with
+    /// a `MerkleProof` that it is part of the block. Full nodes send these to light clients
+    /// instead of blocks.
+    MerkleBlock(BlockHeader, Vec<(Entity, MerkleProof)>),
+}
+impl NakamotoMessage {
+    /// All the items that the message refers to.
+    pub fn items(&self) -> Vec<InventoryItem> {
+        match self {
+            NakamotoMessage::Flood(item) => vec![*item],
+            NakamotoMessage::Inv(items)
+            | NakamotoMessage::GetData(items)
+            | NakamotoMessage::NotFound(items) => items.clone(),
+            NakamotoMessage::Block(block_id) => vec![InventoryItem::Block(*block_id)],
+            NakamotoMessage::Tx(tx_id) => vec![InventoryItem::Transaction(*tx_id)],
+            NakamotoMessage::GetHeaders(locator) | NakamotoMessage::GetMerkleBlocks(locator) => {
+                locator.iter().copied().map(InventoryItem::Block).collect()
+            }
+            NakamotoMessage::FilterLoad(_) => vec![],
+            NakamotoMessage::MerkleBlock(BlockHeader { id: block_id, .. }, proofs) => {
+                std::iter::once(InventoryItem::Block(*block_id))
+                    .chain(
+                        proofs
+                            .iter()
+                            .map(|&(tx_id, _)| InventoryItem::Transaction(tx_id)),
+                    )
+                    .collect()
+            }
+            NakamotoMessage::Headers(headers) => headers
+                .iter()
+                .map(|header| InventoryItem::Block(header.id))
+                .collect(),
+            NakamotoMessage::CmpctBlock(BlockHeader { id: block_id, .. }, tx_ids)
+            | NakamotoMessage::GetBlockTxn(block_id, tx_ids)
+            | NakamotoMessage::BlockTxn(block_id, tx_ids) => {
+                std::iter::once(InventoryItem::Block(*block_id))
+                    .chain(tx_ids.iter().copied().map(InventoryItem::Transaction))
+                    .collect()
+            }
+        }
+    }
+    /// The name of the corresponding Bitcoin P2P message.
+    pub fn command(&self) -> &'static str {
+        match self {
+            NakamotoMessage::Flood(InventoryItem::Block(_)) | NakamotoMessage::Block(_) => "block",
+            NakamotoMessage::Flood(InventoryItem::Transaction(_)) | NakamotoMessage::Tx(_) => "tx",
+            NakamotoMessage::Inv(_) => "inv",
+            NakamotoMessage::GetData(_) => "getdata",
+            NakamotoMessage::NotFound(_) => "notfound",
+            NakamotoMessage::GetHeaders(_) => "getheaders",
+            NakamotoMessage::Headers(_) => "headers",
+            NakamotoMessage::CmpctBlock(..) => "cmpctblock",
+            NakamotoMessage::GetBlockTxn(..) => "getblocktxn",
+            NakamotoMessage::BlockTxn(..) => "blocktxn",
+            NakamotoMessage::FilterLoad(_) => "filterload",
+            NakamotoMessage::GetMerkleBlocks(_) => "getdata",
+            NakamotoMessage::MerkleBlock(..) => "merkleblock",
+        }
+    }
+}
+
+/// How well compact blocks worked for a node (or for all nodes, see `total`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactBlockStats {
+    pub blocks_received: usize,
+    /// Blocks that we could reconstruct from our mempool right away, without another round trip.
+    pub blocks_reconstructed: usize,
+    pub txes_requested: usize,
+    /// What we got in `cmpctblock` and `blocktxn` messages.
+    pub bytes_received: usize,
+    /// What we would have gotten in `block` messages instead.
+    pub bytes_of_full_blocks: usize,
+}
+impl CompactBlockStats {
+    pub fn total(sim: &Simulation) -> Self {
+        let mut total = Self::default();
+        for (_, stats) in sim.world.query::<&CompactBlockStats>().iter() {
+            total.blocks_received += stats.blocks_received;
+            total.blocks_reconstructed += stats.blocks_reconstructed;
+            total.txes_requested += stats.txes_requested;
+            total.bytes_received += stats.bytes_received;
+            total.bytes_of_full_blocks += stats.bytes_of_full_blocks;
+        }
+        total
+    }
+    /// Share of compact blocks that we could reconstruct right away.
+    pub fn reconstruction_rate(&self) -> Option<f64> {
+        (self.blocks_received > 0)
+            .then(|| self.blocks_reconstructed as f64 / self.blocks_received as f64)
+    }
+}
+
+/// Creates transactions at a node that have only reached a random `share_of_nodes` so far, which
+/// makes the mempools of the nodes overlap less.
+#[derive(Debug, Clone)]
+pub struct SpawnPartiallyRelayedTransactions {
+    pub count: usize,
+    pub share_of_nodes: f64,
+}
+impl EntityAction for SpawnPartiallyRelayedTransactions {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let other_nodes: Vec<Entity> = sim
+            .world
+            .query::<&NakamotoNodeState>()
+            .iter()
+            .map(|(node, _)| node)
+            .filter(|&node| node != entity)
+            .collect();
+        for i in 0..self.count {
+            let tx_id = sim
+                .node_interface(entity)
+                .spawn_transaction(Transaction::from_faucet("Bob".to_string(), i as u64 + 1));
+            sim.node_interface(entity)
+                .get::<NakamotoNodeState>()
+                .register_transaction_id(tx_id);
+            for &node in other_nodes.iter() {
+                let share_of_nodes = self.share_of_nodes.clamp(0., 1.);
+                if sim.node_interface(node).rng().gen_bool(share_of_nodes) {
+                    let mut node = sim.node_interface(node);
+                    node.get::<NakamotoNodeState>()
+                        .register_transaction_id(tx_id);
+                    node.get::<SimpleFloodingState<InventoryItem>>()
+                        .own_haves
+                        .insert(InventoryItem::Transaction(tx_id));
+                }
+            }
+        }
+        Ok(())
     }
 }
 
+/// Keeps track of which peer we requested which item from.
+#[derive(Debug, Clone, Default)]
+struct InventoryRelayState {
+    requested: HashMap<InventoryItem, Entity>,
+}
+
 #[derive(Debug, Default)]
 pub struct NakamotoConsensus {
-    flooding: SimpleFlooding<InventoryItem>,
     block_limit: Option<usize>,
+    relay: Relay,
+    difficulty_adjustment: DifficultyAdjustment,
+    monetary_policy: MonetaryPolicy,
+    mempool_policy: MempoolPolicy,
+    fork_choice: Box<dyn ForkChoiceRule>,
+    /// Nodes that follow a different rule than `fork_choice`.
+    node_fork_choices: HashMap<Entity, Box<dyn ForkChoiceRule>>,
+    sybil_resistance: Box<dyn SybilResistance>,
 }
 impl NakamotoConsensus {
     pub fn new() -> Self {
         Self {
-            flooding: SimpleFlooding::new(),
             /// Currently, the block size limit set here is only used when a block is mined
             /// following a `poke`.
             block_limit: None,
+            relay: Relay::Flooding,
+            difficulty_adjustment: DifficultyAdjustment::default(),
+            monetary_policy: MonetaryPolicy::default(),
+            mempool_policy: MempoolPolicy::default(),
+            fork_choice: Box::new(LongestChain),
+            node_fork_choices: HashMap::new(),
+            sybil_resistance: Box::new(ProofOfWork),
         }
     }
     pub fn new_with_block_limit(block_limit: usize) -> Self {
         Self {
-            flooding: SimpleFlooding::new(),
             block_limit: Some(block_limit),
+            relay: Relay::Flooding,
+            difficulty_adjustment: DifficultyAdjustment::default(),
+            monetary_policy: MonetaryPolicy::default(),
+            mempool_policy: MempoolPolicy::default(),
+            fork_choice: Box::new(LongestChain),
+            node_fork_choices: HashMap::new(),
+            sybil_resistance: Box::new(ProofOfWork),
         }
     }
-    fn handle_transaction(node: &mut NodeInterface, tx_id: Entity) -> Result<(), Box<dyn Error>> {
-        node.get::<NakamotoNodeState>()
-            .register_transaction_id(tx_id);
-        Ok(())
+    /// Nodes switch to the given relay once the protocol handles an event for them, e.g., when they
+    /// get their first peer.
+    pub fn with_relay(mut self, relay: Relay) -> Self {
+        self.relay = relay;
+        self
+    }
+    /// Like the relay, nodes switch to these rules once the protocol handles an event for them.
+    pub fn with_difficulty_adjustment(
+        mut self,
+        difficulty_adjustment: DifficultyAdjustment,
+    ) -> Self {
+        self.difficulty_adjustment = difficulty_adjustment;
+        self
+    }
+    /// Like the relay, nodes switch to these rules once the protocol handles an event for them.
+    pub fn with_monetary_policy(mut self, monetary_policy: MonetaryPolicy) -> Self {
+        self.monetary_policy = monetary_policy;
+        self
+    }
+    /// Like the relay, nodes switch to this policy once the protocol handles an event for them.
+    pub fn with_mempool_policy(mut self, mempool_policy: MempoolPolicy) -> Self {
+        self.mempool_policy = mempool_policy;
+        self
+    }
+    /// Like the relay, nodes switch to this rule once the protocol handles an event for them. They
+    /// don't reconsider their tip until they connect the next block, though.
+    pub fn with_fork_choice(mut self, fork_choice: impl ForkChoiceRule + 'static) -> Self {
+        self.fork_choice = Box::new(fork_choice);
+        self
+    }
+    /// Like `with_fork_choice`, but only for one node, e.g., to compare rules side by side.
+    pub fn with_fork_choice_of(
+        mut self,
+        node: Entity,
+        fork_choice: impl ForkChoiceRule + 'static,
+    ) -> Self {
+        self.node_fork_choices.insert(node, Box::new(fork_choice));
+        self
     }
-    fn handle_block(node: &mut NodeInterface, block_id: Entity) -> Result<(), Box<dyn Error>> {
-        let (&block_header, block_contents) = node
-            .get_block(block_id)
+    /// E.g., `ProofOfStake` replaces mining with slot leaders that propose blocks (see
+    /// `Staking`). Like the relay, nodes switch to these rules once the protocol handles an event
+    /// for them.
+    pub fn with_sybil_resistance(
+        mut self,
+        sybil_resistance: impl SybilResistance + 'static,
+    ) -> Self {
+        self.sybil_resistance = Box::new(sybil_resistance);
+        self
+    }
+    /// Light clients don't run this protocol but `LightClient`, which knows what they can do.
+    fn is_light_client(node: &NodeInterface) -> bool {
+        node.has_role(NodeRole::LightClient)
+    }
+    /// Applies our rules to `node`. Returns `false` if the node doesn't take part, see
+    /// `is_light_client`.
+    fn configure(&self, node: &mut NodeInterface) -> bool {
+        if Self::is_light_client(node) {
+            return false;
+        }
+        if node.get::<NakamotoNodeState>().tie_breaking_salt == 0 {
+            let salt = node.rng().gen_range(1..u64::MAX);
+            node.get::<NakamotoNodeState>().tie_breaking_salt = salt;
+        }
+        let fork_choice = self
+            .node_fork_choices
+            .get(&node.id())
+            .unwrap_or(&self.fork_choice)
+            .clone();
+        let state = node.get::<NakamotoNodeState>();
+        state.relay = self.relay;
+        state.difficulty_adjustment = self.difficulty_adjustment;
+        state.monetary_policy = self.monetary_policy;
+        state.mempool_policy = self.mempool_policy;
+        state.fork_choice = fork_choice;
+        state.sybil_resistance = self.sybil_resistance.clone();
+        true
+    }
+    /// Returns `false` if we reject the transaction, e.g., because it double-spends coins, or if
+    /// it doesn't fit into our mempool.
+    fn handle_transaction(node: &mut NodeInterface, tx_id: Entity) -> Result<bool, Box<dyn Error>> {
+        let tx = node
+            .get_transaction(tx_id)
+            .cloned()
+            .ok_or("Received a transaction that doesn't exist!")?;
+        let state = node.get::<NakamotoNodeState>();
+        if state.txes_unconfirmed.contains(&tx_id) || state.txes_confirmed.contains(&tx_id) {
+            return Ok(true);
+        }
+        Self::sync_utxos(node);
+        let replaced_txes = Self::make_room_for_replacement(node, &tx);
+        match Self::validate_transaction(node, tx_id, &tx) {
+            Ok(()) => {
+                node.get::<NakamotoNodeState>()
+                    .register_transaction(tx_id, &tx);
+                if !replaced_txes.is_empty() {
+                    node.get::<MempoolStats>().replaced += replaced_txes.len();
+                    node.log(&format!(
+                        "Replacing {} transactions with one that pays a fee of {} toshis.",
+                        replaced_txes.len(),
+                        tx.fee
+                    ));
+                }
+                Self::enforce_mempool_policy(node);
+                Ok(node
+                    .get::<NakamotoNodeState>()
+                    .txes_unconfirmed
+                    .contains(&tx_id))
+            }
+            Err(error) => {
+                if !replaced_txes.is_empty() {
+                    Self::revalidate_mempool(node, replaced_txes);
+                }
+                node.log(&format!(
+                    "Rejecting a transaction of {} toshis from {} to {}: {}",
+                    tx.value(),
+                    tx.from,
+                    tx.to(),
+                    error
+                ));
+                Ok(false)
+            }
+        }
+    }
+    /// Returns `false` if we reject the block because it breaks our `SybilResistance` rules.
+    fn handle_block(node: &mut NodeInterface, block_id: Entity) -> Result<bool, Box<dyn Error>> {
+        let &block_header = node
+            .get_block_header(block_id)
             .ok_or("Received a block that doesn't exist!")?;
-        let block_contents = block_contents.clone();
+        let proposer = node.get_block_miner(block_id);
+        let state = node.get::<NakamotoNodeState>();
+        let sybil_resistance = &state.sybil_resistance;
+        if let Err(error) = sybil_resistance
+            .validate_proposer(&block_header, proposer)
+            .and_then(|()| sybil_resistance.validate_block(state, &block_header))
+        {
+            node.log(&format!(
+                "Rejected a block of height {}. {}",
+                block_header.height, error
+            ));
+            return Ok(false);
+        }
+        // contents of deeply buried blocks might have been garbage-collected
+        let block_contents = node
+            .get_block_contents(block_id)
+            .cloned()
+            .unwrap_or_default();
+        let old_tip = node.get::<NakamotoNodeState>().tip;
         node.get::<NakamotoNodeState>()
             .register_block(block_header, block_contents);
-        Ok(())
+        Self::notify_if_tip_changed(node, old_tip);
+        Self::sync_utxos(node);
+        Ok(true)
+    }
+    /// Sends a `TipChanged` notification if our tip isn't `old_tip` anymore.
+    pub(super) fn notify_if_tip_changed(node: &mut NodeInterface, old_tip: Option<Entity>) {
+        let state = node.get::<NakamotoNodeState>();
+        let new_tip = state.tip;
+        if new_tip == old_tip {
+            return;
+        }
+        let (disconnected_blocks, connected_blocks) = state.path_between(old_tip, new_tip);
+        let node_id = node.id();
+        node.notify(TipChanged {
+            node: node_id,
+            old_tip,
+            new_tip,
+            disconnected_blocks,
+            connected_blocks,
+        });
+    }
+    /// Returns whether the item is worth relaying.
+    fn handle_item(node: &mut NodeInterface, item: InventoryItem) -> Result<bool, Box<dyn Error>> {
+        match item {
+            InventoryItem::Transaction(tx_id) => Self::handle_transaction(node, tx_id),
+            InventoryItem::Block(block_id) => Self::handle_block(node, block_id),
+        }
     }
+    /// Builds a transaction that pays `value` from `from` to `to` (plus `fee` to the miner), using
+    /// coins of `from` that our UTXO set or our mempool know about, and broadcasts it. Fails if
+    /// there aren't enough.
     fn handle_new_transaction(
         node: &mut NodeInterface,
         from: Address,
         to: Address,
         value: u64,
+        fee: u64,
     ) -> Result<(), Box<dyn Error>> {
+        Self::sync_utxos(node);
+        let tx = if from == FAUCET {
+            Transaction {
+                fee,
+                ..Transaction::from_faucet(to, value)
+            }
+        } else {
+            let needed = value + fee;
+            let mut inputs = vec![];
+            let mut input_value = 0;
+            for (out_point, output) in Self::spendable_outputs(node, &from) {
+                if input_value >= needed {
+                    break;
+                }
+                inputs.push(out_point);
+                input_value += output.value;
+            }
+            if input_value < needed {
+                return Err(TransactionError::InsufficientFunds {
+                    available: input_value,
+                    needed,
+                }
+                .into());
+            }
+            let mut outputs = vec![TxOutput { to, value }];
+            if input_value > needed {
+                outputs.push(TxOutput {
+                    to: from.clone(),
+                    value: input_value - needed,
+                });
+            }
+            Transaction {
+                from,
+                inputs,
+                outputs,
+                fee,
+            }
+        };
         node.log(&format!(
             "Building new transaction: {} toshis from {} to {}.",
-            value, from, to
+            tx.value(),
+            tx.from,
+            tx.to()
         ));
-        let tx_id = node.spawn_transaction(from, to, value);
+        let tx_id = node.spawn_transaction(tx.clone());
         node.get::<NakamotoNodeState>()
-            .register_transaction_id(tx_id);
-        SimpleFlooding::flood(node, InventoryItem::Transaction(tx_id));
-        Ok(())
-    }
-    fn handle_mining_success(
-        node: &mut NodeInterface,
-        block_limit: Option<usize>,
-    ) -> Result<(), Box<dyn Error>> {
-        let tip = node.get::<NakamotoNodeState>().tip;
-        let contents = node
+            .register_transaction(tx_id, &tx);
+        Self::enforce_mempool_policy(node);
+        if !node
             .get::<NakamotoNodeState>()
-            .drain_unconfirmed_transactions(block_limit);
-        let block_header = node.spawn_block(tip, contents);
-        let block_contents = node.get_block_contents(block_header.id).unwrap().clone();
-        node.log(&format!(
-            "Mined a new block of height {} that contains {} transactions.",
-            block_header.height,
-            block_contents.len()
-        ));
-        node.get::<NakamotoNodeState>()
-            .register_block(block_header, block_contents);
-        SimpleFlooding::flood(node, InventoryItem::Block(block_header.id));
-        Ok(())
-    }
-    fn handle_peer_removed(mut node: NodeInterface, peer: Entity) -> Result<(), Box<dyn Error>> {
-        SimpleFlooding::<InventoryItem>::forget_peer(&mut node, peer);
-        Ok(())
-    }
-    fn handle_peer_added(node: &mut NodeInterface, peer: Entity) -> Result<(), Box<dyn Error>> {
-        let all_blocks_sorted = node.get::<NakamotoNodeState>().known_blocks_sorted();
-        SimpleFlooding::<InventoryItem>::flood_peer_with(
-            node,
-            peer,
-            all_blocks_sorted.into_iter().map(InventoryItem::Block),
-        );
+            .txes_unconfirmed
+            .contains(&tx_id)
+        {
+            return Err("The new transaction doesn't fit into our mempool.".into());
+        }
+        Self::announce(node, InventoryItem::Transaction(tx_id));
         Ok(())
     }
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-pub enum InventoryItem {
-    Transaction(Entity),
-    Block(Entity),
-}
-
-impl Protocol for NakamotoConsensus {
-    type MessagePayload = SimpleFloodingMessage<InventoryItem>;
-
-    fn handle_message(
-        &self,
-        mut node: NodeInterface,
-        underlay_message: UnderlayMessage,
-        message_payload: Self::MessagePayload,
-    ) -> Result<(), Box<dyn Error>> {
-        match message_payload.0 {
-            InventoryItem::Transaction(tx_id) => {
-                Self::handle_transaction(&mut node, tx_id)?;
-            }
-            InventoryItem::Block(block_id) => {
-                Self::handle_block(&mut node, block_id)?;
+    /// The outputs that `address` could spend in a new transaction: those in our UTXO set and
+    /// those of transactions in our mempool, unless a transaction in our mempool spends them.
+    fn spendable_outputs(node: &mut NodeInterface, address: &str) -> Vec<(OutPoint, TxOutput)> {
+        let state = node.get::<NakamotoNodeState>();
+        let mut spendable: Vec<(OutPoint, TxOutput)> = state
+            .utxos
+            .owned_by(address)
+            .into_iter()
+            .filter(|&(out_point, _)| state.is_mature(out_point))
+            .map(|(out_point, output)| (out_point, output.clone()))
+            .collect();
+        let mempool: Vec<Entity> = state.txes_unconfirmed.iter().copied().collect();
+        for tx_id in mempool {
+            if let Some(tx) = node.get_transaction(tx_id) {
+                spendable.extend(
+                    tx.outputs
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, output)| output.to == address)
+                        .map(|(index, output)| (OutPoint { tx_id, index }, output.clone())),
+                );
             }
         }
-        self.flooding
-            .handle_message(node, underlay_message, message_payload)
-    }
-
-    fn handle_poke(&self, mut node: NodeInterface) -> Result<(), Box<dyn Error>> {
-        Self::handle_mining_success(&mut node, self.block_limit)
+        let state = node.get::<NakamotoNodeState>();
+        spendable.retain(|(out_point, _)| !state.mempool_spent.contains_key(out_point));
+        spendable
     }
-
-    fn handle_peer_set_update(
-        &self,
-        mut node: NodeInterface,
-        update: PeerSetUpdate,
-    ) -> Result<(), Box<dyn Error>> {
-        match update {
-            PeerSetUpdate::PeerAdded(peer) => {
-                Self::handle_peer_added(&mut node, peer)?;
+    /// Checks whether a transaction could go into the next block on top of our mining tip, given
+    /// the transactions in our mempool.
+    fn validate_transaction(
+        node: &mut NodeInterface,
+        tx_id: Entity,
+        tx: &Transaction,
+    ) -> Result<(), TransactionError> {
+        if tx.inputs.is_empty() {
+            return if tx.from == FAUCET {
+                Ok(())
+            } else {
+                Err(TransactionError::NoInputs)
+            };
+        }
+        let mut input_value = 0;
+        for (i, &input) in tx.inputs.iter().enumerate() {
+            if tx.inputs[..i].contains(&input) {
+                return Err(TransactionError::DoubleSpend(input));
             }
-            PeerSetUpdate::PeerRemoved(peer) => {
-                Self::handle_peer_removed(node, peer)?;
+            let output = Self::unspent_output(node, tx_id, input)?;
+            if output.to != tx.from {
+                return Err(TransactionError::NotOwner(input));
             }
-        };
+            input_value += output.value;
+        }
+        if tx.output_value() + tx.fee > input_value {
+            return Err(TransactionError::Overspend {
+                inputs: input_value,
+                outputs: tx.output_value() + tx.fee,
+            });
+        }
         Ok(())
     }
-}
-
-#[derive(Debug, Clone, Default)]
-pub struct NakamotoNodeState {
-    known_blocks: HashMap<Entity, BlockHeader>,
-    tip: Option<Entity>,
-    fork_tips: HashSet<Entity>,
-    txes_unconfirmed: BTreeSet<Entity>,
-    txes_confirmed: HashSet<Entity>,
-}
-impl NakamotoNodeState {
-    /// Returns `true` if we have updated the tip of the blockchain.
-    fn register_block(&mut self, header: BlockHeader, contents: BlockContents) -> bool {
-        // Our simple logic here assumes that blocks always arrive in the same order.
-        // Making this better might be a TODO.
-        if self.known_blocks.contains_key(&header.id) {
-            false
-        } else if header.id_prev == self.tip {
-            self.known_blocks.insert(header.id, header);
-            self.register_new_tip(header.id, contents);
-            true
-        } else if header.id_prev == None {
-            self.known_blocks.insert(header.id, header);
-            self.fork_tips.insert(header.id);
-            false
-        } else if self.known_blocks.contains_key(&header.id_prev.unwrap()) {
-            self.known_blocks.insert(header.id, header);
-            self.fork_tips.remove(&header.id_prev.unwrap()); // will do nothing if it's a new fork
-            self.fork_tips.insert(header.id);
-            if header.height > self.tip_height() {
-                let old_tip = self.tip.unwrap();
-                self.register_new_tip(header.id, contents);
-                self.fork_tips.remove(&header.id);
-                self.fork_tips.insert(old_tip);
-                true
-            } else {
-                false
+    /// Looks up an output that `tx_id` wants to spend.
+    fn unspent_output(
+        node: &mut NodeInterface,
+        tx_id: Entity,
+        out_point: OutPoint,
+    ) -> Result<TxOutput, TransactionError> {
+        let state = node.get::<NakamotoNodeState>();
+        if state
+            .mempool_spent
+            .get(&out_point)
+            .is_some_and(|&spender| spender != tx_id)
+        {
+            return Err(TransactionError::DoubleSpend(out_point));
+        }
+        if let Some(output) = state.utxos.get(&out_point) {
+            if !state.is_mature(out_point) {
+                return Err(TransactionError::ImmatureCoinbase(out_point));
             }
-        } else {
-            false
+            return Ok(output.clone());
         }
-    }
-    fn register_new_tip(&mut self, block_id: Entity, block_contents: BlockContents) {
-        self.tip = Some(block_id);
-        for tx_id in block_contents.into_iter() {
-            self.txes_unconfirmed.remove(&tx_id);
-            self.txes_confirmed.insert(tx_id);
+        if state.txes_confirmed.contains(&out_point.tx_id) {
+            return Err(TransactionError::DoubleSpend(out_point));
         }
-    }
-    fn register_transaction_id(&mut self, tx_id: Entity) {
-        if !self.txes_confirmed.contains(&tx_id) {
-            self.txes_unconfirmed.insert(tx_id);
+        if !state.txes_unconfirmed.contains(&out_point.tx_id) {
+            return Err(TransactionError::UnknownInput(out_point));
         }
+        node.get_transaction(out_point.tx_id)
+            .and_then(|tx| tx.outputs.get(out_point.index).cloned())
+            .ok_or(TransactionError::UnknownInput(out_point))
     }
-    fn drain_unconfirmed_transactions(
-        &mut self,
-        block_limit: Option<usize>,
-    ) -> impl IntoIterator<Item = Entity> {
-        let mut tmp = BTreeSet::new();
-        if let Some(block_limit) = block_limit {
-            tmp.extend(self.txes_unconfirmed.iter().take(block_limit).copied());
-            for tx in tmp.iter() {
-                self.txes_unconfirmed.remove(tx);
+    /// Brings our UTXO set up to date with our mining tip, disconnecting and connecting blocks as
+    /// needed, and drops transactions from our mempool that aren't valid anymore. Transactions of
+    /// disconnected blocks go back to the mempool if they are still valid.
+    fn sync_utxos(node: &mut NodeInterface) {
+        let state = node.get::<NakamotoNodeState>();
+        let (disconnected, mut connected) = state.path_between(state.utxo_tip, state.mining_tip());
+        if disconnected.is_empty() && connected.is_empty() {
+            return;
+        }
+        let mut readded_txes = vec![];
+        for block_id in disconnected {
+            let txes = Self::block_transactions(node, block_id);
+            let state = node.get::<NakamotoNodeState>();
+            if !state.utxos.disconnect_block(block_id, &txes) {
+                // the undo data is gone, so we have to start over
+                state.utxos = UtxoSet::default();
+                state.coinbase_heights.clear();
+                state.txes_confirmed.clear();
+                state.utxo_tip = None;
+                connected = state.path_between(None, state.mining_tip()).1;
+                node.log("Rebuilding my UTXO set because I can't undo blocks that deep.");
+                break;
+            }
+            state.utxo_tip = state.known_blocks[&block_id].id_prev;
+            for (tx_id, tx) in txes {
+                state.txes_confirmed.remove(&tx_id);
+                // a coinbase transaction is only valid in its own block
+                if state.coinbase_heights.remove(&tx_id).is_none() && !tx.is_coinbase() {
+                    readded_txes.push(tx_id);
+                }
             }
-        } else {
-            std::mem::swap(&mut self.txes_unconfirmed, &mut tmp);
         }
-        tmp
-    }
-    pub fn block_header(&self, block_id: Entity) -> Option<BlockHeader> {
-        self.known_blocks.get(&block_id).copied()
-    }
-    pub fn tip(&self) -> Option<Entity> {
-        self.tip
+        // we can't apply pruned blocks, but we can skip them using a snapshot of a later block
+        let last_snapshot = connected
+            .iter()
+            .rposition(|&block_id| node.get_utxo_snapshot(block_id).is_some());
+        if let Some(index) = last_snapshot {
+            let skips_pruned_blocks = connected[..=index]
+                .iter()
+                .any(|&block_id| node.get_block_contents(block_id).is_none());
+            if skips_pruned_blocks {
+                let snapshot = node.get_utxo_snapshot(connected[index]).unwrap().clone();
+                let state = node.get::<NakamotoNodeState>();
+                state.utxos = snapshot.utxos;
+                state.coinbase_heights = snapshot.coinbase_heights;
+                state.utxo_tip = Some(connected[index]);
+                connected.drain(..=index);
+            }
+        }
+        for block_id in connected {
+            if node.get_block_contents(block_id).is_none() {
+                // we'll get further once we know a block with a snapshot
+                break;
+            }
+            let txes = Self::block_transactions(node, block_id);
+            let state = node.get::<NakamotoNodeState>();
+            state.utxos.connect_block(block_id, &txes);
+            state.utxo_tip = Some(block_id);
+            let height = state.height(Some(block_id));
+            for (tx_id, tx) in txes {
+                state.txes_unconfirmed.remove(&tx_id);
+                state.txes_confirmed.insert(tx_id);
+                if tx.is_coinbase() {
+                    state.coinbase_heights.insert(tx_id, height);
+                }
+            }
+        }
+        let dropped_txes = Self::revalidate_mempool(node, readded_txes);
+        if dropped_txes > 0 {
+            node.log(&format!(
+                "Dropping {} transactions that conflict with the blockchain.",
+                dropped_txes
+            ));
+        }
+        Self::enforce_mempool_policy(node);
     }
-    pub fn fork_tips(&self) -> &HashSet<Entity> {
-        &self.fork_tips
+    /// If our mempool policy allows it and `tx` pays more fees than the transactions in our
+    /// mempool that it conflicts with (and their descendants) together, removes those and returns
+    /// them. It's up to the caller to bring them back if `tx` turns out to be invalid.
+    fn make_room_for_replacement(node: &mut NodeInterface, tx: &Transaction) -> Vec<Entity> {
+        let state = node.get::<NakamotoNodeState>();
+        if !state.mempool_policy.replace_by_fee {
+            return vec![];
+        }
+        let conflicts: Vec<Entity> = tx
+            .inputs
+            .iter()
+            .filter_map(|input| state.mempool_spent.get(input).copied())
+            .collect();
+        if conflicts.is_empty() {
+            return vec![];
+        }
+        let replaced_txes = Self::with_descendants(node, conflicts);
+        let replaced_fees: u64 = replaced_txes
+            .iter()
+            .map(|&tx_id| node.get_transaction(tx_id).map_or(0, |tx| tx.fee))
+            .sum();
+        if tx.fee <= replaced_fees {
+            return vec![];
+        }
+        Self::remove_from_mempool(node, &replaced_txes);
+        replaced_txes
     }
-    pub fn height(&self, block_id: Option<Entity>) -> usize {
-        if let Some(block_id) = block_id {
-            self.known_blocks
-                .get(&block_id)
-                .expect("Requested height of unknown block.")
-                .height
+    /// Removes transactions from our mempool that have been waiting for too long, and then the
+    /// ones with the lowest fees until the rest fits, see `MempoolPolicy`.
+    fn enforce_mempool_policy(node: &mut NodeInterface) {
+        let now = node.now();
+        let state = node.get::<NakamotoNodeState>();
+        let policy = state.mempool_policy;
+        let mempool = &state.txes_unconfirmed;
+        state
+            .mempool_entry_times
+            .retain(|tx_id, _| mempool.contains(tx_id));
+        for &tx_id in mempool.iter() {
+            state.mempool_entry_times.entry(tx_id).or_insert(now);
+        }
+
+        let expired_txes: Vec<Entity> = state
+            .mempool_entry_times
+            .iter()
+            .filter(|(_, &entry_time)| policy.is_expired(entry_time, now))
+            .map(|(&tx_id, _)| tx_id)
+            .collect();
+        if !expired_txes.is_empty() {
+            let expired_txes = Self::with_descendants(node, expired_txes);
+            Self::remove_from_mempool(node, &expired_txes);
+            node.get::<MempoolStats>().expired += expired_txes.len();
+            node.log(&format!(
+                "Forgetting {} transactions that have waited too long for a block.",
+                expired_txes.len()
+            ));
+        }
+
+        let max_transactions = match policy.max_transactions {
+            Some(max_transactions) => max_transactions,
+            None => return,
+        };
+        let mut evicted_txes = vec![];
+        while node.get::<NakamotoNodeState>().txes_unconfirmed.len() > max_transactions {
+            let mempool = node.get::<NakamotoNodeState>().txes_unconfirmed.clone();
+            // among equals, the newest transaction has to go, see `template_order`
+            let cheapest = mempool
+                .into_iter()
+                .min_by_key(|&tx_id| {
+                    let fee = node.get_transaction(tx_id).map_or(0, |tx| tx.fee);
+                    (fee, std::cmp::Reverse(tx_id))
+                })
+                .unwrap();
+            let family = Self::with_descendants(node, vec![cheapest]);
+            Self::remove_from_mempool(node, &family);
+            evicted_txes.extend(family);
+        }
+        if !evicted_txes.is_empty() {
+            node.get::<MempoolStats>().evicted += evicted_txes.len();
+            node.log(&format!(
+                "Evicting {} transactions with the lowest fees from my full mempool.",
+                evicted_txes.len()
+            ));
+        }
+    }
+    /// The given transactions plus all transactions in our mempool that spend their outputs,
+    /// directly or indirectly.
+    fn with_descendants(node: &mut NodeInterface, mut tx_ids: Vec<Entity>) -> Vec<Entity> {
+        tx_ids.sort();
+        tx_ids.dedup();
+        let mempool = node.get::<NakamotoNodeState>().txes_unconfirmed.clone();
+        loop {
+            let children: Vec<Entity> = mempool
+                .iter()
+                .copied()
+                .filter(|tx_id| !tx_ids.contains(tx_id))
+                .filter(|&tx_id| {
+                    node.get_transaction(tx_id).is_some_and(|tx| {
+                        tx.inputs.iter().any(|input| tx_ids.contains(&input.tx_id))
+                    })
+                })
+                .collect();
+            if children.is_empty() {
+                return tx_ids;
+            }
+            tx_ids.extend(children);
+        }
+    }
+    fn remove_from_mempool(node: &mut NodeInterface, tx_ids: &[Entity]) {
+        let state = node.get::<NakamotoNodeState>();
+        for tx_id in tx_ids.iter() {
+            state.txes_unconfirmed.remove(tx_id);
+            state.mempool_entry_times.remove(tx_id);
+        }
+        state
+            .mempool_spent
+            .retain(|_, spender| !tx_ids.contains(spender));
+    }
+    fn block_transactions(
+        node: &mut NodeInterface,
+        block_id: Entity,
+    ) -> Vec<(Entity, Transaction)> {
+        let contents = node
+            .get_block_contents(block_id)
+            .cloned()
+            .unwrap_or_default();
+        contents
+            .into_iter()
+            .filter_map(|tx_id| node.get_transaction(tx_id).map(|tx| (tx_id, tx.clone())))
+            .collect()
+    }
+    /// Validates all transactions in our mempool (plus `additional_txes`) from scratch, parents
+    /// before children. Returns how many of them we dropped.
+    fn revalidate_mempool(node: &mut NodeInterface, additional_txes: Vec<Entity>) -> usize {
+        let state = node.get::<NakamotoNodeState>();
+        let mut pending: Vec<Entity> = std::mem::take(&mut state.txes_unconfirmed)
+            .into_iter()
+            .chain(additional_txes)
+            .filter(|tx_id| !state.txes_confirmed.contains(tx_id))
+            .collect();
+        pending.sort();
+        pending.dedup();
+        state.mempool_spent.clear();
+        loop {
+            let mut still_pending = vec![];
+            for &tx_id in pending.iter() {
+                let tx = match node.get_transaction(tx_id) {
+                    Some(tx) => tx.clone(),
+                    None => continue,
+                };
+                if Self::validate_transaction(node, tx_id, &tx).is_ok() {
+                    node.get::<NakamotoNodeState>()
+                        .register_transaction(tx_id, &tx);
+                } else {
+                    still_pending.push(tx_id);
+                }
+            }
+            if still_pending.len() == pending.len() {
+                return still_pending.len();
+            }
+            pending = still_pending;
+        }
+    }
+    /// Picks transactions from our mempool for a new block, see `template_order`.
+    fn block_template(node: &mut NodeInterface, block_limit: Option<usize>) -> Vec<Entity> {
+        let mempool: Vec<(Entity, Transaction)> = node
+            .get::<NakamotoNodeState>()
+            .txes_unconfirmed
+            .clone()
+            .into_iter()
+            .filter_map(|tx_id| node.get_transaction(tx_id).map(|tx| (tx_id, tx.clone())))
+            .collect();
+        let mut template = template_order(mempool.iter().map(|(tx_id, tx)| (*tx_id, tx)));
+        template.truncate(block_limit.unwrap_or(usize::MAX));
+        template
+    }
+    /// Tells our peers about a transaction without adding it to our own mempool, e.g., because we
+    /// want to double-spend its coins.
+    pub fn broadcast_transaction(node: &mut NodeInterface, tx_id: Entity) {
+        Self::announce(node, InventoryItem::Transaction(tx_id));
+    }
+    fn handle_mining_success(
+        node: &mut NodeInterface,
+        block_limit: Option<usize>,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = node.now();
+        let node_id = node.id();
+        node.get::<NakamotoNodeState>()
+            .sybil_resistance
+            .may_propose(node_id, now)?;
+        Self::sync_utxos(node);
+        Self::enforce_mempool_policy(node);
+        let tip = node.get::<NakamotoNodeState>().mining_tip();
+        let difficulty = node.get::<NakamotoNodeState>().next_difficulty();
+        let mut contents = Self::block_template(node, block_limit);
+        let fees: u64 = contents
+            .iter()
+            .map(|&tx_id| node.get_transaction(tx_id).map_or(0, |tx| tx.fee))
+            .sum();
+        let state = node.get::<NakamotoNodeState>();
+        let subsidy = state
+            .monetary_policy
+            .subsidy(state.height(state.mining_tip()) + 1);
+        let miner = node.label();
+        contents.push(node.spawn_transaction(Transaction::coinbase(miner, subsidy + fees)));
+        let block_header = node.spawn_block_with_difficulty(tip, contents, difficulty);
+        let block_contents = node.get_block_contents(block_header.id).unwrap().clone();
+        node.log(&format!(
+            "Mined a new block of height {} that contains {} transactions.",
+            block_header.height,
+            block_contents.len()
+        ));
+        let state = node.get::<NakamotoNodeState>();
+        let old_tip = state.tip;
+        state.register_block(block_header, block_contents);
+        if state.private_tip.is_some() {
+            state.private_tip = Some(block_header.id);
+        }
+        Self::notify_if_tip_changed(node, old_tip);
+        Self::sync_utxos(node);
+        let state = node.get::<NakamotoNodeState>();
+        if state.withholds_blocks {
+            state.withheld_blocks.push_back(block_header.id);
+            node.log("Keeping the new block to myself for now.");
         } else {
-            0
+            Self::announce(node, InventoryItem::Block(block_header.id));
         }
+        Ok(())
     }
-    pub fn tip_height(&self) -> usize {
-        self.height(self.tip)
+    /// Announces the blocks that the node has withheld so far, up to (and including) height
+    /// `max_height`.
+    pub fn publish_withheld_blocks(node: &mut NodeInterface, max_height: usize) {
+        let mut published_blocks = vec![];
+        let state = node.get::<NakamotoNodeState>();
+        while let Some(&block_id) = state.withheld_blocks.front() {
+            if state.height(Some(block_id)) > max_height {
+                break;
+            }
+            state.withheld_blocks.pop_front();
+            published_blocks.push(block_id);
+        }
+        if !published_blocks.is_empty() {
+            node.log(&format!(
+                "Publishing {} withheld blocks.",
+                published_blocks.len()
+            ));
+        }
+        for block_id in published_blocks {
+            Self::announce(node, InventoryItem::Block(block_id));
+        }
     }
-    /// Returns the ids of all known blocks (forks included) sorted by their block height,
-    /// smallest heights first.
-    pub fn known_blocks_sorted(&self) -> Vec<Entity> {
-        let mut blocks_heights: Vec<(usize, Entity)> = self
-            .known_blocks
+    fn handle_peer_removed(node: &mut NodeInterface, peer: Entity) -> Result<(), Box<dyn Error>> {
+        SimpleFlooding::<InventoryItem>::forget_peer(node, peer);
+        node.get::<NakamotoNodeState>().light_peers.remove(&peer);
+        // whatever we requested from the peer, we'll have to get from someone else now
+        let relay_state = node.get::<InventoryRelayState>();
+        let unanswered: Vec<InventoryItem> = relay_state
+            .requested
             .iter()
-            .map(|(&block_id, &block)| (block.height, block_id))
+            .filter(|(_, &requested_from)| requested_from == peer)
+            .map(|(&item, _)| item)
             .collect();
-        blocks_heights.sort_by(|a, b| a.0.cmp(&b.0));
-        blocks_heights.into_iter().map(|(_, block)| block).collect()
+        for item in unanswered.iter() {
+            relay_state.requested.remove(item);
+        }
+        Self::request_elsewhere(node, unanswered);
+        Ok(())
     }
-    pub fn txes_unconfirmed(&self) -> &BTreeSet<Entity> {
-        &self.txes_unconfirmed
+    fn handle_peer_added(
+        node: &mut NodeInterface,
+        peer: Entity,
+        relay: Relay,
+    ) -> Result<(), Box<dyn Error>> {
+        if node.peer_has_role(peer, NodeRole::LightClient) {
+            // they only want headers and Merkle blocks, and will ask for them
+            node.get::<NakamotoNodeState>()
+                .light_peers
+                .insert(peer, vec![]);
+            return Ok(());
+        }
+        match relay {
+            Relay::Flooding => {
+                let state = node.get::<NakamotoNodeState>();
+                let mut all_blocks_sorted = state.known_blocks_sorted();
+                all_blocks_sorted.retain(|block_id| !state.withheld_blocks.contains(block_id));
+                let flooding_state = node.get::<SimpleFloodingState<InventoryItem>>();
+                for &block_id in all_blocks_sorted.iter() {
+                    flooding_state.register_peer_has(peer, InventoryItem::Block(block_id));
+                }
+                let messages = all_blocks_sorted
+                    .into_iter()
+                    .map(|block_id| NakamotoMessage::Flood(InventoryItem::Block(block_id)));
+                send_all(node, peer, messages);
+            }
+            Relay::Inventory | Relay::CompactBlocks => {
+                let locator = node.get::<NakamotoNodeState>().block_locator();
+                send(node, peer, NakamotoMessage::GetHeaders(locator));
+            }
+        }
+        Ok(())
+    }
+    fn handle_inv(node: &mut NodeInterface, peer: Entity, items: Vec<InventoryItem>) {
+        let flooding_state = node.get::<SimpleFloodingState<InventoryItem>>();
+        let mut new_items = vec![];
+        for item in items {
+            flooding_state.register_peer_has(peer, item);
+            if !flooding_state.own_haves.contains(&item) {
+                new_items.push(item);
+            }
+        }
+        Self::request(node, peer, new_items);
+    }
+    fn handle_get_data(node: &mut NodeInterface, peer: Entity, items: Vec<InventoryItem>) {
+        let flooding_state = node.get::<SimpleFloodingState<InventoryItem>>();
+        let mut responses = vec![];
+        let mut not_found = vec![];
+        for item in items {
+            // we might have garbage-collected the item in the meantime
+            if flooding_state.own_haves.contains(&item) {
+                flooding_state.register_peer_has(peer, item);
+                responses.push(match item {
+                    InventoryItem::Block(block_id) => NakamotoMessage::Block(block_id),
+                    InventoryItem::Transaction(tx_id) => NakamotoMessage::Tx(tx_id),
+                });
+            } else {
+                not_found.push(item);
+            }
+        }
+        if !not_found.is_empty() {
+            responses.push(NakamotoMessage::NotFound(not_found));
+        }
+        send_all(node, peer, responses);
+    }
+    /// Asks other peers for what `peer` couldn't give us.
+    fn handle_not_found(node: &mut NodeInterface, peer: Entity, items: Vec<InventoryItem>) {
+        let relay_state = node.get::<InventoryRelayState>();
+        let items: Vec<InventoryItem> = items
+            .into_iter()
+            .filter(|item| relay_state.requested.get(item) == Some(&peer))
+            .collect();
+        for item in items.iter() {
+            relay_state.requested.remove(item);
+        }
+        let flooding_state = node.get::<SimpleFloodingState<InventoryItem>>();
+        for item in items.iter() {
+            flooding_state.forget_peer_has(peer, item);
+        }
+        Self::request_elsewhere(node, items);
+    }
+    fn handle_requested_item(
+        node: &mut NodeInterface,
+        peer: Entity,
+        item: InventoryItem,
+    ) -> Result<(), Box<dyn Error>> {
+        node.get::<InventoryRelayState>().requested.remove(&item);
+        Self::handle_received_item(node, peer, item)
+    }
+    fn handle_received_item(
+        node: &mut NodeInterface,
+        peer: Entity,
+        item: InventoryItem,
+    ) -> Result<(), Box<dyn Error>> {
+        if Self::handle_item(node, item)? {
+            Self::relay(node, peer, item);
+        }
+        if let InventoryItem::Block(block_id) = item {
+            let missing_block = node
+                .get::<NakamotoNodeState>()
+                .missing_predecessor(block_id);
+            if let Some(missing_block) = missing_block {
+                Self::request(node, peer, vec![InventoryItem::Block(missing_block)]);
+            }
+        }
+        Ok(())
+    }
+    fn handle_get_headers(node: &mut NodeInterface, peer: Entity, locator: Vec<Entity>) {
+        let state = node.get::<NakamotoNodeState>();
+        let mut headers = state.headers_after(&locator, MAX_HEADERS);
+        headers.retain(|header| !state.withheld_blocks.contains(&header.id));
+        if headers.is_empty() {
+            return;
+        }
+        let flooding_state = node.get::<SimpleFloodingState<InventoryItem>>();
+        for header in headers.iter() {
+            flooding_state.register_peer_has(peer, InventoryItem::Block(header.id));
+        }
+        send(node, peer, NakamotoMessage::Headers(headers));
+    }
+    fn handle_headers(node: &mut NodeInterface, peer: Entity, headers: Vec<BlockHeader>) {
+        let has_more = headers.len() == MAX_HEADERS;
+        let last_header = headers.last().copied();
+        let items = headers
+            .into_iter()
+            .map(|header| InventoryItem::Block(header.id))
+            .collect();
+        Self::handle_inv(node, peer, items);
+        if let Some(last_header) = last_header.filter(|_| has_more) {
+            send(
+                node,
+                peer,
+                NakamotoMessage::GetHeaders(vec![last_header.id]),
+            );
+        }
+    }
+    fn handle_get_merkle_blocks(node: &mut NodeInterface, peer: Entity, block_ids: Vec<Entity>) {
+        let state = node.get::<NakamotoNodeState>();
+        let block_ids: Vec<Entity> = block_ids
+            .into_iter()
+            .filter(|block_id| {
+                state.known_blocks.contains_key(block_id)
+                    && !state.withheld_blocks.contains(block_id)
+            })
+            .collect();
+        let messages: Vec<NakamotoMessage> = block_ids
+            .into_iter()
+            .filter_map(|block_id| merkle_block(node, peer, block_id))
+            .collect();
+        send_all(node, peer, messages);
+    }
+    fn handle_compact_block(
+        node: &mut NodeInterface,
+        peer: Entity,
+        header: BlockHeader,
+        tx_ids: Vec<Entity>,
+    ) -> Result<(), Box<dyn Error>> {
+        let item = InventoryItem::Block(header.id);
+        let flooding_state = node.get::<SimpleFloodingState<InventoryItem>>();
+        flooding_state.register_peer_has(peer, item);
+        if flooding_state.own_haves.contains(&item)
+            || node
+                .get::<InventoryRelayState>()
+                .requested
+                .contains_key(&item)
+        {
+            return Ok(());
+        }
+        let mempool = node.get::<NakamotoNodeState>().txes_unconfirmed.clone();
+        let missing_txes: Vec<Entity> = tx_ids
+            .iter()
+            .copied()
+            .filter(|&tx_id| !mempool.contains(&tx_id) && !is_coinbase(node, tx_id))
+            .collect();
+        let bytes_received = message_size(node, &NakamotoMessage::CmpctBlock(header, tx_ids));
+        let bytes_of_full_block = message_size(node, &NakamotoMessage::Block(header.id));
+        let stats = node.get::<CompactBlockStats>();
+        stats.blocks_received += 1;
+        stats.bytes_received += bytes_received;
+        stats.bytes_of_full_blocks += bytes_of_full_block;
+        if missing_txes.is_empty() {
+            stats.blocks_reconstructed += 1;
+            Self::handle_received_item(node, peer, item)
+        } else {
+            stats.txes_requested += missing_txes.len();
+            node.get::<InventoryRelayState>()
+                .requested
+                .insert(item, peer);
+            send(
+                node,
+                peer,
+                NakamotoMessage::GetBlockTxn(header.id, missing_txes),
+            );
+            Ok(())
+        }
+    }
+    fn handle_get_block_txn(
+        node: &mut NodeInterface,
+        peer: Entity,
+        block_id: Entity,
+        tx_ids: Vec<Entity>,
+    ) {
+        let item = InventoryItem::Block(block_id);
+        if node
+            .get::<SimpleFloodingState<InventoryItem>>()
+            .own_haves
+            .contains(&item)
+        {
+            send(node, peer, NakamotoMessage::BlockTxn(block_id, tx_ids));
+        } else {
+            send(node, peer, NakamotoMessage::NotFound(vec![item]));
+        }
+    }
+    fn handle_block_txn(
+        node: &mut NodeInterface,
+        peer: Entity,
+        block_id: Entity,
+        tx_ids: Vec<Entity>,
+    ) -> Result<(), Box<dyn Error>> {
+        let bytes_received = message_size(node, &NakamotoMessage::BlockTxn(block_id, tx_ids));
+        node.get::<CompactBlockStats>().bytes_received += bytes_received;
+        Self::handle_requested_item(node, peer, InventoryItem::Block(block_id))
+    }
+    /// Sends a `getdata` for all the items that we haven't requested yet.
+    fn request(node: &mut NodeInterface, peer: Entity, items: Vec<InventoryItem>) {
+        let relay_state = node.get::<InventoryRelayState>();
+        let items: Vec<InventoryItem> = items
+            .into_iter()
+            .filter(|&item| relay_state.requested.insert(item, peer).is_none())
+            .collect();
+        if !items.is_empty() {
+            send(node, peer, NakamotoMessage::GetData(items));
+        }
+    }
+    /// Requests each of the items from some peer that has it, if there is one.
+    fn request_elsewhere(node: &mut NodeInterface, items: Vec<InventoryItem>) {
+        for item in items {
+            let other_peer = node
+                .get::<SimpleFloodingState<InventoryItem>>()
+                .peers_having(&item)
+                .next();
+            if let Some(other_peer) = other_peer {
+                Self::request(node, other_peer, vec![item]);
+            }
+        }
+    }
+    /// Tells our peers about an item that we just created.
+    fn announce(node: &mut NodeInterface, item: InventoryItem) {
+        match node.get::<NakamotoNodeState>().relay {
+            Relay::Flooding => SimpleFlooding::flood_with(node, item, send_flood),
+            Relay::Inventory => SimpleFlooding::flood_with(node, item, send_inv),
+            Relay::CompactBlocks => SimpleFlooding::flood_with(node, item, send_compact),
+        }
+    }
+    /// Tells our peers about an item that we just got from `sender`.
+    fn relay(node: &mut NodeInterface, sender: Entity, item: InventoryItem) {
+        match node.get::<NakamotoNodeState>().relay {
+            Relay::Flooding => SimpleFlooding::relay_with(node, sender, item, send_flood),
+            Relay::Inventory => SimpleFlooding::relay_with(node, sender, item, send_inv),
+            Relay::CompactBlocks => SimpleFlooding::relay_with(node, sender, item, send_compact),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::hash_map::Entry;
-    use wasm_bindgen_test::*;
+fn send_flood(node: &mut NodeInterface, peer: Entity, item: InventoryItem) {
+    if !send_to_light_peer(node, peer, item) {
+        send(node, peer, NakamotoMessage::Flood(item));
+    }
+}
 
-    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+fn send_inv(node: &mut NodeInterface, peer: Entity, item: InventoryItem) {
+    if !send_to_light_peer(node, peer, item) {
+        send(node, peer, NakamotoMessage::Inv(vec![item]));
+    }
+}
 
-    fn get_state(sim: &Simulation, node_id: Entity) -> NakamotoNodeState {
-        sim.world
-            .query_one::<&NakamotoNodeState>(node_id)
-            .unwrap()
-            .get()
-            .expect("No relevant node state stored?")
-            .clone()
+/// Pushes blocks as compact blocks and announces transactions with `inv` messages.
+fn send_compact(node: &mut NodeInterface, peer: Entity, item: InventoryItem) {
+    if send_to_light_peer(node, peer, item) {
+        return;
+    }
+    match item {
+        InventoryItem::Block(block_id) => {
+            if let Some(&header) = node.get_block_header(block_id) {
+                let tx_ids = node
+                    .get_block_contents(block_id)
+                    .map_or(vec![], |contents| contents.iter().copied().collect());
+                send(node, peer, NakamotoMessage::CmpctBlock(header, tx_ids));
+            }
+        }
+        InventoryItem::Transaction(_) => send_inv(node, peer, item),
+    }
+}
+
+/// Light clients get new blocks pushed as Merkle blocks and don't hear about transactions until
+/// they are in a block. Returns `false` if `peer` isn't a light client.
+fn send_to_light_peer(node: &mut NodeInterface, peer: Entity, item: InventoryItem) -> bool {
+    if !node
+        .get::<NakamotoNodeState>()
+        .light_peers
+        .contains_key(&peer)
+    {
+        return false;
+    }
+    if let InventoryItem::Block(block_id) = item {
+        if let Some(message) = merkle_block(node, peer, block_id) {
+            send(node, peer, message);
+        }
+    }
+    true
+}
+
+/// The block filtered by the addresses that `peer` is interested in. `None` if the block doesn't
+/// exist.
+fn merkle_block(
+    node: &mut NodeInterface,
+    peer: Entity,
+    block_id: Entity,
+) -> Option<NakamotoMessage> {
+    let header = *node.get_block_header(block_id)?;
+    let filter = node
+        .get::<NakamotoNodeState>()
+        .light_peers
+        .get(&peer)
+        .cloned()
+        .unwrap_or_default();
+    let tx_ids: Vec<Entity> = node
+        .get_block_contents(block_id)
+        .map_or(vec![], |contents| contents.iter().copied().collect());
+    let matching: Vec<Entity> = tx_ids
+        .into_iter()
+        .filter(|&tx_id| {
+            node.get_transaction(tx_id)
+                .is_some_and(|tx| filter.iter().any(|address| tx.involves(address)))
+        })
+        .collect();
+    let proofs = matching
+        .into_iter()
+        .filter_map(|tx_id| Some((tx_id, node.merkle_proof(block_id, tx_id)?)))
+        .collect();
+    Some(NakamotoMessage::MerkleBlock(header, proofs))
+}
+
+pub(super) fn send(node: &mut NodeInterface, peer: Entity, message: NakamotoMessage) {
+    send_all(node, peer, [message]);
+}
+
+pub(super) fn send_all(
+    node: &mut NodeInterface,
+    peer: Entity,
+    messages: impl IntoIterator<Item = NakamotoMessage>,
+) {
+    let sized_messages: Vec<(NakamotoMessage, usize)> = messages
+        .into_iter()
+        .map(|message| {
+            let size = message_size(node, &message);
+            (message, size)
+        })
+        .collect();
+    if !sized_messages.is_empty() {
+        node.send_sized_messages(peer, sized_messages);
+    }
+}
+
+/// Compact blocks include their coinbase transactions, since nobody can know them in advance.
+fn is_coinbase(node: &mut NodeInterface, tx_id: Entity) -> bool {
+    node.get_transaction(tx_id)
+        .is_some_and(|tx| tx.is_coinbase())
+}
+
+pub(super) fn message_size(node: &mut NodeInterface, message: &NakamotoMessage) -> usize {
+    let payload_size = match message {
+        NakamotoMessage::Flood(InventoryItem::Transaction(_)) | NakamotoMessage::Tx(_) => {
+            TRANSACTION_SIZE
+        }
+        NakamotoMessage::Flood(InventoryItem::Block(block_id))
+        | NakamotoMessage::Block(block_id) => {
+            let tx_count = node.get_block_contents(*block_id).map_or(0, |c| c.len());
+            BLOCK_HEADER_SIZE + 1 + tx_count * TRANSACTION_SIZE
+        }
+        NakamotoMessage::Inv(items)
+        | NakamotoMessage::GetData(items)
+        | NakamotoMessage::NotFound(items) => 1 + items.len() * INVENTORY_VECTOR_SIZE,
+        NakamotoMessage::GetHeaders(locator) => 4 + 1 + (locator.len() + 1) * HASH_SIZE,
+        NakamotoMessage::Headers(headers) => 1 + headers.len() * (BLOCK_HEADER_SIZE + 1),
+        // header, nonce, short ids, and the coinbase transaction as the only prefilled one
+        NakamotoMessage::CmpctBlock(_, tx_ids) => {
+            let prefilled = tx_ids
+                .iter()
+                .filter(|&&tx_id| is_coinbase(node, tx_id))
+                .count();
+            BLOCK_HEADER_SIZE
+                + 8
+                + 1
+                + (tx_ids.len() - prefilled) * SHORT_TRANSACTION_ID_SIZE
+                + 1
+                + prefilled * TRANSACTION_SIZE
+        }
+        // indexes are differentially encoded, so usually one byte is enough
+        NakamotoMessage::GetBlockTxn(_, tx_ids) => HASH_SIZE + 1 + tx_ids.len(),
+        NakamotoMessage::BlockTxn(_, tx_ids) => HASH_SIZE + 1 + tx_ids.len() * TRANSACTION_SIZE,
+        // the filter and its number of hash functions, tweak, and flags
+        NakamotoMessage::FilterLoad(addresses) => {
+            1 + addresses.len() * FILTER_BYTES_PER_ADDRESS + 4 + 4 + 1
+        }
+        NakamotoMessage::GetMerkleBlocks(block_ids) => 1 + block_ids.len() * INVENTORY_VECTOR_SIZE,
+        // header, number of transactions, the proofs' hashes, and the matching transactions, which
+        // Bitcoin sends in separate `tx` messages
+        NakamotoMessage::MerkleBlock(_, proofs) => {
+            let hashes: usize = proofs
+                .iter()
+                .map(|(_, proof)| proof.siblings.len() + 1)
+                .sum();
+            BLOCK_HEADER_SIZE
+                + 4
+                + 1
+                + hashes * HASH_SIZE
+                + 1
+                + proofs.len() * (MESSAGE_HEADER_SIZE + TRANSACTION_SIZE)
+        }
+    };
+    MESSAGE_HEADER_SIZE + payload_size
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum InventoryItem {
+    Transaction(Entity),
+    Block(Entity),
+}
+
+impl Protocol for NakamotoConsensus {
+    type MessagePayload = NakamotoMessage;
+
+    fn handle_message(
+        &self,
+        mut node: NodeInterface,
+        underlay_message: UnderlayMessage,
+        message_payload: Self::MessagePayload,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.configure(&mut node) {
+            return Ok(());
+        }
+        let peer = underlay_message.source;
+        match message_payload {
+            NakamotoMessage::Flood(item) => {
+                if Self::handle_item(&mut node, item)? {
+                    Self::relay(&mut node, peer, item);
+                }
+            }
+            NakamotoMessage::Inv(items) => Self::handle_inv(&mut node, peer, items),
+            NakamotoMessage::GetData(items) => Self::handle_get_data(&mut node, peer, items),
+            NakamotoMessage::NotFound(items) => Self::handle_not_found(&mut node, peer, items),
+            NakamotoMessage::Block(block_id) => {
+                Self::handle_requested_item(&mut node, peer, InventoryItem::Block(block_id))?;
+            }
+            NakamotoMessage::Tx(tx_id) => {
+                Self::handle_requested_item(&mut node, peer, InventoryItem::Transaction(tx_id))?;
+            }
+            NakamotoMessage::GetHeaders(locator) => {
+                Self::handle_get_headers(&mut node, peer, locator)
+            }
+            NakamotoMessage::Headers(headers) => Self::handle_headers(&mut node, peer, headers),
+            NakamotoMessage::CmpctBlock(header, tx_ids) => {
+                Self::handle_compact_block(&mut node, peer, header, tx_ids)?;
+            }
+            NakamotoMessage::GetBlockTxn(block_id, tx_ids) => {
+                Self::handle_get_block_txn(&mut node, peer, block_id, tx_ids)
+            }
+            NakamotoMessage::BlockTxn(block_id, tx_ids) => {
+                Self::handle_block_txn(&mut node, peer, block_id, tx_ids)?;
+            }
+            NakamotoMessage::FilterLoad(addresses) => {
+                node.get::<NakamotoNodeState>()
+                    .light_peers
+                    .insert(peer, addresses);
+            }
+            NakamotoMessage::GetMerkleBlocks(block_ids) => {
+                Self::handle_get_merkle_blocks(&mut node, peer, block_ids)
+            }
+            NakamotoMessage::MerkleBlock(..) => {} // only light clients care about these
+        }
+        Ok(())
+    }
+
+    fn handle_poke(&self, mut node: NodeInterface) -> Result<(), Box<dyn Error>> {
+        if !self.configure(&mut node) {
+            return Ok(());
+        }
+        Self::handle_mining_success(&mut node, self.block_limit)
+    }
+
+    fn handle_peer_set_update(
+        &self,
+        mut node: NodeInterface,
+        update: PeerSetUpdate,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.configure(&mut node) {
+            return Ok(());
+        }
+        match update {
+            PeerSetUpdate::PeerAdded(peer) => {
+                Self::handle_peer_added(&mut node, peer, self.relay)?;
+            }
+            PeerSetUpdate::PeerRemoved(peer) => {
+                Self::handle_peer_removed(&mut node, peer)?;
+            }
+        };
+        Ok(())
+    }
+}
+
+/// The order in which miners put the transactions of a mempool into blocks: The highest fee
+/// first, but never before a parent that is in the mempool, too. Bitcoin Core is a bit smarter,
+/// it sorts by fee *rate* and lets children pay for their parents.
+pub fn template_order<'a>(mempool: impl Iterator<Item = (Entity, &'a Transaction)>) -> Vec<Entity> {
+    let mut pending: Vec<(Entity, &Transaction)> = mempool.collect();
+    let mut ordered: Vec<Entity> = vec![];
+    loop {
+        let is_pending = |tx_id: Entity| pending.iter().any(|&(id, _)| id == tx_id);
+        let best = pending
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, tx))| tx.inputs.iter().all(|input| !is_pending(input.tx_id)))
+            .max_by_key(|(_, &(tx_id, tx))| (tx.fee, std::cmp::Reverse(tx_id)))
+            .map(|(i, _)| i);
+        match best {
+            Some(i) => ordered.push(pending.remove(i).0),
+            None => return ordered,
+        }
+    }
+}
+
+/// Describes a chain (oldest block first) to a peer in a few block ids, densely near the tip and
+/// then exponentially sparser, like Bitcoin's block locators.
+pub(super) fn block_locator(main_chain: &[Entity]) -> Vec<Entity> {
+    let mut locator = vec![];
+    let mut step = 1;
+    let mut i = main_chain.len();
+    while i > 0 {
+        locator.push(main_chain[i - 1]);
+        if locator.len() >= 10 {
+            step *= 2;
+        }
+        i = i.saturating_sub(step);
+    }
+    if let Some(&first_block) = main_chain.first() {
+        if locator.last() != Some(&first_block) {
+            locator.push(first_block);
+        }
+    }
+    locator
+}
+
+/// How many blocks with unknown predecessors a node keeps around at most. Bitcoin Core uses the
+/// same limit.
+pub const MAX_ORPHAN_BLOCKS: usize = 100;
+
+#[derive(Debug, Clone, Default)]
+pub struct NakamotoNodeState {
+    known_blocks: HashMap<Entity, BlockHeader>,
+    tip: Option<Entity>,
+    fork_tips: HashSet<Entity>,
+    /// Blocks that arrived before their predecessors did. They get connected as soon as the
+    /// missing predecessors arrive, which they do eventually because every block is flooded.
+    orphans: HashMap<Entity, (BlockHeader, BlockContents)>,
+    txes_unconfirmed: BTreeSet<Entity>,
+    txes_confirmed: HashSet<Entity>,
+    /// How the node tells its peers about new items.
+    relay: Relay,
+    difficulty_adjustment: DifficultyAdjustment,
+    /// Whether the node keeps the blocks it mines to itself instead of announcing them right away,
+    /// see `selfish_mining`.
+    withholds_blocks: bool,
+    /// Blocks that this node mined but hasn't announced yet, oldest first.
+    withheld_blocks: VecDeque<Entity>,
+    /// A tip that we prefer over others that our `ForkChoiceRule` considers just as good, see
+    /// `prefer_in_ties`.
+    preferred_tip: Option<Entity>,
+    /// If set, the node mines on top of this block instead of its tip, to build a private fork
+    /// that might be longer than the longest chain one day (see `double_spending`).
+    private_tip: Option<Entity>,
+    /// Which transaction in our mempool spends which output.
+    mempool_spent: HashMap<OutPoint, Entity>,
+    mempool_policy: MempoolPolicy,
+    fork_choice: Box<dyn ForkChoiceRule>,
+    /// Lets `RandomTieBreaking` break ties differently on each node.
+    tie_breaking_salt: u64,
+    /// When the transactions in our mempool entered it, as far as we have noticed.
+    mempool_entry_times: HashMap<Entity, SimSeconds>,
+    /// The coins that are unspent as of `utxo_tip`, see `NakamotoConsensus::sync_utxos`.
+    utxos: UtxoSet,
+    utxo_tip: Option<Entity>,
+    monetary_policy: MonetaryPolicy,
+    /// The heights of the coinbase transactions up to `utxo_tip` that might not be mature yet.
+    coinbase_heights: HashMap<Entity, usize>,
+    /// Our peers that are light clients, with the addresses that they are interested in.
+    light_peers: HashMap<Entity, Vec<Address>>,
+    sybil_resistance: Box<dyn SybilResistance>,
+    /// We never leave the chain that contains this block, see `SybilResistance::finality_depth`.
+    finalized_block: Option<Entity>,
+}
+impl NakamotoNodeState {
+    /// Returns `true` if we have updated the tip of the blockchain.
+    fn register_block(&mut self, header: BlockHeader, contents: BlockContents) -> bool {
+        if self.known_blocks.contains_key(&header.id) || self.orphans.contains_key(&header.id) {
+            return false;
+        }
+        if header
+            .id_prev
+            .is_some_and(|id_prev| !self.known_blocks.contains_key(&id_prev))
+        {
+            self.add_orphan(header, contents);
+            return false;
+        }
+        // connecting a block might make whole chains of orphans connectable
+        let mut tip_updated = false;
+        let mut connectable = vec![(header, contents)];
+        while let Some((header, contents)) = connectable.pop() {
+            let children = self.take_orphans_building_on(header.id);
+            // orphans couldn't be checked when they arrived; their children are invalid as well
+            if self.sybil_resistance.validate_block(self, &header).is_err() {
+                continue;
+            }
+            tip_updated |= self.connect_block(header, contents);
+            connectable.extend(children);
+        }
+        tip_updated
+    }
+    /// Like `register_block`, but the block's predecessor (if any) must be known already.
+    fn connect_block(&mut self, header: BlockHeader, contents: BlockContents) -> bool {
+        self.known_blocks.insert(header.id, header);
+        // every sensible rule prefers a chain over the same chain minus its last block
+        if header.id_prev == self.tip {
+            self.register_new_tip(header.id, contents);
+            return true;
+        }
+        // the new block is a tip now, its predecessor isn't anymore
+        if let Some(id_prev) = header.id_prev {
+            self.fork_tips.remove(&id_prev);
+        }
+        self.fork_tips.insert(header.id);
+        self.reconsider_tip(header.id, contents)
+    }
+    /// Lets our `ForkChoiceRule` choose between our tip and the fork tips again now that
+    /// `new_block` (with `contents`) is one of them. Returns `true` if the tip changed.
+    fn reconsider_tip(&mut self, new_block: Entity, contents: BlockContents) -> bool {
+        let best_tip = self.choose_tip(new_block);
+        if Some(best_tip) == self.tip {
+            return false;
+        }
+        self.fork_tips.remove(&best_tip);
+        self.fork_tips.extend(self.tip);
+        let contents = if best_tip == new_block {
+            contents
+        } else {
+            BlockContents::new()
+        };
+        self.register_new_tip(best_tip, contents);
+        true
+    }
+    /// Makes us prefer the fork tip `block_id` (with `contents`) over other tips that our
+    /// `ForkChoiceRule` considers just as good, i.e., breaks a tie differently than usual (see
+    /// `selfish_mining`). Returns `true` if the tip changed.
+    pub fn prefer_in_ties(&mut self, block_id: Entity, contents: BlockContents) -> bool {
+        if !self.fork_tips.contains(&block_id) {
+            return false;
+        }
+        self.preferred_tip = Some(block_id);
+        self.reconsider_tip(block_id, contents)
+    }
+    /// The tip that our `ForkChoiceRule` prefers after `new_block` arrived. Ties go to our
+    /// preferred tip, then to our current tip, or else to the oldest fork tip.
+    fn choose_tip(&self, new_block: Entity) -> Entity {
+        let blocks = BlockTree::new(&self.known_blocks, self.tie_breaking_salt);
+        let mut best_tip = self.tip.unwrap_or(new_block);
+        let candidates: Vec<Entity> = if self.fork_choice.looks_at_chains_only() {
+            vec![new_block]
+        } else {
+            self.fork_tips.iter().copied().collect()
+        };
+        for fork_tip in candidates {
+            if !self.builds_on_finalized_block(fork_tip) {
+                continue;
+            }
+            match self.fork_choice.compare(&blocks, fork_tip, best_tip) {
+                std::cmp::Ordering::Greater => best_tip = fork_tip,
+                std::cmp::Ordering::Equal if self.preferred_tip == Some(fork_tip) => {
+                    best_tip = fork_tip
+                }
+                std::cmp::Ordering::Equal
+                    if Some(best_tip) != self.tip && self.preferred_tip != Some(best_tip) =>
+                {
+                    best_tip = best_tip.min(fork_tip)
+                }
+                _ => {}
+            }
+        }
+        best_tip
+    }
+    fn builds_on_finalized_block(&self, block_id: Entity) -> bool {
+        let finalized_block = match self.finalized_block {
+            Some(finalized_block) => finalized_block,
+            None => return true,
+        };
+        let finalized_height = self.height(Some(finalized_block));
+        let mut found = false;
+        self.walk_back_while(block_id, |block_id| {
+            found = block_id == finalized_block;
+            !found && self.height(Some(block_id)) > finalized_height
+        });
+        found
+    }
+    fn add_orphan(&mut self, header: BlockHeader, contents: BlockContents) {
+        if self.orphans.len() >= MAX_ORPHAN_BLOCKS {
+            // the highest orphan is the one that is furthest from being connectable
+            let highest_orphan = self
+                .orphans
+                .values()
+                .max_by_key(|(header, _)| header.height)
+                .map(|(header, _)| header.id);
+            if let Some(highest_orphan) = highest_orphan {
+                self.orphans.remove(&highest_orphan);
+            }
+        }
+        self.orphans.insert(header.id, (header, contents));
+    }
+    fn take_orphans_building_on(&mut self, block_id: Entity) -> Vec<(BlockHeader, BlockContents)> {
+        let children: Vec<Entity> = self
+            .orphans
+            .values()
+            .filter(|(header, _)| header.id_prev == Some(block_id))
+            .map(|(header, _)| header.id)
+            .collect();
+        children
+            .iter()
+            .filter_map(|child| self.orphans.remove(child))
+            .collect()
+    }
+    fn register_new_tip(&mut self, block_id: Entity, block_contents: BlockContents) {
+        self.tip = Some(block_id);
+        for tx_id in block_contents.into_iter() {
+            self.txes_unconfirmed.remove(&tx_id);
+            self.txes_confirmed.insert(tx_id);
+        }
+        self.update_finalized_block();
+    }
+    fn update_finalized_block(&mut self) {
+        let finality_depth = match self.sybil_resistance.finality_depth() {
+            Some(finality_depth) => finality_depth,
+            None => return,
+        };
+        let final_height = match self.tip_height().checked_sub(finality_depth) {
+            Some(final_height) if final_height > self.height(self.finalized_block) => final_height,
+            _ => return,
+        };
+        let mut finalized_block = self.finalized_block;
+        if let Some(tip) = self.tip {
+            self.walk_back_while(tip, |block_id| {
+                if self.height(Some(block_id)) == final_height {
+                    finalized_block = Some(block_id);
+                }
+                self.height(Some(block_id)) > final_height
+            });
+        }
+        self.finalized_block = finalized_block;
+    }
+    /// Forgets forks whose tips are at least `finality_depth` blocks behind our tip. Returns the
+    /// ids of all blocks that we forgot about.
+    fn prune_stale_forks(&mut self, finality_depth: usize) -> Vec<Entity> {
+        let tip_height = self.tip_height();
+        let (stale_fork_tips, fresh_fork_tips): (Vec<Entity>, Vec<Entity>) =
+            self.fork_tips.iter().copied().partition(|&fork_tip| {
+                Some(fork_tip) != self.private_tip
+                    && Some(fork_tip) != self.utxo_tip
+                    && self.height(Some(fork_tip)) + finality_depth <= tip_height
+            });
+
+        let mut blocks_to_keep = HashSet::new();
+        for block_id in fresh_fork_tips
+            .into_iter()
+            .chain(self.tip)
+            .chain(self.private_tip)
+            .chain(self.utxo_tip)
+        {
+            self.walk_back_while(block_id, |block_id| blocks_to_keep.insert(block_id));
+        }
+        let mut forgotten_blocks = vec![];
+        for &fork_tip in stale_fork_tips.iter() {
+            self.walk_back_while(fork_tip, |block_id| {
+                if blocks_to_keep.contains(&block_id) {
+                    false
+                } else {
+                    forgotten_blocks.push(block_id);
+                    true
+                }
+            });
+            self.fork_tips.remove(&fork_tip);
+        }
+        for block_id in forgotten_blocks.iter() {
+            self.known_blocks.remove(block_id);
+        }
+        self.orphans.retain(|&block_id, (header, _)| {
+            let is_stale = header.height + finality_depth <= tip_height;
+            if is_stale {
+                forgotten_blocks.push(block_id);
+            }
+            !is_stale
+        });
+        forgotten_blocks
+    }
+    /// Follows the chain of known blocks from `block_id` towards genesis for as long as `f` returns
+    /// `true`.
+    fn walk_back_while(&self, block_id: Entity, mut f: impl FnMut(Entity) -> bool) {
+        let mut next_block = Some(block_id);
+        while let Some(block_id) = next_block {
+            if !f(block_id) {
+                break;
+            }
+            next_block = self.known_blocks.get(&block_id).and_then(|b| b.id_prev);
+        }
+    }
+    /// If `block_id` is an orphan, the block that we need to connect it.
+    fn missing_predecessor(&self, block_id: Entity) -> Option<Entity> {
+        let mut missing_block = None;
+        let mut next_orphan = self.orphans.get(&block_id);
+        while let Some((header, _)) = next_orphan {
+            missing_block = header.id_prev;
+            next_orphan = header
+                .id_prev
+                .and_then(|id_prev| self.orphans.get(&id_prev));
+        }
+        missing_block
+    }
+    /// The blocks of the longest chain, starting with the first one.
+    fn main_chain(&self) -> Vec<Entity> {
+        let mut main_chain = vec![];
+        if let Some(tip) = self.tip {
+            self.walk_back_while(tip, |block_id| {
+                main_chain.push(block_id);
+                true
+            });
+        }
+        main_chain.reverse();
+        main_chain
+    }
+    fn block_locator(&self) -> Vec<Entity> {
+        block_locator(&self.main_chain())
+    }
+    /// Up to `max_headers` headers of our longest chain following the fork point with the chain
+    /// described by `locator`.
+    fn headers_after(&self, locator: &[Entity], max_headers: usize) -> Vec<BlockHeader> {
+        let main_chain = self.main_chain();
+        let start = locator
+            .iter()
+            .find_map(|block_id| main_chain.iter().position(|b| b == block_id))
+            .map_or(0, |fork_point| fork_point + 1);
+        main_chain
+            .iter()
+            .skip(start)
+            .take(max_headers)
+            .map(|block_id| self.known_blocks[block_id])
+            .collect()
+    }
+    fn register_transaction_id(&mut self, tx_id: Entity) {
+        if !self.txes_confirmed.contains(&tx_id) {
+            self.txes_unconfirmed.insert(tx_id);
+        }
+    }
+    /// Adds a transaction to our mempool unless it spends an output that a transaction we have
+    /// seen first spends, like Bitcoin Core does. Returns `false` if we reject it. Doesn't check
+    /// anything else, see `NakamotoConsensus::validate_transaction`.
+    pub fn register_transaction(&mut self, tx_id: Entity, tx: &Transaction) -> bool {
+        if tx.inputs.iter().any(|input| {
+            self.mempool_spent
+                .get(input)
+                .is_some_and(|&other_tx| other_tx != tx_id)
+        }) {
+            return false;
+        }
+        for &input in tx.inputs.iter() {
+            self.mempool_spent.insert(input, tx_id);
+        }
+        self.register_transaction_id(tx_id);
+        true
+    }
+    /// The blocks to disconnect (newest first) and to connect (oldest first) to get from the chain
+    /// ending in `from` to the one ending in `to`.
+    fn path_between(&self, from: Option<Entity>, to: Option<Entity>) -> (Vec<Entity>, Vec<Entity>) {
+        let (mut from, mut to) = (from, to);
+        let (mut disconnected, mut connected) = (vec![], vec![]);
+        while from != to {
+            let from_height = from.map_or(0, |block_id| self.known_blocks[&block_id].height);
+            let to_height = to.map_or(0, |block_id| self.known_blocks[&block_id].height);
+            if from_height >= to_height {
+                disconnected.push(from.unwrap());
+                from = self.known_blocks[&from.unwrap()].id_prev;
+            }
+            if to_height >= from_height {
+                connected.push(to.unwrap());
+                to = self.known_blocks[&to.unwrap()].id_prev;
+            }
+        }
+        connected.reverse();
+        (disconnected, connected)
+    }
+    pub fn block_header(&self, block_id: Entity) -> Option<BlockHeader> {
+        self.known_blocks.get(&block_id).copied()
+    }
+    pub fn tip(&self) -> Option<Entity> {
+        self.tip
+    }
+    /// The block that we mine on top of: our private tip if we have one, otherwise our tip.
+    pub fn mining_tip(&self) -> Option<Entity> {
+        self.private_tip.or(self.tip)
+    }
+    pub fn private_tip(&self) -> Option<Entity> {
+        self.private_tip
+    }
+    /// Lets the node mine on top of `block_id` (if it is `Some`), no matter how long the longest
+    /// chain gets. The private tip follows the blocks that the node mines.
+    pub fn set_private_tip(&mut self, block_id: Option<Entity>) {
+        self.private_tip = block_id;
+    }
+    /// The coins that are unspent on the chain we mine on, as far as we have caught up with it.
+    pub fn utxos(&self) -> &UtxoSet {
+        &self.utxos
+    }
+    /// The transaction in our mempool that spends `out_point`, if there is one.
+    pub fn spent_in_mempool(&self, out_point: OutPoint) -> Option<Entity> {
+        self.mempool_spent.get(&out_point).copied()
+    }
+    pub fn withholds_blocks(&self) -> bool {
+        self.withholds_blocks
+    }
+    pub fn withheld_blocks(&self) -> &VecDeque<Entity> {
+        &self.withheld_blocks
+    }
+    pub fn set_withholds_blocks(&mut self, withholds_blocks: bool) {
+        self.withholds_blocks = withholds_blocks;
+    }
+    /// Forgets about withheld blocks, e.g., because the rest of the network has overtaken them.
+    /// The blocks stay known, so they still count as a (stale) fork.
+    pub fn discard_withheld_blocks(&mut self) {
+        self.withheld_blocks.clear();
+    }
+    pub fn difficulty_adjustment(&self) -> &DifficultyAdjustment {
+        &self.difficulty_adjustment
+    }
+    pub fn monetary_policy(&self) -> &MonetaryPolicy {
+        &self.monetary_policy
+    }
+    pub fn mempool_policy(&self) -> &MempoolPolicy {
+        &self.mempool_policy
+    }
+    pub fn fork_choice(&self) -> &dyn ForkChoiceRule {
+        &*self.fork_choice
+    }
+    pub fn sybil_resistance(&self) -> &dyn SybilResistance {
+        &*self.sybil_resistance
+    }
+    /// Only set if our `SybilResistance` rules have a finality depth.
+    pub fn finalized_block(&self) -> Option<Entity> {
+        self.finalized_block
+    }
+    /// Whether the output could go into the next block on top of our mining tip, as far as the
+    /// coinbase maturity is concerned.
+    pub fn is_mature(&self, out_point: OutPoint) -> bool {
+        self.coinbase_heights
+            .get(&out_point.tx_id)
+            .is_none_or(|&coinbase_height| {
+                self.monetary_policy
+                    .is_mature(coinbase_height, self.height(self.mining_tip()) + 1)
+            })
+    }
+    /// The difficulty of the next block that we mine, on top of `mining_tip`.
+    pub fn next_difficulty(&self) -> f64 {
+        let tip = self.mining_tip().and_then(|tip| self.block_header(tip));
+        self.difficulty_adjustment
+            .next_difficulty(tip, |block_id| self.block_header(block_id))
+    }
+    pub fn fork_tips(&self) -> &HashSet<Entity> {
+        &self.fork_tips
+    }
+    /// Headers of blocks whose predecessors we are still waiting for.
+    pub fn orphans(&self) -> impl Iterator<Item = &BlockHeader> {
+        self.orphans.values().map(|(header, _)| header)
+    }
+    pub fn height(&self, block_id: Option<Entity>) -> usize {
+        if let Some(block_id) = block_id {
+            self.known_blocks
+                .get(&block_id)
+                .expect("Requested height of unknown block.")
+                .height
+        } else {
+            0
+        }
+    }
+    pub fn tip_height(&self) -> usize {
+        self.height(self.tip)
+    }
+    /// Returns the ids of all known blocks (forks included) sorted by their block height,
+    /// smallest heights first.
+    pub fn known_blocks_sorted(&self) -> Vec<Entity> {
+        let mut blocks_heights: Vec<(usize, Entity)> = self
+            .known_blocks
+            .iter()
+            .map(|(&block_id, &block)| (block.height, block_id))
+            .collect();
+        blocks_heights.sort_by(|a, b| a.0.cmp(&b.0));
+        blocks_heights.into_iter().map(|(_, block)| block).collect()
+    }
+    pub fn txes_unconfirmed(&self) -> &BTreeSet<Entity> {
+        &self.txes_unconfirmed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::Entry;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    fn get_state(sim: &Simulation, node_id: Entity) -> NakamotoNodeState {
+        sim.world
+            .query_one::<&NakamotoNodeState>(node_id)
+            .unwrap()
+            .get()
+            .expect("No relevant node state stored?")
+            .clone()
+    }
+
+    #[wasm_bindgen_test]
+    fn blocks_get_distributed() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        let node3 = sim.spawn_random_node();
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+        sim.add_peer(node2, node3);
+        sim.add_peer(node3, node2);
+
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
+
+        let state1 = get_state(&sim, node1);
+        let state2 = get_state(&sim, node2);
+        let state3 = get_state(&sim, node3);
+
+        assert_eq!(state1.tip, state2.tip);
+        assert_eq!(state1.tip, state3.tip);
+    }
+
+    #[wasm_bindgen_test]
+    fn transactions_get_distributed() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        let node3 = sim.spawn_random_node();
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+        sim.add_peer(node2, node3);
+        sim.add_peer(node3, node2);
+
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 32),
+        ));
+        sim.catch_up(100.);
+
+        let state1 = get_state(&sim, node1);
+        let state2 = get_state(&sim, node2);
+        let state3 = get_state(&sim, node3);
+
+        assert!(!state1.txes_unconfirmed.is_empty());
+        assert_eq!(state1.txes_unconfirmed, state2.txes_unconfirmed);
+        assert_eq!(state1.txes_unconfirmed, state3.txes_unconfirmed);
+    }
+
+    #[wasm_bindgen_test]
+    fn transactions_end_up_in_blocks() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 32),
+        ));
+        sim.catch_up(100.);
+
+        sim.do_now(ForSpecific(node2, MineBlock));
+        sim.catch_up(100.);
+
+        let state1 = get_state(&sim, node1);
+        let state2 = get_state(&sim, node2);
+
+        assert!(state1.txes_unconfirmed.is_empty());
+        assert!(state2.txes_unconfirmed.is_empty());
+        assert!(!state1.txes_confirmed.is_empty());
+
+        let block_id = state1.tip().unwrap();
+        let block_contents = sim
+            .world
+            .query_one::<&BlockContents>(block_id)
+            .unwrap()
+            .get()
+            .expect("Block contents do not exist?")
+            .clone();
+
+        // the transaction plus the miner's coinbase
+        assert!(block_contents.len() == 2);
+    }
+
+    #[wasm_bindgen_test]
+    fn block_limits_get_honored() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::new_with_block_limit(2),
+        ));
+
+        let node = sim.spawn_random_node();
+
+        sim.do_now(ForSpecific(
+            node,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 32),
+        ));
+        sim.do_now(ForSpecific(
+            node,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 15),
+        ));
+        sim.do_now(ForSpecific(
+            node,
+            BuildAndBroadcastTransaction::from("Faucet", "Charlie", 12),
+        ));
+        sim.catch_up(100.);
+
+        sim.do_now(ForSpecific(node, MineBlockWithLimit(2)));
+        sim.catch_up(100.);
+
+        let state = get_state(&sim, node);
+
+        assert_eq!(1, state.txes_unconfirmed.len());
+        assert_eq!(3, state.txes_confirmed.len());
+
+        let block_id = state.tip().unwrap();
+        let block_contents = sim
+            .world
+            .query_one::<&BlockContents>(block_id)
+            .unwrap()
+            .get()
+            .expect("Block contents do not exist?")
+            .clone();
+
+        // the coinbase doesn't count towards the limit
+        assert_eq!(3, block_contents.len());
+    }
+
+    #[wasm_bindgen_test]
+    fn blocks_prefer_transactions_with_higher_fees() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        let node = sim.spawn_random_node();
+        for (to, fee) in [("Alice", 1), ("Bob", 5), ("Charlie", 3)] {
+            sim.do_now(ForSpecific(
+                node,
+                BuildAndBroadcastTransaction::from("Faucet", to, 100).with_fee(fee),
+            ));
+        }
+        // a high fee doesn't help if the parent transaction pays a low one
+        sim.do_now(ForSpecific(
+            node,
+            BuildAndBroadcastTransaction::from("Alice", "Bob", 50).with_fee(10),
+        ));
+        sim.catch_up(100.);
+        let fees_in_tip = |sim: &Simulation| -> Vec<u64> {
+            let state = get_state(sim, node);
+            let contents = sim
+                .world
+                .get::<BlockContents>(state.tip().unwrap())
+                .unwrap();
+            let mut fees: Vec<u64> = contents
+                .iter()
+                .map(|&tx_id| sim.world.get::<Transaction>(tx_id).unwrap())
+                .filter(|tx| !tx.is_coinbase())
+                .map(|tx| tx.fee)
+                .collect();
+            fees.sort();
+            fees
+        };
+
+        sim.do_now(ForSpecific(node, MineBlockWithLimit(2)));
+        sim.catch_up(100.);
+        assert_eq!(vec![3, 5], fees_in_tip(&sim));
+        sim.do_now(ForSpecific(node, MineBlockWithLimit(2)));
+        sim.catch_up(100.);
+        assert_eq!(vec![1, 10], fees_in_tip(&sim));
+        assert!(get_state(&sim, node).txes_unconfirmed.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn miners_get_subsidy_and_fees_once_the_coinbase_matures() {
+        let mut sim = Simulation::new();
+        let policy = MonetaryPolicy::default().with_coinbase_maturity(2);
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_monetary_policy(policy),
+        ));
+        let node = sim.spawn_random_node();
+        let peer = sim.spawn_random_node();
+        sim.node_interface(node).connect(peer);
+        let miner = sim.label(node);
+        sim.do_now(ForSpecific(
+            node,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 10).with_fee(3),
+        ));
+        sim.do_now(ForSpecific(node, MineBlock));
+        sim.catch_up(100.);
+        let reward = policy.subsidy(1) + 3;
+        assert_eq!(reward, get_state(&sim, node).utxos().balance(&miner));
+
+        let spend = BuildAndBroadcastTransaction::from(&miner, "Bob", reward);
+        assert!(spend.clone().execute_for(&mut sim, node).is_err());
+        sim.do_now(ForSpecific(node, MineBlock));
+        sim.catch_up(100.);
+        assert!(spend.execute_for(&mut sim, node).is_ok());
+        assert_eq!(1, get_state(&sim, node).txes_unconfirmed.len());
+    }
+
+    #[wasm_bindgen_test]
+    fn invalid_transactions_are_rejected() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.node_interface(node1).connect(node2);
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Faucet", "Alice", 50),
+        ));
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
+        let coins = OutPoint {
+            tx_id: *get_state(&sim, node2)
+                .txes_confirmed
+                .iter()
+                .find(|&&tx_id| !sim.world.get::<Transaction>(tx_id).unwrap().is_coinbase())
+                .unwrap(),
+            index: 0,
+        };
+        assert_eq!(50, get_state(&sim, node2).utxos().balance("Alice"));
+
+        let result =
+            BuildAndBroadcastTransaction::from("Alice", "Bob", 80).execute_for(&mut sim, node1);
+        assert!(result.is_err());
+        let mut node = sim.node_interface(node1);
+        let spend = |from: &str, value: u64| Transaction {
+            from: from.into(),
+            inputs: vec![coins],
+            outputs: vec![TxOutput {
+                to: "Bob".into(),
+                value,
+            }],
+            fee: 0,
+        };
+        let minted = Transaction {
+            inputs: vec![],
+            ..spend("Alice", 10)
+        };
+        for tx in [spend("Alice", 80), spend("Mallory", 50), minted] {
+            let tx_id = node.spawn_transaction(tx);
+            NakamotoConsensus::broadcast_transaction(&mut node, tx_id);
+        }
+        sim.catch_up(100.);
+        assert!(get_state(&sim, node2).txes_unconfirmed.is_empty());
+
+        // spending unconfirmed change works, spending the same coins twice doesn't
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Alice", "Bob", 30),
+        ));
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Alice", "Charlie", 20),
+        ));
+        sim.catch_up(100.);
+        let mut node = sim.node_interface(node1);
+        let tx_id = node.spawn_transaction(spend("Alice", 50));
+        NakamotoConsensus::broadcast_transaction(&mut node, tx_id);
+        sim.catch_up(100.);
+        let state2 = get_state(&sim, node2);
+        assert_eq!(2, state2.txes_unconfirmed.len());
+        assert!(!state2.txes_unconfirmed.contains(&tx_id));
+    }
+
+    #[wasm_bindgen_test]
+    fn full_mempools_evict_the_lowest_fees() {
+        let mut sim = Simulation::new();
+        let policy = MempoolPolicy::default().with_max_transactions(3);
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_mempool_policy(policy),
+        ));
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.node_interface(node1).connect(node2);
+        for (to, fee) in [("Alice", 3), ("Bob", 1), ("Charlie", 5), ("Dave", 2)] {
+            sim.do_now(ForSpecific(
+                node1,
+                BuildAndBroadcastTransaction::from("Faucet", to, 100).with_fee(fee),
+            ));
+            sim.catch_up(100.);
+        }
+        let mempool_fees = |sim: &Simulation, node: Entity| -> Vec<u64> {
+            let mut fees: Vec<u64> = get_state(sim, node)
+                .txes_unconfirmed
+                .iter()
+                .map(|&tx_id| sim.world.get::<Transaction>(tx_id).unwrap().fee)
+                .collect();
+            fees.sort();
+            fees
+        };
+        assert_eq!(vec![2, 3, 5], mempool_fees(&sim, node1));
+        assert_eq!(vec![2, 3, 5], mempool_fees(&sim, node2));
+        assert_eq!(2, MempoolStats::total(&sim).evicted);
+
+        // children leave together with their parents
+        let execute = |sim: &mut Simulation, action: BuildAndBroadcastTransaction| {
+            let result = action.execute_for(sim, node1);
+            sim.catch_up(100.);
+            result
+        };
+        assert!(execute(
+            &mut sim,
+            BuildAndBroadcastTransaction::from("Alice", "Erin", 50).with_fee(4)
+        )
+        .is_ok());
+        assert!(execute(
+            &mut sim,
+            BuildAndBroadcastTransaction::from("Faucet", "Frank", 100).with_fee(6)
+        )
+        .is_ok());
+        assert_eq!(vec![5, 6], mempool_fees(&sim, node2));
+
+        // among equally cheap transactions, the newest one has to go
+        assert!(execute(
+            &mut sim,
+            BuildAndBroadcastTransaction::from("Faucet", "Gina", 100)
+        )
+        .is_ok());
+        assert!(execute(
+            &mut sim,
+            BuildAndBroadcastTransaction::from("Faucet", "Hank", 100)
+        )
+        .is_err());
+        assert_eq!(vec![0, 5, 6], mempool_fees(&sim, node2));
+    }
+
+    #[wasm_bindgen_test]
+    fn old_transactions_expire() {
+        let mut sim = Simulation::new();
+        let policy = MempoolPolicy::default().with_expiry(SimSeconds::from(60.));
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_mempool_policy(policy),
+        ));
+        let node = sim.spawn_random_node();
+        let peer = sim.spawn_random_node();
+        sim.node_interface(node).connect(peer);
+        sim.do_now(ForSpecific(
+            node,
+            BuildAndBroadcastTransaction::from("Faucet", "Alice", 100),
+        ));
+        sim.work_until(SimSeconds::from(30.));
+        sim.do_now(ForSpecific(
+            node,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 100),
+        ));
+        sim.work_until(SimSeconds::from(70.));
+        assert_eq!(2, get_state(&sim, node).txes_unconfirmed.len());
+
+        // new transactions make the nodes look at their mempools again
+        sim.do_now(ForSpecific(
+            node,
+            BuildAndBroadcastTransaction::from("Faucet", "Carol", 100),
+        ));
+        sim.work_until(SimSeconds::from(71.));
+        for node in [node, peer] {
+            let state = get_state(&sim, node);
+            let mut recipients: Vec<String> = state
+                .txes_unconfirmed
+                .iter()
+                .map(|&tx_id| {
+                    sim.world
+                        .get::<Transaction>(tx_id)
+                        .unwrap()
+                        .to()
+                        .to_string()
+                })
+                .collect();
+            recipients.sort();
+            assert_eq!(vec!["Bob", "Carol"], recipients);
+        }
+        assert_eq!(2, MempoolStats::total(&sim).expired);
+    }
+
+    #[wasm_bindgen_test]
+    fn higher_fees_replace_conflicting_transactions() {
+        let mut sim = Simulation::new();
+        let policy = MempoolPolicy::default().with_replace_by_fee();
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_mempool_policy(policy),
+        ));
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.node_interface(node1).connect(node2);
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Faucet", "Alice", 50),
+        ));
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
+        let coins = get_state(&sim, node2).utxos().owned_by("Alice")[0].0;
+        let spend = |to: &str, fee: u64| Transaction {
+            from: "Alice".into(),
+            inputs: vec![coins],
+            outputs: vec![TxOutput {
+                to: to.into(),
+                value: 50 - fee,
+            }],
+            fee,
+        };
+
+        let mut tx_ids = vec![];
+        for tx in [spend("Bob", 2), spend("Mallory", 2), spend("Mallory", 3)] {
+            let mut node = sim.node_interface(node1);
+            let tx_id = node.spawn_transaction(tx);
+            NakamotoConsensus::broadcast_transaction(&mut node, tx_id);
+            sim.catch_up(100.);
+            tx_ids.push(tx_id);
+        }
+        let state2 = get_state(&sim, node2);
+        assert_eq!(1, state2.txes_unconfirmed.len());
+        assert!(state2.txes_unconfirmed.contains(&tx_ids[2]));
+        assert_eq!(Some(tx_ids[2]), state2.spent_in_mempool(coins));
+        assert_eq!(1, MempoolStats::total(&sim).replaced);
+    }
+
+    #[wasm_bindgen_test]
+    fn reorgs_return_transactions_to_the_mempool() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Faucet", "Alice", 50),
+        ));
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Alice", "Bob", 20),
+        ));
+        sim.do_now(MultipleTimes::new(ForSpecific(node2, MineBlock), 2));
+        sim.catch_up(100.);
+        assert_eq!(50, get_state(&sim, node1).utxos().balance("Alice"));
+        assert_eq!(1, get_state(&sim, node1).txes_unconfirmed.len());
+
+        sim.node_interface(node1).connect(node2);
+        sim.catch_up(100.);
+        let state1 = get_state(&sim, node1);
+        assert_eq!(state1.tip(), get_state(&sim, node2).tip());
+        assert_eq!(0, state1.utxos().balance("Alice"));
+        // only the coinbases of node2's blocks; node1's coinbase is gone for good
+        assert_eq!(2, state1.txes_confirmed.len());
+        // the payment to Bob still works because it spends a transaction that is back in the mempool
+        assert_eq!(2, state1.txes_unconfirmed.len());
+    }
+
+    #[derive(Default)]
+    struct TipChanges(Vec<TipChanged>);
+    impl EventHandler for TipChanges {
+        fn handle_event(
+            &mut self,
+            sim: &mut Simulation,
+            event: Event,
+        ) -> Result<(), Box<dyn Error>> {
+            self.0.extend(sim.notification::<TipChanged>(event));
+            Ok(())
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn reorgs_get_notified() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        let tip_changes = sim.add_event_handler(TipChanges::default());
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.do_now(MultipleTimes::new(ForSpecific(node2, MineBlock), 2));
+        sim.catch_up(100.);
+        let stale_block = get_state(&sim, node1).tip().unwrap();
+        let main_chain = get_state(&sim, node2).main_chain();
+
+        sim.node_interface(node1).connect(node2);
+        sim.catch_up(100.);
+        let handlers = sim.additional_event_handlers();
+        let handlers = handlers.borrow();
+        let tip_changes = &handlers.get::<TipChanges>(tip_changes).unwrap().0;
+        assert_eq!(4, tip_changes.len());
+        let reorgs: Vec<&TipChanged> = tip_changes.iter().filter(|t| t.is_reorg()).collect();
+        assert_eq!(
+            vec![&TipChanged {
+                node: node1,
+                old_tip: Some(stale_block),
+                new_tip: Some(main_chain[1]),
+                disconnected_blocks: vec![stale_block],
+                connected_blocks: main_chain,
+            }],
+            reorgs
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn transactions_are_not_registered_if_already_confirmed() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 32),
+        ));
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
+
+        let mut state2 = get_state(&sim, node2);
+
+        assert!(state2.txes_unconfirmed.is_empty());
+        assert!(!state2.txes_confirmed.is_empty());
+
+        let tx_id = state2.txes_confirmed.iter().cloned().next().unwrap();
+
+        state2.register_transaction_id(tx_id);
+
+        assert!(state2.txes_unconfirmed.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn forks_get_registered() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        let node3 = sim.spawn_random_node();
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+        sim.add_peer(node2, node3);
+        sim.add_peer(node3, node2);
+
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
+
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.do_now(ForSpecific(node3, MineBlock));
+        sim.catch_up(100.);
+
+        let state1 = get_state(&sim, node1);
+        let state3 = get_state(&sim, node3);
+
+        assert_ne!(state1.tip, state3.tip);
+
+        let fork_tip_1 = state1
+            .fork_tips
+            .into_iter()
+            .next()
+            .expect("No forks registered?!");
+        assert_eq!(fork_tip_1, state3.tip.unwrap());
+    }
+
+    #[wasm_bindgen_test]
+    fn forks_get_resolved() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        let node3 = sim.spawn_random_node();
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+        sim.add_peer(node2, node3);
+        sim.add_peer(node3, node2);
+
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
+
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.do_now(ForSpecific(node3, MineBlock));
+        sim.catch_up(100.);
+
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
+
+        let state1 = get_state(&sim, node1);
+        let state3 = get_state(&sim, node3);
+
+        assert_eq!(state1.tip, state3.tip);
+    }
+
+    #[wasm_bindgen_test]
+    fn in_perfect_case_all_stored_blocks_are_connected_to_genesis() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        sim.do_now(SpawnRandomNodes(8));
+        sim.do_now(MakeDelaunayNetwork);
+
+        sim.catch_up(1.);
+
+        for _ in 0..20 {
+            sim.do_now(ForRandomNode(MineBlock));
+            sim.catch_up(100.);
+        }
+
+        let tested_node = sim.pick_random_node().unwrap();
+        let state = get_state(&sim, tested_node);
+
+        let mut remaining_blocks = state.known_blocks.clone();
+
+        let mut queue = vec![state.tip];
+        queue.extend(state.fork_tips.clone().into_iter().map(Some));
+
+        while !queue.is_empty() {
+            let block_id = queue.pop().unwrap();
+            if let Some(block_id) = block_id {
+                if !state.known_blocks.contains_key(&block_id) {
+                    panic!("Block not connected to genesis hash!");
+                }
+                if let Entry::Occupied(block_entry) = remaining_blocks.entry(block_id) {
+                    queue.push(block_entry.get().id_prev);
+                    block_entry.remove();
+                }
+            }
+        }
+        assert!(remaining_blocks.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn nakamoto_consensus_recovers_from_splits() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.do_now(ForSpecific(node1, MineBlock));
+
+        sim.do_now(ForSpecific(node2, MineBlock));
+        sim.do_now(ForSpecific(node2, MineBlock));
+
+        sim.catch_up(10.);
+
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+
+        sim.catch_up(10.);
+
+        let state1 = get_state(&sim, node1);
+        let state2 = get_state(&sim, node2);
+
+        assert_eq!(state1.height(state1.tip), state2.height(state2.tip));
+        assert_eq!(state1.tip, state2.tip);
+    }
+
+    #[wasm_bindgen_test]
+    fn orphans_get_connected_when_their_predecessors_arrive() {
+        let mut sim = Simulation::new();
+        let node = sim.spawn_random_node();
+        let mut node = sim.node_interface(node);
+        let initial_difficulty = DifficultyAdjustment::default().initial_difficulty;
+        let block1 = node.spawn_block_with_difficulty(None, [], initial_difficulty);
+        let block2 = node.spawn_block(Some(block1.id), []);
+        let block3 = node.spawn_block(Some(block2.id), []);
+
+        let mut state = NakamotoNodeState::default();
+        assert!(!state.register_block(block3, BlockContents::new()));
+        assert!(!state.register_block(block2, BlockContents::new()));
+        assert_eq!(None, state.tip());
+        assert_eq!(2, state.orphans().count());
+
+        assert!(state.register_block(block1, BlockContents::new()));
+        assert_eq!(Some(block3.id), state.tip());
+        assert_eq!(3, state.known_blocks.len());
+        assert_eq!(0, state.orphans().count());
+    }
+
+    #[wasm_bindgen_test]
+    fn preferred_tips_only_win_ties() {
+        let mut sim = Simulation::new();
+        let node = sim.spawn_random_node();
+        let mut node = sim.node_interface(node);
+        let initial_difficulty = DifficultyAdjustment::default().initial_difficulty;
+        let genesis = node.spawn_block_with_difficulty(None, [], initial_difficulty);
+        let block_a = node.spawn_block(Some(genesis.id), []);
+        let block_b = node.spawn_block(Some(genesis.id), []);
+        let mut state = NakamotoNodeState::default();
+        for block in [genesis, block_a, block_b] {
+            state.register_block(block, BlockContents::new());
+        }
+        assert_eq!(Some(block_a.id), state.tip());
+
+        assert!(state.prefer_in_ties(block_b.id, BlockContents::new()));
+        assert_eq!(Some(block_b.id), state.tip());
+        assert!(state.fork_tips().contains(&block_a.id));
+
+        let block_c = node.spawn_block(Some(block_a.id), []);
+        state.register_block(block_c, BlockContents::new());
+        assert_eq!(Some(block_c.id), state.tip());
+        assert!(!state.prefer_in_ties(block_b.id, BlockContents::new()));
+        assert_eq!(Some(block_c.id), state.tip());
+    }
+
+    #[wasm_bindgen_test]
+    fn longer_chain_arriving_out_of_order_becomes_the_tip() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.do_now(MultipleTimes::new(ForSpecific(node1, MineBlock), 2));
+        sim.catch_up(10.);
+        let old_tip = get_state(&sim, node1).tip().unwrap();
+
+        let mut node = sim.node_interface(node2);
+        let initial_difficulty = DifficultyAdjustment::default().initial_difficulty;
+        let block1 = node.spawn_block_with_difficulty(None, [], initial_difficulty);
+        let block2 = node.spawn_block(Some(block1.id), []);
+        let block3 = node.spawn_block(Some(block2.id), []);
+        for block in [block3, block2, block1] {
+            sim.send_message(
+                node2,
+                node1,
+                NakamotoMessage::Flood(InventoryItem::Block(block.id)),
+            );
+            sim.catch_up(10.);
+        }
+
+        let state1 = get_state(&sim, node1);
+        assert_eq!(Some(block3.id), state1.tip());
+        assert_eq!(3, state1.tip_height());
+        assert!(state1.fork_tips().contains(&old_tip));
+        assert_eq!(0, state1.orphans().count());
+    }
+
+    #[wasm_bindgen_test]
+    fn fork_choice_rules_can_disagree() {
+        // the difficulty rises a lot after a quick block and drops a lot after a slow one
+        let adjustment = DifficultyAdjustment::default().with_rule(RetargetingRule::PerBlock {
+            half_life: SimSeconds::from(100.),
+        });
+        // a long chain of easy blocks and a short chain ending in a hard block; returns the tip
+        // and the hard block
+        let tip_and_hard_block = |consensus: NakamotoConsensus| {
+            let mut sim = Simulation::new();
+            sim.add_event_handler(InvokeProtocolForAllNodes(
+                consensus.with_difficulty_adjustment(adjustment),
+            ));
+            let miner = sim.spawn_random_node();
+            let node = sim.spawn_random_node();
+            let mut headers: HashMap<Entity, BlockHeader> = HashMap::new();
+            let mut spawn_block = |sim: &mut Simulation, id_prev: Option<Entity>| {
+                let parent = id_prev.map(|id_prev| headers[&id_prev]);
+                let difficulty =
+                    adjustment.next_difficulty(parent, |block_id| headers.get(&block_id).copied());
+                let header =
+                    sim.node_interface(miner)
+                        .spawn_block_with_difficulty(id_prev, [], difficulty);
+                headers.insert(header.id, header);
+                header
+            };
+            let genesis = spawn_block(&mut sim, None);
+            let quick = spawn_block(&mut sim, Some(genesis.id));
+            let hard = spawn_block(&mut sim, Some(quick.id));
+            sim.work_until(SimSeconds::from(10_000.));
+            let slow = spawn_block(&mut sim, Some(genesis.id));
+            let easy1 = spawn_block(&mut sim, Some(slow.id));
+            let easy2 = spawn_block(&mut sim, Some(easy1.id));
+            for block in [genesis, quick, hard, slow, easy1, easy2] {
+                let message = NakamotoMessage::Flood(InventoryItem::Block(block.id));
+                sim.send_message(miner, node, message);
+                sim.catch_up(10.);
+            }
+            (get_state(&sim, node).tip().unwrap(), hard.id)
+        };
+        let (tip, hard_block) = tip_and_hard_block(NakamotoConsensus::default());
+        assert_ne!(hard_block, tip);
+        let (tip, hard_block) =
+            tip_and_hard_block(NakamotoConsensus::default().with_fork_choice(HeaviestChain));
+        assert_eq!(hard_block, tip);
+        // the nodes are spawned in the same order in each simulation
+        let node = {
+            let mut sim = Simulation::new();
+            sim.spawn_random_node();
+            sim.spawn_random_node()
+        };
+        let (tip, hard_block) = tip_and_hard_block(
+            NakamotoConsensus::default().with_fork_choice_of(node, HeaviestChain),
+        );
+        assert_eq!(hard_block, tip);
+    }
+
+    #[wasm_bindgen_test]
+    fn blocks_with_wrong_difficulty_are_rejected() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_fork_choice(HeaviestChain),
+        ));
+        let miner = sim.spawn_random_node();
+        let node = sim.spawn_random_node();
+        let initial_difficulty = DifficultyAdjustment::default().initial_difficulty;
+        let mut miner_interface = sim.node_interface(miner);
+        let genesis = miner_interface.spawn_block_with_difficulty(None, [], initial_difficulty);
+        let heavy = miner_interface.spawn_block_with_difficulty(None, [], 1000.);
+        let valid = miner_interface.spawn_block(Some(genesis.id), []);
+        let invalid = miner_interface.spawn_block_with_difficulty(Some(valid.id), [], 1000.);
+        // the last one arrives before its predecessor, as an orphan
+        for block in [heavy, invalid, genesis, valid] {
+            let message = NakamotoMessage::Flood(InventoryItem::Block(block.id));
+            sim.send_message(miner, node, message);
+            sim.catch_up(10.);
+        }
+
+        let state = get_state(&sim, node);
+        assert_eq!(Some(valid.id), state.tip());
+        assert_eq!(None, state.block_header(heavy.id));
+        assert_eq!(None, state.block_header(invalid.id));
+        assert_eq!(0, state.orphans().count());
+    }
+
+    #[wasm_bindgen_test]
+    fn rounding_errors_in_the_difficulty_are_tolerated() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        let miner = sim.spawn_random_node();
+        let node = sim.spawn_random_node();
+        let initial_difficulty = DifficultyAdjustment::default().initial_difficulty;
+        let almost_initial_difficulty = initial_difficulty * (1. + 1e-12);
+        let block = sim.node_interface(miner).spawn_block_with_difficulty(
+            None,
+            [],
+            almost_initial_difficulty,
+        );
+        let message = NakamotoMessage::Flood(InventoryItem::Block(block.id));
+        sim.send_message(miner, node, message);
+        sim.catch_up(10.);
+
+        assert_eq!(Some(block.id), get_state(&sim, node).tip());
     }
 
     #[wasm_bindgen_test]
-    fn blocks_get_distributed() {
+    fn inventory_relay_distributes_blocks_and_transactions() {
         let mut sim = Simulation::new();
-        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_relay(Relay::Inventory),
+        ));
 
         let node1 = sim.spawn_random_node();
         let node2 = sim.spawn_random_node();
@@ -320,22 +3218,77 @@ mod tests {
         sim.add_peer(node2, node1);
         sim.add_peer(node2, node3);
         sim.add_peer(node3, node2);
+        sim.catch_up(10.);
+
+        sim.do_now(ForSpecific(
+            node3,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 42),
+        ));
+        sim.catch_up(10.);
+        assert_eq!(1, get_state(&sim, node1).txes_unconfirmed().len());
 
         sim.do_now(ForSpecific(node1, MineBlock));
-        sim.catch_up(100.);
+        sim.catch_up(10.);
 
         let state1 = get_state(&sim, node1);
-        let state2 = get_state(&sim, node2);
         let state3 = get_state(&sim, node3);
+        assert!(state1.tip().is_some());
+        assert_eq!(state1.tip(), state3.tip());
+        assert!(state3.txes_unconfirmed().is_empty());
+        assert_eq!(2, state3.txes_confirmed.len());
+    }
 
-        assert_eq!(state1.tip, state2.tip);
-        assert_eq!(state1.tip, state3.tip);
+    #[wasm_bindgen_test]
+    fn newly_connected_peers_sync_headers_first() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_relay(Relay::Inventory),
+        ));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.do_now(MultipleTimes::new(ForSpecific(node1, MineBlock), 30));
+        sim.do_now(ForSpecific(node2, MineBlock));
+        sim.catch_up(10.);
+
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+        sim.catch_up(10.);
+
+        let state1 = get_state(&sim, node1);
+        let state2 = get_state(&sim, node2);
+        assert_eq!(30, state2.tip_height());
+        assert_eq!(state1.tip(), state2.tip());
+        assert_eq!(1, state1.fork_tips().len());
     }
 
     #[wasm_bindgen_test]
-    fn transactions_get_distributed() {
+    fn missing_predecessors_get_requested() {
         let mut sim = Simulation::new();
-        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_relay(Relay::Inventory),
+        ));
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.do_now(MultipleTimes::new(ForSpecific(node2, MineBlock), 3));
+        sim.catch_up(10.);
+        let tip = get_state(&sim, node2).tip().unwrap();
+
+        sim.send_message(node2, node1, NakamotoMessage::Block(tip));
+        sim.catch_up(10.);
+
+        let state1 = get_state(&sim, node1);
+        assert_eq!(Some(tip), state1.tip());
+        assert_eq!(0, state1.orphans().count());
+    }
+
+    #[wasm_bindgen_test]
+    fn compact_blocks_get_reconstructed_from_mempool() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_relay(Relay::CompactBlocks),
+        ));
 
         let node1 = sim.spawn_random_node();
         let node2 = sim.spawn_random_node();
@@ -344,105 +3297,125 @@ mod tests {
         sim.add_peer(node2, node1);
         sim.add_peer(node2, node3);
         sim.add_peer(node3, node2);
+        sim.catch_up(10.);
 
-        sim.do_now(ForSpecific(
-            node1,
-            BuildAndBroadcastTransaction::from("Alice", "Bob", 32),
-        ));
-        sim.catch_up(100.);
-
-        let state1 = get_state(&sim, node1);
-        let state2 = get_state(&sim, node2);
-        let state3 = get_state(&sim, node3);
+        for value in 1..=10 {
+            sim.do_now(ForSpecific(
+                node3,
+                BuildAndBroadcastTransaction::from("Faucet", "Bob", value),
+            ));
+        }
+        sim.catch_up(10.);
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(10.);
 
-        assert!(!state1.txes_unconfirmed.is_empty());
-        assert_eq!(state1.txes_unconfirmed, state2.txes_unconfirmed);
-        assert_eq!(state1.txes_unconfirmed, state3.txes_unconfirmed);
+        assert_eq!(get_state(&sim, node1).tip(), get_state(&sim, node3).tip());
+        let stats = CompactBlockStats::total(&sim);
+        assert_eq!(2, stats.blocks_received);
+        assert_eq!(Some(1.), stats.reconstruction_rate());
+        assert_eq!(0, stats.txes_requested);
+        // the coinbase always gets sent in full, which matters for such a small block
+        assert!(stats.bytes_received * 5 < stats.bytes_of_full_blocks);
     }
 
     #[wasm_bindgen_test]
-    fn transactions_end_up_in_blocks() {
+    fn missing_transactions_of_compact_blocks_get_requested() {
         let mut sim = Simulation::new();
-        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_relay(Relay::CompactBlocks),
+        ));
 
         let node1 = sim.spawn_random_node();
         let node2 = sim.spawn_random_node();
         sim.add_peer(node1, node2);
         sim.add_peer(node2, node1);
+        sim.catch_up(10.);
 
         sim.do_now(ForSpecific(
             node1,
-            BuildAndBroadcastTransaction::from("Alice", "Bob", 32),
+            SpawnPartiallyRelayedTransactions {
+                count: 10,
+                share_of_nodes: 0.,
+            },
         ));
-        sim.catch_up(100.);
-
-        sim.do_now(ForSpecific(node2, MineBlock));
-        sim.catch_up(100.);
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(10.);
 
-        let state1 = get_state(&sim, node1);
         let state2 = get_state(&sim, node2);
-
-        assert!(state1.txes_unconfirmed.is_empty());
-        assert!(state2.txes_unconfirmed.is_empty());
-        assert!(!state1.txes_confirmed.is_empty());
-
-        let block_id = state1.tip().unwrap();
-        let block_contents = sim
-            .world
-            .query_one::<&BlockContents>(block_id)
-            .unwrap()
-            .get()
-            .expect("Block contents do not exist?")
-            .clone();
-
-        assert!(block_contents.len() == 1);
+        assert_eq!(get_state(&sim, node1).tip(), state2.tip());
+        assert_eq!(11, state2.txes_confirmed.len());
+        let stats = CompactBlockStats::total(&sim);
+        assert_eq!(1, stats.blocks_received);
+        assert_eq!(Some(0.), stats.reconstruction_rate());
+        assert_eq!(10, stats.txes_requested);
     }
 
     #[wasm_bindgen_test]
-    fn block_limits_get_honored() {
+    fn items_that_a_peer_doesnt_have_get_requested_elsewhere() {
         let mut sim = Simulation::new();
         sim.add_event_handler(InvokeProtocolForAllNodes(
-            NakamotoConsensus::new_with_block_limit(2),
+            NakamotoConsensus::default().with_relay(Relay::Inventory),
         ));
 
-        let node = sim.spawn_random_node();
-
-        sim.do_now(ForSpecific(
-            node,
-            BuildAndBroadcastTransaction::from("Alice", "Bob", 32),
-        ));
-        sim.do_now(ForSpecific(
-            node,
-            BuildAndBroadcastTransaction::from("Charlie", "Bob", 15),
-        ));
-        sim.do_now(ForSpecific(
-            node,
-            BuildAndBroadcastTransaction::from("Alice", "Charlie", 12),
-        ));
-        sim.catch_up(100.);
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        let node3 = sim.spawn_random_node();
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(10.);
+        let block = InventoryItem::Block(get_state(&sim, node1).tip().unwrap());
 
-        sim.do_now(ForSpecific(node, MineBlockWithLimit(2)));
-        sim.catch_up(100.);
+        // node3 doesn't have the block (anymore), but node2 asks it first
+        sim.send_message(node3, node2, NakamotoMessage::Inv(vec![block]));
+        sim.catch_up(10.);
+        sim.send_message(node1, node2, NakamotoMessage::Inv(vec![block]));
+        sim.catch_up(10.);
 
-        let state = get_state(&sim, node);
+        assert_eq!(get_state(&sim, node1).tip(), get_state(&sim, node2).tip());
 
-        assert_eq!(1, state.txes_unconfirmed.len());
-        assert_eq!(2, state.txes_confirmed.len());
+        // this time, node2 knows right away who else has the block
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(10.);
+        let block = InventoryItem::Block(get_state(&sim, node1).tip().unwrap());
+        sim.node_interface(node2)
+            .get::<SimpleFloodingState<InventoryItem>>()
+            .register_peer_has(node1, block);
+        sim.send_message(node3, node2, NakamotoMessage::Inv(vec![block]));
+        sim.catch_up(10.);
 
-        let block_id = state.tip().unwrap();
-        let block_contents = sim
+        assert_eq!(get_state(&sim, node1).tip(), get_state(&sim, node2).tip());
+        assert!(sim
             .world
-            .query_one::<&BlockContents>(block_id)
+            .get::<InventoryRelayState>(node2)
             .unwrap()
-            .get()
-            .expect("Block contents do not exist?")
-            .clone();
+            .requested
+            .is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn block_locators_get_sparser_towards_genesis() {
+        let mut sim = Simulation::new();
+        let node = sim.spawn_random_node();
+        sim.do_now(MultipleTimes::new(ForSpecific(node, MineBlock), 100));
+        sim.catch_up(10.);
 
-        assert_eq!(2, block_contents.len());
+        let state = get_state(&sim, node);
+        let heights: Vec<usize> = state
+            .block_locator()
+            .into_iter()
+            .map(|block_id| state.height(Some(block_id)))
+            .collect();
+        assert_eq!(
+            vec![100, 99, 98, 97, 96, 95, 94, 93, 92, 91, 89, 85, 77, 61, 29, 1],
+            heights
+        );
+        assert_eq!(
+            39,
+            state.headers_after(&state.block_locator()[13..], 100).len()
+        );
     }
 
     #[wasm_bindgen_test]
-    fn transactions_are_not_registered_if_already_confirmed() {
+    fn garbage_collection_prunes_deeply_buried_blocks_and_transactions() {
         let mut sim = Simulation::new();
         sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
 
@@ -453,25 +3426,34 @@ mod tests {
 
         sim.do_now(ForSpecific(
             node1,
-            BuildAndBroadcastTransaction::from("Alice", "Bob", 32),
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 32),
         ));
-        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.do_now(MultipleTimes::new(ForSpecific(node1, MineBlock), 4));
         sim.catch_up(100.);
 
-        let mut state2 = get_state(&sim, node2);
-
-        assert!(state2.txes_unconfirmed.is_empty());
-        assert!(!state2.txes_confirmed.is_empty());
+        let first_block = get_state(&sim, node2).known_blocks_sorted()[0];
+        let tx_id = *get_state(&sim, node2)
+            .txes_confirmed
+            .iter()
+            .find(|&&tx_id| !sim.world.get::<Transaction>(tx_id).unwrap().is_coinbase())
+            .unwrap();
 
-        let tx_id = state2.txes_confirmed.iter().cloned().next().unwrap();
+        sim.do_now(CollectGarbage::new(3));
+        sim.catch_up(100.);
 
-        state2.register_transaction_id(tx_id);
+        assert!(sim.world.get::<BlockHeader>(first_block).is_ok());
+        assert!(sim.world.get::<BlockContents>(first_block).is_err());
+        assert!(sim.world.get::<Transaction>(tx_id).is_err());
+        // only the coinbases of the blocks that are still stored in full
+        assert_eq!(3, get_state(&sim, node1).txes_confirmed.len());
+        assert_eq!(3, get_state(&sim, node2).txes_confirmed.len());
 
-        assert!(state2.txes_unconfirmed.is_empty());
+        let tip = get_state(&sim, node2).tip().unwrap();
+        assert!(sim.world.get::<BlockContents>(tip).is_ok());
     }
 
     #[wasm_bindgen_test]
-    fn forks_get_registered() {
+    fn garbage_collection_removes_stale_forks() {
         let mut sim = Simulation::new();
         sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
 
@@ -490,112 +3472,130 @@ mod tests {
         sim.do_now(ForSpecific(node3, MineBlock));
         sim.catch_up(100.);
 
-        let state1 = get_state(&sim, node1);
-        let state3 = get_state(&sim, node3);
+        let fork_tip = *get_state(&sim, node1).fork_tips().iter().next().unwrap();
 
-        assert_ne!(state1.tip, state3.tip);
+        sim.do_now(MultipleTimes::new(ForSpecific(node1, MineBlock), 3));
+        sim.catch_up(100.);
 
-        let fork_tip_1 = state1
-            .fork_tips
-            .into_iter()
-            .next()
-            .expect("No forks registered?!");
-        assert_eq!(fork_tip_1, state3.tip.unwrap());
+        sim.do_now(CollectGarbage::new(3));
+        sim.catch_up(100.);
+
+        for node in [node1, node2, node3] {
+            let state = get_state(&sim, node);
+            assert!(state.fork_tips().is_empty());
+            assert!(state.block_header(fork_tip).is_none());
+        }
+        assert!(!sim.world.contains(fork_tip));
     }
 
     #[wasm_bindgen_test]
-    fn forks_get_resolved() {
+    fn new_peers_sync_after_garbage_collection() {
         let mut sim = Simulation::new();
         sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
 
         let node1 = sim.spawn_random_node();
         let node2 = sim.spawn_random_node();
-        let node3 = sim.spawn_random_node();
-        sim.add_peer(node1, node2);
-        sim.add_peer(node2, node1);
-        sim.add_peer(node2, node3);
-        sim.add_peer(node3, node2);
 
-        sim.do_now(ForSpecific(node1, MineBlock));
-        sim.catch_up(100.);
+        sim.do_now(ForSpecific(
+            node1,
+            BuildAndBroadcastTransaction::from("Faucet", "Bob", 100),
+        ));
+        sim.do_now(MultipleTimes::new(ForSpecific(node1, MineBlock), 5));
+        sim.catch_up(10.);
 
-        sim.do_now(ForSpecific(node1, MineBlock));
-        sim.do_now(ForSpecific(node3, MineBlock));
-        sim.catch_up(100.);
+        sim.do_now(CollectGarbage::new(2));
+        sim.catch_up(10.);
 
-        sim.do_now(ForSpecific(node1, MineBlock));
-        sim.catch_up(100.);
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+        sim.catch_up(10.);
 
         let state1 = get_state(&sim, node1);
-        let state3 = get_state(&sim, node3);
+        let state2 = get_state(&sim, node2);
 
-        assert_eq!(state1.tip, state3.tip);
+        assert_eq!(5, state2.tip_height());
+        assert_eq!(state1.tip, state2.tip);
+        assert_eq!(state1.utxos().len(), state2.utxos().len());
+        assert_eq!(100, state2.utxos().balance("Bob"));
+
+        sim.do_now(ForSpecific(
+            node2,
+            BuildAndBroadcastTransaction::from("Bob", "Alice", 1),
+        ));
+        sim.do_now(ForSpecific(node2, MineBlock));
+        sim.catch_up(10.);
+        assert_eq!(1, get_state(&sim, node1).utxos().balance("Alice"));
     }
 
     #[wasm_bindgen_test]
-    fn in_perfect_case_all_stored_blocks_are_connected_to_genesis() {
+    fn pause_when_fork_occurs() {
         let mut sim = Simulation::new();
         sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        let i = sim.add_event_handler(Breakpoints::new().with(ForkOccurred::new()));
 
-        sim.do_now(SpawnRandomNodes(8));
-        sim.do_now(MakeDelaunayNetwork);
-
-        sim.catch_up(1.);
-
-        for _ in 0..20 {
-            sim.do_now(ForRandomNode(MineBlock));
-            sim.catch_up(100.);
-        }
-
-        let tested_node = sim.pick_random_node().unwrap();
-        let state = get_state(&sim, tested_node);
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
 
-        let mut remaining_blocks = state.known_blocks.clone();
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
+        assert!(!sim.time.paused());
 
-        let mut queue = vec![state.tip];
-        queue.extend(state.fork_tips.clone().into_iter().map(Some));
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.do_now(ForSpecific(node2, MineBlock));
+        sim.catch_up(100.);
+        assert!(sim.time.paused());
 
-        while !queue.is_empty() {
-            let block_id = queue.pop().unwrap();
-            if let Some(block_id) = block_id {
-                if !state.known_blocks.contains_key(&block_id) {
-                    panic!("Block not connected to genesis hash!");
-                }
-                if let Entry::Occupied(block_entry) = remaining_blocks.entry(block_id) {
-                    queue.push(block_entry.get().id_prev);
-                    block_entry.remove();
-                }
-            }
-        }
-        assert!(remaining_blocks.is_empty());
+        let handlers = sim.additional_event_handlers();
+        let handlers = handlers.borrow();
+        let hit = handlers.get::<Breakpoints>(i).unwrap().last_hit().unwrap();
+        assert!(hit.reason.starts_with("A fork occurred at height 2"));
     }
 
     #[wasm_bindgen_test]
-    fn nakamoto_consensus_recovers_from_splits() {
+    fn fork_detection_forgets_buried_blocks() {
         let mut sim = Simulation::new();
         sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
-
         let node1 = sim.spawn_random_node();
         let node2 = sim.spawn_random_node();
+        sim.add_peer(node1, node2);
+        sim.add_peer(node2, node1);
+        let mut fork_occurred = ForkOccurred::new().with_finality_depth(2);
+        let event = Event::Node(node1, NodeEvent::Poke);
 
-        sim.do_now(ForSpecific(node1, MineBlock));
-        sim.do_now(ForSpecific(node1, MineBlock));
-        sim.do_now(ForSpecific(node1, MineBlock));
+        for _ in 0..10 {
+            sim.do_now(ForSpecific(node1, MineBlock));
+            sim.catch_up(100.);
+            assert_eq!(None, fork_occurred.check(&sim, event));
+        }
+        assert_eq!(8, fork_occurred.min_height);
+        assert_eq!(3, fork_occurred.seen_blocks.len());
+        assert_eq!(3, fork_occurred.first_children.len());
 
+        sim.do_now(ForSpecific(node1, MineBlock));
         sim.do_now(ForSpecific(node2, MineBlock));
-        sim.do_now(ForSpecific(node2, MineBlock));
+        sim.catch_up(100.);
+        let reason = fork_occurred.check(&sim, event).unwrap();
+        assert!(reason.starts_with("A fork occurred at height 11"));
+    }
 
-        sim.catch_up(10.);
+    #[wasm_bindgen_test]
+    fn pause_when_tip_of_node_changes() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
 
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
         sim.add_peer(node1, node2);
         sim.add_peer(node2, node1);
 
-        sim.catch_up(10.);
+        sim.add_event_handler(Breakpoints::new().with(TipOfNodeChanged::new(node2)));
 
-        let state1 = get_state(&sim, node1);
-        let state2 = get_state(&sim, node2);
+        sim.do_now(ForSpecific(node1, MineBlock));
+        sim.catch_up(100.);
 
-        assert_eq!(state1.height(state1.tip), state2.height(state2.tip));
-        assert_eq!(state1.tip, state2.tip);
+        assert!(sim.time.paused());
+        assert_eq!(get_state(&sim, node1).tip, get_state(&sim, node2).tip);
     }
 }
@@ -0,0 +1,924 @@
+use super::*;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// How long replicas wait for a request to get executed before they suspect the primary.
+pub const DEFAULT_REQUEST_TIMEOUT: f64 = 2.;
+
+/// Clients number their requests. The paper uses timestamps, which works the same.
+pub type RequestId = (Entity, u64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PbftRequest {
+    pub client: Entity,
+    pub number: u64,
+    pub operation: String,
+}
+impl PbftRequest {
+    pub fn id(&self) -> RequestId {
+        (self.client, self.number)
+    }
+}
+
+/// A request that the primary assigned a sequence number to. `None` stands for a *null request*
+/// that fills a gap in the sequence numbers after a view change and doesn't do anything.
+pub type Proposal = (usize, Option<PbftRequest>);
+/// A replica's last executed sequence number and its prepared proposals, by view.
+type ViewChangeVote = (usize, Vec<(usize, Proposal)>);
+
+#[derive(Debug, Clone)]
+pub enum PbftMessage {
+    Request(PbftRequest),
+    PrePrepare {
+        view: usize,
+        sequence: usize,
+        request: Option<PbftRequest>,
+    },
+    Prepare {
+        view: usize,
+        sequence: usize,
+        request: Option<RequestId>,
+    },
+    Commit {
+        view: usize,
+        sequence: usize,
+        request: Option<RequestId>,
+    },
+    Reply {
+        view: usize,
+        request: RequestId,
+        /// How many requests the replica has executed, including this one.
+        result: usize,
+    },
+    ViewChange {
+        view: usize,
+        last_executed: usize,
+        /// All proposals that the replica has prepared, with the views they were prepared in.
+        prepared: Vec<(usize, Proposal)>,
+    },
+    NewView {
+        view: usize,
+        pre_prepares: Vec<Proposal>,
+    },
+    /// Timers of replicas. Outdated if the replica has started another timer since.
+    ReplicaTimeout {
+        generation: usize,
+    },
+    /// Timers of clients, for retransmitting requests.
+    ClientTimeout {
+        number: u64,
+    },
+}
+impl PbftMessage {
+    /// The phase of the protocol that the message belongs to, e.g., for `NetView`.
+    pub fn phase(&self) -> &'static str {
+        match self {
+            PbftMessage::Request(_) => "request",
+            PbftMessage::PrePrepare { .. } => "pre-prepare",
+            PbftMessage::Prepare { .. } => "prepare",
+            PbftMessage::Commit { .. } => "commit",
+            PbftMessage::Reply { .. } => "reply",
+            PbftMessage::ViewChange { .. } => "view-change",
+            PbftMessage::NewView { .. } => "new-view",
+            PbftMessage::ReplicaTimeout { .. } | PbftMessage::ClientTimeout { .. } => "timeout",
+        }
+    }
+}
+
+/// Lets a client send a request to the replicas (see `Pbft`).
+#[derive(Debug, Clone)]
+pub struct SendPbftRequest(pub String);
+impl EntityAction for SendPbftRequest {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let mut node = sim.node_interface(entity);
+        node.get::<PbftClientState>()
+            .queue
+            .push_back(self.0.clone());
+        // clients send the next request when poked
+        PokeNode.execute_for(sim, entity)
+    }
+}
+
+/// Makes a replica stop doing anything, or lets it resume. Crashing the primary is the easiest
+/// way to make the other replicas change the view.
+#[derive(Debug, Clone, Copy)]
+pub struct SetCrashed(pub bool);
+impl EntityAction for SetCrashed {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let mut node = sim.node_interface(entity);
+        let state = node.get::<PbftReplicaState>();
+        state.crashed = self.0;
+        // crashed replicas ignore their timers, so they would never start one again
+        state.stop_timer();
+        node.log(if self.0 { "Crashed." } else { "Recovered." });
+        Ok(())
+    }
+}
+
+/// Practical Byzantine Fault Tolerance, as proposed by Castro and Liskov (1999). Unlike Nakamoto
+/// consensus, PBFT needs a fixed set of `replicas` that everybody knows - it's *permissioned*. Of
+/// `3f + 1` replicas, up to `f` may be faulty. All other nodes are clients that send requests.
+///
+/// In each *view*, one replica is the *primary*. It assigns sequence numbers to requests and
+/// announces them with a pre-prepare message. The other replicas (*backups*) agree on the
+/// order in two more phases, prepare and commit, each of which needs a quorum of `2f + 1`
+/// replicas. Then all replicas execute the request and reply to the client, which waits for
+/// `f + 1` matching replies. If a request doesn't get executed in time, the backups suspect the
+/// primary and move to the next view, whose primary is the next replica.
+///
+/// Simplifications: Replicas trust the sender of a message (there's nobody to forge them), and
+/// they neither take checkpoints nor garbage-collect their logs.
+#[derive(Debug, Clone)]
+pub struct Pbft {
+    replicas: Vec<Entity>,
+    request_timeout: SimSeconds,
+}
+impl Pbft {
+    pub fn new(replicas: Vec<Entity>) -> Self {
+        Self {
+            replicas,
+            request_timeout: SimSeconds::from(DEFAULT_REQUEST_TIMEOUT),
+        }
+    }
+    pub fn with_request_timeout(mut self, request_timeout: SimSeconds) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+    pub fn replicas(&self) -> &[Entity] {
+        &self.replicas
+    }
+    /// How many faulty replicas the protocol tolerates.
+    pub fn max_faulty(&self) -> usize {
+        self.replicas.len().saturating_sub(1) / 3
+    }
+    fn quorum(&self) -> usize {
+        2 * self.max_faulty() + 1
+    }
+    pub fn primary(&self, view: usize) -> Option<Entity> {
+        if self.replicas.is_empty() {
+            None
+        } else {
+            Some(self.replicas[view % self.replicas.len()])
+        }
+    }
+    fn is_primary(&self, node: &NodeInterface, view: usize) -> bool {
+        self.primary(view) == Some(node.id())
+    }
+    /// Sends the message to all other replicas.
+    fn broadcast(&self, node: &mut NodeInterface, message: PbftMessage) {
+        for &replica in &self.replicas {
+            if replica != node.id() {
+                node.send_message(replica, message.clone());
+            }
+        }
+    }
+
+    fn handle_request(&self, node: &mut NodeInterface, sender: Entity, request: PbftRequest) {
+        let state = node.get::<PbftReplicaState>();
+        let view = state.view;
+        if let Some(&result) = state.results.get(&request.id()) {
+            // the reply must have gotten lost or been too slow
+            let reply = PbftMessage::Reply {
+                view,
+                request: request.id(),
+                result,
+            };
+            node.send_message(request.client, reply);
+            return;
+        }
+        state.pending.insert(request.id(), request.clone());
+        let view_changing = state.view_changing;
+        if self.is_primary(node, view) {
+            if !view_changing {
+                self.assign_sequence(node, request);
+            }
+            return;
+        }
+        if sender == request.client && !view_changing {
+            if let Some(primary) = self.primary(view) {
+                node.send_message(primary, PbftMessage::Request(request));
+            }
+        }
+        if !node.get::<PbftReplicaState>().timer_running {
+            self.start_timer(node);
+        }
+    }
+    fn assign_sequence(&self, node: &mut NodeInterface, request: PbftRequest) {
+        let state = node.get::<PbftReplicaState>();
+        let view = state.view;
+        let already_assigned = state
+            .log
+            .values()
+            .any(|entry| entry.view == view && entry.request_id() == Some(request.id()));
+        if already_assigned {
+            return;
+        }
+        let sequence = state.next_sequence.max(state.last_executed + 1);
+        state.next_sequence = sequence + 1;
+        let entry = LogEntry {
+            view,
+            pre_prepared: true,
+            request: Some(request.clone()),
+            ..Default::default()
+        };
+        state.log.insert(sequence, entry);
+        node.log(&format!(
+            "Assigned sequence number {} to \"{}\".",
+            sequence, request.operation
+        ));
+        let pre_prepare = PbftMessage::PrePrepare {
+            view,
+            sequence,
+            request: Some(request),
+        };
+        self.broadcast(node, pre_prepare);
+    }
+    /// Accepts the primary's proposal for `sequence`, unless we accepted a different one for the
+    /// same view already.
+    fn accept_pre_prepare(
+        &self,
+        node: &mut NodeInterface,
+        view: usize,
+        sequence: usize,
+        request: Option<PbftRequest>,
+    ) {
+        let own_id = node.id();
+        let state = node.get::<PbftReplicaState>();
+        if sequence <= state.last_executed {
+            return;
+        }
+        let request_id = request.as_ref().map(PbftRequest::id);
+        let entry = state.entry(view, sequence);
+        if entry.pre_prepared && entry.request_id() != request_id {
+            return;
+        }
+        entry.pre_prepared = true;
+        entry.request = request.clone();
+        entry.prepares.insert(own_id, request_id);
+        if let Some(request) = request {
+            if !state.results.contains_key(&request.id()) {
+                state.pending.insert(request.id(), request);
+            }
+        }
+        let prepare = PbftMessage::Prepare {
+            view,
+            sequence,
+            request: request_id,
+        };
+        self.broadcast(node, prepare);
+        if !node.get::<PbftReplicaState>().timer_running {
+            self.start_timer(node);
+        }
+        self.check_progress(node, sequence);
+    }
+    /// Sends our commit once the proposal is prepared, and executes what we can.
+    fn check_progress(&self, node: &mut NodeInterface, sequence: usize) {
+        let own_id = node.id();
+        let quorum = self.quorum();
+        let state = node.get::<PbftReplicaState>();
+        let view = state.view;
+        let commit = match state.log.get_mut(&sequence) {
+            Some(entry)
+                if entry.view == view && entry.is_prepared(quorum) && !entry.sent_commit =>
+            {
+                entry.sent_commit = true;
+                entry.commits.insert(own_id, entry.request_id());
+                Some(PbftMessage::Commit {
+                    view: entry.view,
+                    sequence,
+                    request: entry.request_id(),
+                })
+            }
+            _ => None,
+        };
+        if let Some(commit) = commit {
+            self.broadcast(node, commit);
+        }
+        self.execute_committed(node);
+    }
+    fn execute_committed(&self, node: &mut NodeInterface) {
+        let quorum = self.quorum();
+        let mut executed_any = false;
+        loop {
+            let state = node.get::<PbftReplicaState>();
+            let sequence = state.last_executed + 1;
+            let request = match state.log.get(&sequence) {
+                Some(entry) if entry.is_committed(quorum) => entry.request.clone(),
+                _ => break,
+            };
+            state.last_executed = sequence;
+            executed_any = true;
+            let request = match request {
+                Some(request) if !state.results.contains_key(&request.id()) => request,
+                // null requests and requests that got assigned two sequence numbers
+                _ => continue,
+            };
+            state.executed.push(request.clone());
+            let result = state.executed.len();
+            state.results.insert(request.id(), result);
+            state.pending.remove(&request.id());
+            let view = state.view;
+            node.log(&format!(
+                "Executed \"{}\" (sequence number {}).",
+                request.operation, sequence
+            ));
+            let reply = PbftMessage::Reply {
+                view,
+                request: request.id(),
+                result,
+            };
+            node.send_message(request.client, reply);
+        }
+        let state = node.get::<PbftReplicaState>();
+        if executed_any && !state.view_changing {
+            // the primary is doing its job
+            if state.pending.is_empty() {
+                state.stop_timer();
+            } else {
+                self.start_timer(node);
+            }
+        }
+    }
+    /// Nobody watches the primary but the backups, unless the view is changing.
+    fn start_timer(&self, node: &mut NodeInterface) {
+        let view = node.get::<PbftReplicaState>().view;
+        let is_primary = self.is_primary(node, view);
+        let state = node.get::<PbftReplicaState>();
+        state.stop_timer();
+        if is_primary && !state.view_changing {
+            return;
+        }
+        state.timer_running = true;
+        let generation = state.timer_generation;
+        // the paper doubles the timeout with each failed view change, which lets replicas that
+        // started out in different views meet eventually
+        let timeout = self.request_timeout * 2_f64.powi(state.failed_view_changes as i32);
+        node.set_timer(timeout, PbftMessage::ReplicaTimeout { generation });
+    }
+    fn handle_replica_timeout(&self, node: &mut NodeInterface, generation: usize) {
+        let state = node.get::<PbftReplicaState>();
+        if generation != state.timer_generation {
+            return;
+        }
+        state.timer_running = false;
+        // waiting for a new view that doesn't come also counts as a failure
+        if state.view_changing || !state.pending.is_empty() {
+            let view = state.view + 1;
+            self.start_view_change(node, view);
+        }
+    }
+    fn start_view_change(&self, node: &mut NodeInterface, view: usize) {
+        let own_id = node.id();
+        let quorum = self.quorum();
+        let state = node.get::<PbftReplicaState>();
+        if state.view_changing {
+            state.failed_view_changes += 1;
+        }
+        state.view = view;
+        state.view_changing = true;
+        let last_executed = state.last_executed;
+        let prepared: Vec<(usize, Proposal)> = state
+            .log
+            .iter()
+            .filter(|(_, entry)| entry.is_prepared(quorum))
+            .map(|(&sequence, entry)| (entry.view, (sequence, entry.request.clone())))
+            .collect();
+        state
+            .view_changes
+            .entry(view)
+            .or_default()
+            .insert(own_id, (last_executed, prepared.clone()));
+        node.log(&format!("Suspecting the primary, moving to view {}.", view));
+        let view_change = PbftMessage::ViewChange {
+            view,
+            last_executed,
+            prepared,
+        };
+        self.broadcast(node, view_change);
+        self.start_timer(node);
+        self.check_new_view(node, view);
+    }
+    fn handle_view_change(
+        &self,
+        node: &mut NodeInterface,
+        sender: Entity,
+        view: usize,
+        last_executed: usize,
+        prepared: Vec<(usize, Proposal)>,
+    ) {
+        let state = node.get::<PbftReplicaState>();
+        let current_view = state.view;
+        if view < current_view || (view == current_view && !state.view_changing) {
+            return;
+        }
+        let senders = state.view_changes.entry(view).or_default();
+        senders.insert(sender, (last_executed, prepared));
+        // if f + 1 replicas suspect the primary, at least one correct replica does
+        let suspicious = senders.len() > self.max_faulty();
+        if view > current_view && suspicious {
+            self.start_view_change(node, view);
+        } else {
+            self.check_new_view(node, view);
+        }
+    }
+    /// If we are the primary of `view` and a quorum wants to move there, we start it.
+    fn check_new_view(&self, node: &mut NodeInterface, view: usize) {
+        let quorum = self.quorum();
+        if !self.is_primary(node, view) {
+            return;
+        }
+        let state = node.get::<PbftReplicaState>();
+        if state.view != view || !state.view_changing {
+            return;
+        }
+        let view_changes = match state.view_changes.get(&view) {
+            Some(view_changes) if view_changes.len() >= quorum => view_changes,
+            _ => return,
+        };
+        // every proposal that might have been executed somewhere was prepared by at least one
+        // replica of the quorum; if there are several, the most recent one wins
+        let first_sequence = view_changes
+            .values()
+            .map(|(last_executed, _)| last_executed + 1)
+            .min()
+            .unwrap_or(1);
+        let mut proposals: BTreeMap<usize, (usize, Option<PbftRequest>)> = BTreeMap::new();
+        for (_, prepared) in view_changes.values() {
+            for (prepared_view, (sequence, request)) in prepared {
+                if *sequence < first_sequence {
+                    continue;
+                }
+                let newer = proposals
+                    .get(sequence)
+                    .is_none_or(|(known_view, _)| prepared_view > known_view);
+                if newer {
+                    proposals.insert(*sequence, (*prepared_view, request.clone()));
+                }
+            }
+        }
+        let last_sequence = proposals.keys().next_back().copied().unwrap_or(0);
+        let pre_prepares: Vec<Proposal> = (first_sequence..=last_sequence)
+            .map(|sequence| {
+                let request = proposals.remove(&sequence).and_then(|(_, request)| request);
+                (sequence, request)
+            })
+            .collect();
+        state.next_sequence = last_sequence + 1;
+        node.log(&format!("Starting view {} as the new primary.", view));
+        let new_view = PbftMessage::NewView {
+            view,
+            pre_prepares: pre_prepares.clone(),
+        };
+        self.broadcast(node, new_view);
+        self.enter_view(node, view, pre_prepares);
+        let pending: Vec<PbftRequest> = node
+            .get::<PbftReplicaState>()
+            .pending
+            .values()
+            .cloned()
+            .collect();
+        for request in pending {
+            self.assign_sequence(node, request);
+        }
+    }
+    fn enter_view(&self, node: &mut NodeInterface, view: usize, pre_prepares: Vec<Proposal>) {
+        let is_primary = self.is_primary(node, view);
+        let state = node.get::<PbftReplicaState>();
+        state.view = view;
+        state.view_changing = false;
+        state.failed_view_changes = 0;
+        state.stop_timer();
+        for (sequence, request) in pre_prepares {
+            if !is_primary {
+                self.accept_pre_prepare(node, view, sequence, request);
+                continue;
+            }
+            let state = node.get::<PbftReplicaState>();
+            if sequence > state.last_executed {
+                let entry = state.entry(view, sequence);
+                entry.pre_prepared = true;
+                entry.request = request;
+            }
+        }
+        node.log(&format!("Entered view {}.", view));
+        let state = node.get::<PbftReplicaState>();
+        if !state.pending.is_empty() && !state.timer_running {
+            self.start_timer(node);
+        }
+        // prepares and commits for the new view might have arrived before the new view
+        let sequences: Vec<usize> = node
+            .get::<PbftReplicaState>()
+            .log
+            .iter()
+            .filter(|(_, entry)| entry.view == view)
+            .map(|(&sequence, _)| sequence)
+            .collect();
+        for sequence in sequences {
+            self.check_progress(node, sequence);
+        }
+    }
+
+    fn send_next_request(&self, node: &mut NodeInterface) {
+        let client = node.id();
+        let state = node.get::<PbftClientState>();
+        if state.outstanding.is_some() {
+            return;
+        }
+        let operation = match state.queue.pop_front() {
+            Some(operation) => operation,
+            None => return,
+        };
+        state.next_number += 1;
+        let request = PbftRequest {
+            client,
+            number: state.next_number,
+            operation,
+        };
+        state.outstanding = Some(request.clone());
+        state.replies.clear();
+        let view = state.view;
+        if let Some(primary) = self.primary(view) {
+            node.send_message(primary, PbftMessage::Request(request.clone()));
+        }
+        node.set_timer(
+            self.request_timeout,
+            PbftMessage::ClientTimeout {
+                number: request.number,
+            },
+        );
+    }
+    fn handle_reply(
+        &self,
+        node: &mut NodeInterface,
+        replica: Entity,
+        view: usize,
+        request_id: RequestId,
+        result: usize,
+    ) {
+        let state = node.get::<PbftClientState>();
+        let request = match &state.outstanding {
+            Some(request) if request.id() == request_id => request.clone(),
+            _ => return,
+        };
+        state.view = state.view.max(view);
+        state.replies.insert(replica, result);
+        let matching_replies = state.replies.values().filter(|&&r| r == result).count();
+        // at least one of them comes from a correct replica
+        if matching_replies > self.max_faulty() {
+            state.outstanding = None;
+            state.completed.push((request.clone(), result));
+            node.log(&format!("\"{}\" is done.", request.operation));
+            self.send_next_request(node);
+        }
+    }
+    fn handle_client_timeout(&self, node: &mut NodeInterface, number: u64) {
+        let request = match &node.get::<PbftClientState>().outstanding {
+            Some(request) if request.number == number => request.clone(),
+            _ => return,
+        };
+        // maybe the primary is faulty, let's ask everybody
+        for &replica in &self.replicas {
+            node.send_message(replica, PbftMessage::Request(request.clone()));
+        }
+        node.set_timer(self.request_timeout, PbftMessage::ClientTimeout { number });
+    }
+}
+
+impl Protocol for Pbft {
+    type MessagePayload = PbftMessage;
+
+    fn handle_message(
+        &self,
+        mut node: NodeInterface,
+        underlay_message: UnderlayMessage,
+        message_payload: Self::MessagePayload,
+    ) -> Result<(), Box<dyn Error>> {
+        let sender = underlay_message.source;
+        if !self.replicas.contains(&node.id()) {
+            if let PbftMessage::Reply {
+                view,
+                request,
+                result,
+            } = message_payload
+            {
+                self.handle_reply(&mut node, sender, view, request, result);
+            }
+            return Ok(());
+        }
+        if node.get::<PbftReplicaState>().crashed {
+            return Ok(());
+        }
+        let state = node.get::<PbftReplicaState>();
+        let current_view = state.view;
+        let view_changing = state.view_changing;
+        match message_payload {
+            PbftMessage::Request(request) => self.handle_request(&mut node, sender, request),
+            PbftMessage::PrePrepare {
+                view,
+                sequence,
+                request,
+            } => {
+                if view == current_view && !view_changing && self.primary(view) == Some(sender) {
+                    self.accept_pre_prepare(&mut node, view, sequence, request);
+                }
+            }
+            PbftMessage::Prepare {
+                view,
+                sequence,
+                request,
+            } => {
+                if view == current_view && self.primary(view) != Some(sender) {
+                    let entry = node.get::<PbftReplicaState>().entry(view, sequence);
+                    entry.prepares.insert(sender, request);
+                    self.check_progress(&mut node, sequence);
+                }
+            }
+            PbftMessage::Commit {
+                view,
+                sequence,
+                request,
+            } => {
+                if view == current_view {
+                    let entry = node.get::<PbftReplicaState>().entry(view, sequence);
+                    entry.commits.insert(sender, request);
+                    self.check_progress(&mut node, sequence);
+                }
+            }
+            PbftMessage::ViewChange {
+                view,
+                last_executed,
+                prepared,
+            } => self.handle_view_change(&mut node, sender, view, last_executed, prepared),
+            PbftMessage::NewView { view, pre_prepares } => {
+                let newer = view > current_view || (view == current_view && view_changing);
+                if newer && self.primary(view) == Some(sender) {
+                    self.enter_view(&mut node, view, pre_prepares);
+                }
+            }
+            PbftMessage::Reply { .. }
+            | PbftMessage::ReplicaTimeout { .. }
+            | PbftMessage::ClientTimeout { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn handle_timer(
+        &self,
+        mut node: NodeInterface,
+        timer_payload: Self::MessagePayload,
+    ) -> Result<(), Box<dyn Error>> {
+        match timer_payload {
+            PbftMessage::ReplicaTimeout { generation }
+                if !node.get::<PbftReplicaState>().crashed =>
+            {
+                self.handle_replica_timeout(&mut node, generation);
+            }
+            PbftMessage::ClientTimeout { number } => self.handle_client_timeout(&mut node, number),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Clients send their next request, if they have one (see `SendPbftRequest`).
+    fn handle_poke(&self, mut node: NodeInterface) -> Result<(), Box<dyn Error>> {
+        if !self.replicas.contains(&node.id()) {
+            self.send_next_request(&mut node);
+        }
+        Ok(())
+    }
+}
+
+/// What a replica knows about one sequence number.
+#[derive(Debug, Clone, Default)]
+struct LogEntry {
+    view: usize,
+    /// Whether we know the primary's proposal for this view.
+    pre_prepared: bool,
+    request: Option<PbftRequest>,
+    prepares: HashMap<Entity, Option<RequestId>>,
+    commits: HashMap<Entity, Option<RequestId>>,
+    sent_commit: bool,
+}
+impl LogEntry {
+    fn request_id(&self) -> Option<RequestId> {
+        self.request.as_ref().map(PbftRequest::id)
+    }
+    fn votes(&self, votes: &HashMap<Entity, Option<RequestId>>) -> usize {
+        let request_id = self.request_id();
+        votes.values().filter(|&&vote| vote == request_id).count()
+    }
+    /// The primary doesn't send a prepare, so `2f` prepares from backups are enough.
+    fn is_prepared(&self, quorum: usize) -> bool {
+        self.pre_prepared && self.votes(&self.prepares) + 1 >= quorum
+    }
+    fn is_committed(&self, quorum: usize) -> bool {
+        self.is_prepared(quorum) && self.votes(&self.commits) >= quorum
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PbftReplicaState {
+    view: usize,
+    /// Whether we are waiting for the primary of `view` to start it.
+    view_changing: bool,
+    /// The next sequence number that we assign as the primary.
+    next_sequence: usize,
+    log: BTreeMap<usize, LogEntry>,
+    last_executed: usize,
+    /// The replicated state: all requests, in the order of execution.
+    executed: Vec<PbftRequest>,
+    results: HashMap<RequestId, usize>,
+    /// Requests that we know about but haven't executed yet.
+    pending: HashMap<RequestId, PbftRequest>,
+    /// By view, who wants to move there, with their last executed sequence number and prepared
+    /// proposals.
+    view_changes: BTreeMap<usize, HashMap<Entity, ViewChangeVote>>,
+    timer_generation: usize,
+    timer_running: bool,
+    /// View changes since we were last in a view that worked.
+    failed_view_changes: u32,
+    crashed: bool,
+}
+impl PbftReplicaState {
+    /// The entry for `sequence`, forgetting what we know about earlier views.
+    fn entry(&mut self, view: usize, sequence: usize) -> &mut LogEntry {
+        let entry = self.log.entry(sequence).or_default();
+        if entry.view != view {
+            *entry = LogEntry {
+                view,
+                ..Default::default()
+            };
+        }
+        entry
+    }
+    fn stop_timer(&mut self) {
+        self.timer_generation += 1;
+        self.timer_running = false;
+    }
+    pub fn view(&self) -> usize {
+        self.view
+    }
+    pub fn is_view_changing(&self) -> bool {
+        self.view_changing
+    }
+    pub fn executed(&self) -> &[PbftRequest] {
+        &self.executed
+    }
+    pub fn last_executed(&self) -> usize {
+        self.last_executed
+    }
+    pub fn is_crashed(&self) -> bool {
+        self.crashed
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PbftClientState {
+    /// Operations that we haven't requested yet.
+    queue: VecDeque<String>,
+    next_number: u64,
+    outstanding: Option<PbftRequest>,
+    /// Results of `outstanding`, by replica.
+    replies: HashMap<Entity, usize>,
+    /// The latest view that a replica told us about.
+    view: usize,
+    completed: Vec<(PbftRequest, usize)>,
+}
+impl PbftClientState {
+    pub fn outstanding(&self) -> Option<&PbftRequest> {
+        self.outstanding.as_ref()
+    }
+    /// Requests that the replicas executed, with their results.
+    pub fn completed(&self) -> &[(PbftRequest, usize)] {
+        &self.completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Four replicas (tolerating one fault) and a client.
+    fn pbft_sim() -> (Simulation, Vec<Entity>, Entity) {
+        let mut sim = Simulation::new();
+        let replicas: Vec<Entity> = (0..4).map(|_| sim.spawn_random_node()).collect();
+        let client = sim.spawn_random_node();
+        sim.add_event_handler(InvokeProtocolForAllNodes(Pbft::new(replicas.clone())));
+        (sim, replicas, client)
+    }
+
+    fn replica_state(sim: &Simulation, replica: Entity) -> PbftReplicaState {
+        (*sim.world.get::<PbftReplicaState>(replica).unwrap()).clone()
+    }
+
+    fn client_state(sim: &Simulation, client: Entity) -> PbftClientState {
+        (*sim.world.get::<PbftClientState>(client).unwrap()).clone()
+    }
+
+    #[wasm_bindgen_test]
+    fn replicas_execute_requests_in_the_same_order() {
+        let (mut sim, replicas, client) = pbft_sim();
+        let other_client = sim.spawn_random_node();
+        for operation in ["a", "b", "c"] {
+            sim.do_now(ForSpecific(client, SendPbftRequest(operation.to_string())));
+            sim.do_now(ForSpecific(
+                other_client,
+                SendPbftRequest(operation.to_string()),
+            ));
+        }
+        sim.work_until(SimSeconds::from(10.));
+
+        let executed = replica_state(&sim, replicas[0]).executed().to_vec();
+        assert_eq!(6, executed.len());
+        for &replica in &replicas {
+            let state = replica_state(&sim, replica);
+            assert_eq!(executed, state.executed());
+            assert_eq!(0, state.view());
+        }
+        let completed = client_state(&sim, client).completed().to_vec();
+        let operations: Vec<&str> = completed
+            .iter()
+            .map(|(request, _)| request.operation.as_str())
+            .collect();
+        assert_eq!(vec!["a", "b", "c"], operations);
+        assert!(client_state(&sim, client).outstanding().is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn one_crashed_backup_doesnt_stop_progress() {
+        let (mut sim, replicas, client) = pbft_sim();
+        sim.do_now(ForSpecific(replicas[3], SetCrashed(true)));
+        sim.do_now(ForSpecific(client, SendPbftRequest("a".to_string())));
+        sim.work_until(SimSeconds::from(1.));
+        assert_eq!(1, client_state(&sim, client).completed().len());
+        assert!(replica_state(&sim, replicas[3]).executed().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn backups_replace_a_crashed_primary() {
+        let (mut sim, replicas, client) = pbft_sim();
+        sim.do_now(ForSpecific(client, SendPbftRequest("a".to_string())));
+        sim.work_until(SimSeconds::from(1.));
+        sim.do_now(ForSpecific(replicas[0], SetCrashed(true)));
+        sim.do_now(ForSpecific(client, SendPbftRequest("b".to_string())));
+        sim.work_until(SimSeconds::from(1.5));
+        assert_eq!(1, client_state(&sim, client).completed().len());
+
+        // the client retransmits to all replicas, the backups time out and change the view
+        sim.work_until(SimSeconds::from(20.));
+        assert_eq!(2, client_state(&sim, client).completed().len());
+        for &replica in &replicas[1..] {
+            let state = replica_state(&sim, replica);
+            assert_eq!(1, state.view());
+            assert!(!state.is_view_changing());
+            let operations: Vec<&str> = state
+                .executed()
+                .iter()
+                .map(|request| request.operation.as_str())
+                .collect();
+            assert_eq!(vec!["a", "b"], operations);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn recovered_replicas_suspect_a_faulty_primary_again() {
+        let (mut sim, replicas, client) = pbft_sim();
+        sim.do_now(ForSpecific(replicas[0], SetCrashed(true)));
+        sim.do_now(ForSpecific(client, SendPbftRequest("a".to_string())));
+        // the client retransmits to all replicas, so the backups start their timers
+        sim.work_until(SimSeconds::from(3.));
+        for &replica in &replicas[2..] {
+            assert!(replica_state(&sim, replica).timer_running);
+            sim.do_now(ForSpecific(replica, SetCrashed(true)));
+        }
+        // their timers go off while they are crashed
+        sim.work_until(SimSeconds::from(6.));
+        for &replica in &replicas[2..] {
+            sim.do_now(ForSpecific(replica, SetCrashed(false)));
+        }
+        assert!(client_state(&sim, client).completed().is_empty());
+
+        sim.work_until(SimSeconds::from(60.));
+        assert_eq!(1, client_state(&sim, client).completed().len());
+        for &replica in &replicas[1..] {
+            assert_ne!(0, replica_state(&sim, replica).view());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn crashing_two_of_four_replicas_stops_progress() {
+        let (mut sim, replicas, client) = pbft_sim();
+        sim.do_now(ForSpecific(replicas[2], SetCrashed(true)));
+        sim.do_now(ForSpecific(replicas[3], SetCrashed(true)));
+        sim.do_now(ForSpecific(client, SendPbftRequest("a".to_string())));
+        sim.work_until(SimSeconds::from(20.));
+        assert!(client_state(&sim, client).completed().is_empty());
+
+        // but safety holds, and things continue once enough replicas are back
+        sim.do_now(ForSpecific(replicas[3], SetCrashed(false)));
+        sim.work_until(SimSeconds::from(60.));
+        assert_eq!(1, client_state(&sim, client).completed().len());
+    }
+}
@@ -0,0 +1,202 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// How many addresses a node remembers at most. Bitcoin Core's address manager holds tens of
+/// thousands, but our networks are tiny.
+pub const ADDRESS_BOOK_CAPACITY: usize = 64;
+/// How many addresses a node sends in reply to a `GetAddr` at most.
+pub const MAX_ADDRESSES_PER_MESSAGE: usize = 8;
+
+/// Drops all of a node's connections and opens new ones to the `n` addresses it heard about most
+/// recently, roughly what a Bitcoin node does after a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reconnect(pub usize);
+impl EntityAction for Reconnect {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let old_peers: Vec<Entity> = sim.peers_mut(entity).iter().copied().collect();
+        let mut node = sim.node_interface(entity);
+        let new_peers: Vec<Entity> = node
+            .get::<AddressBook>()
+            .most_recent(self.0 + 1)
+            .into_iter()
+            .filter(|&address| address != entity)
+            .take(self.0)
+            .collect();
+        for peer in old_peers {
+            node.disconnect(peer);
+        }
+        for &peer in new_peers.iter() {
+            node.connect(peer);
+        }
+        node.log(&format!(
+            "Reconnecting to the {} addresses I heard about last.",
+            new_peers.len()
+        ));
+        Ok(())
+    }
+}
+
+/// A node's view of which other nodes exist, see `PeerDiscovery`.
+#[derive(Debug, Clone, Default)]
+pub struct AddressBook {
+    /// When we last heard about each address.
+    addresses: BTreeMap<Entity, SimSeconds>,
+    /// If set, the node answers `GetAddr` with these addresses instead of ones from its book.
+    /// Honest nodes don't do that, of course.
+    advertised: Option<Vec<Entity>>,
+}
+impl AddressBook {
+    /// Adds an address or refreshes it. If the book is full, the address we heard about the
+    /// longest time ago makes room.
+    pub fn hear(&mut self, address: Entity, now: SimSeconds) {
+        self.addresses.insert(address, now);
+        if self.addresses.len() > ADDRESS_BOOK_CAPACITY {
+            let oldest = self
+                .addresses
+                .iter()
+                .min_by_key(|&(_, &heard)| heard)
+                .map(|(&address, _)| address)
+                .unwrap();
+            self.addresses.remove(&oldest);
+        }
+    }
+    pub fn contains(&self, address: Entity) -> bool {
+        self.addresses.contains_key(&address)
+    }
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.addresses.keys().copied()
+    }
+    /// Up to `n` addresses, the ones we heard about most recently first.
+    pub fn most_recent(&self, n: usize) -> Vec<Entity> {
+        let mut addresses: Vec<(Entity, SimSeconds)> =
+            self.addresses.iter().map(|(&a, &t)| (a, t)).collect();
+        addresses.sort_by(|(a1, t1), (a2, t2)| t2.cmp(t1).then(a1.cmp(a2)));
+        addresses.into_iter().take(n).map(|(a, _)| a).collect()
+    }
+    pub fn advertised(&self) -> Option<&Vec<Entity>> {
+        self.advertised.as_ref()
+    }
+    pub fn set_advertised(&mut self, advertised: Option<Vec<Entity>>) {
+        self.advertised = advertised;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryMessage {
+    GetAddr,
+    Addr(Vec<Entity>),
+}
+
+/// A simple take on Bitcoin's `getaddr`/`addr` gossip: Whenever a node gets a new peer, it asks
+/// it for addresses of other nodes and adds them to its `AddressBook`. Which nodes a node connects
+/// to is still up to whoever sends `AddPeer` or `Reconnect` commands.
+///
+/// Meant to run alongside another protocol, e.g., `NakamotoConsensus`.
+#[derive(Debug, Clone, Default)]
+pub struct PeerDiscovery;
+impl Protocol for PeerDiscovery {
+    type MessagePayload = DiscoveryMessage;
+
+    fn handle_message(
+        &self,
+        mut node: NodeInterface,
+        underlay_message: UnderlayMessage,
+        message_payload: Self::MessagePayload,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = node.now();
+        match message_payload {
+            DiscoveryMessage::GetAddr => {
+                let book = node.get::<AddressBook>();
+                let addresses = if let Some(advertised) = book.advertised() {
+                    advertised.clone()
+                } else {
+                    let known: Vec<Entity> = book
+                        .iter()
+                        .filter(|&address| address != underlay_message.source)
+                        .collect();
+                    let mut addresses: Vec<Entity> = known
+                        .choose_multiple(node.rng(), MAX_ADDRESSES_PER_MESSAGE - 1)
+                        .copied()
+                        .collect();
+                    addresses.push(underlay_message.dest);
+                    addresses
+                };
+                node.send_message(underlay_message.source, DiscoveryMessage::Addr(addresses));
+            }
+            DiscoveryMessage::Addr(addresses) => {
+                let book = node.get::<AddressBook>();
+                for address in addresses
+                    .into_iter()
+                    .take(MAX_ADDRESSES_PER_MESSAGE)
+                    .filter(|&address| address != underlay_message.dest)
+                {
+                    book.hear(address, now);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_poke(&self, _node: NodeInterface) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn handle_peer_set_update(
+        &self,
+        mut node: NodeInterface,
+        update: PeerSetUpdate,
+    ) -> Result<(), Box<dyn Error>> {
+        if let PeerSetUpdate::PeerAdded(peer) = update {
+            let now = node.now();
+            node.get::<AddressBook>().hear(peer, now);
+            node.send_message(peer, DiscoveryMessage::GetAddr);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn nodes_learn_about_peers_of_their_peers() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(PeerDiscovery));
+        let nodes: Vec<Entity> = (0..3).map(|_| sim.spawn_random_node()).collect();
+        for (a, b) in [(nodes[0], nodes[1]), (nodes[1], nodes[2])] {
+            sim.node_interface(a).connect(b);
+        }
+        sim.work_until(SimSeconds::from(10.));
+        // node 0 only ever got to know node 2 through node 1
+        let book = sim.world.get::<AddressBook>(nodes[0]).unwrap();
+        assert!(book.contains(nodes[1]));
+        assert!(book.contains(nodes[2]));
+        assert!(!book.contains(nodes[0]));
+    }
+
+    #[wasm_bindgen_test]
+    fn address_book_forgets_oldest_addresses() {
+        let mut world = World::new();
+        let mut book = AddressBook::default();
+        let first = world.spawn(());
+        book.hear(first, SimSeconds::from(0.));
+        let mut last = first;
+        for i in 0..ADDRESS_BOOK_CAPACITY {
+            last = world.spawn(());
+            book.hear(last, SimSeconds::from(1. + i as f64));
+        }
+        assert_eq!(ADDRESS_BOOK_CAPACITY, book.len());
+        assert!(!book.contains(first));
+        assert_eq!(vec![last], book.most_recent(1));
+    }
+}
@@ -0,0 +1,450 @@
+use super::*;
+use blockchain_types::*;
+use nakamoto_consensus::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use sybil_resistance::*;
+
+/// How far `ProofOfStake::next_slot_led_by` looks ahead. Nodes with less than a thousandth of the
+/// stake might not find their next slot, but they will once time goes on.
+const MAX_SLOTS_AHEAD: u64 = 10_000;
+
+/// Longest-chain Proof-of-Stake, roughly like the first version of Ouroboros: Time is divided into
+/// slots, and for each slot, one node gets picked at random to be the *slot leader*, with chances
+/// proportional to its stake. Only the slot leader may propose a block in that slot. Because
+/// everyone can check who the slot leader was, blocks don't need any work - and nodes without
+/// stake can't create valid blocks at all.
+///
+/// If a `finality_depth` is set, blocks that are buried that deep under a node's tip are final: The
+/// node never switches to a chain that doesn't contain them, no matter how long it is.
+///
+/// Configure nodes with `NakamotoConsensus::with_sybil_resistance`, and add `Staking` to let slot
+/// leaders actually propose their blocks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofOfStake {
+    slot_duration: SimSeconds,
+    /// Real systems derive the stakes from the coins on the chain and fix them for each epoch. Here,
+    /// they are simply part of the consensus rules.
+    stakes: BTreeMap<Entity, f64>,
+    /// Stands in for the randomness beacon of real systems, which nobody can predict or bias.
+    seed: u64,
+    finality_depth: Option<usize>,
+}
+impl ProofOfStake {
+    pub fn new(slot_duration: SimSeconds) -> Self {
+        Self {
+            slot_duration,
+            stakes: BTreeMap::new(),
+            seed: 0,
+            finality_depth: None,
+        }
+    }
+    pub fn with_stake(mut self, node: Entity, stake: f64) -> Self {
+        if stake > 0. {
+            self.stakes.insert(node, stake);
+        } else {
+            self.stakes.remove(&node);
+        }
+        self
+    }
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    pub fn with_finality_depth(mut self, finality_depth: usize) -> Self {
+        self.finality_depth = Some(finality_depth);
+        self
+    }
+    pub fn slot_duration(&self) -> SimSeconds {
+        self.slot_duration
+    }
+    pub fn stakes(&self) -> &BTreeMap<Entity, f64> {
+        &self.stakes
+    }
+    pub fn stake_of(&self, node: Entity) -> f64 {
+        self.stakes.get(&node).copied().unwrap_or_default()
+    }
+    pub fn total_stake(&self) -> f64 {
+        self.stakes.values().sum()
+    }
+    pub fn slot_at(&self, time: SimSeconds) -> u64 {
+        let slot = (time / self.slot_duration).floor() as u64;
+        // `slot_start` might round differently
+        if self.slot_start(slot + 1) <= time {
+            slot + 1
+        } else {
+            slot
+        }
+    }
+    pub fn slot_start(&self, slot: u64) -> SimSeconds {
+        self.slot_duration * slot as f64
+    }
+    /// The node that may propose a block in `slot`, if anybody has stake.
+    pub fn leader(&self, slot: u64) -> Option<Entity> {
+        let total_stake = self.total_stake();
+        if total_stake <= 0. {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        (self.seed, slot).hash(&mut hasher);
+        let lottery = hasher.finish() as f64 / u64::MAX as f64 * total_stake;
+        let mut stake_so_far = 0.;
+        for (&node, &stake) in &self.stakes {
+            stake_so_far += stake;
+            if lottery < stake_so_far {
+                return Some(node);
+            }
+        }
+        // rounding errors
+        self.stakes.keys().next_back().copied()
+    }
+    /// The first slot from `first_slot` on that `node` leads.
+    pub fn next_slot_led_by(&self, node: Entity, first_slot: u64) -> Option<u64> {
+        if self.stake_of(node) <= 0. {
+            return None;
+        }
+        (first_slot..first_slot + MAX_SLOTS_AHEAD).find(|&slot| self.leader(slot) == Some(node))
+    }
+    /// Whether `proposer` led the slot that the block was proposed in.
+    pub fn is_valid_proposer(&self, header: &BlockHeader, proposer: Entity) -> bool {
+        self.leader(self.slot_at(header.time)) == Some(proposer)
+    }
+}
+impl SybilResistance for ProofOfStake {
+    fn validate_proposer(
+        &self,
+        header: &BlockHeader,
+        proposer: Option<Entity>,
+    ) -> Result<(), Box<dyn Error>> {
+        if proposer.is_some_and(|proposer| self.is_valid_proposer(header, proposer)) {
+            Ok(())
+        } else {
+            Err("Its proposer wasn't the slot leader.".into())
+        }
+    }
+    fn may_propose(&self, node: Entity, time: SimSeconds) -> Result<(), Box<dyn Error>> {
+        if self.leader(self.slot_at(time)) == Some(node) {
+            Ok(())
+        } else {
+            Err("Only the slot leader may propose a block.".into())
+        }
+    }
+    fn finality_depth(&self) -> Option<usize> {
+        self.finality_depth
+    }
+}
+
+/// When a node proposes its next block, see `Staking`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProposalSchedule {
+    pub next_slot: Option<u64>,
+    pub last_slot: Option<u64>,
+    /// The rules and the slot that `next_slot` was looked for with. Finding it can take long.
+    rules: Option<ProofOfStake>,
+    checked_slot: Option<u64>,
+}
+impl ProposalSchedule {
+    /// Whether we still know the next slot at `time` without looking for it again: The rules need
+    /// to be the same, and either the next slot hasn't passed yet or, if there is none, we have
+    /// looked in the current slot already.
+    fn is_up_to_date(&self, proof_of_stake: Option<&ProofOfStake>, time: SimSeconds) -> bool {
+        if self.rules.as_ref() != proof_of_stake {
+            return false;
+        }
+        let current_slot = proof_of_stake.map(|proof_of_stake| proof_of_stake.slot_at(time));
+        match (self.next_slot, current_slot) {
+            (Some(next_slot), Some(current_slot)) => next_slot >= current_slot,
+            _ => self.checked_slot == current_slot,
+        }
+    }
+}
+
+/// Lets every node propose a block on top of its tip at the start of each slot that it leads,
+/// according to its `ProofOfStake` rules. The counterpart of `Mining`, so don't add both.
+///
+/// Add it *after* `NakamotoConsensus` so that it sees changes of the rules right away.
+#[derive(Debug, Clone, Default)]
+pub struct Staking {
+    block_limit: Option<usize>,
+}
+impl Staking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_block_limit(mut self, block_limit: usize) -> Self {
+        self.block_limit = Some(block_limit);
+        self
+    }
+    fn update_schedule(&self, sim: &mut Simulation, node: Entity) {
+        let now = sim.time.now();
+        let proof_of_stake = match sim.world.get::<NakamotoNodeState>(node) {
+            Ok(state) => {
+                let proof_of_stake = (*state.sybil_resistance())
+                    .as_any()
+                    .downcast_ref::<ProofOfStake>();
+                let schedule = sim.world.get::<ProposalSchedule>(node);
+                if schedule.is_ok_and(|schedule| schedule.is_up_to_date(proof_of_stake, now)) {
+                    return;
+                }
+                proof_of_stake.cloned()
+            }
+            Err(_) => return,
+        };
+        let mut node_interface = sim.node_interface(node);
+        let schedule = node_interface.get::<ProposalSchedule>();
+        let current_slot = proof_of_stake
+            .as_ref()
+            .map(|proof_of_stake| proof_of_stake.slot_at(now));
+        let next_slot = proof_of_stake.as_ref().and_then(|proof_of_stake| {
+            let current_slot = proof_of_stake.slot_at(now);
+            let first_slot = schedule
+                .last_slot
+                .map_or(current_slot, |last_slot| current_slot.max(last_slot + 1));
+            proof_of_stake.next_slot_led_by(node, first_slot)
+        });
+        let rescheduled = next_slot != schedule.next_slot;
+        schedule.next_slot = next_slot;
+        schedule.checked_slot = current_slot;
+        schedule.rules = proof_of_stake.clone();
+        if !rescheduled {
+            return;
+        }
+        if let (Some(proof_of_stake), Some(slot)) = (proof_of_stake, next_slot) {
+            let delay = proof_of_stake.slot_start(slot) - now;
+            let propose_block = ProposeBlock {
+                slot,
+                block_limit: self.block_limit,
+            };
+            sim.do_in(
+                delay.max(SimSeconds::from(0.)),
+                ForSpecific(node, propose_block),
+            );
+        }
+    }
+}
+impl EventHandler for Staking {
+    fn handle_event(&mut self, sim: &mut Simulation, event: Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            Event::Node(node, _) => self.update_schedule(sim, node),
+            // commands might change anything, e.g., spawn nodes or let them propose blocks
+            Event::Command(_) => {
+                let nodes: Vec<Entity> = sim
+                    .world
+                    .query::<&NakamotoNodeState>()
+                    .iter()
+                    .map(|(node, _)| node)
+                    .collect();
+                for node in nodes {
+                    self.update_schedule(sim, node);
+                }
+            }
+            Event::Generic(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Scheduled by `Staking`. Does nothing if the node has rescheduled in the meantime.
+#[derive(Debug, Clone)]
+struct ProposeBlock {
+    slot: u64,
+    block_limit: Option<usize>,
+}
+impl EntityAction for ProposeBlock {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let mut node = sim.node_interface(entity);
+        let schedule = node.get::<ProposalSchedule>();
+        if schedule.next_slot != Some(self.slot) {
+            return Ok(());
+        }
+        schedule.next_slot = None;
+        schedule.last_slot = Some(self.slot);
+        // `Staking` looks for the next slot right away
+        schedule.checked_slot = None;
+        match self.block_limit {
+            Some(block_limit) => MineBlockWithLimit(block_limit).execute_for(sim, entity),
+            None => MineBlock.execute_for(sim, entity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    fn get_state(sim: &Simulation, node: Entity) -> NakamotoNodeState {
+        (*sim.world.get::<NakamotoNodeState>(node).unwrap()).clone()
+    }
+
+    #[wasm_bindgen_test]
+    fn leaders_get_picked_proportionally_to_stake() {
+        let mut sim = Simulation::new();
+        let big_staker = sim.spawn_random_node();
+        let small_staker = sim.spawn_random_node();
+        let no_staker = sim.spawn_random_node();
+        let proof_of_stake = ProofOfStake::new(SimSeconds::from(1.))
+            .with_stake(big_staker, 3.)
+            .with_stake(small_staker, 1.)
+            .with_stake(no_staker, 0.);
+        let slots = 10_000;
+        let big_staker_slots = (0..slots)
+            .filter(|&slot| proof_of_stake.leader(slot) == Some(big_staker))
+            .count();
+        let share = big_staker_slots as f64 / slots as f64;
+        assert!(share > 0.72 && share < 0.78);
+        assert_eq!(None, proof_of_stake.next_slot_led_by(no_staker, 0));
+        let slot = proof_of_stake.next_slot_led_by(small_staker, 5).unwrap();
+        assert!(slot >= 5);
+        assert_eq!(Some(small_staker), proof_of_stake.leader(slot));
+        assert_eq!(
+            slot,
+            proof_of_stake.slot_at(proof_of_stake.slot_start(slot))
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn schedules_are_looked_up_again_only_when_needed() {
+        let mut sim = Simulation::new();
+        let node = sim.spawn_random_node();
+        let proof_of_stake = ProofOfStake::new(SimSeconds::from(10.)).with_stake(node, 1.);
+        let schedule = ProposalSchedule {
+            next_slot: Some(3),
+            last_slot: Some(1),
+            rules: Some(proof_of_stake.clone()),
+            checked_slot: Some(2),
+        };
+        assert!(schedule.is_up_to_date(Some(&proof_of_stake), SimSeconds::from(25.)));
+        assert!(schedule.is_up_to_date(Some(&proof_of_stake), SimSeconds::from(35.)));
+        assert!(!schedule.is_up_to_date(Some(&proof_of_stake), SimSeconds::from(45.)));
+        let other_rules = proof_of_stake.clone().with_seed(1);
+        assert!(!schedule.is_up_to_date(Some(&other_rules), SimSeconds::from(25.)));
+        assert!(!schedule.is_up_to_date(None, SimSeconds::from(25.)));
+
+        // e.g., because the node has too little stake to lead any of the next few slots
+        let nothing_planned = ProposalSchedule {
+            next_slot: None,
+            ..schedule
+        };
+        assert!(nothing_planned.is_up_to_date(Some(&proof_of_stake), SimSeconds::from(25.)));
+        assert!(!nothing_planned.is_up_to_date(Some(&proof_of_stake), SimSeconds::from(35.)));
+    }
+
+    #[wasm_bindgen_test]
+    fn slot_leaders_build_a_chain() {
+        let mut sim = Simulation::new();
+        let nodes: Vec<Entity> = (0..4).map(|_| sim.spawn_random_node()).collect();
+        let proof_of_stake = nodes
+            .iter()
+            .fold(ProofOfStake::new(SimSeconds::from(20.)), |pos, &node| {
+                pos.with_stake(node, 1.)
+            });
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_sybil_resistance(proof_of_stake.clone()),
+        ));
+        sim.add_event_handler(Staking::new());
+        sim.do_now(MakeDelaunayNetwork);
+        sim.work_until(SimSeconds::from(20. * 50. + 10.));
+
+        let state = get_state(&sim, nodes[0]);
+        // one block for each of the slots 0 to 50
+        assert_eq!(51, state.tip_height());
+        assert_eq!(0, state.fork_tips().len());
+        for &node in &nodes {
+            assert_eq!(state.tip(), get_state(&sim, node).tip());
+        }
+        let tip = state.block_header(state.tip().unwrap()).unwrap();
+        let proposer = sim.world.get::<MinedBy>(tip.id).unwrap().0;
+        assert!(proof_of_stake.is_valid_proposer(&tip, proposer));
+    }
+
+    #[wasm_bindgen_test]
+    fn blocks_from_others_than_the_slot_leader_get_rejected() {
+        let mut sim = Simulation::new();
+        let leader = sim.spawn_random_node();
+        let other_node = sim.spawn_random_node();
+        let proof_of_stake = ProofOfStake::new(SimSeconds::from(20.)).with_stake(leader, 1.);
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_sybil_resistance(proof_of_stake),
+        ));
+        sim.add_peer(leader, other_node);
+        sim.add_peer(other_node, leader);
+        sim.work_until(SimSeconds::from(1.));
+
+        // nodes don't even try to propose blocks outside of their slots...
+        sim.do_now(ForSpecific(other_node, MineBlock));
+        sim.work_until(SimSeconds::from(2.));
+        assert_eq!(0, get_state(&sim, other_node).tip_height());
+
+        // ...and if they do anyway, nobody accepts them
+        let mut node = sim.node_interface(other_node);
+        let block = node.spawn_block(None, []);
+        sim.send_message(
+            other_node,
+            leader,
+            NakamotoMessage::Flood(InventoryItem::Block(block.id)),
+        );
+        sim.work_until(SimSeconds::from(3.));
+        assert_eq!(0, get_state(&sim, leader).tip_height());
+
+        sim.do_now(ForSpecific(leader, MineBlock));
+        sim.work_until(SimSeconds::from(4.));
+        assert_eq!(1, get_state(&sim, other_node).tip_height());
+    }
+
+    #[wasm_bindgen_test]
+    fn final_blocks_survive_longer_chains() {
+        let mut sim = Simulation::new();
+        let node = sim.spawn_random_node();
+        let attacker = sim.spawn_random_node();
+        // the attacker leads every slot
+        let proof_of_stake = ProofOfStake::new(SimSeconds::from(1.))
+            .with_stake(attacker, 1.)
+            .with_finality_depth(2);
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_sybil_resistance(proof_of_stake),
+        ));
+        sim.add_peer(attacker, node);
+        sim.work_until(SimSeconds::from(0.5));
+
+        // the attacker secretly builds a long chain, then publishes an honest chain of 3 blocks
+        let mut attacker_interface = sim.node_interface(attacker);
+        let mut secret_chain = vec![attacker_interface.spawn_block(None, [])];
+        for _ in 0..4 {
+            let id_prev = secret_chain.last().unwrap().id;
+            secret_chain.push(attacker_interface.spawn_block(Some(id_prev), []));
+        }
+        let mut public_chain = vec![];
+        for _ in 0..3 {
+            sim.work_until(sim.time.now() + SimSeconds::from(1.));
+            let id_prev = public_chain.last().map(|header: &BlockHeader| header.id);
+            let header = sim.node_interface(attacker).spawn_block(id_prev, []);
+            sim.send_message(
+                attacker,
+                node,
+                NakamotoMessage::Flood(InventoryItem::Block(header.id)),
+            );
+            public_chain.push(header);
+        }
+        sim.work_until(sim.time.now() + SimSeconds::from(1.));
+        let state = get_state(&sim, node);
+        assert_eq!(Some(public_chain[2].id), state.tip());
+        assert_eq!(Some(public_chain[0].id), state.finalized_block());
+
+        // the longer secret chain forks off before the final block
+        for header in secret_chain {
+            sim.send_message(
+                attacker,
+                node,
+                NakamotoMessage::Flood(InventoryItem::Block(header.id)),
+            );
+        }
+        sim.work_until(sim.time.now() + SimSeconds::from(1.));
+        let state = get_state(&sim, node);
+        assert_eq!(Some(public_chain[2].id), state.tip());
+        assert!(state.fork_tips().len() == 1);
+    }
+}
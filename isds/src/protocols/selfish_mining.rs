@@ -0,0 +1,400 @@
+use super::*;
+use mining::*;
+use nakamoto_consensus::*;
+use std::collections::{HashMap, HashSet};
+
+/// Turns a node into a selfish miner (see `SelfishMining`) that has `hashrate_share` of the total
+/// hashrate, given the current hashrates of all other nodes.
+///
+/// `gamma` is the share of honest nodes that mine on the selfish miner's block during a race,
+/// i.e., how much better connected the selfish miner is.
+#[derive(Debug, Clone, Copy)]
+pub struct BecomeSelfishMiner {
+    pub hashrate_share: f64,
+    pub gamma: f64,
+}
+impl EntityAction for BecomeSelfishMiner {
+    fn execute_for(&self, sim: &mut Simulation, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let others_hashrate: f64 = sim
+            .world
+            .query::<&Hashrate>()
+            .iter()
+            .filter(|&(node, _)| node != entity)
+            .map(|(_, hashrate)| hashrate.0)
+            .sum();
+        let share = self.hashrate_share.clamp(0., 0.99);
+        sim.add_node_role(entity, NodeRole::Attacker)?;
+        let mut node = sim.node_interface(entity);
+        node.get::<Hashrate>().0 = others_hashrate * share / (1. - share);
+        node.get::<NakamotoNodeState>().set_withholds_blocks(true);
+        node.get::<SelfishMinerState>().gamma = self.gamma.clamp(0., 1.);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SelfishMinerState {
+    gamma: f64,
+    /// Height of the longest chain that the rest of the network knows about, as far as we know.
+    public_height: usize,
+    /// Our block that is competing with an honest block of the same height, if any.
+    race: Option<Entity>,
+    /// Honest nodes that will mine on top of our block during the race.
+    race_supporters: HashSet<Entity>,
+}
+
+impl SelfishMinerState {
+    pub fn gamma(&self) -> f64 {
+        self.gamma
+    }
+}
+
+/// The selfish mining strategy of Eyal and Sirer ("Majority is not Enough: Bitcoin Mining is
+/// Vulnerable", 2014), for nodes that have a `SelfishMinerState` (see `BecomeSelfishMiner`). Such
+/// nodes withhold the blocks they mine and only publish them to invalidate honest blocks:
+///
+/// - When an honest block reduces their lead to one, they publish everything and win.
+/// - When an honest block catches up with their single private block, they publish it and a race
+///   between the two branches starts.
+/// - When they are further ahead, they publish as many blocks as the honest chain has.
+/// - When the honest chain is longer, they give up and mine on top of it.
+///
+/// The honest nodes that support the selfish miner during a race (see `BecomeSelfishMiner`) get
+/// picked at random when the race starts and switch to the selfish block once they know it.
+///
+/// Add it after `NakamotoConsensus` and before `Mining`, so that honest nodes that switch branches
+/// immediately mine on the new branch.
+#[derive(Debug, Clone, Default)]
+pub struct SelfishMining;
+impl SelfishMining {
+    fn update_selfish_miner(&self, sim: &mut Simulation, node: Entity) {
+        let (private_height, public_height) = match sim.world.get::<NakamotoNodeState>(node) {
+            Ok(state) => (state.tip_height(), public_height(&state)),
+            Err(_) => return,
+        };
+        let mut node_interface = sim.node_interface(node);
+        let selfish_state = node_interface.get::<SelfishMinerState>();
+        let honest_block_found = public_height > selfish_state.public_height;
+        // a new public block decides an ongoing race, one way or the other
+        let mut race = selfish_state.race.filter(|_| !honest_block_found);
+        if public_height > private_height {
+            // the honest chain is longer, let's mine on top of it
+            node_interface
+                .get::<NakamotoNodeState>()
+                .discard_withheld_blocks();
+        } else if !node_interface
+            .get::<NakamotoNodeState>()
+            .withheld_blocks()
+            .is_empty()
+        {
+            let lead = private_height - public_height;
+            if race.is_some() {
+                // we extended our branch during the race and win it
+                race = None;
+                NakamotoConsensus::publish_withheld_blocks(&mut node_interface, private_height);
+            } else if honest_block_found && lead == 0 {
+                race = node_interface.get::<NakamotoNodeState>().tip();
+                NakamotoConsensus::publish_withheld_blocks(&mut node_interface, private_height);
+                node_interface.log("Racing the honest nodes with my block!");
+            } else if honest_block_found && lead == 1 {
+                NakamotoConsensus::publish_withheld_blocks(&mut node_interface, private_height);
+            } else if honest_block_found {
+                NakamotoConsensus::publish_withheld_blocks(&mut node_interface, public_height);
+            }
+        }
+        // our own blocks are public now, too
+        let public_height = self::public_height(node_interface.get::<NakamotoNodeState>());
+        node_interface.get::<SelfishMinerState>().public_height = public_height;
+        let race_changed = node_interface.get::<SelfishMinerState>().race != race;
+        if race_changed {
+            let race_supporters = match race {
+                Some(_) => self.pick_race_supporters(sim, node),
+                None => HashSet::new(),
+            };
+            let mut node_interface = sim.node_interface(node);
+            let selfish_state = node_interface.get::<SelfishMinerState>();
+            selfish_state.race = race;
+            selfish_state.race_supporters = race_supporters;
+        }
+    }
+    fn pick_race_supporters(&self, sim: &mut Simulation, selfish_miner: Entity) -> HashSet<Entity> {
+        let gamma = sim
+            .node_interface(selfish_miner)
+            .get::<SelfishMinerState>()
+            .gamma;
+        let honest_nodes: Vec<Entity> = sim
+            .world
+            .query::<&NakamotoNodeState>()
+            .without::<SelfishMinerState>()
+            .iter()
+            .map(|(node, _)| node)
+            .collect();
+        let mut node_interface = sim.node_interface(selfish_miner);
+        honest_nodes
+            .into_iter()
+            .filter(|_| node_interface.rng().gen_bool(gamma))
+            .collect()
+    }
+    /// Lets an honest node switch to a selfish miner's block if it supports it in the race.
+    fn update_honest_node(&self, sim: &mut Simulation, node: Entity) {
+        let races: Vec<Entity> = sim
+            .world
+            .query::<&SelfishMinerState>()
+            .iter()
+            .filter(|(_, selfish_state)| selfish_state.race_supporters.contains(&node))
+            .filter_map(|(_, selfish_state)| selfish_state.race)
+            .collect();
+        for block_id in races {
+            let is_fork_tip = sim
+                .world
+                .get::<NakamotoNodeState>(node)
+                .is_ok_and(|state| state.fork_tips().contains(&block_id));
+            if is_fork_tip {
+                // whether the block is good enough is still up to the node's fork-choice rule
+                let mut node_interface = sim.node_interface(node);
+                let block_contents = node_interface
+                    .get_block_contents(block_id)
+                    .cloned()
+                    .unwrap_or_default();
+                let old_tip = node_interface.get::<NakamotoNodeState>().tip();
+                node_interface
+                    .get::<NakamotoNodeState>()
+                    .prefer_in_ties(block_id, block_contents);
+                NakamotoConsensus::notify_if_tip_changed(&mut node_interface, old_tip);
+            }
+        }
+    }
+}
+impl EventHandler for SelfishMining {
+    fn handle_event(&mut self, sim: &mut Simulation, event: Event) -> Result<(), Box<dyn Error>> {
+        let selfish_miners: Vec<Entity> = sim
+            .world
+            .query::<&SelfishMinerState>()
+            .iter()
+            .map(|(node, _)| node)
+            .collect();
+        match event {
+            Event::Node(node, _) if selfish_miners.contains(&node) => {
+                self.update_selfish_miner(sim, node);
+            }
+            Event::Node(node, _) => self.update_honest_node(sim, node),
+            // selfish miners might have mined a block
+            Event::Command(_) => {
+                for node in selfish_miners {
+                    self.update_selfish_miner(sim, node);
+                }
+            }
+            Event::Generic(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// The height of the longest chain that doesn't contain any of our withheld blocks.
+fn public_height(state: &NakamotoNodeState) -> usize {
+    let first_withheld_height = state
+        .withheld_blocks()
+        .front()
+        .map(|&block_id| state.height(Some(block_id)));
+    state
+        .tip()
+        .iter()
+        .chain(state.fork_tips().iter())
+        .map(|&block_id| {
+            let height = state.height(Some(block_id));
+            match first_withheld_height {
+                Some(first_withheld_height) if state.withheld_blocks().contains(&block_id) => {
+                    first_withheld_height - 1
+                }
+                _ => height,
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// How the blocks on the longest chain (as seen by `viewing_node`) are split between selfish and
+/// honest miners. Mining revenue is proportional to these numbers.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SelfishMiningRevenue {
+    pub selfish_blocks: usize,
+    pub honest_blocks: usize,
+    /// The selfish miners' share of the total hashrate.
+    pub selfish_hashrate_share: f64,
+}
+impl SelfishMiningRevenue {
+    pub fn of(sim: &Simulation, viewing_node: Entity) -> Self {
+        let blocks_by_miner = blocks_on_longest_chain_by_miner(sim, viewing_node);
+        let mut revenue = Self::default();
+        for (miner, blocks) in blocks_by_miner {
+            if sim.world.get::<SelfishMinerState>(miner).is_ok() {
+                revenue.selfish_blocks += blocks;
+            } else {
+                revenue.honest_blocks += blocks;
+            }
+        }
+        let hashrates: HashMap<Entity, f64> = sim
+            .world
+            .query::<&Hashrate>()
+            .iter()
+            .map(|(node, hashrate)| (node, hashrate.0))
+            .collect();
+        let total_hashrate: f64 = hashrates.values().sum();
+        if total_hashrate > 0. {
+            let selfish_hashrate: f64 = hashrates
+                .iter()
+                .filter(|(&node, _)| sim.world.get::<SelfishMinerState>(node).is_ok())
+                .map(|(_, hashrate)| hashrate)
+                .sum();
+            revenue.selfish_hashrate_share = selfish_hashrate / total_hashrate;
+        }
+        revenue
+    }
+    /// The selfish miners' share of the blocks, if there are any blocks.
+    pub fn selfish_share(&self) -> Option<f64> {
+        let total_blocks = self.selfish_blocks + self.honest_blocks;
+        if total_blocks > 0 {
+            Some(self.selfish_blocks as f64 / total_blocks as f64)
+        } else {
+            None
+        }
+    }
+}
+
+/// The share of blocks that a selfish miner with a hashrate share of `alpha` gets in the long run,
+/// according to Eyal and Sirer's model. Selfish mining pays off if this is larger than `alpha`.
+pub fn expected_selfish_share(alpha: f64, gamma: f64) -> f64 {
+    let numerator =
+        alpha * (1. - alpha).powi(2) * (4. * alpha + gamma * (1. - 2. * alpha)) - alpha.powi(3);
+    let denominator = 1. - alpha * (1. + (2. - alpha) * alpha);
+    numerator / denominator
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use difficulty::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    fn get_state(sim: &Simulation, node: Entity) -> NakamotoNodeState {
+        (*sim.world.get::<NakamotoNodeState>(node).unwrap()).clone()
+    }
+
+    #[wasm_bindgen_test]
+    fn selfish_miner_overrides_honest_block_with_lead_of_two() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        sim.add_event_handler(SelfishMining);
+
+        let selfish_miner = sim.spawn_random_node();
+        let honest_node = sim.spawn_random_node();
+        sim.add_peer(selfish_miner, honest_node);
+        sim.add_peer(honest_node, selfish_miner);
+        sim.do_now(ForSpecific(
+            selfish_miner,
+            BecomeSelfishMiner {
+                hashrate_share: 0.3,
+                gamma: 0.,
+            },
+        ));
+        sim.do_now(ForSpecific(selfish_miner, MineBlock));
+        sim.do_now(ForSpecific(selfish_miner, MineBlock));
+        sim.work_until(SimSeconds::from(10.));
+        assert_eq!(2, get_state(&sim, selfish_miner).withheld_blocks().len());
+        assert_eq!(0, get_state(&sim, honest_node).tip_height());
+
+        sim.do_now(ForSpecific(honest_node, MineBlock));
+        sim.work_until(SimSeconds::from(20.));
+        let selfish_state = get_state(&sim, selfish_miner);
+        let honest_state = get_state(&sim, honest_node);
+        assert!(selfish_state.withheld_blocks().is_empty());
+        assert_eq!(2, honest_state.tip_height());
+        assert_eq!(selfish_state.tip(), honest_state.tip());
+    }
+
+    #[wasm_bindgen_test]
+    fn selfish_miner_gives_up_when_behind() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(NakamotoConsensus::default()));
+        sim.add_event_handler(SelfishMining);
+
+        let selfish_miner = sim.spawn_random_node();
+        let honest_node = sim.spawn_random_node();
+        sim.add_peer(selfish_miner, honest_node);
+        sim.add_peer(honest_node, selfish_miner);
+        sim.do_now(ForSpecific(
+            selfish_miner,
+            BecomeSelfishMiner {
+                hashrate_share: 0.3,
+                gamma: 0.,
+            },
+        ));
+        sim.do_now(ForSpecific(selfish_miner, MineBlock));
+        sim.work_until(SimSeconds::from(10.));
+        // the honest node catches up: race
+        sim.do_now(ForSpecific(honest_node, MineBlock));
+        sim.work_until(SimSeconds::from(20.));
+        assert!(get_state(&sim, selfish_miner).withheld_blocks().is_empty());
+        // ...and wins it
+        sim.do_now(ForSpecific(honest_node, MineBlock));
+        sim.work_until(SimSeconds::from(30.));
+
+        let selfish_state = get_state(&sim, selfish_miner);
+        let honest_state = get_state(&sim, honest_node);
+        assert_eq!(2, selfish_state.tip_height());
+        assert_eq!(selfish_state.tip(), honest_state.tip());
+        let revenue = SelfishMiningRevenue::of(&sim, honest_node);
+        assert_eq!(0, revenue.selfish_blocks);
+        assert_eq!(2, revenue.honest_blocks);
+    }
+
+    #[wasm_bindgen_test]
+    fn expected_share_matches_eyal_and_sirer() {
+        // selfish mining doesn't pay off for small miners...
+        assert!(expected_selfish_share(0.25, 0.) < 0.25);
+        // ...but does for large ones
+        assert!(expected_selfish_share(0.4, 0.) > 0.4);
+        // the threshold is 1/3 for gamma = 0 and 1/4 for gamma = 1/2
+        assert!((expected_selfish_share(1. / 3., 0.) - 1. / 3.).abs() < 1e-9);
+        assert!((expected_selfish_share(0.25, 0.5) - 0.25).abs() < 1e-9);
+        // all honest nodes support the selfish miner: always profitable
+        assert!(expected_selfish_share(0.1, 1.) > 0.1);
+    }
+
+    #[wasm_bindgen_test]
+    fn selfish_miner_gets_more_than_its_fair_share() {
+        let mut sim = Simulation::new();
+        sim.seed_rng(1);
+        sim.add_event_handler(InvokeProtocolForAllNodes(
+            NakamotoConsensus::default().with_difficulty_adjustment(
+                DifficultyAdjustment::for_block_interval(SimSeconds::from(600.), 10.)
+                    .with_rule(RetargetingRule::Fixed),
+            ),
+        ));
+        sim.add_event_handler(SelfishMining);
+        sim.add_event_handler(Mining::new());
+
+        let selfish_miner = sim.spawn_random_node();
+        sim.do_now(SpawnRandomNodes(6));
+        sim.do_now(MakeDelaunayNetwork);
+        sim.do_now(ForEachNode(SetHashrate(1.)));
+        sim.do_now(ForSpecific(
+            selfish_miner,
+            BecomeSelfishMiner {
+                hashrate_share: 0.4,
+                gamma: 0.5,
+            },
+        ));
+        sim.work_until(SimSeconds::from(600. * 1000.));
+
+        let honest_node = sim.pick_random_other_node(selfish_miner).unwrap();
+        let revenue = SelfishMiningRevenue::of(&sim, honest_node);
+        let expected_share = expected_selfish_share(0.4, 0.5);
+        let share = revenue.selfish_share().unwrap();
+        assert!((revenue.selfish_hashrate_share - 0.4).abs() < 1e-9);
+        assert!(share > 0.45);
+        assert!((share - expected_share).abs() < 0.06);
+    }
+}
@@ -1,5 +1,4 @@
 use super::*;
-use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::marker::PhantomData;
@@ -39,11 +38,14 @@ impl<T: Payload + Hash + Eq> Protocol for SimpleFlooding<T> {
         underlay_message: UnderlayMessage,
         message_payload: Self::MessagePayload,
     ) -> Result<(), Box<dyn Error>> {
-        let message = message_payload.0;
-        register_sender(&mut node, &message, underlay_message.source);
-        if is_new(&mut node, &message) {
-            Self::flood(&mut node, message);
-        }
+        Self::relay_with(
+            &mut node,
+            underlay_message.source,
+            message_payload.0,
+            |node, peer, message| {
+                node.send_message(peer, SimpleFloodingMessage(message));
+            },
+        );
         Ok(())
     }
     fn handle_poke(&self, mut node: NodeInterface) -> Result<(), Box<dyn Error>> {
@@ -87,6 +89,17 @@ impl<T> Default for SimpleFloodingState<T> {
 
 impl<T: Payload + Hash + Eq> SimpleFlooding<T> {
     pub fn flood(node: &mut NodeInterface, message: T) {
+        Self::flood_with(node, message, |node, peer, message| {
+            node.send_message(peer, SimpleFloodingMessage(message));
+        });
+    }
+    /// Like `flood`, but leaves the actual sending to `send`, so that other protocols can reuse the
+    /// bookkeeping with their own message types.
+    pub fn flood_with(
+        node: &mut NodeInterface,
+        message: T,
+        mut send: impl FnMut(&mut NodeInterface, Entity, T),
+    ) {
         let peers = node.get::<PeerSet>().clone(); // TODO: again, the clone here is not ideal
         let flooding_state = node.get::<SimpleFloodingState<T>>();
 
@@ -94,22 +107,26 @@ impl<T: Payload + Hash + Eq> SimpleFlooding<T> {
 
         flooding_state.own_haves.insert(message.clone());
         for peer in peers.into_iter() {
-            match flooding_state.peer_haves.entry(peer) {
-                Entry::Occupied(mut e) => {
-                    if e.get_mut().insert(message.clone()) {
-                        next_hops.push(peer);
-                    }
-                }
-                Entry::Vacant(e) => {
-                    let mut new_set = HashSet::new();
-                    new_set.insert(message.clone());
-                    e.insert(new_set);
-                    next_hops.push(peer);
-                }
+            if flooding_state.register_peer_has(peer, message.clone()) {
+                next_hops.push(peer);
             }
         }
         for peer in next_hops.into_iter() {
-            node.send_message(peer, SimpleFloodingMessage(message.clone()));
+            send(node, peer, message.clone());
+        }
+    }
+    /// What we do with a message that we got from `sender`: remember that `sender` has it and
+    /// flood it if it's new to us.
+    pub fn relay_with(
+        node: &mut NodeInterface,
+        sender: Entity,
+        message: T,
+        send: impl FnMut(&mut NodeInterface, Entity, T),
+    ) {
+        let flooding_state = node.get::<SimpleFloodingState<T>>();
+        flooding_state.register_peer_has(sender, message.clone());
+        if !flooding_state.own_haves.contains(&message) {
+            Self::flood_with(node, message, send);
         }
     }
     pub fn forget_peer(node: &mut NodeInterface, peer: Entity) {
@@ -130,23 +147,30 @@ impl<T: Payload + Hash + Eq> SimpleFlooding<T> {
     }
 }
 
-fn is_new<T: Payload + Hash + Eq>(node: &mut NodeInterface, message: &T) -> bool {
-    let flooding_state = node.get::<SimpleFloodingState<T>>();
-    !flooding_state.own_haves.contains(message)
-}
-
-fn register_sender<T: Payload + Hash + Eq>(node: &mut NodeInterface, message: &T, sender: Entity) {
-    let flooding_state = node.get::<SimpleFloodingState<T>>();
-    match flooding_state.peer_haves.entry(sender) {
-        Entry::Occupied(mut e) => {
-            e.get_mut().insert(message.clone());
+impl<T: Payload + Hash + Eq> SimpleFloodingState<T> {
+    /// For cleaning up after messages that won't be flooded anymore.
+    pub fn forget(&mut self, mut is_forgotten: impl FnMut(&T) -> bool) {
+        self.own_haves.retain(|message| !is_forgotten(message));
+        for haves in self.peer_haves.values_mut() {
+            haves.retain(|message| !is_forgotten(message));
         }
-        Entry::Vacant(e) => {
-            let mut new_set = HashSet::new();
-            new_set.insert(message.clone());
-            e.insert(new_set);
+    }
+    /// Returns `true` if we didn't know yet that `peer` has `message`.
+    pub fn register_peer_has(&mut self, peer: Entity, message: T) -> bool {
+        self.peer_haves.entry(peer).or_default().insert(message)
+    }
+    /// For when `peer` turns out not to have `message` (anymore).
+    pub fn forget_peer_has(&mut self, peer: Entity, message: &T) {
+        if let Some(haves) = self.peer_haves.get_mut(&peer) {
+            haves.remove(message);
         }
     }
+    pub fn peers_having<'a>(&'a self, message: &'a T) -> impl Iterator<Item = Entity> + 'a {
+        self.peer_haves
+            .iter()
+            .filter(move |(_, haves)| haves.contains(message))
+            .map(|(&peer, _)| peer)
+    }
 }
 
 #[cfg(test)]
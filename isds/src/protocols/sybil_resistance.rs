@@ -0,0 +1,80 @@
+use super::*;
+use blockchain_types::*;
+use dyn_clone::DynClone;
+use nakamoto_consensus::*;
+
+/// Relative deviation from the expected difficulty that a block header may have, to allow for
+/// rounding errors.
+const DIFFICULTY_TOLERANCE: f64 = 1e-9;
+
+/// Who may add blocks to the chain. Without such rules, anybody could create any number of
+/// identities (*Sybils*) and outvote everyone else. Nodes that run `NakamotoConsensus` follow
+/// `ProofOfWork` unless configured otherwise, e.g., with `ProofOfStake`.
+pub trait SybilResistance: DynClone + AsAny + std::fmt::Debug + Sync + Send {
+    /// Checks whether `proposer` may have created the block, as far as that doesn't depend on other
+    /// blocks.
+    fn validate_proposer(
+        &self,
+        _header: &BlockHeader,
+        _proposer: Option<Entity>,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    /// Checks the block against its predecessors, which `state` knows. Blocks whose predecessor
+    /// `state` doesn't know yet pass, but get checked again once it does.
+    fn validate_block(
+        &self,
+        _state: &NakamotoNodeState,
+        _header: &BlockHeader,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    /// Whether `node` may create a block at `time`.
+    fn may_propose(&self, _node: Entity, _time: SimSeconds) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    /// Blocks that are buried this deep under a node's tip are final: The node never switches to
+    /// a chain that doesn't contain them, no matter how long it is.
+    fn finality_depth(&self) -> Option<usize> {
+        None
+    }
+}
+dyn_clone::clone_trait_object!(SybilResistance);
+
+impl Default for Box<dyn SybilResistance> {
+    fn default() -> Self {
+        Box::new(ProofOfWork)
+    }
+}
+
+/// Bitcoin's rules: Anybody may create a block, but it needs the difficulty that the node's
+/// `DifficultyAdjustment` demands, which takes work (see `Mining`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProofOfWork;
+impl SybilResistance for ProofOfWork {
+    fn validate_block(
+        &self,
+        state: &NakamotoNodeState,
+        header: &BlockHeader,
+    ) -> Result<(), Box<dyn Error>> {
+        let parent = match header.id_prev {
+            Some(id_prev) => match state.block_header(id_prev) {
+                Some(parent) => Some(parent),
+                None => return Ok(()),
+            },
+            None => None,
+        };
+        let expected = state
+            .difficulty_adjustment()
+            .next_difficulty(parent, |block_id| state.block_header(block_id));
+        // Retargeting multiplies and divides, so different paths to the same difficulty may
+        // round differently.
+        if (header.difficulty.into_inner() - expected).abs()
+            <= expected.abs() * DIFFICULTY_TOLERANCE
+        {
+            Ok(())
+        } else {
+            Err("Its difficulty is wrong.".into())
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use super::*;
+
+/// Something that we might want to pause the simulation for.
+pub trait BreakCondition {
+    /// Called after each event that was handled while breakpoints are enabled. Returns the reason
+    /// for pausing if the simulation should pause now.
+    ///
+    /// Conditions that describe a state rather than an event should only fire once when they
+    /// become true; otherwise, the simulation would pause again right after resuming.
+    fn check(&mut self, sim: &Simulation, event: Event) -> Option<String>;
+    /// If the condition might become true at a specific time, even if nothing else happens then.
+    /// A wake-up event for that time is scheduled while handling the next event.
+    fn wake_up_at(&self) -> Option<SimSeconds> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakpointHit {
+    pub time: SimSeconds,
+    pub reason: String,
+}
+
+/// Pauses `Time` as soon as one of the registered conditions is met and remembers why.
+///
+/// Add it *after* the event handlers that change the state you want to watch (e.g., your
+/// protocol), so that it sees the effects of each event right away.
+#[derive(Default)]
+pub struct Breakpoints {
+    conditions: Vec<Box<dyn BreakCondition>>,
+    wake_ups_pending: Vec<SimSeconds>,
+    last_hit: Option<BreakpointHit>,
+    is_enabled: bool,
+}
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self {
+            is_enabled: true,
+            ..Default::default()
+        }
+    }
+    pub fn with(mut self, condition: impl BreakCondition + 'static) -> Self {
+        self.add(condition);
+        self
+    }
+    pub fn add(&mut self, condition: impl BreakCondition + 'static) {
+        self.wake_ups_pending.extend(condition.wake_up_at());
+        self.conditions.push(Box::new(condition));
+    }
+    pub fn clear(&mut self) {
+        self.conditions.clear();
+        self.wake_ups_pending.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.conditions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+    pub fn last_hit(&self) -> Option<&BreakpointHit> {
+        self.last_hit.as_ref()
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled
+    }
+    pub fn set_enabled(&mut self, is_enabled: bool) {
+        self.is_enabled = is_enabled;
+    }
+    pub fn toggle_enabled(&mut self) {
+        self.is_enabled = !self.is_enabled;
+    }
+}
+impl EventHandler for Breakpoints {
+    fn handle_event(&mut self, sim: &mut Simulation, event: Event) -> Result<(), Box<dyn Error>> {
+        for time_due in self.wake_ups_pending.drain(..) {
+            if time_due > sim.time.now() {
+                sim.do_at(time_due, WakeUp);
+            }
+        }
+        if !self.is_enabled {
+            return Ok(());
+        }
+        // all conditions get to see the event, so that they can keep track of their state
+        let reasons: Vec<String> = self
+            .conditions
+            .iter_mut()
+            .filter_map(|condition| condition.check(sim, event))
+            .collect();
+        if !reasons.is_empty() {
+            let reason = reasons.join("; ");
+            sim.log(format!("Paused: {}", reason));
+            sim.time.pause();
+            self.last_hit = Some(BreakpointHit {
+                time: sim.time.now(),
+                reason,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Makes sure that there is an event at a time that a `BreakCondition` cares about.
+#[derive(Debug, Clone)]
+struct WakeUp;
+impl Command for WakeUp {
+    fn execute(&self, _: &mut Simulation) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimTimeReached {
+    time: SimSeconds,
+    fired: bool,
+}
+impl SimTimeReached {
+    pub fn new(time: SimSeconds) -> Self {
+        Self { time, fired: false }
+    }
+}
+impl BreakCondition for SimTimeReached {
+    fn check(&mut self, sim: &Simulation, _: Event) -> Option<String> {
+        (!self.fired && sim.time.now() >= self.time).then(|| {
+            self.fired = true;
+            format!("Simulation time reached {}s.", self.time)
+        })
+    }
+    fn wake_up_at(&self) -> Option<SimSeconds> {
+        Some(self.time)
+    }
+}
+
+/// Fires whenever a node sends a message with a payload of type `P`.
+pub struct MessageOfTypeSent<P: Payload>(std::marker::PhantomData<P>);
+impl<P: Payload> MessageOfTypeSent<P> {
+    pub fn new() -> Self {
+        Self(std::marker::PhantomData)
+    }
+}
+impl<P: Payload> Default for MessageOfTypeSent<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P: Payload> BreakCondition for MessageOfTypeSent<P> {
+    fn check(&mut self, sim: &Simulation, event: Event) -> Option<String> {
+        if let Event::Node(node, NodeEvent::MessageSent(message)) = event {
+            sim.world.get::<P>(message).ok().map(|_| {
+                format!(
+                    "{} sent a message of type {}.",
+                    sim.name(node),
+                    short_type_name(std::any::type_name::<P>())
+                )
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// For all those conditions that we don't have a ready-made `BreakCondition` for. Fires when
+/// `predicate` becomes true.
+pub struct BreakWhen<F: FnMut(&Simulation) -> bool> {
+    reason: String,
+    predicate: F,
+    was_met: bool,
+}
+impl<F: FnMut(&Simulation) -> bool> BreakWhen<F> {
+    pub fn new(reason: &str, predicate: F) -> Self {
+        Self {
+            reason: reason.to_string(),
+            predicate,
+            was_met: false,
+        }
+    }
+}
+impl<F: FnMut(&Simulation) -> bool> BreakCondition for BreakWhen<F> {
+    fn check(&mut self, sim: &Simulation, _: Event) -> Option<String> {
+        let is_met = (self.predicate)(sim);
+        let became_true = is_met && !self.was_met;
+        self.was_met = is_met;
+        became_true.then(|| self.reason.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[derive(Debug, Clone, Default)]
+    struct TestPayload<T>(T);
+
+    #[wasm_bindgen_test]
+    fn pause_exactly_when_sim_time_is_reached() {
+        let mut sim = Simulation::new();
+        let i = sim.add_event_handler(Breakpoints::new().with(SimTimeReached::new(42.0.into())));
+        sim.time.set_speed(1000.);
+
+        sim.do_now(AtStaticIntervals::new(WakeUp, SimSeconds::from(100.)));
+        sim.catch_up(1.);
+
+        assert!(sim.time.paused());
+        assert_eq!(SimSeconds::from(42.), sim.time.now());
+
+        let handlers = sim.additional_event_handlers();
+        let handlers = handlers.borrow();
+        let hit = handlers.get::<Breakpoints>(i).unwrap().last_hit().unwrap();
+        assert_eq!(SimSeconds::from(42.), hit.time);
+        assert_eq!("Simulation time reached 42s.", hit.reason);
+    }
+
+    #[wasm_bindgen_test]
+    fn pause_on_message_of_specific_type_only() {
+        let mut sim = Simulation::new();
+        let i = sim.add_event_handler(
+            Breakpoints::new().with(MessageOfTypeSent::<TestPayload<u32>>::new()),
+        );
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+
+        sim.send_message(node1, node2, ());
+        sim.catch_up(1.);
+        assert!(!sim.time.paused());
+
+        sim.send_message(node1, node2, TestPayload(23_u32));
+        sim.send_message(node1, node2, ());
+        sim.catch_up(1.);
+        assert!(sim.time.paused());
+
+        let handlers = sim.additional_event_handlers();
+        let handlers = handlers.borrow();
+        let hit = handlers.get::<Breakpoints>(i).unwrap().last_hit().unwrap();
+        assert_eq!(
+            format!(
+                "{} sent a message of type TestPayload<u32>.",
+                sim.name(node1)
+            ),
+            hit.reason
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn predicates_only_fire_when_they_become_true() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(
+            Breakpoints::new().with(BreakWhen::new("There are nodes.", |sim: &Simulation| {
+                sim.world.query::<&UnderlayNodeName>().iter().count() > 0
+            })),
+        );
+        sim.do_now(SpawnRandomNodes(3));
+        sim.do_now(AtStaticIntervals::new(WakeUp, SimSeconds::from(1.)));
+
+        sim.catch_up(1.);
+        assert!(sim.time.paused());
+
+        sim.time.toggle_paused();
+        sim.catch_up(10.);
+        assert!(!sim.time.paused());
+    }
+}
@@ -78,4 +78,45 @@ mod tests {
             .collect();
         assert_eq!(expected, actual);
     }
+
+    #[wasm_bindgen_test]
+    fn executed_commands_are_despawned() {
+        let mut sim = Simulation::new();
+        sim.do_now(TestCommand);
+        sim.do_in(OrderedFloat(1.), TestCommand);
+        sim.do_in(OrderedFloat(1000.), TestCommand);
+        sim.work_until(OrderedFloat(10.));
+
+        let expected = 1; // the one that is still due
+        let actual = sim
+            .world
+            .query_mut::<&Box<dyn Command>>()
+            .into_iter()
+            .count();
+        assert_eq!(expected, actual);
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct FailingCommand;
+    impl Command for FailingCommand {
+        fn execute(&self, _sim: &mut Simulation) -> Result<(), Box<dyn Error>> {
+            Err("This command always fails.".into())
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn failed_commands_are_despawned() {
+        let mut sim = Simulation::new();
+        sim.do_now(FailingCommand);
+        sim.do_now(AtStaticIntervals::new(FailingCommand, OrderedFloat(1.)));
+        sim.work_until(OrderedFloat(10.5));
+
+        let expected = 1; // the next repetition
+        let actual = sim
+            .world
+            .query_mut::<&Box<dyn Command>>()
+            .into_iter()
+            .count();
+        assert_eq!(expected, actual);
+    }
 }
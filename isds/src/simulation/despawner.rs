@@ -4,11 +4,19 @@ use super::*;
 pub struct Despawner;
 impl EventHandler for Despawner {
     fn handle_event(&mut self, sim: &mut Simulation, event: Event) -> Result<(), Box<dyn Error>> {
-        if let Event::Node(_, node_event) = event {
-            match node_event {
+        match event {
+            // commands that repeat themselves schedule a fresh copy, so we won't need this one again
+            Event::Command(command) => sim.world.despawn(command)?,
+            Event::Node(_, node_event) => match node_event {
                 NodeEvent::MessageArrived(message) => sim.world.despawn(message)?,
                 NodeEvent::TimerFired(timer) => sim.world.despawn(timer)?,
                 _ => (),
+            },
+            Event::Generic(entity) => {
+                // notifications only matter while they are being handled
+                if sim.world.get::<notifications::Notification>(entity).is_ok() {
+                    sim.world.despawn(entity)?;
+                }
             }
         }
         Ok(())
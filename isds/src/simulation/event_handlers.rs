@@ -2,14 +2,20 @@ use super::*;
 
 pub trait EventHandler: AsAny {
     fn handle_event(&mut self, sim: &mut Simulation, event: Event) -> Result<(), Box<dyn Error>>;
+    /// Called at the start of each `Simulation::catch_up`, i.e., usually once per frame.
+    fn on_catch_up(&mut self, _sim: &mut Simulation, _elapsed_real_time: RealSeconds) {}
 }
 
 // we need this for enabling downcasting
 pub trait AsAny: 'static {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn type_name(&self) -> &'static str;
 }
 impl<T: 'static> AsAny for T {
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -54,6 +60,28 @@ impl EventHandlers {
         }
         Ok(())
     }
+    /// Like `handle_event`, but also notes down which handlers reacted to the event, i.e., logged
+    /// something or scheduled new events.
+    pub(crate) fn handle_event_traced(
+        &mut self,
+        sim: &mut Simulation,
+        event: Event,
+        reactions: &mut Vec<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        for handler in self.0.iter_mut() {
+            let activity_before = (sim.logger.count(), sim.event_queue.len());
+            handler.handle_event(sim, event)?;
+            if (sim.logger.count(), sim.event_queue.len()) != activity_before {
+                reactions.push(short_type_name((**handler).type_name()));
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn on_catch_up(&mut self, sim: &mut Simulation, elapsed_real_time: RealSeconds) {
+        for handler in self.0.iter_mut() {
+            handler.on_catch_up(sim, elapsed_real_time);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1,12 +1,176 @@
 use super::*;
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, VecDeque};
 
-#[derive(Debug, Default, Clone)]
+/// A calendar queue (R. Brown, 1988): events are sorted into buckets ("days") of a fixed width
+/// that together form a "year". Pushing and popping are amortized O(1) as long as the bucket width
+/// roughly matches the typical distance between consecutive events, which is why we re-estimate
+/// the width whenever the queue grows or shrinks a lot.
+///
+/// Events with identical `time_due` are popped in the order in which they were pushed.
+#[derive(Debug, Clone)]
 pub struct EventQueue {
-    heap: BinaryHeap<TimedEvent>,
+    buckets: Vec<VecDeque<TimedEvent>>,
+    bucket_width: f64,
+    /// Index of the "day" (counted from time 0) where we start looking for the next event.
+    current_day: u64,
+    len: usize,
     next_event_id: usize,
 }
 impl EventQueue {
+    const MIN_BUCKETS: usize = 2;
+    /// How many of the earliest events we look at when estimating a new bucket width.
+    const WIDTH_SAMPLE_SIZE: usize = 25;
+
+    pub fn new() -> Self {
+        Self {
+            buckets: vec![VecDeque::new(); Self::MIN_BUCKETS],
+            bucket_width: 1.,
+            current_day: 0,
+            len: 0,
+            next_event_id: 0,
+        }
+    }
+    pub fn push(&mut self, time_due: SimSeconds, event: Event) {
+        let timed_event = TimedEvent {
+            time_due,
+            event,
+            id: self.next_event_id,
+        };
+        self.next_event_id += 1;
+
+        let day = self.day_of(time_due);
+        if day < self.current_day || self.len == 0 {
+            self.current_day = day;
+        }
+        self.insert(timed_event);
+        self.len += 1;
+
+        if self.len > 2 * self.buckets.len() {
+            self.resize(2 * self.buckets.len());
+        }
+    }
+    pub fn pop(&mut self) -> Option<(SimSeconds, Event)> {
+        let (bucket_index, day) = self.find_next()?;
+        let te = self.buckets[bucket_index].pop_front().unwrap();
+        self.current_day = day;
+        self.len -= 1;
+
+        if self.len < self.buckets.len() / 2 && self.buckets.len() > Self::MIN_BUCKETS {
+            self.resize(self.buckets.len() / 2);
+        }
+        Some((te.time_due, te.event))
+    }
+    pub fn peek(&self) -> Option<(SimSeconds, Event)> {
+        self.find_next().map(|(bucket_index, _)| {
+            let te = self.buckets[bucket_index].front().unwrap();
+            (te.time_due, te.event)
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn day_of(&self, time_due: SimSeconds) -> u64 {
+        (time_due.into_inner() / self.bucket_width).max(0.) as u64
+    }
+    fn bucket_of(&self, day: u64) -> usize {
+        (day % self.buckets.len() as u64) as usize
+    }
+    /// Buckets are kept sorted so that their front is always their earliest event.
+    fn insert(&mut self, timed_event: TimedEvent) {
+        let bucket = self.bucket_of(self.day_of(timed_event.time_due));
+        let bucket = &mut self.buckets[bucket];
+        match bucket.back() {
+            Some(last) if *last > timed_event => {
+                let position = bucket.partition_point(|te| *te < timed_event);
+                bucket.insert(position, timed_event);
+            }
+            // most of the time, new events go to the back
+            _ => bucket.push_back(timed_event),
+        }
+    }
+    /// Returns the index of the bucket that holds the next event, together with that event's day.
+    fn find_next(&self) -> Option<(usize, u64)> {
+        if self.len == 0 {
+            return None;
+        }
+        // walk through one year of days, starting with the current one...
+        let year_end = self.current_day.saturating_add(self.buckets.len() as u64);
+        for day in self.current_day..year_end {
+            let bucket_index = self.bucket_of(day);
+            if let Some(te) = self.buckets[bucket_index].front() {
+                if self.day_of(te.time_due) <= day {
+                    return Some((bucket_index, day));
+                }
+            }
+        }
+        // ...and if all events are further away than that, search directly
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bucket)| bucket.front().map(|te| (i, te)))
+            .min_by(|(_, te1), (_, te2)| te1.cmp(te2))
+            .map(|(i, te)| (i, self.day_of(te.time_due)))
+    }
+    fn resize(&mut self, n_buckets: usize) {
+        let mut all_events: Vec<TimedEvent> =
+            self.buckets.iter_mut().flat_map(|b| b.drain(..)).collect();
+
+        // we only need the earliest events sorted for estimating the new width
+        let sample_size = all_events.len().min(Self::WIDTH_SAMPLE_SIZE);
+        if sample_size < all_events.len() {
+            all_events.select_nth_unstable(sample_size);
+        }
+        all_events[..sample_size].sort_unstable();
+        self.bucket_width =
+            estimate_bucket_width(&all_events[..sample_size]).unwrap_or(self.bucket_width);
+
+        self.buckets = vec![VecDeque::new(); n_buckets];
+        self.current_day = all_events.first().map_or(0, |te| self.day_of(te.time_due));
+
+        for timed_event in all_events.into_iter() {
+            let bucket = self.bucket_of(self.day_of(timed_event.time_due));
+            self.buckets[bucket].push_back(timed_event);
+        }
+        for bucket in self.buckets.iter_mut() {
+            bucket.make_contiguous().sort_unstable();
+        }
+    }
+}
+impl Default for EventQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Brown's heuristic: three times the average distance between (sorted) events, ignoring
+/// distances that are much larger than the average. Returns `None` if there is nothing to go on.
+fn estimate_bucket_width(sorted_events: &[TimedEvent]) -> Option<f64> {
+    let gaps: Vec<f64> = sorted_events
+        .windows(2)
+        .map(|w| (w[1].time_due - w[0].time_due).into_inner())
+        .collect();
+    let mean = |gaps: &mut dyn Iterator<Item = f64>| {
+        let (sum, count) = gaps.fold((0., 0), |(sum, count), gap| (sum + gap, count + 1));
+        (count > 0).then_some(sum / count as f64)
+    };
+    let first_mean = mean(&mut gaps.iter().copied())?;
+    let second_mean = mean(&mut gaps.iter().copied().filter(|&gap| gap <= 2. * first_mean))?;
+    let width = 3. * second_mean;
+    (width > 0. && width.is_finite()).then_some(width)
+}
+
+/// The straightforward implementation that `EventQueue` replaced. Kept around as a reference, for
+/// tests and benchmarks.
+#[derive(Debug, Default, Clone)]
+pub struct HeapEventQueue {
+    heap: BinaryHeap<std::cmp::Reverse<TimedEvent>>,
+    next_event_id: usize,
+}
+impl HeapEventQueue {
     pub fn new() -> Self {
         Self {
             heap: Default::default(),
@@ -14,18 +178,18 @@ impl EventQueue {
         }
     }
     pub fn push(&mut self, time_due: SimSeconds, event: Event) {
-        self.heap.push(TimedEvent {
+        self.heap.push(std::cmp::Reverse(TimedEvent {
             time_due,
             event,
             id: self.next_event_id,
-        });
+        }));
         self.next_event_id += 1;
     }
     pub fn pop(&mut self) -> Option<(SimSeconds, Event)> {
-        self.heap.pop().map(|te| (te.time_due, te.event))
+        self.heap.pop().map(|te| (te.0.time_due, te.0.event))
     }
     pub fn peek(&self) -> Option<(SimSeconds, Event)> {
-        self.heap.peek().map(|te| (te.time_due, te.event))
+        self.heap.peek().map(|te| (te.0.time_due, te.0.event))
     }
 }
 
@@ -37,15 +201,11 @@ struct TimedEvent {
 }
 impl Ord for TimedEvent {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // Ordered by importance:
-        // the most important event is the one with the lowest `time_due`...
-        let time_based = self.time_due.cmp(&other.time_due).reverse();
-        if time_based == std::cmp::Ordering::Equal {
-            // if `time_due` is identical, events scheduled first are more important
-            self.id.cmp(&other.id).reverse()
-        } else {
-            time_based
-        }
+        // the earliest event comes first; if `time_due` is identical, events scheduled first come
+        // first
+        self.time_due
+            .cmp(&other.time_due)
+            .then_with(|| self.id.cmp(&other.id))
     }
 }
 impl PartialOrd for TimedEvent {
@@ -53,3 +213,103 @@ impl PartialOrd for TimedEvent {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_distr::{Distribution, Exp};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    fn test_events(n: usize) -> Vec<Event> {
+        let mut world = World::new();
+        (0..n).map(|i| Event::Generic(world.spawn((i,)))).collect()
+    }
+
+    #[wasm_bindgen_test]
+    fn many_simultaneous_events_are_popped_in_order_of_scheduling() {
+        let mut queue = EventQueue::new();
+        let events = test_events(1000);
+
+        // enough events to trigger a couple of resizes
+        for &event in events.iter() {
+            queue.push(OrderedFloat(42.), event);
+        }
+        let actual: Vec<Event> = std::iter::from_fn(|| queue.pop().map(|(_, e)| e)).collect();
+
+        assert_eq!(events, actual);
+    }
+
+    #[wasm_bindgen_test]
+    fn calendar_queue_behaves_like_binary_heap() {
+        let mut rng = rand::thread_rng();
+        let interval_distribution = Exp::new(1. / 600.).unwrap();
+        let events = test_events(100);
+
+        let mut calendar_queue = EventQueue::new();
+        let mut heap_queue = HeapEventQueue::new();
+        let mut now = OrderedFloat(0.);
+
+        // a "hold" pattern, interspersed with bursts of pushes and pops to trigger resizes
+        for round in 0..5000 {
+            let pushes = if round % 500 < 100 { 3 } else { 1 };
+            for _ in 0..pushes {
+                let event = *events.choose(&mut rng).unwrap();
+                // some events are simultaneous, most are not
+                let time_due = if rng.gen_bool(0.2) {
+                    now
+                } else {
+                    now + interval_distribution.sample(&mut rng)
+                };
+                calendar_queue.push(time_due, event);
+                heap_queue.push(time_due, event);
+            }
+            let pops = if round % 500 >= 400 { 4 } else { 1 };
+            for _ in 0..pops {
+                assert_eq!(heap_queue.peek(), calendar_queue.peek());
+                let popped = heap_queue.pop();
+                assert_eq!(popped, calendar_queue.pop());
+                if let Some((time_due, _)) = popped {
+                    now = time_due;
+                }
+            }
+        }
+        while let Some(popped) = heap_queue.pop() {
+            assert_eq!(Some(popped), calendar_queue.pop());
+        }
+        assert!(calendar_queue.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn events_far_in_the_future_are_found() {
+        let mut queue = EventQueue::new();
+        let events = test_events(3);
+
+        queue.push(OrderedFloat(1e12), events[0]);
+        queue.push(OrderedFloat(0.5), events[1]);
+        queue.push(OrderedFloat(1e6), events[2]);
+
+        assert_eq!(Some((OrderedFloat(0.5), events[1])), queue.pop());
+        assert_eq!(Some((OrderedFloat(1e6), events[2])), queue.pop());
+        assert_eq!(Some((OrderedFloat(1e12), events[0])), queue.pop());
+        assert_eq!(None, queue.pop());
+    }
+
+    #[wasm_bindgen_test]
+    fn events_beyond_the_last_day_are_found() {
+        let mut queue = EventQueue::new();
+        let events = test_events(3);
+
+        // so far away that their days don't fit into a `u64`
+        queue.push(OrderedFloat(1e301), events[0]);
+        queue.push(OrderedFloat(1e300), events[1]);
+        queue.push(OrderedFloat(1.), events[2]);
+
+        assert_eq!(Some((OrderedFloat(1.), events[2])), queue.pop());
+        assert_eq!(Some((OrderedFloat(1e300), events[1])), queue.peek());
+        assert_eq!(Some((OrderedFloat(1e300), events[1])), queue.pop());
+        assert_eq!(Some((OrderedFloat(1e301), events[0])), queue.pop());
+        assert_eq!(None, queue.pop());
+    }
+}
@@ -5,17 +5,24 @@ use super::*;
 
 pub struct Logger {
     log: VecDeque<(SimSeconds, String)>,
+    count: usize,
 }
 impl Logger {
     pub fn new() -> Self {
         Self {
             log: VecDeque::new(),
+            count: 0,
         }
     }
     pub fn log(&mut self, sim_time: SimSeconds, message: String) {
         log!(format!("{}: {}", sim_time, message));
         self.log.push_front((sim_time, message));
         self.log.truncate(12);
+        self.count += 1;
+    }
+    /// Number of messages logged so far, including those that were already dropped.
+    pub fn count(&self) -> usize {
+        self.count
     }
     pub fn entries(&self) -> impl DoubleEndedIterator<Item = &(SimSeconds, String)> {
         self.log.iter()
@@ -7,36 +7,54 @@ pub use hecs::{Entity, World};
 pub use rand::prelude::{IteratorRandom, Rng, SliceRandom};
 pub use std::error::Error;
 
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::any::Any;
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
+mod breakpoints;
 mod command;
 mod command_repeaters;
 mod despawner;
 mod event_handlers;
 mod event_queue;
 mod logger;
+mod node_identity;
 mod node_interface;
+mod notifications;
 mod peers;
 mod protocol;
 mod shared;
+mod speed_control;
+mod stepping;
 mod time;
 mod time_control;
+mod timers;
 mod underlay;
 
 use despawner::Despawner;
 
+pub use breakpoints::{
+    BreakCondition, BreakWhen, BreakpointHit, Breakpoints, MessageOfTypeSent, SimTimeReached,
+};
 pub use command::{Command, EntityAction, ForSpecific};
 pub use command_repeaters::{AtRandomIntervals, AtStaticIntervals, MultipleTimes};
-pub use event_handlers::{EventHandler, EventHandlers};
-pub use event_queue::EventQueue;
+pub use event_handlers::{AsAny, EventHandler, EventHandlers};
+pub use event_queue::{EventQueue, HeapEventQueue};
 pub use logger::Logger;
-pub use node_interface::{blockchain_types, NodeInterface};
+pub use node_identity::{
+    ForRandomNodeWithout, NodeIdentity, NodeLabel, NodeRole, NodeRoles, SpawnNode,
+};
+pub use node_interface::{block_hashes, blockchain_types, NodeInterface};
 pub use protocol::{InvokeProtocolForAllNodes, Payload, PokeNode, PokeSpecificNode, Protocol};
 pub use shared::*;
+pub use speed_control::{
+    ConstantSpeed, Eased, SkipIdlePeriods, SlowMotionOnMessages, SpeedController, SpeedPolicy,
+    TargetTimePerBlock,
+};
+pub use stepping::ProcessedEvent;
 pub use time::{OrderedFloat, RealSeconds, SimSeconds, Time, TimeSpan};
 pub use time_control::SlowDownOnMessages;
 
@@ -69,11 +87,18 @@ pub struct Simulation {
     #[readonly]
     pub logger: Logger,
 
+    /// Set by `step` and friends only, so it might be outdated if other events were processed
+    /// since (see `last_step_is_current`).
+    #[readonly]
+    pub last_step: Option<ProcessedEvent>,
+
     additional_event_handlers: Rc<RefCell<EventHandlers>>,
     underlay_config: UnderlayConfig,
+    /// See `enable_block_hashes`.
+    block_hashes: Option<usize>,
 
     event_queue: EventQueue,
-    rng: ThreadRng,
+    rng: StdRng,
 }
 impl Simulation {
     pub fn new() -> Self {
@@ -84,12 +109,18 @@ impl Simulation {
             time: Time::new(0.1),
             world: World::new(),
             logger: Logger::new(),
+            last_step: None,
             additional_event_handlers: Rc::new(RefCell::new(EventHandlers::new())),
             underlay_config: UnderlayConfig::new(width, height),
+            block_hashes: None,
             event_queue: EventQueue::new(),
-            rng: rand::thread_rng(),
+            rng: StdRng::from_entropy(),
         }
     }
+    /// Makes everything random that happens from now on reproducible, e.g., for tests.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
     /// Returns the index of the event handler, in case you want to modify it later.
     pub fn add_event_handler(&mut self, event_handler: impl EventHandler + 'static) -> usize {
         self.additional_event_handlers
@@ -108,6 +139,9 @@ impl Simulation {
     pub fn schedule_at(&mut self, time_due: SimSeconds, event: Event) {
         self.event_queue.push(time_due, event);
     }
+    pub fn next_event_time(&self) -> Option<SimSeconds> {
+        self.event_queue.peek().map(|(time_due, _)| time_due)
+    }
     pub fn work_until(&mut self, target_sim_time: SimSeconds) {
         while self
             .event_queue
@@ -120,12 +154,20 @@ impl Simulation {
         self.time.advance_sim_time_to(target_sim_time);
     }
     pub fn catch_up(&mut self, elapsed_real_time: RealSeconds) {
+        Rc::clone(&self.additional_event_handlers)
+            .borrow_mut()
+            .on_catch_up(self, elapsed_real_time);
+
         // a bit complicated because we need to account for the possibility that the speed of time
         // changes mid-way; otherwise like `work_until`
         let mut reference_sim_time = self.time.now();
         let mut remaining_real_time = elapsed_real_time;
         let mut last_speed = self.time.speed();
         let mut target_sim_time = self.time.after(remaining_real_time);
+        // while paused, events are only processed by stepping (see `step`)
+        if self.time.paused() {
+            return;
+        }
 
         while self
             .event_queue
@@ -135,6 +177,10 @@ impl Simulation {
         {
             self.process_next_event();
 
+            // something (e.g., a breakpoint) paused time - stop right here
+            if self.time.paused() {
+                return;
+            }
             // the speed of time changed for some reason!
             if self.time.speed() != last_speed {
                 remaining_real_time -=
@@ -149,18 +195,45 @@ impl Simulation {
     pub fn process_next_event(&mut self) {
         let (time_due, event) = self.event_queue.pop().unwrap();
         self.time.advance_sim_time_to(time_due);
-        if let Err(e) = self.handle_event(event) {
+        if let Err(e) = self.handle_event(event, None) {
             self.log(format!("Error handling event: {}", e));
         }
     }
-    fn handle_event(&mut self, event: Event) -> Result<(), Box<dyn Error>> {
-        command::Handler.handle_event(self, event)?;
+    /// If `reactions` is given, the names of the handlers that reacted to `event` are added to it.
+    fn handle_event(
+        &mut self,
+        event: Event,
+        reactions: Option<&mut Vec<String>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let handled = self.dispatch_event(event, reactions);
+        // even if handling failed, the entities that belong to the event aren't needed anymore
+        let despawned = Despawner.handle_event(self, event);
+        handled.and(despawned)
+    }
+    fn dispatch_event(
+        &mut self,
+        event: Event,
+        reactions: Option<&mut Vec<String>>,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(reactions) = reactions {
+            if let Event::Command(command) = event {
+                reactions.push(format!(
+                    "{:?}",
+                    &**self.world.get::<Box<dyn Command>>(command)?
+                ));
+            }
+            command::Handler.handle_event(self, event)?;
 
-        Rc::clone(&self.additional_event_handlers)
-            .borrow_mut()
-            .handle_event(self, event)?;
+            Rc::clone(&self.additional_event_handlers)
+                .borrow_mut()
+                .handle_event_traced(self, event, reactions)?;
+        } else {
+            command::Handler.handle_event(self, event)?;
 
-        Despawner.handle_event(self, event)?;
+            Rc::clone(&self.additional_event_handlers)
+                .borrow_mut()
+                .handle_event(self, event)?;
+        }
         Ok(())
     }
     pub fn name(&self, entity: Entity) -> String {
@@ -184,6 +257,22 @@ impl Default for Simulation {
     }
 }
 
+/// A type name (as returned by `std::any::type_name`) without the module paths, e.g., `Foo<Bar>`
+/// instead of `crate::foo::Foo<crate::bar::Bar>`.
+pub(crate) fn short_type_name(type_name: &str) -> String {
+    let mut parts = type_name.split("::").peekable();
+    let mut short_name = String::new();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_some() {
+            // drop the module name at the end of this part
+            short_name.push_str(part.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_'));
+        } else {
+            short_name.push_str(part);
+        }
+    }
+    short_name
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
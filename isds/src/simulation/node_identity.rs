@@ -0,0 +1,253 @@
+use super::*;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// A human-friendly label for a node, e.g., "Alice". Unlike the node's name (see
+/// `UnderlayNodeName`), labels don't need to be unique.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NodeLabel(pub String);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum NodeRole {
+    Miner,
+    Wallet,
+    Attacker,
+    LightClient,
+}
+impl fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let role = match self {
+            NodeRole::Miner => "miner",
+            NodeRole::Wallet => "wallet",
+            NodeRole::Attacker => "attacker",
+            NodeRole::LightClient => "light client",
+        };
+        write!(f, "{}", role)
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct NodeRoles(pub BTreeSet<NodeRole>);
+impl NodeRoles {
+    pub fn has(&self, role: NodeRole) -> bool {
+        self.0.contains(&role)
+    }
+}
+
+/// Everything about a node's identity that can be chosen when spawning it. Without a name, the
+/// node gets a unique random name.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct NodeIdentity {
+    pub name: Option<String>,
+    pub label: Option<String>,
+    pub roles: BTreeSet<NodeRole>,
+}
+impl NodeIdentity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+    pub fn with_role(mut self, role: NodeRole) -> Self {
+        self.roles.insert(role);
+        self
+    }
+}
+
+/// Like `SpawnRandomNodes`, but for a single node with a specific identity.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpawnNode(pub NodeIdentity);
+impl Command for SpawnNode {
+    fn execute(&self, sim: &mut Simulation) -> Result<(), Box<dyn Error>> {
+        sim.spawn_node(self.0.clone())?;
+        Ok(())
+    }
+}
+
+/// Like `ForRandomNode`, but never picks a node with the given role, e.g., no light client for
+/// mining.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForRandomNodeWithout<A: EntityAction>(pub NodeRole, pub A);
+impl<A: EntityAction> Command for ForRandomNodeWithout<A> {
+    fn execute(&self, sim: &mut Simulation) -> Result<(), Box<dyn Error>> {
+        let mut nodes = sim.all_nodes();
+        nodes.retain(|&node| !sim.has_role(node, self.0));
+        let node = *nodes
+            .choose(&mut sim.rng)
+            .ok_or_else(|| format!("Not enough nodes that aren't a {}?", self.0))?;
+        self.1.execute_for(sim, node)
+    }
+}
+
+impl Simulation {
+    /// Spawns a node at a random position. Fails if the requested name is already taken.
+    pub fn spawn_node(&mut self, identity: NodeIdentity) -> Result<Entity, Box<dyn Error>> {
+        let node = self.spawn_random_node();
+        self.apply_identity(node, identity)
+    }
+    pub fn spawn_node_at_position(
+        &mut self,
+        identity: NodeIdentity,
+        x: f32,
+        y: f32,
+    ) -> Result<Entity, Box<dyn Error>> {
+        let node = self.spawn_random_node_at_position(x, y);
+        self.apply_identity(node, identity)
+    }
+    fn apply_identity(
+        &mut self,
+        node: Entity,
+        identity: NodeIdentity,
+    ) -> Result<Entity, Box<dyn Error>> {
+        if let Some(name) = identity.name {
+            if let Err(e) = self.rename_node(node, &name) {
+                self.world.despawn(node)?;
+                return Err(e);
+            }
+        }
+        if let Some(label) = identity.label {
+            self.set_node_label(node, &label)?;
+        }
+        for role in identity.roles {
+            self.add_node_role(node, role)?;
+        }
+        Ok(node)
+    }
+    pub fn rename_node(&mut self, node: Entity, name: &str) -> Result<(), Box<dyn Error>> {
+        match self.node_by_name(name) {
+            Some(other_node) if other_node != node => {
+                Err(format!("There already is a node named {}.", name).into())
+            }
+            _ => {
+                *self.world.get_mut::<UnderlayNodeName>(node)? = UnderlayNodeName(name.to_string());
+                Ok(())
+            }
+        }
+    }
+    pub fn node_by_name(&self, name: &str) -> Option<Entity> {
+        self.world
+            .query::<&UnderlayNodeName>()
+            .iter()
+            .find(|(_, node_name)| node_name.0 == name)
+            .map(|(node, _)| node)
+    }
+    pub fn set_node_label(&mut self, node: Entity, label: &str) -> Result<(), Box<dyn Error>> {
+        self.world.get::<UnderlayNodeName>(node)?;
+        self.world.insert_one(node, NodeLabel(label.to_string()))?;
+        Ok(())
+    }
+    pub fn nodes_labeled(&self, label: &str) -> Vec<Entity> {
+        self.world
+            .query::<&NodeLabel>()
+            .iter()
+            .filter(|(_, node_label)| node_label.0 == label)
+            .map(|(node, _)| node)
+            .collect()
+    }
+    pub fn add_node_role(&mut self, node: Entity, role: NodeRole) -> Result<(), Box<dyn Error>> {
+        self.world.get::<UnderlayNodeName>(node)?;
+        if let Ok(roles) = self.world.query_one_mut::<&mut NodeRoles>(node) {
+            roles.0.insert(role);
+        } else {
+            self.world
+                .insert_one(node, NodeRoles(BTreeSet::from([role])))?;
+        }
+        Ok(())
+    }
+    pub fn remove_node_role(&mut self, node: Entity, role: NodeRole) {
+        if let Ok(mut roles) = self.world.get_mut::<NodeRoles>(node) {
+            roles.0.remove(&role);
+        }
+    }
+    pub fn has_role(&self, node: Entity, role: NodeRole) -> bool {
+        self.world
+            .get::<NodeRoles>(node)
+            .is_ok_and(|roles| roles.has(role))
+    }
+    pub fn nodes_with_role(&self, role: NodeRole) -> Vec<Entity> {
+        self.world
+            .query::<&NodeRoles>()
+            .iter()
+            .filter(|(_, roles)| roles.has(role))
+            .map(|(node, _)| node)
+            .collect()
+    }
+    /// The node's label if it has one, otherwise the same as `name`.
+    pub fn label(&self, entity: Entity) -> String {
+        match self.world.get::<NodeLabel>(entity) {
+            Ok(label) => label.0.clone(),
+            Err(_) => self.name(entity),
+        }
+    }
+    /// Name, label and roles of a node, e.g., for tooltips.
+    pub fn describe_node(&self, node: Entity) -> String {
+        let mut description = self.name(node);
+        if let Ok(label) = self.world.get::<NodeLabel>(node) {
+            description = format!("{} ({})", label.0, description);
+        }
+        if let Ok(roles) = self.world.get::<NodeRoles>(node) {
+            if !roles.0.is_empty() {
+                let roles: Vec<String> = roles.0.iter().map(|r| r.to_string()).collect();
+                description = format!("{}: {}", description, roles.join(", "));
+            }
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn default_names_are_unique() {
+        let mut sim = Simulation::new();
+        let nodes: Vec<Entity> = (0..2000).map(|_| sim.spawn_random_node()).collect();
+
+        let names: BTreeSet<String> = nodes.iter().map(|&node| sim.name(node)).collect();
+        assert_eq!(nodes.len(), names.len());
+    }
+
+    #[wasm_bindgen_test]
+    fn spawn_node_with_identity_and_look_it_up() {
+        let mut sim = Simulation::new();
+        sim.spawn_random_node();
+        let alice = sim
+            .spawn_node(
+                NodeIdentity::named("alice")
+                    .with_label("Alice")
+                    .with_role(NodeRole::Wallet),
+            )
+            .unwrap();
+
+        assert_eq!(Some(alice), sim.node_by_name("alice"));
+        assert_eq!(vec![alice], sim.nodes_labeled("Alice"));
+        assert_eq!(vec![alice], sim.nodes_with_role(NodeRole::Wallet));
+        assert!(sim.nodes_with_role(NodeRole::Miner).is_empty());
+        assert_eq!("Alice", sim.label(alice));
+        assert_eq!("Alice (alice): wallet", sim.describe_node(alice));
+    }
+
+    #[wasm_bindgen_test]
+    fn names_cannot_be_taken_twice() {
+        let mut sim = Simulation::new();
+        let bob = sim.spawn_node(NodeIdentity::named("bob")).unwrap();
+        let other = sim.spawn_random_node();
+
+        assert!(sim.spawn_node(NodeIdentity::named("bob")).is_err());
+        assert!(sim.rename_node(other, "bob").is_err());
+        assert!(sim.rename_node(bob, "bob").is_ok());
+        assert_eq!(2, sim.all_nodes().len());
+    }
+}
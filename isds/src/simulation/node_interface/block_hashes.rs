@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+use super::*;
+use blockchain_types::*;
+
+/// The part of a block header that Bitcoin actually hashes, attached to blocks next to their
+/// `BlockHeader` if the simulation was configured with `enable_block_hashes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashedHeader {
+    /// The `hash` of the previous block, all zeros for the first block.
+    pub prev_hash: Hash256,
+    pub merkle_root: Hash256,
+    pub time: SimSeconds,
+    /// What miners vary until the hash has enough leading zero bits.
+    pub nonce: u64,
+    pub hash: Hash256,
+}
+impl HashedHeader {
+    /// The hash that the header should have, given its other fields.
+    pub fn compute_hash(&self) -> Hash256 {
+        let mut data = vec![];
+        data.extend(self.prev_hash.0);
+        data.extend(self.merkle_root.0);
+        data.extend(self.time.to_bits().to_le_bytes());
+        data.extend(self.nonce.to_le_bytes());
+        Hash256::of(&data)
+    }
+}
+
+/// Changes the value of a transaction's first output after the fact - something that shouldn't
+/// be possible, but the simulation lets us try. Every block hash from the transaction's block
+/// onwards then no longer matches the data, see `Simulation::broken_hash_links`.
+#[derive(Debug, Clone)]
+pub struct TamperWithTransaction(pub u64);
+impl EntityAction for TamperWithTransaction {
+    fn execute_for(&self, sim: &mut Simulation, tx_id: Entity) -> Result<(), Box<dyn Error>> {
+        let mut tx = sim.world.get_mut::<Transaction>(tx_id)?;
+        let output = tx
+            .outputs
+            .first_mut()
+            .ok_or("The transaction has no outputs to tamper with!")?;
+        output.value = self.0;
+        Ok(())
+    }
+}
+
+impl Simulation {
+    /// Makes newly spawned blocks carry a `HashedHeader`. Their miners search for a nonce so that
+    /// the block hash starts with `leading_zero_bits` zero bits, which takes about
+    /// `2^leading_zero_bits` tries, so keep this small.
+    pub fn enable_block_hashes(&mut self, leading_zero_bits: usize) {
+        self.block_hashes = Some(leading_zero_bits);
+    }
+    pub fn block_hashes(&self) -> Option<usize> {
+        self.block_hashes
+    }
+    /// The Merkle root of what the block currently contains, or `None` if the contents were
+    /// pruned.
+    pub fn merkle_root_of(&self, block_id: Entity) -> Option<Hash256> {
+        self.transaction_hashes(block_id)
+            .map(|tx_hashes| merkle_root(&tx_hashes))
+    }
+    /// Recomputes the hashes of `blocks` (a chain, oldest first) from the data they contain,
+    /// trusting only the `prev_hash` of the oldest one. Returns the blocks whose recomputed hash
+    /// no longer matches the one that they were mined with. Since each block hash depends on the
+    /// previous one, tampering with a single block breaks all blocks after it, too.
+    pub fn broken_hash_links(&self, blocks: &[Entity]) -> HashSet<Entity> {
+        let mut broken = HashSet::new();
+        let mut prev_hash = None;
+        for &block_id in blocks {
+            let header = match self.world.get::<HashedHeader>(block_id) {
+                Ok(header) => *header,
+                Err(_) => {
+                    prev_hash = None;
+                    continue;
+                }
+            };
+            let recomputed = HashedHeader {
+                prev_hash: prev_hash.unwrap_or(header.prev_hash),
+                // pruned contents can't be checked anymore
+                merkle_root: self.merkle_root_of(block_id).unwrap_or(header.merkle_root),
+                ..header
+            }
+            .compute_hash();
+            if recomputed != header.hash {
+                broken.insert(block_id);
+            }
+            prev_hash = Some(recomputed);
+        }
+        broken
+    }
+}
+
+impl<'a> NodeInterface<'a> {
+    pub fn get_hashed_header(&mut self, block_id: Entity) -> Option<HashedHeader> {
+        self.sim
+            .world
+            .get::<HashedHeader>(block_id)
+            .ok()
+            .map(|h| *h)
+    }
+    /// Attaches a `HashedHeader` to a freshly spawned block, searching for a nonce first.
+    pub(super) fn hash_block(&mut self, block_header: &BlockHeader, leading_zero_bits: usize) {
+        let prev_hash = block_header
+            .id_prev
+            .and_then(|id_prev| self.get_hashed_header(id_prev))
+            .map_or(Hash256::default(), |prev| prev.hash);
+        let mut header = HashedHeader {
+            prev_hash,
+            merkle_root: block_header.merkle_root,
+            time: block_header.time,
+            nonce: 0,
+            hash: Hash256::default(),
+        };
+        loop {
+            header.hash = header.compute_hash();
+            if header.hash.leading_zero_bits() >= leading_zero_bits {
+                break;
+            }
+            header.nonce += 1;
+        }
+        self.sim.world.insert_one(block_header.id, header).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn tampering_breaks_all_later_hash_links() {
+        let mut sim = Simulation::new();
+        sim.enable_block_hashes(4);
+        let node = sim.spawn_random_node();
+        let mut node_interface = sim.node_interface(node);
+        let mut blocks = vec![];
+        let mut tx_ids = vec![];
+        for value in 1..=4 {
+            let tx_id =
+                node_interface.spawn_transaction(Transaction::from_faucet("Bob".into(), value));
+            let header = node_interface.spawn_block(blocks.last().copied(), [tx_id]);
+            let hashed = node_interface.get_hashed_header(header.id).unwrap();
+            assert!(hashed.hash.leading_zero_bits() >= 4);
+            if let Some(&prev) = blocks.last() {
+                let prev = node_interface.get_hashed_header(prev).unwrap();
+                assert_eq!(prev.hash, hashed.prev_hash);
+            }
+            blocks.push(header.id);
+            tx_ids.push(tx_id);
+        }
+        assert!(sim.broken_hash_links(&blocks).is_empty());
+
+        ForSpecific(tx_ids[1], TamperWithTransaction(1000))
+            .execute(&mut sim)
+            .unwrap();
+        let broken = sim.broken_hash_links(&blocks);
+        assert_eq!(HashSet::from_iter(blocks[1..].iter().copied()), broken);
+    }
+}
@@ -1,19 +1,35 @@
 use hecs::QueryItem;
-use std::collections::BTreeSet;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 
 use super::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BlockHeader {
     /// Substitute for the block's hash. We don't want to deal with the complexity of actual block
-    /// hashes.
+    /// hashes everywhere - if you want them anyway, see `block_hashes::HashedHeader`.
     pub id: Entity,
     /// `None` only for first block.
     pub id_prev: Option<Entity>,
     /// Not usually part of header but handy for us here.
     pub height: usize,
+    /// When the block was mined. Unlike in Bitcoin, miners can't lie about this.
+    pub time: SimSeconds,
+    /// Expected number of hashes needed to find this block. Bitcoin encodes this as a *target*
+    /// that the block's hash must not exceed; the difficulty is inversely proportional to it.
+    pub difficulty: OrderedFloat<f64>,
+    /// Commits to the block's transactions, so that light clients can check `MerkleProof`s
+    /// without knowing the block's contents.
+    pub merkle_root: Hash256,
 }
 
+/// The node that spawned a block. Not part of the block itself, but handy for statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinedBy(pub Entity);
+
+/// The transactions of a block, in the order of the leaves of its Merkle tree (see
+/// `merkle_tree`).
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct BlockContents(BTreeSet<Entity>);
 impl BlockContents {
@@ -43,13 +59,345 @@ impl IntoIterator for BlockContents {
     }
 }
 
+/// Points to an output of an earlier transaction, i.e., to some coins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OutPoint {
+    pub tx_id: Entity,
+    pub index: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Transaction {
-    pub from: Address,
+pub struct TxOutput {
     pub to: Address,
     pub value: u64,
 }
 
+/// A transaction spends the outputs of earlier transactions (its inputs) and creates new outputs
+/// that are worth at most as much, like in Bitcoin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// The address that owns all the inputs. This stands in for signatures: We trust that
+    /// nobody builds transactions from an address that isn't theirs.
+    pub from: Address,
+    pub inputs: Vec<OutPoint>,
+    /// By convention, the first output pays the recipient and a second one returns the change to
+    /// `from`.
+    pub outputs: Vec<TxOutput>,
+    /// What the sender pays to the miner of the block that contains the transaction. In Bitcoin,
+    /// the fee is implicit: it's whatever the inputs are worth beyond the outputs.
+    pub fee: u64,
+}
+impl Transaction {
+    /// A transaction from the `FAUCET` that creates `value` new coins for `to`.
+    pub fn from_faucet(to: Address, value: u64) -> Self {
+        Self {
+            from: FAUCET.to_string(),
+            inputs: vec![],
+            outputs: vec![TxOutput { to, value }],
+            fee: 0,
+        }
+    }
+    /// The transaction with which the miner of a block pays itself the block's subsidy and fees.
+    pub fn coinbase(miner: Address, value: u64) -> Self {
+        Self {
+            from: COINBASE.to_string(),
+            inputs: vec![],
+            outputs: vec![TxOutput { to: miner, value }],
+            fee: 0,
+        }
+    }
+    pub fn is_coinbase(&self) -> bool {
+        self.from == COINBASE && self.inputs.is_empty()
+    }
+    /// The recipient of the first output.
+    pub fn to(&self) -> &Address {
+        self.outputs.first().map_or(&self.from, |output| &output.to)
+    }
+    /// The value of the first output.
+    pub fn value(&self) -> u64 {
+        self.outputs.first().map_or(0, |output| output.value)
+    }
+    /// Whether the transaction spends coins of `address` or pays to it.
+    pub fn involves(&self, address: &str) -> bool {
+        self.from == address || self.outputs.iter().any(|output| output.to == address)
+    }
+    pub fn output_value(&self) -> u64 {
+        self.outputs.iter().map(|output| output.value).sum()
+    }
+    /// Two different transactions that spend the same coins conflict: At most one of them can
+    /// end up on the blockchain, the other one is a *double spend*.
+    pub fn conflicts_with(&self, other: &Transaction) -> bool {
+        self != other && self.inputs.iter().any(|input| other.inputs.contains(input))
+    }
+}
+
+/// Transactions from this address don't need inputs, they create new coins out of thin air.
+/// Bitcoin has no such thing, but it's handy for giving users some coins to play with.
+pub const FAUCET: &str = "Faucet";
+/// The sender of coinbase transactions, see `Transaction::coinbase`. Only miners can create them,
+/// and only as part of their blocks.
+pub const COINBASE: &str = "Coinbase";
+
+/// Why a node refuses a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionError {
+    /// The coins have been spent already, on the blockchain or by a transaction in the mempool.
+    DoubleSpend(OutPoint),
+    /// The node doesn't know the output, e.g., because the transaction that created it hasn't
+    /// arrived yet.
+    UnknownInput(OutPoint),
+    /// The coins belong to another address than the transaction's sender.
+    NotOwner(OutPoint),
+    /// The coins come from a coinbase transaction that isn't buried deeply enough yet.
+    ImmatureCoinbase(OutPoint),
+    /// The outputs and the fee are worth more than the inputs.
+    Overspend { inputs: u64, outputs: u64 },
+    /// Only the faucet can create coins.
+    NoInputs,
+    /// The sender doesn't own enough coins, as far as the node knows.
+    InsufficientFunds { available: u64, needed: u64 },
+}
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DoubleSpend(_) => write!(f, "It double-spends coins."),
+            Self::UnknownInput(_) => write!(f, "It spends coins that I don't know."),
+            Self::NotOwner(_) => write!(f, "It spends coins of someone else."),
+            Self::ImmatureCoinbase(_) => write!(f, "It spends newly mined coins too early."),
+            Self::Overspend { inputs, outputs } => write!(
+                f,
+                "It spends {} toshis, but its inputs are only worth {}.",
+                outputs, inputs
+            ),
+            Self::NoInputs => write!(f, "It has no inputs."),
+            Self::InsufficientFunds { available, needed } => write!(
+                f,
+                "Only {} of the {} toshis needed are available.",
+                available, needed
+            ),
+        }
+    }
+}
+impl Error for TransactionError {}
+
+/// The unspent transaction outputs (UTXOs) of a chain, i.e., who owns which coins after its last
+/// block. Keeps enough *undo data* for disconnecting blocks again, like Bitcoin Core does.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet {
+    outputs: HashMap<OutPoint, TxOutput>,
+    /// The outputs that each block spent.
+    undo_data: HashMap<Entity, Vec<(OutPoint, TxOutput)>>,
+}
+impl UtxoSet {
+    pub fn get(&self, out_point: &OutPoint) -> Option<&TxOutput> {
+        self.outputs.get(out_point)
+    }
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+    /// The unspent outputs of `address`, sorted by out point.
+    pub fn owned_by<'a>(&'a self, address: &'a str) -> Vec<(OutPoint, &'a TxOutput)> {
+        let mut owned: Vec<(OutPoint, &TxOutput)> = self
+            .outputs
+            .iter()
+            .filter(|(_, output)| output.to == address)
+            .map(|(&out_point, output)| (out_point, output))
+            .collect();
+        owned.sort_by_key(|&(out_point, _)| out_point);
+        owned
+    }
+    pub fn balance(&self, address: &str) -> u64 {
+        self.owned_by(address)
+            .into_iter()
+            .map(|(_, output)| output.value)
+            .sum()
+    }
+    /// Applies the transactions of a block. They may spend each other's outputs.
+    pub fn connect_block(&mut self, block_id: Entity, txes: &[(Entity, Transaction)]) {
+        for (tx_id, tx) in txes.iter() {
+            for (index, output) in tx.outputs.iter().enumerate() {
+                let out_point = OutPoint {
+                    tx_id: *tx_id,
+                    index,
+                };
+                self.outputs.insert(out_point, output.clone());
+            }
+        }
+        let mut spent = vec![];
+        for (_, tx) in txes.iter() {
+            for input in tx.inputs.iter() {
+                if let Some(output) = self.outputs.remove(input) {
+                    spent.push((*input, output));
+                }
+            }
+        }
+        self.undo_data.insert(block_id, spent);
+    }
+    /// Reverts `connect_block`. Returns `false` if we don't have the undo data for the block.
+    pub fn disconnect_block(&mut self, block_id: Entity, txes: &[(Entity, Transaction)]) -> bool {
+        let spent = match self.undo_data.remove(&block_id) {
+            Some(spent) => spent,
+            None => return false,
+        };
+        for (out_point, output) in spent {
+            self.outputs.insert(out_point, output);
+        }
+        for (tx_id, tx) in txes.iter() {
+            for index in 0..tx.outputs.len() {
+                self.outputs.remove(&OutPoint {
+                    tx_id: *tx_id,
+                    index,
+                });
+            }
+        }
+        true
+    }
+    /// Drops undo data of blocks that will never be disconnected, e.g., because they are buried
+    /// deep enough.
+    pub fn forget_undo_data(&mut self, mut f: impl FnMut(Entity) -> bool) {
+        self.undo_data.retain(|&block_id, _| !f(block_id));
+    }
+}
+
+/// A (double) SHA-256 hash, like the ones Bitcoin uses for blocks and transactions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hash256(pub [u8; 32]);
+impl Hash256 {
+    /// Bitcoin hashes everything twice: `SHA-256(SHA-256(data))`.
+    pub fn of(data: &[u8]) -> Self {
+        Self(Sha256::digest(Sha256::digest(data)).into())
+    }
+    pub fn leading_zero_bits(&self) -> usize {
+        let zero_bytes = self.0.iter().take_while(|&&byte| byte == 0).count();
+        zero_bytes * 8
+            + self
+                .0
+                .get(zero_bytes)
+                .map_or(0, |byte| byte.leading_zeros() as usize)
+    }
+    /// The first 4 bytes in hex, which is enough to tell hashes apart in a small simulation.
+    pub fn short(&self) -> String {
+        hex::encode(&self.0[..4])
+    }
+}
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl Transaction {
+    /// Hashes everything in the transaction. Inputs point to earlier transactions by their entity,
+    /// which stands in for the earlier transaction's hash.
+    pub fn hash(&self) -> Hash256 {
+        let mut data = vec![];
+        push_str(&mut data, &self.from);
+        for input in self.inputs.iter() {
+            data.extend(input.tx_id.to_bits().get().to_le_bytes());
+            data.extend((input.index as u64).to_le_bytes());
+        }
+        for output in self.outputs.iter() {
+            push_str(&mut data, &output.to);
+            data.extend(output.value.to_le_bytes());
+        }
+        data.extend(self.fee.to_le_bytes());
+        Hash256::of(&data)
+    }
+}
+fn push_str(data: &mut Vec<u8>, s: &str) {
+    data.extend((s.len() as u64).to_le_bytes());
+    data.extend(s.as_bytes());
+}
+
+fn hash_pair(left: Hash256, right: Hash256) -> Hash256 {
+    Hash256::of(&[left.0, right.0].concat())
+}
+
+/// All levels of the Merkle tree over the hashes of a block's transactions, from the leaves up to
+/// the root. Each level combines the hashes of the level below pairwise, duplicating the last one
+/// if there is an odd number of them, like Bitcoin does.
+pub fn merkle_tree(tx_hashes: &[Hash256]) -> Vec<Vec<Hash256>> {
+    let mut levels = vec![tx_hashes.to_vec()];
+    while levels.last().unwrap().len() > 1 {
+        let next_level = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| hash_pair(pair[0], *pair.last().unwrap()))
+            .collect();
+        levels.push(next_level);
+    }
+    levels
+}
+
+/// A block header only needs to contain this to commit to all of the block's transactions. All
+/// zeros for an empty block.
+pub fn merkle_root(tx_hashes: &[Hash256]) -> Hash256 {
+    merkle_tree(tx_hashes)
+        .last()
+        .and_then(|level| level.first())
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Proves that a transaction is part of a block to someone who only knows the block's Merkle root,
+/// without sending them the block's other transactions: It contains the hashes that the
+/// transaction's hash gets combined with on its way up to the root, one per level of the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// The position of the transaction in the block.
+    pub index: usize,
+    /// Leaf level first.
+    pub siblings: Vec<Hash256>,
+}
+impl MerkleProof {
+    /// `None` if there is no transaction at `index`.
+    pub fn new(tx_hashes: &[Hash256], index: usize) -> Option<Self> {
+        if index >= tx_hashes.len() {
+            return None;
+        }
+        let levels = merkle_tree(tx_hashes);
+        let siblings = levels[..levels.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(depth, level)| {
+                let position = index >> depth;
+                // the last hash of an odd level is its own sibling
+                *level.get(position ^ 1).unwrap_or(&level[position])
+            })
+            .collect();
+        Some(Self { index, siblings })
+    }
+    /// The Merkle root that `tx_hash` leads to, given the proof.
+    pub fn root_from(&self, tx_hash: Hash256) -> Hash256 {
+        self.siblings
+            .iter()
+            .enumerate()
+            .fold(tx_hash, |hash, (depth, &sibling)| {
+                if (self.index >> depth) & 1 == 0 {
+                    hash_pair(hash, sibling)
+                } else {
+                    hash_pair(sibling, hash)
+                }
+            })
+    }
+    pub fn verify(&self, tx_hash: Hash256, merkle_root: Hash256) -> bool {
+        self.root_from(tx_hash) == merkle_root
+    }
+}
+
+/// The UTXO set after some block, together with the heights of the coinbase transactions in it
+/// that might not be mature yet. Nodes that sync a chain whose older blocks were pruned start from
+/// such a snapshot, similar to Bitcoin Core's *assumeutxo*.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSnapshot {
+    pub utxos: UtxoSet,
+    pub coinbase_heights: HashMap<Entity, usize>,
+}
+
 pub type Address = String;
 pub const TOSHIS_PER_COIN: u64 = 10_u64.pow(8);
 
@@ -64,19 +412,36 @@ pub fn toshis_from(coins: f64) -> i64 {
 impl<'a> NodeInterface<'a> {
     /// Registers a transaction in the global database, where it is immutable via the node
     /// interface.
-    pub fn spawn_transaction(&mut self, from: Address, to: Address, value: u64) -> Entity {
-        self.sim.world.spawn((Transaction { from, to, value },))
+    pub fn spawn_transaction(&mut self, tx: Transaction) -> Entity {
+        self.sim.world.spawn((tx,))
     }
     pub fn get_transaction(&mut self, tx_id: Entity) -> Option<QueryItem<&Transaction>> {
         self.sim.world.query_one_mut::<&Transaction>(tx_id).ok()
     }
     /// Registers a block in the global database, where it is immutable via the node interface.
     /// Set `id_prev` to `None` if this will be the first block in a chain (after the virtual
-    /// genesis block).
+    /// genesis block). The block has the same difficulty as its predecessor (or 1 if it is the
+    /// first block), use `spawn_block_with_difficulty` if consensus rules say otherwise.
+    ///
+    /// Careful: Nodes running `NakamotoConsensus` reject blocks whose difficulty doesn't follow
+    /// their `DifficultyAdjustment`, whose initial difficulty is rarely 1. Spawn the first block
+    /// for them with `spawn_block_with_difficulty` and `DifficultyAdjustment::next_difficulty`,
+    /// and do the same for blocks at retargeting heights.
     pub fn spawn_block(
         &mut self,
         id_prev: Option<Entity>,
         contents: impl IntoIterator<Item = Entity>,
+    ) -> BlockHeader {
+        let difficulty = id_prev
+            .and_then(|id_prev| self.get_block_header(id_prev))
+            .map_or(1., |header| header.difficulty.into_inner());
+        self.spawn_block_with_difficulty(id_prev, contents, difficulty)
+    }
+    pub fn spawn_block_with_difficulty(
+        &mut self,
+        id_prev: Option<Entity>,
+        contents: impl IntoIterator<Item = Entity>,
+        difficulty: f64,
     ) -> BlockHeader {
         let height = if let Some(id_prev) = id_prev {
             self.get_block_header(id_prev)
@@ -86,17 +451,27 @@ impl<'a> NodeInterface<'a> {
         } else {
             1
         };
+        let block_contents: BlockContents = contents.into_iter().collect();
+        let tx_hashes: Vec<Hash256> = block_contents
+            .iter()
+            .filter_map(|&tx_id| self.get_transaction(tx_id).map(|tx| tx.hash()))
+            .collect();
         let id = self.sim.world.reserve_entity();
         let block_header = BlockHeader {
             id,
             id_prev,
             height,
+            time: self.sim.time.now(),
+            difficulty: OrderedFloat(difficulty),
+            merkle_root: merkle_root(&tx_hashes),
         };
-        let block_contents: BlockContents = contents.into_iter().collect();
         self.sim
             .world
-            .insert(id, (block_header, block_contents))
+            .insert(id, (block_header, block_contents, MinedBy(self.node)))
             .unwrap();
+        if let Some(leading_zero_bits) = self.sim.block_hashes() {
+            self.hash_block(&block_header, leading_zero_bits);
+        }
         block_header
     }
     pub fn get_block(
@@ -117,6 +492,72 @@ impl<'a> NodeInterface<'a> {
             .query_one_mut::<&BlockContents>(block_id)
             .ok()
     }
+    pub fn get_block_miner(&mut self, block_id: Entity) -> Option<Entity> {
+        self.sim
+            .world
+            .query_one_mut::<&MinedBy>(block_id)
+            .ok()
+            .map(|miner| miner.0)
+    }
+    /// `None` if the transaction isn't part of the block or the block's contents were pruned.
+    pub fn merkle_proof(&mut self, block_id: Entity, tx_id: Entity) -> Option<MerkleProof> {
+        let index = self
+            .get_block_contents(block_id)?
+            .iter()
+            .position(|&id| id == tx_id)?;
+        MerkleProof::new(&self.sim.transaction_hashes(block_id)?, index)
+    }
+    /// See `Simulation::save_utxo_snapshot`.
+    pub fn get_utxo_snapshot(&mut self, block_id: Entity) -> Option<QueryItem<'_, &UtxoSnapshot>> {
+        self.sim.world.query_one_mut::<&UtxoSnapshot>(block_id).ok()
+    }
+}
+
+impl Simulation {
+    /// The hashes of a block's transactions, in the order of the leaves of its Merkle tree. `None`
+    /// if the block's contents were pruned.
+    pub fn transaction_hashes(&self, block_id: Entity) -> Option<Vec<Hash256>> {
+        let contents = self.world.get::<BlockContents>(block_id).ok()?;
+        Some(
+            contents
+                .iter()
+                .filter_map(|&tx_id| self.world.get::<Transaction>(tx_id).ok())
+                .map(|tx| tx.hash())
+                .collect(),
+        )
+    }
+    /// Removes a block (header and contents) from the global database, e.g., because it is part of
+    /// a stale fork that no node cares about anymore.
+    pub fn despawn_block(&mut self, block_id: Entity) -> Result<(), Box<dyn Error>> {
+        self.world.query_one_mut::<&BlockHeader>(block_id)?;
+        self.world.despawn(block_id)?;
+        Ok(())
+    }
+    /// Removes only the contents of a block from the global database, keeping its header. This is
+    /// similar to what pruned Bitcoin nodes do with old blocks.
+    pub fn prune_block_contents(&mut self, block_id: Entity) -> Result<(), Box<dyn Error>> {
+        self.world.remove_one::<BlockContents>(block_id)?;
+        Ok(())
+    }
+    /// Remembers what the UTXO set looked like after a block, for nodes that can't compute it
+    /// themselves because the contents of the blocks before were pruned.
+    pub fn save_utxo_snapshot(
+        &mut self,
+        block_id: Entity,
+        snapshot: UtxoSnapshot,
+    ) -> Result<(), Box<dyn Error>> {
+        self.world.insert_one(block_id, snapshot)?;
+        Ok(())
+    }
+    pub fn remove_utxo_snapshot(&mut self, block_id: Entity) -> Result<(), Box<dyn Error>> {
+        self.world.remove_one::<UtxoSnapshot>(block_id)?;
+        Ok(())
+    }
+    pub fn despawn_transaction(&mut self, tx_id: Entity) -> Result<(), Box<dyn Error>> {
+        self.world.query_one_mut::<&Transaction>(tx_id)?;
+        self.world.despawn(tx_id)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -129,10 +570,55 @@ mod tests {
 
     impl Transaction {
         fn new(from: Address, to: Address, value: u64) -> Self {
-            Self { from, to, value }
+            Self::spending(from, vec![], to, value)
+        }
+        fn spending(from: Address, inputs: Vec<OutPoint>, to: Address, value: u64) -> Self {
+            Self {
+                from,
+                inputs,
+                outputs: vec![TxOutput { to, value }],
+                fee: 0,
+            }
         }
     }
 
+    #[wasm_bindgen_test]
+    fn hashes_are_double_sha256() {
+        assert_eq!(
+            "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50",
+            Hash256::of(b"hello").to_string()
+        );
+        let mut hash = Hash256::default();
+        hash.0[1] = 0b0010_0000;
+        assert_eq!(10, hash.leading_zero_bits());
+    }
+
+    #[wasm_bindgen_test]
+    fn merkle_roots_duplicate_the_last_hash_of_odd_levels() {
+        let hashes: Vec<Hash256> = (0..3_u8).map(|i| Hash256::of(&[i])).collect();
+        let pair = hash_pair;
+        assert_eq!(hashes[0], merkle_root(&hashes[..1]));
+        assert_eq!(
+            pair(pair(hashes[0], hashes[1]), pair(hashes[2], hashes[2])),
+            merkle_root(&hashes)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn merkle_proofs_verify_only_their_transaction() {
+        let hashes: Vec<Hash256> = (0..5_u8).map(|i| Hash256::of(&[i])).collect();
+        let root = merkle_root(&hashes);
+        for (index, &hash) in hashes.iter().enumerate() {
+            let proof = MerkleProof::new(&hashes, index).unwrap();
+            assert_eq!(3, proof.siblings.len());
+            assert!(proof.verify(hash, root));
+            assert!(!proof.verify(Hash256::of(b"forged"), root));
+        }
+        let proof = MerkleProof::new(&hashes, 2).unwrap();
+        assert!(!proof.verify(hashes[3], root));
+        assert_eq!(None, MerkleProof::new(&hashes, 5));
+    }
+
     #[wasm_bindgen_test]
     fn transactions_are_spawned_and_gettable() {
         let mut sim = Simulation::new();
@@ -146,16 +632,70 @@ mod tests {
         let a2 = "Bob".to_string();
         let a3 = "Charlie".to_string();
 
-        let tx_1_id = node.spawn_transaction(a1.clone(), a2.clone(), 123);
-        let tx_2_id = node.spawn_transaction(a2.clone(), a3.clone(), 155);
-
         let expected_tx_1 = Transaction::new(a1.clone(), a2.clone(), 123);
         let expected_tx_2 = Transaction::new(a2.clone(), a3.clone(), 155);
 
+        let tx_1_id = node.spawn_transaction(expected_tx_1.clone());
+        let tx_2_id = node.spawn_transaction(expected_tx_2.clone());
+
         assert_eq!(Some(expected_tx_1), node.get_transaction(tx_1_id).cloned());
         assert_eq!(Some(expected_tx_2), node.get_transaction(tx_2_id).cloned());
     }
 
+    #[wasm_bindgen_test]
+    fn transactions_spending_the_same_coins_conflict() {
+        let mut sim = Simulation::new();
+        sim.do_now(SpawnRandomNodes(1));
+        sim.catch_up(10.);
+
+        let node_id = sim.pick_random_node().unwrap();
+        let mut node = sim.node_interface(node_id);
+
+        let coins_tx_id = node.spawn_transaction(Transaction::from_faucet("Bob".into(), 100));
+        let coins = OutPoint {
+            tx_id: coins_tx_id,
+            index: 0,
+        };
+        let tx_1 = Transaction::spending("Bob".into(), vec![coins], "Charlie".into(), 100);
+        let tx_2 = Transaction::spending("Bob".into(), vec![coins], "Bob".into(), 100);
+        let unrelated = Transaction::new("Bob".into(), "Charlie".into(), 100);
+
+        assert!(tx_1.conflicts_with(&tx_2));
+        assert!(!tx_1.conflicts_with(&tx_1));
+        assert!(!tx_1.conflicts_with(&unrelated));
+        assert!(!unrelated.conflicts_with(&unrelated.clone()));
+    }
+
+    #[wasm_bindgen_test]
+    fn utxo_set_follows_connected_and_disconnected_blocks() {
+        let mut world = World::new();
+        let (block_1, block_2) = (world.spawn(()), world.spawn(()));
+        let (tx_1_id, tx_2_id) = (world.spawn(()), world.spawn(()));
+        let coins = OutPoint {
+            tx_id: tx_1_id,
+            index: 0,
+        };
+        let tx_1 = Transaction::from_faucet("Alice".into(), 100);
+        let mut tx_2 = Transaction::spending("Alice".into(), vec![coins], "Bob".into(), 60);
+        tx_2.outputs.push(TxOutput {
+            to: "Alice".into(),
+            value: 40,
+        });
+
+        let mut utxos = UtxoSet::default();
+        utxos.connect_block(block_1, &[(tx_1_id, tx_1)]);
+        assert_eq!(100, utxos.balance("Alice"));
+        utxos.connect_block(block_2, &[(tx_2_id, tx_2.clone())]);
+        assert_eq!(40, utxos.balance("Alice"));
+        assert_eq!(60, utxos.balance("Bob"));
+        assert_eq!(None, utxos.get(&coins));
+
+        assert!(utxos.disconnect_block(block_2, &[(tx_2_id, tx_2.clone())]));
+        assert_eq!(100, utxos.balance("Alice"));
+        assert_eq!(0, utxos.balance("Bob"));
+        assert!(!utxos.disconnect_block(block_2, &[(tx_2_id, tx_2)]));
+    }
+
     #[wasm_bindgen_test]
     fn block_headers_are_spawned_and_gettable() {
         let mut sim = Simulation::new();
@@ -207,8 +747,8 @@ mod tests {
         let a2 = "Bob".to_string();
         let a3 = "Charlie".to_string();
 
-        let tx_1_id = node.spawn_transaction(a1.clone(), a2.clone(), 123);
-        let tx_2_id = node.spawn_transaction(a2.clone(), a3.clone(), 155);
+        let tx_1_id = node.spawn_transaction(Transaction::new(a1.clone(), a2.clone(), 123));
+        let tx_2_id = node.spawn_transaction(Transaction::new(a2.clone(), a3.clone(), 155));
 
         let block_1_header = node.spawn_block(None, []);
         let block_2_header = node.spawn_block(Some(block_1_header.id), vec![tx_1_id, tx_2_id]);
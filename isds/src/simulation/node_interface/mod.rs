@@ -2,6 +2,7 @@ use super::*;
 
 use hecs::QueryItem;
 
+pub mod block_hashes;
 pub mod blockchain_types;
 
 pub struct NodeInterface<'a> {
@@ -22,6 +23,10 @@ impl<'a> NodeInterface<'a> {
         self.sim
             .log(format!("{}: {}", self.sim.name(self.node), message));
     }
+    /// See `Simulation::notify`.
+    pub fn notify<N: Payload>(&mut self, notification: N) {
+        self.sim.notify(notification);
+    }
     pub fn send_message<P: Payload>(&mut self, dest: Entity, payload: P) -> Entity {
         let source = self.node;
         self.sim.send_message(source, dest, payload)
@@ -34,7 +39,58 @@ impl<'a> NodeInterface<'a> {
         let source = self.node;
         self.sim.send_messages(source, dest, payloads)
     }
+    pub fn send_sized_message<P: Payload>(
+        &mut self,
+        dest: Entity,
+        payload: P,
+        size: usize,
+    ) -> Entity {
+        let source = self.node;
+        self.sim.send_sized_message(source, dest, payload, size)
+    }
+    pub fn send_sized_messages<P: Payload>(
+        &mut self,
+        dest: Entity,
+        payloads: impl IntoIterator<Item = (P, usize)>,
+    ) -> Vec<Entity> {
+        let source = self.node;
+        self.sim.send_sized_messages(source, dest, payloads)
+    }
+    /// See `Simulation::set_timer`.
+    pub fn set_timer<P: Payload>(&mut self, duration: SimSeconds, payload: P) -> Entity {
+        let node = self.node;
+        self.sim.set_timer(node, duration, payload)
+    }
     pub fn rng(&mut self) -> &mut impl Rng {
         &mut self.sim.rng
     }
+    pub fn id(&self) -> Entity {
+        self.node
+    }
+    pub fn now(&self) -> SimSeconds {
+        self.sim.time.now()
+    }
+    /// See `Simulation::label`.
+    pub fn label(&self) -> String {
+        self.sim.label(self.node)
+    }
+    /// See `Simulation::has_role`.
+    pub fn has_role(&self, role: NodeRole) -> bool {
+        self.sim.has_role(self.node, role)
+    }
+    /// Whether a peer has a role. This stands in for what Bitcoin nodes tell each other about
+    /// themselves when they connect, e.g., that they are light clients that don't serve blocks.
+    pub fn peer_has_role(&self, peer: Entity, role: NodeRole) -> bool {
+        self.sim.has_role(peer, role)
+    }
+    /// Makes `peer` and this node peers of each other.
+    pub fn connect(&mut self, peer: Entity) {
+        self.sim.add_peer(self.node, peer);
+        self.sim.add_peer(peer, self.node);
+    }
+    /// The opposite of `connect`.
+    pub fn disconnect(&mut self, peer: Entity) {
+        self.sim.remove_peer(self.node, peer);
+        self.sim.remove_peer(peer, self.node);
+    }
 }
@@ -0,0 +1,67 @@
+use super::*;
+
+/// Marks entities that carry a notification, see `Simulation::notify`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Notification {
+    pub type_name: &'static str,
+}
+
+impl Simulation {
+    /// Tells all event handlers that something happened, e.g., that a node switched to another
+    /// chain (see `nakamoto_consensus::TipChanged`). Handlers get the notification as a `Generic`
+    /// event right after the current event and can read it using `notification`. Afterwards, it is
+    /// gone.
+    pub fn notify<N: Payload>(&mut self, notification: N) {
+        let type_name = std::any::type_name::<N>();
+        let entity = self.world.spawn((notification, Notification { type_name }));
+        self.schedule_now(Event::Generic(entity));
+    }
+    /// The notification of type `N` that `event` carries, if it carries one.
+    pub fn notification<N: Payload>(&self, event: Event) -> Option<N> {
+        match event {
+            Event::Generic(entity) => self.world.get::<N>(entity).ok().map(|n| (*n).clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Ping(u32);
+
+    #[derive(Default)]
+    struct PingCollector(Vec<Ping>);
+    impl EventHandler for PingCollector {
+        fn handle_event(
+            &mut self,
+            sim: &mut Simulation,
+            event: Event,
+        ) -> Result<(), Box<dyn Error>> {
+            self.0.extend(sim.notification::<Ping>(event));
+            Ok(())
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn notifications_reach_handlers_and_get_despawned() {
+        let mut sim = Simulation::new();
+        let collector = sim.add_event_handler(PingCollector::default());
+        sim.notify(Ping(23));
+        sim.notify(42_u64);
+        let entities_before = sim.world.len();
+        assert_eq!("Ping notification.", sim.step().unwrap().description);
+        sim.catch_up(1.);
+
+        let handlers = sim.additional_event_handlers();
+        let handlers = handlers.borrow();
+        let collected = &handlers.get::<PingCollector>(collector).unwrap().0;
+        assert_eq!(&vec![Ping(23)], collected);
+        assert_eq!(entities_before - 2, sim.world.len());
+    }
+}
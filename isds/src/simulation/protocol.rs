@@ -40,6 +40,16 @@ pub trait Protocol: 'static {
         Ok(())
     }
 
+    /// What to do once a timer that the node set (see `NodeInterface::set_timer`) fires. Optional
+    /// because not every protocol needs timers.
+    fn handle_timer(
+        &self,
+        _node: NodeInterface,
+        _timer_payload: Self::MessagePayload,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
     /// What to do once the peer set changes. Optional because not every protocol needs peers or
     /// wants to use the default peer set abstraction.
     fn handle_peer_set_update(
@@ -85,8 +95,12 @@ impl<P: Protocol> InvokeProtocolForAllNodes<P> {
                 }
                 // not my message payload, not my business
             }
-            NodeEvent::TimerFired(_) => {
-                todo!();
+            NodeEvent::TimerFired(timer) => {
+                if let Ok(payload) = sim.world.query_one_mut::<&P::MessagePayload>(timer) {
+                    let payload = payload.clone();
+                    self.0.handle_timer(sim.node_interface(node), payload)?;
+                }
+                // not my timer, not my business
             }
             NodeEvent::PeerSetChanged(update) => {
                 self.0
@@ -0,0 +1,396 @@
+use super::*;
+use blockchain_types::BlockHeader;
+
+/// Decides how fast simulation time should run, based on the speed that the user chose.
+pub trait SpeedPolicy {
+    fn name(&self) -> &str;
+    /// Called after each event, before `speed` is asked for.
+    fn observe(&mut self, _sim: &Simulation, _event: Event) {}
+    /// The speed at which time should run from now on. Called after each event (with
+    /// `elapsed_real_time` set to 0) and at the start of each frame.
+    fn speed(&mut self, sim: &Simulation, base_speed: f64, elapsed_real_time: RealSeconds) -> f64;
+}
+
+/// Lets one of several `SpeedPolicy`s control the speed of `Time`. The first policy is always
+/// `ConstantSpeed`, i.e., "do nothing".
+///
+/// Changes of the speed made by others (e.g., the user using `TimeControls`) are interpreted as
+/// relative changes of the base speed that the policies work with. Don't use this together with
+/// `SlowDownOnMessages` - they would fight each other.
+pub struct SpeedController {
+    policies: Vec<Box<dyn SpeedPolicy>>,
+    selected: usize,
+    base_speed: Option<f64>,
+    last_speed_set: f64,
+}
+impl SpeedController {
+    pub fn new() -> Self {
+        Self {
+            policies: vec![Box::new(ConstantSpeed)],
+            selected: 0,
+            base_speed: None,
+            last_speed_set: 0.,
+        }
+    }
+    pub fn with(mut self, policy: impl SpeedPolicy + 'static) -> Self {
+        self.policies.push(Box::new(policy));
+        self
+    }
+    /// Like `with`, but also makes `policy` the active one.
+    pub fn with_selected(self, policy: impl SpeedPolicy + 'static) -> Self {
+        let mut controller = self.with(policy);
+        controller.selected = controller.policies.len() - 1;
+        controller
+    }
+    pub fn policy_names(&self) -> Vec<&str> {
+        self.policies.iter().map(|p| p.name()).collect()
+    }
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+    pub fn select(&mut self, sim: &mut Simulation, index: usize) {
+        if index < self.policies.len() {
+            self.selected = index;
+            self.apply(sim, 0.);
+        }
+    }
+    pub fn select_next(&mut self, sim: &mut Simulation) {
+        self.select(sim, (self.selected + 1) % self.policies.len());
+    }
+    fn apply(&mut self, sim: &mut Simulation, elapsed_real_time: RealSeconds) {
+        let current_speed = sim.time.speed();
+        let base_speed = match self.base_speed {
+            // somebody else changed the speed since we last did
+            Some(base_speed) if current_speed != self.last_speed_set => {
+                if self.last_speed_set > 0. {
+                    base_speed * current_speed / self.last_speed_set
+                } else {
+                    current_speed
+                }
+            }
+            Some(base_speed) => base_speed,
+            None => current_speed,
+        };
+        self.base_speed = Some(base_speed);
+
+        let speed = self.policies[self.selected].speed(sim, base_speed, elapsed_real_time);
+        if speed != current_speed {
+            sim.time.set_speed(speed);
+        }
+        self.last_speed_set = speed;
+    }
+}
+impl Default for SpeedController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl EventHandler for SpeedController {
+    fn handle_event(&mut self, sim: &mut Simulation, event: Event) -> Result<(), Box<dyn Error>> {
+        // all policies keep track of what is going on, so that we can switch between them anytime
+        for policy in self.policies.iter_mut() {
+            policy.observe(sim, event);
+        }
+        self.apply(sim, 0.);
+        Ok(())
+    }
+    fn on_catch_up(&mut self, sim: &mut Simulation, elapsed_real_time: RealSeconds) {
+        self.apply(sim, elapsed_real_time);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConstantSpeed;
+impl SpeedPolicy for ConstantSpeed {
+    fn name(&self) -> &str {
+        "Constant speed"
+    }
+    fn speed(&mut self, _: &Simulation, base_speed: f64, _: RealSeconds) -> f64 {
+        base_speed
+    }
+}
+
+/// Like `SlowDownOnMessages`, but as a `SpeedPolicy`.
+#[derive(Debug, Clone)]
+pub struct SlowMotionOnMessages {
+    slow_speed: f64,
+    is_relevant_message: fn(Entity, &World) -> bool,
+    messages_in_flight: usize,
+}
+impl SlowMotionOnMessages {
+    pub fn new(slow_speed: f64, is_relevant_message: fn(Entity, &World) -> bool) -> Self {
+        Self {
+            slow_speed,
+            is_relevant_message,
+            messages_in_flight: 0,
+        }
+    }
+}
+impl SpeedPolicy for SlowMotionOnMessages {
+    fn name(&self) -> &str {
+        "Slow down on messages"
+    }
+    fn observe(&mut self, sim: &Simulation, event: Event) {
+        match event {
+            Event::Node(_, NodeEvent::MessageSent(message))
+                if (self.is_relevant_message)(message, &sim.world) =>
+            {
+                self.messages_in_flight += 1;
+            }
+            Event::Node(_, NodeEvent::MessageArrived(message))
+                if (self.is_relevant_message)(message, &sim.world) =>
+            {
+                // they might have been in flight before we were created
+                self.messages_in_flight = self.messages_in_flight.saturating_sub(1);
+            }
+            _ => {}
+        }
+    }
+    fn speed(&mut self, _: &Simulation, base_speed: f64, _: RealSeconds) -> f64 {
+        if self.messages_in_flight > 0 {
+            self.slow_speed.min(base_speed)
+        } else {
+            base_speed
+        }
+    }
+}
+
+/// Eases between the speeds requested by another policy instead of jumping, changing the speed by
+/// at most a factor of two every `half_life`.
+///
+/// Slowing down is the exception: as the interesting stuff might be over before the next frame
+/// comes around, we jump down right away - but only up to a factor of `MAX_JUMP` above the target.
+#[derive(Debug, Clone)]
+pub struct Eased<P: SpeedPolicy> {
+    inner: P,
+    name: String,
+    half_life: RealSeconds,
+    current_speed: Option<f64>,
+}
+impl<P: SpeedPolicy> Eased<P> {
+    const MAX_JUMP: f64 = 8.;
+
+    pub fn new(inner: P, half_life: RealSeconds) -> Self {
+        let name = format!("{} (smoothly)", inner.name());
+        Self {
+            inner,
+            name,
+            half_life,
+            current_speed: None,
+        }
+    }
+}
+impl<P: SpeedPolicy> SpeedPolicy for Eased<P> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn observe(&mut self, sim: &Simulation, event: Event) {
+        self.inner.observe(sim, event)
+    }
+    fn speed(&mut self, sim: &Simulation, base_speed: f64, elapsed_real_time: RealSeconds) -> f64 {
+        let target_speed = self.inner.speed(sim, base_speed, elapsed_real_time);
+        let current_speed = self.current_speed.unwrap_or(target_speed);
+        let speed = if current_speed <= 0. || target_speed <= 0. {
+            target_speed
+        } else {
+            // we ease on a logarithmic scale, where changes of speed are perceived equally
+            let log_distance = (target_speed / current_speed).log2();
+            let max_step = elapsed_real_time / self.half_life;
+            let mut speed = current_speed * log_distance.clamp(-max_step, max_step).exp2();
+            if speed > target_speed * Self::MAX_JUMP {
+                speed = target_speed * Self::MAX_JUMP;
+            }
+            speed
+        };
+        self.current_speed = Some(speed);
+        speed
+    }
+}
+
+/// Runs at the base speed while messages are in flight, but otherwise fast-forwards so that the
+/// next event happens within `max_idle_time` (in real time).
+#[derive(Debug, Clone)]
+pub struct SkipIdlePeriods {
+    max_idle_time: RealSeconds,
+    /// Next event we are heading for and the speed that we chose for getting there.
+    heading_for: Option<(SimSeconds, f64)>,
+}
+impl SkipIdlePeriods {
+    pub fn new(max_idle_time: RealSeconds) -> Self {
+        Self {
+            max_idle_time,
+            heading_for: None,
+        }
+    }
+}
+impl SpeedPolicy for SkipIdlePeriods {
+    fn name(&self) -> &str {
+        "Skip idle periods"
+    }
+    fn speed(&mut self, sim: &Simulation, base_speed: f64, _: RealSeconds) -> f64 {
+        let is_idle = sim.world.query::<&UnderlayMessage>().iter().len() == 0;
+        let next_event_time = sim.next_event_time();
+        match (is_idle, next_event_time) {
+            (true, Some(next_event_time)) if next_event_time > sim.time.now() => {
+                // keep the speed as long as we are heading for the same event, otherwise we would
+                // slow down more and more and never get there
+                match self.heading_for {
+                    Some((time, speed)) if time == next_event_time => speed.max(base_speed),
+                    _ => {
+                        let speed = ((next_event_time - sim.time.now()).into_inner()
+                            / self.max_idle_time)
+                            .max(base_speed);
+                        self.heading_for = Some((next_event_time, speed));
+                        speed
+                    }
+                }
+            }
+            _ => {
+                self.heading_for = None;
+                base_speed
+            }
+        }
+    }
+}
+
+/// Chooses the speed so that, on average, each new block takes roughly `real_time_per_block`.
+/// Block intervals are estimated using a moving average, starting at `expected_block_interval`.
+/// The base speed is ignored.
+#[derive(Debug, Clone)]
+pub struct TargetTimePerBlock {
+    real_time_per_block: RealSeconds,
+    mean_block_interval: f64,
+    last_block_count: Option<usize>,
+    last_block_time: SimSeconds,
+}
+impl TargetTimePerBlock {
+    /// Weight of the newest block interval in the moving average.
+    const SMOOTHING: f64 = 0.2;
+
+    pub fn new(real_time_per_block: RealSeconds, expected_block_interval: SimSeconds) -> Self {
+        Self {
+            real_time_per_block,
+            mean_block_interval: expected_block_interval.into_inner(),
+            last_block_count: None,
+            last_block_time: OrderedFloat(0.),
+        }
+    }
+}
+impl SpeedPolicy for TargetTimePerBlock {
+    fn name(&self) -> &str {
+        "Fixed duration per block"
+    }
+    fn observe(&mut self, sim: &Simulation, _: Event) {
+        let block_count = sim.world.query::<&BlockHeader>().iter().len();
+        if self
+            .last_block_count
+            .is_some_and(|count| block_count > count)
+        {
+            let interval = (sim.time.now() - self.last_block_time).into_inner();
+            self.mean_block_interval =
+                Self::SMOOTHING * interval + (1. - Self::SMOOTHING) * self.mean_block_interval;
+            self.last_block_time = sim.time.now();
+        }
+        // might also decrease because of garbage collection
+        self.last_block_count = Some(block_count);
+    }
+    fn speed(&mut self, _: &Simulation, _: f64, _: RealSeconds) -> f64 {
+        self.mean_block_interval / self.real_time_per_block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[derive(Debug, Clone)]
+    struct DoNothing;
+    impl Command for DoNothing {
+        fn execute(&self, _: &mut Simulation) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn user_speed_changes_carry_over_to_base_speed() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(
+            SpeedController::new().with_selected(SlowMotionOnMessages::new(0.01, |_, _| true)),
+        );
+        sim.time.set_speed(100.);
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+
+        sim.send_message(node1, node2, ());
+        sim.catch_up(0.);
+        assert_eq!(0.01, sim.time.speed());
+
+        // e.g., user clicks on "slow down" while message is in flight
+        sim.time.slow_down_tenfold_clamped();
+        sim.catch_up(1000.);
+        assert!((sim.time.speed() - 10.).abs() < 1e-9);
+    }
+
+    #[wasm_bindgen_test]
+    fn skip_idle_periods_reaches_next_event_in_time() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(SpeedController::new().with_selected(SkipIdlePeriods::new(0.5)));
+        let base_speed = sim.time.speed();
+        sim.do_in(SimSeconds::from(600.), DoNothing);
+
+        for _ in 0..31 {
+            sim.catch_up(1. / 60.); // 60 FPS
+        }
+        assert!(sim.time.now() >= SimSeconds::from(600.));
+        assert_eq!(base_speed, sim.time.speed());
+    }
+
+    #[wasm_bindgen_test]
+    fn easing_never_changes_speed_too_much_at_once() {
+        let mut sim = Simulation::new();
+        let i = sim.add_event_handler(
+            SpeedController::new()
+                .with(SlowMotionOnMessages::new(0.01, |_, _| true))
+                .with(Eased::new(
+                    SlowMotionOnMessages::new(0.01, |_, _| true),
+                    0.5,
+                )),
+        );
+        sim.time.set_speed(100.);
+        let handlers = sim.additional_event_handlers();
+        handlers
+            .borrow_mut()
+            .get_mut::<SpeedController>(i)
+            .unwrap()
+            .select(&mut sim, 2);
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+
+        sim.send_message(node1, node2, ());
+        sim.catch_up(0.);
+        assert_eq!(0.08, sim.time.speed());
+
+        sim.catch_up(0.5);
+        assert!(sim.time.speed() >= 0.04);
+        assert!(sim.time.speed() < 0.08);
+    }
+
+    #[wasm_bindgen_test]
+    fn target_time_per_block_adapts_to_block_intervals() {
+        let mut policy = TargetTimePerBlock::new(10., SimSeconds::from(600.));
+        let mut sim = Simulation::new();
+        assert_eq!(60., policy.speed(&sim, 1., 0.));
+
+        let node = sim.spawn_random_node();
+        policy.observe(&sim, Event::Node(node, NodeEvent::Poke));
+
+        sim.work_until(SimSeconds::from(100.));
+        sim.node_interface(node).spawn_block(None, vec![]);
+        policy.observe(&sim, Event::Node(node, NodeEvent::Poke));
+
+        assert_eq!(50., policy.speed(&sim, 1., 0.));
+    }
+}
@@ -0,0 +1,215 @@
+use super::*;
+
+/// What happened while processing a single event using `Simulation::step` and friends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessedEvent {
+    pub time: SimSeconds,
+    pub event: Event,
+    pub description: String,
+    /// The command that was executed and/or the event handlers that reacted to the event by
+    /// logging something or scheduling new events.
+    pub reactions: Vec<String>,
+}
+
+impl Simulation {
+    /// So that stepping doesn't hang if there is no matching event but an endless stream of other
+    /// events (e.g., repeated commands).
+    pub const MAX_EVENTS_SKIPPED_PER_STEP: usize = 100_000;
+
+    /// Like `process_next_event`, but also tells (and remembers in `last_step`) what happened.
+    /// Returns `None` if there was no event.
+    pub fn step(&mut self) -> Option<ProcessedEvent> {
+        let (time_due, event) = self.event_queue.pop()?;
+        self.time.advance_sim_time_to(time_due);
+
+        let description = self.describe_event(event);
+        let mut reactions = vec![];
+        if let Err(e) = self.handle_event(event, Some(&mut reactions)) {
+            self.log(format!("Error handling event: {}", e));
+        }
+        self.last_step = Some(ProcessedEvent {
+            time: time_due,
+            event,
+            description,
+            reactions,
+        });
+        self.last_step.clone()
+    }
+    /// Whether `last_step` is what the simulation is paused at, i.e., time didn't move on since.
+    pub fn last_step_is_current(&self) -> bool {
+        self.time.paused()
+            && self
+                .last_step
+                .as_ref()
+                .is_some_and(|step| step.time == self.time.now())
+    }
+    /// Processes events up to and including the next event for which `is_target` is true.
+    pub fn step_until(&mut self, is_target: impl Fn(Event) -> bool) -> Option<ProcessedEvent> {
+        for _ in 0..Self::MAX_EVENTS_SKIPPED_PER_STEP {
+            let (_, event) = self.event_queue.peek()?;
+            if is_target(event) {
+                return self.step();
+            }
+            self.process_next_event();
+        }
+        None
+    }
+    pub fn step_to_next_message_delivery(&mut self) -> Option<ProcessedEvent> {
+        self.step_until(|event| matches!(event, Event::Node(_, NodeEvent::MessageArrived(_))))
+    }
+    pub fn step_to_next_command(&mut self) -> Option<ProcessedEvent> {
+        self.step_until(|event| matches!(event, Event::Command(_)))
+    }
+    /// A human-readable description of an event that is yet to be processed.
+    pub fn describe_event(&self, event: Event) -> String {
+        match event {
+            Event::Command(command) => match self.world.get::<Box<dyn Command>>(command) {
+                Ok(command) => format!("Command: {:?}", &**command),
+                Err(_) => "Command (missing)".to_string(),
+            },
+            Event::Node(node, node_event) => {
+                let node_name = self.name(node);
+                match node_event {
+                    NodeEvent::MessageSent(message) => {
+                        let size = match self.world.get::<MessageSize>(message) {
+                            Ok(size) => format!(" of {} bytes", size.0),
+                            Err(_) => String::new(),
+                        };
+                        match self.message_endpoints(message) {
+                            Some((_, dest)) => {
+                                format!("{} sent a message{} to {}.", node_name, size, dest)
+                            }
+                            None => format!("{} sent a message{}.", node_name, size),
+                        }
+                    }
+                    NodeEvent::MessageArrived(message) => match self.message_endpoints(message) {
+                        Some((source, _)) => {
+                            format!("A message from {} arrived at {}.", source, node_name)
+                        }
+                        None => format!("A message arrived at {}.", node_name),
+                    },
+                    NodeEvent::TimerFired(_) => format!("A timer of {} fired.", node_name),
+                    NodeEvent::PeerSetChanged(PeerSetUpdate::PeerAdded(peer)) => {
+                        format!("{} added {} as a peer.", node_name, self.name(peer))
+                    }
+                    NodeEvent::PeerSetChanged(PeerSetUpdate::PeerRemoved(peer)) => {
+                        format!("{} removed {} from its peers.", node_name, self.name(peer))
+                    }
+                    NodeEvent::Poke => format!("{} got poked.", node_name),
+                }
+            }
+            Event::Generic(entity) => match self.world.get::<notifications::Notification>(entity) {
+                Ok(notification) => {
+                    format!("{} notification.", short_type_name(notification.type_name))
+                }
+                Err(_) => format!("Generic event ({:?}).", entity),
+            },
+        }
+    }
+    fn message_endpoints(&self, message: Entity) -> Option<(String, String)> {
+        self.world
+            .get::<UnderlayMessage>(message)
+            .ok()
+            .map(|m| (self.name(m.source), self.name(m.dest)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[derive(Debug, Clone)]
+    struct LogSomething;
+    impl EventHandler for LogSomething {
+        fn handle_event(
+            &mut self,
+            sim: &mut Simulation,
+            event: Event,
+        ) -> Result<(), Box<dyn Error>> {
+            if let Event::Node(_, NodeEvent::MessageArrived(_)) = event {
+                sim.log("Got it!".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn step_to_next_message_delivery_and_see_who_reacted() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(LogSomething);
+
+        let node1 = sim.spawn_random_node();
+        let node2 = sim.spawn_random_node();
+        sim.do_now(PokeSpecificNode(node1));
+        sim.send_message(node1, node2, ());
+
+        let step = sim.step_to_next_message_delivery().unwrap();
+        assert_eq!(
+            Event::Node(node2, NodeEvent::MessageArrived(step_message(&step))),
+            step.event
+        );
+        assert_eq!(
+            format!(
+                "A message from {} arrived at {}.",
+                sim.name(node1),
+                sim.name(node2)
+            ),
+            step.description
+        );
+        assert_eq!(vec!["LogSomething".to_string()], step.reactions);
+        assert_eq!(Some(step.clone()), sim.last_step);
+
+        sim.do_in(SimSeconds::from(1.), PokeSpecificNode(node1));
+        sim.process_next_event();
+        assert_eq!(Some(step), sim.last_step);
+        sim.time.pause();
+        assert!(!sim.last_step_is_current());
+    }
+
+    #[wasm_bindgen_test]
+    fn catching_up_while_paused_keeps_the_step() {
+        let mut sim = Simulation::new();
+        let node = sim.spawn_random_node();
+        sim.do_now(PokeSpecificNode(node));
+        sim.do_now(PokeSpecificNode(node));
+        sim.do_now(PokeSpecificNode(node));
+
+        sim.time.pause();
+        let step = sim.step();
+        assert!(sim.last_step_is_current());
+        let next_event_time = sim.next_event_time();
+        let queue_length = sim.event_queue.len();
+
+        sim.catch_up(1. / 60.);
+
+        assert_eq!(step, sim.last_step);
+        assert!(sim.last_step_is_current());
+        assert_eq!(next_event_time, sim.next_event_time());
+        assert_eq!(queue_length, sim.event_queue.len());
+    }
+
+    #[wasm_bindgen_test]
+    fn step_to_next_command() {
+        let mut sim = Simulation::new();
+        let node = sim.spawn_random_node();
+        sim.send_message(node, node, ());
+        sim.do_in(SimSeconds::from(1000.), PokeSpecificNode(node));
+
+        let step = sim.step_to_next_command().unwrap();
+
+        assert_eq!(SimSeconds::from(1000.), sim.time.now());
+        assert!(step.description.starts_with("Command: PokeSpecificNode"));
+        assert!(step.reactions[0].starts_with("PokeSpecificNode"));
+        assert_eq!(None, sim.step_to_next_command());
+    }
+
+    fn step_message(step: &ProcessedEvent) -> Entity {
+        match step.event {
+            Event::Node(_, NodeEvent::MessageArrived(message)) => message,
+            _ => panic!("not a message delivery"),
+        }
+    }
+}
@@ -46,6 +46,9 @@ impl Time {
     pub fn slow_down_tenfold_clamped(&mut self) {
         self.speed_factor = (self.speed_factor / 10f64).clamp(0.001f64, 1000f64);
     }
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
     pub fn toggle_paused(&mut self) {
         self.paused = !self.paused;
     }
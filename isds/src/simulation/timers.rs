@@ -0,0 +1,73 @@
+use super::*;
+
+impl Simulation {
+    /// Lets `node` handle `payload` after `duration`, like a message that it sends to itself (see
+    /// `Protocol::handle_timer`). Timers can't be cancelled - protocols that need this can put a
+    /// counter into the payload and ignore timers whose counter is outdated.
+    pub fn set_timer<P: Payload>(
+        &mut self,
+        node: Entity,
+        duration: SimSeconds,
+        payload: P,
+    ) -> Entity {
+        let timer = self.world.spawn((payload,));
+        self.schedule_in(duration, Event::Node(node, NodeEvent::TimerFired(timer)));
+        timer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    #[derive(Debug, Clone, Copy, Default)]
+    struct Alarms(usize);
+
+    /// Sets an alarm when poked, and another one whenever an alarm goes off, until three went off.
+    struct AlarmClock;
+    impl Protocol for AlarmClock {
+        type MessagePayload = usize;
+
+        fn handle_message(
+            &self,
+            _node: NodeInterface,
+            _underlay_message: UnderlayMessage,
+            _message_payload: Self::MessagePayload,
+        ) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+        fn handle_poke(&self, mut node: NodeInterface) -> Result<(), Box<dyn Error>> {
+            node.set_timer(SimSeconds::from(10.), 1_usize);
+            Ok(())
+        }
+        fn handle_timer(
+            &self,
+            mut node: NodeInterface,
+            timer_payload: Self::MessagePayload,
+        ) -> Result<(), Box<dyn Error>> {
+            node.get::<Alarms>().0 = timer_payload;
+            if timer_payload < 3 {
+                node.set_timer(SimSeconds::from(10.), timer_payload + 1);
+            }
+            Ok(())
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn timers_fire_after_their_duration() {
+        let mut sim = Simulation::new();
+        sim.add_event_handler(InvokeProtocolForAllNodes(AlarmClock));
+        let node = sim.spawn_random_node();
+        sim.do_now(PokeSpecificNode(node));
+        sim.work_until(SimSeconds::from(25.));
+        assert_eq!(2, sim.world.get::<Alarms>(node).unwrap().0);
+        let entities_before = sim.world.len();
+        sim.work_until(SimSeconds::from(100.));
+        assert_eq!(3, sim.world.get::<Alarms>(node).unwrap().0);
+        // the fired timers got despawned
+        assert_eq!(entities_before - 1, sim.world.len());
+    }
+}
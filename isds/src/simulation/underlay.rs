@@ -49,6 +49,7 @@ pub struct UnderlayConfig {
     width: f32,
     height: f32,
     message_speed: f64,
+    bandwidth: f64,
 }
 impl UnderlayConfig {
     pub fn new(width: f32, height: f32) -> Self {
@@ -58,10 +59,13 @@ impl UnderlayConfig {
             width,
             height,
             message_speed,
+            // 8 Mbit/s
+            bandwidth: 1_000_000.,
         }
     }
 }
 
+/// Unique among all nodes; see also `NodeLabel`.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct UnderlayNodeName(pub String);
 
@@ -97,6 +101,11 @@ impl UnderlayLine {
     }
 }
 
+/// Size of a message in bytes. Only messages sent with `send_sized_message` have one, and only
+/// they take time to transmit in addition to the latency.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MessageSize(pub usize);
+
 #[derive(Debug, Copy, Clone)]
 pub struct UnderlayMessage {
     pub source: Entity,
@@ -111,12 +120,13 @@ impl Simulation {
         self.underlay_config.height
     }
     pub fn spawn_random_node(&mut self) -> Entity {
+        let name = self.unique_random_node_name();
         self.world
-            .spawn(random_node(&self.underlay_config, &mut self.rng))
+            .spawn(random_node(name, &self.underlay_config, &mut self.rng))
     }
     pub fn spawn_random_node_at_position(&mut self, x: f32, y: f32) -> Entity {
-        self.world
-            .spawn(random_node_at_position(x, y, &mut self.rng))
+        let name = self.unique_random_node_name();
+        self.world.spawn(random_node_at_position(name, x, y))
     }
     pub fn despawn_most_crowded_node(&mut self) -> Result<(), String> {
         if let Some(node) = self.most_crowded_node() {
@@ -167,6 +177,65 @@ impl Simulation {
         }
         message_entities
     }
+    /// Bytes per second that a node can send to each of its peers.
+    pub fn set_underlay_bandwidth(&mut self, bytes_per_second: f64) {
+        self.underlay_config.bandwidth = bytes_per_second;
+    }
+    pub fn send_sized_message<P: Payload>(
+        &mut self,
+        source: Entity,
+        dest: Entity,
+        payload: P,
+        size: usize,
+    ) -> Entity {
+        self.send_sized_messages(source, dest, [(payload, size)])[0]
+    }
+    /// Like `send_messages`, but each message also takes time to transmit depending on its size,
+    /// and the messages are transmitted one after the other.
+    pub fn send_sized_messages<P: Payload>(
+        &mut self,
+        source: Entity,
+        dest: Entity,
+        payloads: impl IntoIterator<Item = (P, usize)>,
+    ) -> Vec<Entity> {
+        let mut start_time = self.time.now();
+        let mut message_entities = vec![];
+        for (payload, size) in payloads.into_iter() {
+            let transmission_time = size as f64 / self.underlay_config.bandwidth;
+            let (arrival_time, message_entity) =
+                self.spawn_message_entity(source, dest, start_time, payload);
+            let arrival_time = arrival_time + transmission_time;
+            self.world
+                .insert(
+                    message_entity,
+                    (
+                        MessageSize(size),
+                        TimeSpan {
+                            start: start_time,
+                            end: arrival_time,
+                        },
+                    ),
+                )
+                .unwrap();
+            self.schedule_message(source, dest, message_entity, arrival_time);
+            message_entities.push(message_entity);
+            start_time += transmission_time;
+        }
+        message_entities
+    }
+    fn unique_random_node_name(&mut self) -> String {
+        let mut max_number = 10_000;
+        loop {
+            for _ in 0..10 {
+                let name = format!("n{:#04}", self.rng.gen_range(0..max_number));
+                if self.node_by_name(&name).is_none() {
+                    return name;
+                }
+            }
+            // names are getting scarce
+            max_number *= 10;
+        }
+    }
     /// Warning: Current implementation ist not very efficient!
     fn most_crowded_node(&mut self) -> Option<Entity> {
         let all_nodes: Vec<(Entity, UnderlayPosition)> = self
@@ -242,22 +311,18 @@ impl Simulation {
 }
 
 fn random_node(
+    name: String,
     underlay_config: &UnderlayConfig,
     rng: &mut impl Rng,
 ) -> (UnderlayNodeName, UnderlayPosition) {
     random_node_at_position(
+        name,
         rng.gen_range(0f32..underlay_config.width),
         rng.gen_range(0f32..underlay_config.height),
-        rng,
     )
 }
 
-fn random_node_at_position(
-    x: f32,
-    y: f32,
-    rng: &mut impl Rng,
-) -> (UnderlayNodeName, UnderlayPosition) {
-    let name = format!("n{:#04}", rng.gen_range(0..10_000));
+fn random_node_at_position(name: String, x: f32, y: f32) -> (UnderlayNodeName, UnderlayPosition) {
     (UnderlayNodeName(name), UnderlayPosition { x, y })
 }
 
@@ -305,6 +370,28 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[wasm_bindgen_test]
+    fn sized_messages_take_time_to_transmit_one_after_the_other() {
+        let mut sim = Simulation::new();
+        sim.set_underlay_bandwidth(1000.);
+        let node1 = sim.spawn_random_node_at_position(0., 0.);
+        let node2 = sim.spawn_random_node_at_position(0., 0.);
+        let messages = sim.send_sized_messages(node1, node2, [((), 500), ((), 1000)]);
+
+        let arrival_times: Vec<SimSeconds> = messages
+            .iter()
+            .map(|&message| sim.world.get::<TimeSpan>(message).unwrap().end)
+            .collect();
+        assert_eq!(
+            vec![SimSeconds::from(0.5), SimSeconds::from(1.5)],
+            arrival_times
+        );
+        assert_eq!(
+            MessageSize(1000),
+            *sim.world.get::<MessageSize>(messages[1]).unwrap()
+        );
+    }
+
     #[wasm_bindgen_test]
     fn most_crowded_node_in_line_is_middle_node() {
         let mut sim = Simulation::new();
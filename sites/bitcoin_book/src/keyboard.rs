@@ -9,7 +9,7 @@ pub fn keyboard_shortcuts() -> Html {
         - `[←]`/`[→]` ⇨ control simulation speed
         - `[m]` ⇨ a random node will "mine" a block
         - `[t]` ⇨ a random node will send out a random transaction
-        - `[s]` ⇨ toggle slowdown on messages
+        - `[s]` ⇨ toggle slowdown on messages (or switch between speed controls)
         "#
     }
 }
@@ -63,8 +63,10 @@ fn init_keyboard_listener_with_block_mine_command(
                     e.prevent_default()
                 }
                 "m" => {
-                    sim.borrow_mut()
-                        .do_now(isds::ForRandomNode(mine_action.clone()));
+                    sim.borrow_mut().do_now(isds::ForRandomNodeWithout(
+                        isds::NodeRole::LightClient,
+                        mine_action.clone(),
+                    ));
                     e.prevent_default()
                 }
                 "t" => {
@@ -73,12 +75,16 @@ fn init_keyboard_listener_with_block_mine_command(
                 }
                 "s" => {
                     let mut sim = sim.borrow_mut();
+                    let handlers = sim.additional_event_handlers();
+                    let mut handlers = handlers.borrow_mut();
                     if let Some(slowdown_handler) =
-                        sim.additional_event_handlers()
-                            .borrow_mut()
-                            .get_mut::<isds::SlowDownOnMessages>(slowdown_handler_index)
+                        handlers.get_mut::<isds::SlowDownOnMessages>(slowdown_handler_index)
                     {
                         slowdown_handler.toggle_enabled(&mut sim);
+                    } else if let Some(speed_controller) =
+                        handlers.get_mut::<isds::SpeedController>(slowdown_handler_index)
+                    {
+                        speed_controller.select_next(&mut sim);
                     }
                     e.prevent_default()
                 }
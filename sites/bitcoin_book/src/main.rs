@@ -33,6 +33,8 @@ enum Route {
     Consensus,
     #[at("/consensus/pow")]
     ConsensusPow,
+    #[at("/consensus/attacks")]
+    ConsensusAttacks,
     #[at("/network")]
     Network,
     #[at("/network/standalone")]
@@ -52,6 +54,7 @@ impl Route {
             Route::BlockchainHashes => html! { <pages::blockchain::Hashes /> },
             Route::Consensus => html! { <pages::Consensus /> },
             Route::ConsensusPow => html! { <pages::consensus::Pow /> },
+            Route::ConsensusAttacks => html! { <pages::consensus::Attacks /> },
             Route::Network => html! { <pages::Network /> },
             Route::NetworkStandalone => html! { <pages::network::Standalone /> },
             Route::Beyond => html! { <pages::Beyond /> },
@@ -74,6 +77,7 @@ impl Route {
             Route::BlockchainHashes => "Hashes",
             Route::Consensus => "Consensus",
             Route::ConsensusPow => "Proof of Work (PoW)",
+            Route::ConsensusAttacks => "Attacks",
             Route::Network => "Network",
             Route::NetworkStandalone => "Standalone",
             Route::Beyond => "Beyond",
@@ -85,6 +89,7 @@ impl Route {
         match self {
             Route::BlockchainHashes => Some(Route::Blockchain),
             Route::ConsensusPow => Some(Route::Consensus),
+            Route::ConsensusAttacks => Some(Route::Consensus),
             Route::NetworkStandalone => Some(Route::Network),
             Route::Layers => None,
             _ => Some(Route::Layers),
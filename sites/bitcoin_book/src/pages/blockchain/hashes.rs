@@ -1,8 +1,8 @@
 use super::*;
+use isds::EntityAction;
 
 #[function_component(Hashes)]
 pub fn hashes() -> Html {
-    let (sim, node) = init_simulation();
     html! {
         <SimplePage title="What makes the blockchain so immutable?" footer=true >
             <div class="block pb-2">
@@ -12,14 +12,7 @@ pub fn hashes() -> Html {
                 </span>
             </div>
             <div class="block bp-2">
-                <isds::Isds sim={ sim.into_shared() }>
-                    <isds::BlockchainView
-                        viewing_node={ node }
-                        max_visible_blocks=8
-                        show_unconfirmed_txes=false
-                        show_header=false
-                    />
-                </isds::Isds>
+                <TamperingExample />
             </div>
             <div class="block">
                 {
@@ -30,6 +23,13 @@ pub fn hashes() -> Html {
                         This hash value, or just *hash* for short,
                         is the result of applying a hash function to all of the data in that block,
                         including the hash of the block before it.
+                        The first line of each block above shows the beginning of its hash.
+
+                        Go ahead and tamper with an old transaction!
+                        Its block's hash changes - and with it the input to the hash of the next block,
+                        and of the block after that...
+                        Every arrow from the tampered block onwards turns red:
+                        the hashes no longer match the data that they were computed from.
 
                         Let's have a look at what hash functions do!
                         Below you can play around with `SHA-256` - the specific hash function used by Bitcoin.
@@ -91,6 +91,26 @@ pub fn hashes() -> Html {
                     </div>
                 </div>
             </div>
+            <div class="block pt-5">
+                <h3 class="title is-4">{"Hashing many transactions at once"}</h3>
+                {
+                    indoc_markdown_content! { r#"
+                        A block can contain thousands of transactions.
+                        Instead of hashing all of them in one go,
+                        Bitcoin hashes each transaction on its own and then combines the hashes pairwise,
+                        again and again, until only one hash is left: the *Merkle root*.
+                        Only the Merkle root goes into the block's header, and with it into the block's hash.
+
+                        Click on a transaction below.
+                        The hashes in orange are all it takes to prove that the transaction is part of the block
+                        to someone who only knows the Merkle root -
+                        they can recompute the highlighted path up to the root themselves.
+                        And if you alter the transaction, every hash on that path changes, too.
+                        "#
+                    }
+                }
+                <MerkleExample />
+            </div>
             <div class="block pt-5">
                 <h3 class="title is-4">{"A note on cryptography..."}</h3>
                     {
@@ -111,8 +131,110 @@ pub fn hashes() -> Html {
     }
 }
 
+/// How deep the transaction that we tamper with is buried.
+const TAMPERED_BLOCK_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, Copy)]
+enum TamperingMsg {
+    Tamper,
+    Undo,
+}
+
+struct TamperingExample {
+    sim: isds::SharedSimulation,
+    node: isds::Entity,
+    tampered_tx: isds::Entity,
+    original_value: u64,
+    is_tampered: bool,
+}
+impl Component for TamperingExample {
+    type Message = TamperingMsg;
+    type Properties = ();
+
+    fn create(_: &Context<Self>) -> Self {
+        let (sim, node) = init_simulation();
+        let mut tip = sim
+            .world
+            .get::<isds::nakamoto_consensus::NakamotoNodeState>(node)
+            .unwrap()
+            .tip();
+        for _ in 1..TAMPERED_BLOCK_DEPTH {
+            tip = tip.and_then(|block_id| {
+                sim.world
+                    .get::<isds::blockchain_types::BlockHeader>(block_id)
+                    .unwrap()
+                    .id_prev
+            });
+        }
+        let tampered_tx = *sim
+            .world
+            .get::<isds::blockchain_types::BlockContents>(tip.unwrap())
+            .unwrap()
+            .iter()
+            .next()
+            .unwrap();
+        let original_value = sim
+            .world
+            .get::<isds::blockchain_types::Transaction>(tampered_tx)
+            .unwrap()
+            .value();
+        Self {
+            sim: sim.into_shared(),
+            node,
+            tampered_tx,
+            original_value,
+            is_tampered: false,
+        }
+    }
+
+    fn update(&mut self, _: &Context<Self>, msg: Self::Message) -> bool {
+        let (value, is_tampered) = match msg {
+            TamperingMsg::Tamper => (self.original_value * 100, true),
+            TamperingMsg::Undo => (self.original_value, false),
+        };
+        // the simulation is paused, so we don't schedule this for later
+        isds::block_hashes::TamperWithTransaction(value)
+            .execute_for(&mut self.sim.borrow_mut(), self.tampered_tx)
+            .unwrap();
+        self.is_tampered = is_tampered;
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        html! {
+            <isds::Isds sim={ self.sim.clone() }>
+                <isds::BlockchainView
+                    viewing_node={ self.node }
+                    max_visible_blocks=8
+                    show_unconfirmed_txes=false
+                    show_header=false
+                />
+                <div class="has-text-centered">
+                    if self.is_tampered {
+                        <button
+                            class="button"
+                            onclick={ ctx.link().callback(|_| TamperingMsg::Undo) }
+                        >
+                            { "Undo the tampering" }
+                        </button>
+                    } else {
+                        <button
+                            class="button is-danger"
+                            onclick={ ctx.link().callback(|_| TamperingMsg::Tamper) }
+                        >
+                            { format!("Tamper with a transaction {} blocks deep", TAMPERED_BLOCK_DEPTH) }
+                        </button>
+                    }
+                </div>
+            </isds::Isds>
+        }
+    }
+}
+
 fn init_simulation() -> (isds::Simulation, isds::Entity) {
     let mut sim = isds::Simulation::new();
+    // few enough leading zero bits that mining the example is quick
+    sim.enable_block_hashes(8);
     sim.add_event_handler(isds::InvokeProtocolForAllNodes(
         isds::nakamoto_consensus::NakamotoConsensus::default(),
     ));
@@ -125,3 +247,42 @@ fn init_simulation() -> (isds::Simulation, isds::Entity) {
     sim.time.toggle_paused();
     (sim, node)
 }
+
+#[function_component(MerkleExample)]
+fn merkle_example() -> Html {
+    let example = use_state(|| {
+        let mut sim = isds::Simulation::new();
+        sim.add_event_handler(isds::InvokeProtocolForAllNodes(
+            isds::nakamoto_consensus::NakamotoConsensus::default(),
+        ));
+        let node = sim.spawn_random_node();
+        for (to, value) in [
+            ("Alice", 5),
+            ("Bob", 3),
+            ("Charlie", 8),
+            ("Dave", 1),
+            ("Eve", 2),
+        ] {
+            sim.do_now(isds::ForSpecific(
+                node,
+                isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(
+                    "Faucet",
+                    to,
+                    value * isds::blockchain_types::TOSHIS_PER_COIN,
+                ),
+            ));
+        }
+        sim.do_now(isds::ForSpecific(node, isds::nakamoto_consensus::MineBlock));
+        sim.work_until(isds::SimSeconds::from(1.));
+        sim.time.toggle_paused();
+        (sim.into_shared(), node)
+    });
+    let (sim, node) = (*example).clone();
+    html! {
+        <isds::Isds { sim }>
+            <div class="box">
+                <isds::MerkleTree viewing_node={ node } highlight_class={ "has-fill-info" } />
+            </div>
+        </isds::Isds>
+    }
+}
@@ -0,0 +1,628 @@
+use super::*;
+
+#[function_component(Attacks)]
+pub fn attacks() -> Html {
+    html! {
+        <>
+        <Header title="Attacks on Nakamoto Consensus">
+            {
+                indoc_markdown_content! { r#"
+                    Bitcoin's consensus relies on the assumption that most of the hashrate is *honest*:
+                    Honest miners mine on top of the longest chain they know
+                    and publish every block they find right away.
+                    But what if some miners don't play by these rules?
+                    "#
+                }
+            }
+        </Header>
+        <Section>
+            <h3 class="title is-4">{ "Selfish mining" }</h3>
+            <div class="block">
+                {
+                    indoc_markdown_content! { r#"
+                        A *selfish miner* keeps the blocks it finds to itself and mines on its secret chain.
+                        While honest miners waste their work on blocks that will be replaced anyway,
+                        the selfish miner only publishes its blocks when the honest chain gets close:
+
+                        - If the honest miners catch up to one block behind, it publishes everything and wins.
+                        - If they catch up completely, it publishes its block and hopes that enough
+                          honest miners mine on top of it.
+                          The share of honest miners that do is called *gamma* -
+                          it depends on how well-connected the selfish miner is.
+                        - If the honest chain gets ahead, it gives up and mines on top of it.
+
+                        Ittay Eyal and Emin Gün Sirer showed in 2014 that this earns the selfish miner
+                        *more* than its fair share of the blocks if it has enough hashrate.
+                        How much is enough depends on gamma:
+                        With a gamma of zero, a third of the total hashrate does the trick.
+                        With a gamma of one half, a quarter is enough.
+
+                        Below, the node labeled "Selfish miner" does exactly that.
+                        Use the sliders to change its share of the hashrate and its gamma,
+                        and compare its share of the blocks on the longest chain with its share of the hashrate.
+                        It takes a few hundred blocks until the numbers settle!
+                        "#
+                    }
+                }
+            </div>
+            <div class="block">
+                <SelfishMiningExample />
+            </div>
+        </Section>
+        <Section>
+            <h3 class="title is-4">{ "Double spending" }</h3>
+            <div class="block">
+                {
+                    indoc_markdown_content! { r#"
+                        A payment on the blockchain is only as final as the block it is in.
+                        Mallory, an attacker with some hashrate, pays a merchant
+                        and waits until the merchant has seen the payment in a block or two and hands out the goods.
+                        Meanwhile, she secretly mines a chain that doesn't contain the payment,
+                        but a *conflicting* transaction that spends the same coins and pays them back to herself.
+                        Only one of the two transactions can be on the blockchain.
+                        If her secret chain becomes longer than the honest one,
+                        she publishes it, all nodes switch to it, and the payment to the merchant is gone.
+
+                        This is why exchanges and merchants wait for several *confirmations*,
+                        i.e., blocks on top of the block with the payment, before they consider it final:
+                        With less than half of the hashrate, the attacker's chances to catch up
+                        shrink exponentially with every confirmation.
+                        With more than half of the hashrate, she catches up sooner or later anyway -
+                        that's a *51% attack*, and no number of confirmations helps against it.
+
+                        Below, choose Mallory's share of the hashrate and how many confirmations the merchant waits for,
+                        then let her pay the merchant.
+                        Watch the payment in the merchant's wallet and in the blockchain,
+                        where transactions that spend the same coins are shown in red.
+                        If she falls too far behind, she gives up.
+                        "#
+                    }
+                }
+            </div>
+            <div class="block">
+                <DoubleSpendExample />
+            </div>
+        </Section>
+        <Section>
+            <h3 class="title is-4">{ "Eclipse attacks" }</h3>
+            <div class="block">
+                {
+                    indoc_markdown_content! { r#"
+                        A node only knows what its peers tell it.
+                        If attackers manage to occupy *all* of a node's connections,
+                        they decide which blocks and transactions it gets to see - the node is *eclipsed*.
+                        They can hold back the honest chain so that the victim's view gets stale,
+                        or feed it a chain that they mine themselves,
+                        e.g., to double-spend against the victim without needing a majority of the hashrate.
+
+                        There are two ways to get there:
+                        The attackers can open so many connections to the victim that its honest peers get pushed out.
+                        Or they flood the victim's address book with their own addresses
+                        when it asks its peers about other nodes.
+                        The next time the victim picks new peers, e.g., after a restart, it only finds attackers -
+                        as long as there are enough of them to fill all of its slots.
+                        Ethan Heilman, Alison Kendler, Aviv Zohar and Sharon Goldberg
+                        showed in 2015 that this worked against Bitcoin nodes,
+                        which is why Bitcoin Core has become much pickier about which addresses it keeps and connects to.
+
+                        Below, the attackers have a little bit of hashrate.
+                        Start one of the attacks and watch the victim fall behind the honest network.
+                        "#
+                    }
+                }
+            </div>
+            <div class="block">
+                <EclipseExample />
+            </div>
+        </Section>
+        <Footer />
+        </>
+    }
+}
+
+struct SelfishMiningExample {
+    sim: isds::SharedSimulation,
+    selfish_miner: isds::Entity,
+    honest_node: isds::Entity,
+    hashrate_share: f64,
+    gamma: f64,
+}
+
+enum SelfishMiningExampleMsg {
+    SetHashrateShare(f64),
+    SetGamma(f64),
+}
+
+impl SelfishMiningExample {
+    fn become_selfish_miner(&self) -> isds::ForSpecific<isds::selfish_mining::BecomeSelfishMiner> {
+        isds::ForSpecific(
+            self.selfish_miner,
+            isds::selfish_mining::BecomeSelfishMiner {
+                hashrate_share: self.hashrate_share,
+                gamma: self.gamma,
+            },
+        )
+    }
+}
+
+impl Component for SelfishMiningExample {
+    type Message = SelfishMiningExampleMsg;
+    type Properties = ();
+
+    fn create(_: &Context<Self>) -> Self {
+        let mut sim = isds::Simulation::new();
+        // 11 honest nodes with a hashrate of 1 each, plus the selfish miner
+        let honest_hashrate = 11.;
+        let selfish_hashrate_share = 0.3;
+        sim.add_event_handler(isds::InvokeProtocolForAllNodes(
+            isds::nakamoto_consensus::NakamotoConsensus::default().with_difficulty_adjustment(
+                isds::difficulty::DifficultyAdjustment::for_block_interval(
+                    isds::SimSeconds::from(600.),
+                    honest_hashrate / (1. - selfish_hashrate_share),
+                ),
+            ),
+        ));
+        sim.add_event_handler(isds::selfish_mining::SelfishMining);
+        sim.add_event_handler(isds::mining::Mining::new());
+        sim.do_now(isds::AtStaticIntervals::new(
+            isds::nakamoto_consensus::CollectGarbage::new(24),
+            isds::SimSeconds::from(3600.),
+        ));
+
+        let selfish_miner = sim.spawn_random_node();
+        sim.set_node_label(selfish_miner, "Selfish miner").unwrap();
+        let honest_node = sim.spawn_random_node();
+        sim.do_now(isds::SpawnRandomNodes(10));
+        sim.do_now(isds::MakeDelaunayNetwork);
+        sim.do_now(isds::ForEachNode(isds::mining::SetHashrate(1.)));
+        // a block every second or so
+        sim.time.set_speed(600.);
+
+        let example = Self {
+            sim: sim.into_shared(),
+            selfish_miner,
+            honest_node,
+            hashrate_share: selfish_hashrate_share,
+            gamma: 0.5,
+        };
+        example
+            .sim
+            .borrow_mut()
+            .do_now(example.become_selfish_miner());
+        example
+    }
+
+    fn update(&mut self, _: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            SelfishMiningExampleMsg::SetHashrateShare(hashrate_share) => {
+                self.hashrate_share = hashrate_share;
+            }
+            SelfishMiningExampleMsg::SetGamma(gamma) => {
+                self.gamma = gamma;
+            }
+        }
+        self.sim.borrow_mut().do_now(self.become_selfish_miner());
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let on_hashrate_share_input = ctx.link().callback(|e: InputEvent| {
+            let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
+            SelfishMiningExampleMsg::SetHashrateShare(input.value_as_number() / 100.)
+        });
+        let on_gamma_input = ctx.link().callback(|e: InputEvent| {
+            let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
+            SelfishMiningExampleMsg::SetGamma(input.value_as_number() / 100.)
+        });
+        html! {
+            <isds::Isds sim={ self.sim.clone() }>
+                <isds::TimeUi />
+                <div class="columns">
+                    <div class="column has-text-centered">
+                        <label>
+                            { format!("Share of the hashrate: {:.0}%", self.hashrate_share * 100.) }
+                            <br />
+                            <input
+                                type="range"
+                                min="0"
+                                max="50"
+                                value={ (self.hashrate_share * 100.).to_string() }
+                                oninput={ on_hashrate_share_input }
+                            />
+                        </label>
+                    </div>
+                    <div class="column has-text-centered">
+                        <label>
+                            { format!("Gamma: {:.0}%", self.gamma * 100.) }
+                            <br />
+                            <input
+                                type="range"
+                                min="0"
+                                max="100"
+                                value={ (self.gamma * 100.).to_string() }
+                                oninput={ on_gamma_input }
+                            />
+                        </label>
+                    </div>
+                </div>
+                <div class="box">
+                    <isds::SelfishMiningRevenueView viewing_node={ self.honest_node } />
+                </div>
+                <div class="columns">
+                    <div class="column">
+                        <div class="box">
+                            <isds::BlockchainView
+                                viewing_node={ Some(self.selfish_miner) }
+                                max_visible_blocks={ 4 }
+                                show_unconfirmed_txes={ false }
+                                highlight_class={ "has-fill-danger" }
+                            />
+                        </div>
+                    </div>
+                    <div class="column">
+                        <div class="box">
+                            <isds::BlockchainView
+                                viewing_node={ Some(self.honest_node) }
+                                max_visible_blocks={ 4 }
+                                show_unconfirmed_txes={ false }
+                                highlight_class={ "has-fill-info" }
+                            />
+                        </div>
+                    </div>
+                </div>
+                <div class="box">
+                    <isds::NetView
+                        highlight_class={ "has-fill-danger" }
+                        node_highlight_on_hover={ true }
+                        buffer_space=25.
+                    />
+                </div>
+            </isds::Isds>
+        }
+    }
+}
+
+/// Mallory's coins are paid out by the faucet in chunks of this many coins.
+const DOUBLE_SPEND_COINS: f64 = 1.;
+
+struct DoubleSpendExample {
+    sim: isds::SharedSimulation,
+    attacker: isds::Entity,
+    merchant_node: isds::Entity,
+    n_honest_nodes: usize,
+    hashrate_share: f64,
+    confirmations: usize,
+}
+
+enum DoubleSpendExampleMsg {
+    SetHashrateShare(f64),
+    SetConfirmations(usize),
+    Attack,
+}
+
+impl DoubleSpendExample {
+    fn set_attacker_hashrate(&self) -> isds::ForSpecific<isds::mining::SetHashrate> {
+        // all honest nodes have a hashrate of 1
+        let honest_hashrate = self.n_honest_nodes as f64;
+        isds::ForSpecific(
+            self.attacker,
+            isds::mining::SetHashrate(
+                honest_hashrate * self.hashrate_share / (1. - self.hashrate_share),
+            ),
+        )
+    }
+}
+
+impl Component for DoubleSpendExample {
+    type Message = DoubleSpendExampleMsg;
+    type Properties = ();
+
+    fn create(_: &Context<Self>) -> Self {
+        let mut sim = isds::Simulation::new();
+        let n_honest_nodes = 9;
+        let hashrate_share = 0.3;
+        sim.add_event_handler(isds::InvokeProtocolForAllNodes(
+            isds::nakamoto_consensus::NakamotoConsensus::default().with_difficulty_adjustment(
+                isds::difficulty::DifficultyAdjustment::for_block_interval(
+                    isds::SimSeconds::from(600.),
+                    n_honest_nodes as f64 / (1. - hashrate_share),
+                ),
+            ),
+        ));
+        sim.add_event_handler(isds::double_spending::DoubleSpending);
+        sim.add_event_handler(isds::mining::Mining::new());
+        sim.do_now(isds::AtStaticIntervals::new(
+            isds::nakamoto_consensus::CollectGarbage::new(24),
+            isds::SimSeconds::from(3600.),
+        ));
+
+        let attacker = sim.spawn_random_node();
+        sim.set_node_label(attacker, "Mallory").unwrap();
+        let merchant_node = sim.spawn_random_node();
+        sim.set_node_label(merchant_node, "Merchant's node")
+            .unwrap();
+        sim.do_now(isds::SpawnRandomNodes(n_honest_nodes - 1));
+        sim.do_now(isds::MakeDelaunayNetwork);
+        sim.do_now(isds::ForEachNode(isds::mining::SetHashrate(1.)));
+        // coins for a few attacks, they end up in the first block
+        sim.do_now(isds::MultipleTimes::new(
+            isds::ForSpecific(
+                merchant_node,
+                isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(
+                    "Faucet",
+                    "Mallory",
+                    isds::blockchain_types::toshis_from(DOUBLE_SPEND_COINS) as u64,
+                ),
+            ),
+            20,
+        ));
+        // a block every two seconds or so
+        sim.time.set_speed(300.);
+
+        let example = Self {
+            sim: sim.into_shared(),
+            attacker,
+            merchant_node,
+            n_honest_nodes,
+            hashrate_share,
+            confirmations: 2,
+        };
+        example
+            .sim
+            .borrow_mut()
+            .do_now(example.set_attacker_hashrate());
+        example
+    }
+
+    fn update(&mut self, _: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            DoubleSpendExampleMsg::SetHashrateShare(hashrate_share) => {
+                self.hashrate_share = hashrate_share;
+                self.sim.borrow_mut().do_now(self.set_attacker_hashrate());
+            }
+            DoubleSpendExampleMsg::SetConfirmations(confirmations) => {
+                self.confirmations = confirmations;
+            }
+            DoubleSpendExampleMsg::Attack => {
+                self.sim.borrow_mut().do_now(isds::ForSpecific(
+                    self.attacker,
+                    isds::double_spending::StartDoubleSpendAttack::new(
+                        "Mallory",
+                        "Merchant",
+                        isds::blockchain_types::toshis_from(DOUBLE_SPEND_COINS) as u64,
+                    )
+                    .with_confirmations(self.confirmations),
+                ));
+            }
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let on_hashrate_share_input = ctx.link().callback(|e: InputEvent| {
+            let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
+            DoubleSpendExampleMsg::SetHashrateShare(input.value_as_number() / 100.)
+        });
+        let on_confirmations_input = ctx.link().callback(|e: InputEvent| {
+            let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
+            DoubleSpendExampleMsg::SetConfirmations(input.value_as_number() as usize)
+        });
+        let on_attack_click = ctx.link().callback(|_| DoubleSpendExampleMsg::Attack);
+        html! {
+            <isds::Isds sim={ self.sim.clone() }>
+                <isds::TimeUi />
+                <div class="columns is-vcentered">
+                    <div class="column has-text-centered">
+                        <label>
+                            { format!("Mallory's share of the hashrate: {:.0}%", self.hashrate_share * 100.) }
+                            <br />
+                            <input
+                                type="range"
+                                min="5"
+                                max="70"
+                                value={ (self.hashrate_share * 100.).to_string() }
+                                oninput={ on_hashrate_share_input }
+                            />
+                        </label>
+                    </div>
+                    <div class="column has-text-centered">
+                        <label>
+                            { format!("Confirmations: {}", self.confirmations) }
+                            <br />
+                            <input
+                                type="range"
+                                min="1"
+                                max="6"
+                                value={ self.confirmations.to_string() }
+                                oninput={ on_confirmations_input }
+                            />
+                        </label>
+                    </div>
+                    <div class="column has-text-centered">
+                        <button class="button is-danger" onclick={ on_attack_click }>
+                            { format!("Pay the merchant {} coins", DOUBLE_SPEND_COINS) }
+                        </button>
+                    </div>
+                </div>
+                <div class="box">
+                    <isds::DoubleSpendView attacker={ self.attacker } merchant_node={ self.merchant_node } />
+                </div>
+                <div class="columns">
+                    <div class="column is-one-third">
+                        <div class="box">
+                            <isds::Wallet
+                                full_node={ Some(self.merchant_node) }
+                                address={ "Merchant".to_string() }
+                                send_whitelist={
+                                    Some(isds::SendWhitelist::new(
+                                        vec!["Supplier".to_string()],
+                                        vec![DOUBLE_SPEND_COINS / 2.],
+                                    ))
+                                }
+                            />
+                        </div>
+                    </div>
+                    <div class="column">
+                        <div class="box">
+                            <isds::BlockchainView
+                                viewing_node={ Some(self.merchant_node) }
+                                max_visible_blocks={ 4 }
+                                show_unconfirmed_txes={ false }
+                                highlight_class={ "has-fill-info" }
+                            />
+                        </div>
+                        <div class="box">
+                            <isds::BlockchainView
+                                viewing_node={ Some(self.attacker) }
+                                max_visible_blocks={ 4 }
+                                show_unconfirmed_txes={ false }
+                                highlight_class={ "has-fill-info" }
+                            />
+                        </div>
+                    </div>
+                </div>
+                <div class="box">
+                    <isds::NetView
+                        highlight_class={ "has-fill-danger" }
+                        node_highlight_on_hover={ true }
+                        buffer_space=25.
+                    />
+                </div>
+            </isds::Isds>
+        }
+    }
+}
+
+struct EclipseExample {
+    sim: isds::SharedSimulation,
+    victim: isds::Entity,
+    attackers: Vec<isds::Entity>,
+    honest_node: isds::Entity,
+}
+
+enum EclipseExampleMsg {
+    Attack(isds::eclipse_attack::EclipseMethod),
+}
+
+impl Component for EclipseExample {
+    type Message = EclipseExampleMsg;
+    type Properties = ();
+
+    fn create(_: &Context<Self>) -> Self {
+        let mut sim = isds::Simulation::new();
+        let n_honest_nodes = 14;
+        let n_attackers = 6;
+        let attacker_hashrate = 0.25;
+        sim.add_event_handler(isds::InvokeProtocolForAllNodes(
+            isds::nakamoto_consensus::NakamotoConsensus::default().with_difficulty_adjustment(
+                isds::difficulty::DifficultyAdjustment::for_block_interval(
+                    isds::SimSeconds::from(600.),
+                    n_honest_nodes as f64 + n_attackers as f64 * attacker_hashrate,
+                ),
+            ),
+        ));
+        sim.add_event_handler(isds::InvokeProtocolForAllNodes(
+            isds::peer_discovery::PeerDiscovery,
+        ));
+        sim.add_event_handler(isds::mining::Mining::new());
+        sim.do_now(isds::AtStaticIntervals::new(
+            isds::nakamoto_consensus::CollectGarbage::new(24),
+            isds::SimSeconds::from(3600.),
+        ));
+
+        let victim = sim.spawn_random_node();
+        sim.set_node_label(victim, "Victim").unwrap();
+        let honest_node = sim.spawn_random_node();
+        let attackers: Vec<isds::Entity> = (0..n_attackers)
+            .map(|_| {
+                let attacker = sim.spawn_random_node();
+                sim.set_node_label(attacker, "Attacker").unwrap();
+                attacker
+            })
+            .collect();
+        sim.do_now(isds::SpawnRandomNodes(n_honest_nodes - 2));
+        sim.do_now(isds::MakeDelaunayNetwork);
+        sim.do_now(isds::ForEachNode(isds::mining::SetHashrate(1.)));
+        for &attacker in attackers.iter() {
+            sim.do_now(isds::ForSpecific(
+                attacker,
+                isds::mining::SetHashrate(attacker_hashrate),
+            ));
+        }
+        // a block every two seconds or so
+        sim.time.set_speed(300.);
+
+        Self {
+            sim: sim.into_shared(),
+            victim,
+            attackers,
+            honest_node,
+        }
+    }
+
+    fn update(&mut self, _: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            EclipseExampleMsg::Attack(method) => {
+                self.sim
+                    .borrow_mut()
+                    .do_now(isds::eclipse_attack::EclipseNode {
+                        victim: self.victim,
+                        attackers: self.attackers.clone(),
+                        method,
+                    });
+            }
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let on_fill_peer_set_click = ctx.link().callback(|_| {
+            EclipseExampleMsg::Attack(isds::eclipse_attack::EclipseMethod::FillPeerSet)
+        });
+        let on_poison_address_book_click = ctx.link().callback(|_| {
+            EclipseExampleMsg::Attack(isds::eclipse_attack::EclipseMethod::PoisonAddressBook)
+        });
+        html! {
+            <isds::Isds sim={ self.sim.clone() }>
+                <isds::TimeUi />
+                <div class="buttons is-centered">
+                    <button class="button is-danger" onclick={ on_fill_peer_set_click }>
+                        { "Take over the victim's connections" }
+                    </button>
+                    <button class="button is-danger is-outlined" onclick={ on_poison_address_book_click }>
+                        { "Poison the victim's address book" }
+                    </button>
+                </div>
+                <div class="columns">
+                    <div class="column">
+                        <div class="box">
+                            <isds::BlockchainView
+                                viewing_node={ Some(self.victim) }
+                                max_visible_blocks={ 4 }
+                                show_unconfirmed_txes={ false }
+                                highlight_class={ "has-fill-info" }
+                            />
+                        </div>
+                    </div>
+                    <div class="column">
+                        <div class="box">
+                            <isds::BlockchainView
+                                viewing_node={ Some(self.honest_node) }
+                                max_visible_blocks={ 4 }
+                                show_unconfirmed_txes={ false }
+                                highlight_class={ "has-fill-info" }
+                            />
+                        </div>
+                    </div>
+                </div>
+                <div class="box">
+                    <isds::NetView
+                        highlight_class={ "has-fill-danger" }
+                        node_highlight_on_hover={ true }
+                        buffer_space=25.
+                    />
+                </div>
+            </isds::Isds>
+        }
+    }
+}
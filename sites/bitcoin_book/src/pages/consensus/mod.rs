@@ -1,5 +1,8 @@
 use super::*;
 
+mod attacks;
+pub use attacks::Attacks;
+
 mod pow;
 pub use pow::Pow;
 
@@ -15,7 +18,11 @@ pub fn consensus() -> Html {
                 <Link<Route> to={Route::ConsensusPow}>
                     { "Proof-of-Work" }
                 </Link<Route>>
-                    { " - a central pillar of Bitcoin's approach to consensus." }
+                    { " - a central pillar of Bitcoin's approach to consensus - and some of the " }
+                <Link<Route> to={Route::ConsensusAttacks}>
+                    { "attacks" }
+                </Link<Route>>
+                    { " on it." }
             </div>
         </SimplePage>
     }
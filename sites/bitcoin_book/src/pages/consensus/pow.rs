@@ -60,10 +60,21 @@ pub fn pow() -> Html {
                         (the [blockchain](blockchain) is basically a logbook).
                         This is not great - imagine the `coin` balance in your wallet fluctuating all the time
                         because the system can't make up its mind about what happened.
+
+                        By the way, "longest chain, first seen wins ties" is just one possible *fork-choice rule*.
+                        Below, the two bottom nodes listen to the same competitors,
+                        but you can pick a different rule for each of them.
+                        With *random tie-breaking*, a node doesn't stick with the chain it saw first,
+                        but flips a coin whenever two chains are equally long.
+                        *GHOST* also counts the blocks of stale forks for the chain they branch off from.
+                        (A node only applies a new rule once it receives the next block.)
                         "#
                     }
                 }
             </div>
+            <div class="block">
+                <ForkChoiceExample />
+            </div>
         </Section>
         <Section>
             <h3 class="title is-4">{ "We'll have to work then..." }</h3>
@@ -81,7 +92,13 @@ pub fn pow() -> Html {
                         (This puzzle-solving is also known as *mining*.
                         Nodes get to create new `coins` in each block they create,
                         i.e., for each puzzle they solve.
-                        They are *mining* these `coins`...)
+                        They are *mining* these `coins`...
+                        The first transaction of each block, the *coinbase* transaction, pays the miner
+                        a fixed *block subsidy* plus the fees of all the other transactions in the block.
+                        The subsidy started at 50 `coins` and halves every 210,000 blocks, i.e., about every four years,
+                        so there will never be more than 21 million `coins`.
+                        And because a longer chain could still replace a block - and its coinbase with it -
+                        miners can only spend newly mined `coins` once 100 blocks confirm them.)
 
                         But what kind of puzzles are we talking about?
                         Depending on how you explored this website, you might have already come across our page
@@ -124,9 +141,57 @@ pub fn pow() -> Html {
                     indoc_markdown_content! { r#"
                         And these are the very basics behind Proof-of-Work as it is used by
                         Bitcoin and comparable blockchain systems.
-                        What we didn't cover, among other things, is that the difficulty of creating
-                        new blocks is adapted over time.
-                        We also didn't discuss the many criticisms that can be voiced against
+                        "#
+                    }
+                }
+            </div>
+        </Section>
+        <Section>
+            <h3 class="title is-4">{ "Adjusting the difficulty" }</h3>
+            <div class="block">
+                {
+                    indoc_markdown_content! { r#"
+                        How long it takes until *someone* solves the puzzle depends on two things:
+                        how hard the puzzle is,
+                        and how many hashes all miners together can try per second (their *hashrate*).
+                        Miners come and go and buy faster hardware,
+                        so the total hashrate changes all the time.
+                        If the difficulty stayed the same,
+                        blocks would be found faster and faster as more miners join.
+
+                        That's why every block header carries the difficulty target that its hash had to meet,
+                        and why all nodes follow the same rule to adjust it:
+                        Every 2016 blocks, they look at how long these blocks took.
+                        Bitcoin aims for one block every 10 minutes,
+                        so 2016 blocks should take about two weeks.
+                        If they took only one week, the difficulty doubles.
+                        If they took four weeks, it halves.
+                        (To prevent wild jumps, it never changes by more than a factor of four at once.)
+                        Because everyone can check the difficulty in the headers against this rule,
+                        miners can't simply make their own puzzles easier.
+
+                        Below, three miners work on the same chain.
+                        To save you two weeks of waiting,
+                        the difficulty is adjusted every 20 blocks here.
+                        Use the sliders to change the miners' hashrates and watch how the block intervals
+                        first deviate from the 10 minutes target,
+                        and then return to it after the next adjustment.
+                        "#
+                    }
+                }
+            </div>
+            <div class="block">
+                <DifficultyExample />
+            </div>
+            <div class="block">
+                {
+                    indoc_markdown_content! { r#"
+                        Note that the difficulty adjustment always lags behind:
+                        When a lot of hashrate joins, blocks come faster until the end of the window.
+                        When a lot of hashrate leaves, the remaining miners might need a long time
+                        to get to the end of the window.
+
+                        We didn't discuss the many criticisms that can be voiced against
                         PoW-based systems, for example that their energy consumption is **HUGE**.
                         You might find pointers for further study in the ["Beyond"](beyond) section.
                         "#
@@ -219,6 +284,149 @@ fn no_pow_example() -> Html {
     }
 }
 
+fn fork_choice_names() -> Vec<&'static str> {
+    vec![
+        "Longest chain",
+        "Longest chain, random tie-breaking",
+        "GHOST",
+        "Most work",
+    ]
+}
+
+fn with_fork_choice(
+    consensus: isds::nakamoto_consensus::NakamotoConsensus,
+    node: isds::Entity,
+    rule_index: usize,
+) -> isds::nakamoto_consensus::NakamotoConsensus {
+    use isds::fork_choice::*;
+    match rule_index {
+        1 => consensus.with_fork_choice_of(node, RandomTieBreaking::new(LongestChain)),
+        2 => consensus.with_fork_choice_of(node, Ghost),
+        3 => consensus.with_fork_choice_of(node, HeaviestChain),
+        _ => consensus.with_fork_choice_of(node, LongestChain),
+    }
+}
+
+#[function_component(ForkChoiceExample)]
+fn fork_choice_example() -> Html {
+    let mut sim = isds::Simulation::new_with_underlay_dimensions(200., 50.);
+
+    let left_node = sim.spawn_random_node_at_position(0., 0.);
+    let right_node = sim.spawn_random_node_at_position(200., 0.);
+    let bottom_nodes = [
+        sim.spawn_random_node_at_position(50., 50.),
+        sim.spawn_random_node_at_position(150., 50.),
+    ];
+    let initial_rules = [0, 1];
+    let consensus = bottom_nodes.into_iter().zip(initial_rules).fold(
+        isds::nakamoto_consensus::NakamotoConsensus::new(),
+        |consensus, (node, rule_index)| with_fork_choice(consensus, node, rule_index),
+    );
+    let protocol_index = sim.add_event_handler(isds::InvokeProtocolForAllNodes(consensus));
+    for bottom_node in bottom_nodes {
+        sim.add_peer(left_node, bottom_node);
+        sim.add_peer(right_node, bottom_node);
+        // little hack to make sure that the bottom nodes are initialized
+        sim.add_peer(bottom_node, left_node);
+        sim.remove_peer(bottom_node, left_node);
+    }
+
+    let sim = sim.into_shared();
+
+    let on_button = |node| {
+        let sim = sim.clone();
+        Callback::from(move |_| {
+            sim.borrow_mut()
+                .do_now(MineBlockWithOneRandomTransaction(node))
+        })
+    };
+    let on_rule_change = |node| {
+        let sim = sim.clone();
+        Callback::from(move |e: yew::Event| {
+            let rule_index = e
+                .target_unchecked_into::<web_sys::HtmlSelectElement>()
+                .selected_index() as usize;
+            let sim = sim.borrow();
+            let handlers = sim.additional_event_handlers();
+            let mut handlers = handlers.borrow_mut();
+            if let Some(protocol) = handlers.get_mut::<isds::InvokeProtocolForAllNodes<
+                isds::nakamoto_consensus::NakamotoConsensus,
+            >>(protocol_index)
+            {
+                protocol.0 = with_fork_choice(std::mem::take(&mut protocol.0), node, rule_index);
+            }
+        })
+    };
+
+    html! {
+        <isds::Isds sim={ sim.clone() }>
+            <div class="columns">
+                {
+                    [left_node, right_node].iter().map(|&node| html! {
+                        <div class="column">
+                            <div class="box">
+                                <isds::BlockchainView
+                                    viewing_node={ Some(node) }
+                                    max_visible_blocks={ 4 }
+                                    show_unconfirmed_txes={ false }
+                                    highlight_class={ "has-fill-info" }
+                                />
+                                <div class="has-text-centered p-5">
+                                    <button
+                                        class="button"
+                                        onclick={ on_button(node) }
+                                    >
+                                        { "Propose a block for free!" }
+                                    </button>
+                                </div>
+                            </div>
+                        </div>
+                    }).collect::<Html>()
+                }
+            </div>
+            <div class="columns is-centered">
+                <div class="column is-half-desktop">
+                    <div class="box">
+                        <isds::NetView
+                            toggle_edges_on_click={ false }
+                            node_highlight_on_hover={ true }
+                            highlight_class={ "has-fill-info" }
+                            buffer_space=25.
+                        />
+                    </div>
+                </div>
+            </div>
+            <div class="columns">
+                {
+                    bottom_nodes.into_iter().zip(initial_rules).map(|(node, initial_rule)| html! {
+                        <div class="column">
+                            <div class="box">
+                                <div class="has-text-centered pb-3">
+                                    <div class="select is-small" title="The node's fork-choice rule">
+                                        <select onchange={ on_rule_change(node) }>
+                                            {
+                                                fork_choice_names().into_iter().enumerate().map(|(i, name)| html! {
+                                                    <option selected={ i == initial_rule }>{ name }</option>
+                                                }).collect::<Html>()
+                                            }
+                                        </select>
+                                    </div>
+                                </div>
+                                <isds::BlockchainView
+                                    viewing_node={ Some(node) }
+                                    max_visible_blocks={ 4 }
+                                    show_unconfirmed_txes={ false }
+                                    highlight_class={ "has-fill-info" }
+                                />
+                            </div>
+                        </div>
+                    }).collect::<Html>()
+                }
+            </div>
+        </isds::Isds>
+    }
+}
+
 struct PowExample {
     sim: isds::SharedSimulation,
     left_node: isds::Entity,
@@ -361,6 +569,128 @@ impl Component for PowExample {
     }
 }
 
+/// Blocks per difficulty adjustment in `DifficultyExample`.
+const RETARGET_WINDOW: usize = 20;
+
+struct DifficultyExample {
+    sim: isds::SharedSimulation,
+    miners: Vec<(isds::Entity, f64)>,
+}
+
+enum DifficultyExampleMsg {
+    SetHashrate(usize, f64),
+}
+
+impl Component for DifficultyExample {
+    type Message = DifficultyExampleMsg;
+    type Properties = ();
+
+    fn create(_: &Context<Self>) -> Self {
+        let mut sim = isds::Simulation::new_with_underlay_dimensions(200., 50.);
+        let difficulty_adjustment = isds::difficulty::DifficultyAdjustment::for_block_interval(
+            isds::SimSeconds::from(600.),
+            3.,
+        )
+        .with_rule(isds::difficulty::RetargetingRule::Window {
+            window: RETARGET_WINDOW,
+            max_factor: isds::difficulty::BITCOIN_MAX_ADJUSTMENT_FACTOR,
+        });
+        sim.add_event_handler(isds::InvokeProtocolForAllNodes(
+            isds::nakamoto_consensus::NakamotoConsensus::new()
+                .with_difficulty_adjustment(difficulty_adjustment),
+        ));
+        sim.add_event_handler(isds::mining::Mining::new());
+
+        let nodes = [
+            sim.spawn_random_node_at_position(0., 0.),
+            sim.spawn_random_node_at_position(100., 50.),
+            sim.spawn_random_node_at_position(200., 0.),
+        ];
+        for &node in nodes.iter() {
+            for &peer in nodes.iter().filter(|&&peer| peer != node) {
+                sim.add_peer(node, peer);
+            }
+            sim.do_now(isds::ForSpecific(node, isds::mining::SetHashrate(1.)));
+        }
+        // a block every two seconds or so
+        sim.time.set_speed(300.);
+
+        Self {
+            sim: sim.into_shared(),
+            miners: nodes.iter().map(|&node| (node, 1.)).collect(),
+        }
+    }
+
+    fn update(&mut self, _: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            DifficultyExampleMsg::SetHashrate(i, hashrate) => {
+                let (node, old_hashrate) = &mut self.miners[i];
+                *old_hashrate = hashrate;
+                self.sim.borrow_mut().do_now(isds::ForSpecific(
+                    *node,
+                    isds::mining::SetHashrate(hashrate),
+                ));
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let sliders = self
+            .miners
+            .iter()
+            .enumerate()
+            .map(|(i, &(node, hashrate))| {
+                let on_input = ctx.link().callback(move |e: InputEvent| {
+                    let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
+                    DifficultyExampleMsg::SetHashrate(i, input.value_as_number())
+                });
+                html! {
+                    <div class="column has-text-centered">
+                        <label>
+                            <isds::EntityName entity={ Some(node) } />
+                            { format!(" mines with a hashrate of {:.1}", hashrate) }
+                            <br />
+                            <input
+                                type="range"
+                                min="0"
+                                max="10"
+                                step="0.5"
+                                value={ hashrate.to_string() }
+                                oninput={ on_input }
+                            />
+                        </label>
+                    </div>
+                }
+            })
+            .collect::<Html>();
+        let viewing_node = self.miners[0].0;
+        html! {
+            <isds::Isds sim={ self.sim.clone() }>
+                <isds::TimeUi />
+                <div class="columns">
+                    { sliders }
+                </div>
+                <div class="box">
+                    <isds::DifficultyView { viewing_node } recent_blocks={ RETARGET_WINDOW } />
+                </div>
+                <div class="columns is-centered">
+                    <div class="column is-half-desktop">
+                        <div class="box">
+                            <isds::NetView
+                                toggle_edges_on_click={ false }
+                                node_highlight_on_hover={ true }
+                                highlight_class={ "has-fill-info" }
+                                buffer_space=25.
+                            />
+                        </div>
+                    </div>
+                </div>
+            </isds::Isds>
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MineBlockWithOneRandomTransaction(pub isds::Entity);
 impl isds::Command for MineBlockWithOneRandomTransaction {
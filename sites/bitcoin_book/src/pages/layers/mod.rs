@@ -34,8 +34,8 @@ impl Component for Layers {
         sim.time.set_speed(1.);
 
         let users = vec![
-            User::new("Alice", Some(sim.pick_random_node().unwrap()), true),
-            User::new("Bob", Some(sim.pick_random_node().unwrap()), true),
+            User::with_new_wallet_node(&mut sim, "Alice"),
+            User::with_new_light_client(&mut sim, "Bob"),
             User::new("Charlie", None, false),
         ];
 
@@ -97,36 +97,45 @@ impl Component for Layers {
 impl Layers {
     fn view_application_layer(&self) -> Html {
         let wallet_send_amounts = vec![0.5, 1., 5., 10.];
+        let wallet_fees = vec![0., 0.01, 0.1];
+        let bob = &self.users[1];
         view_layer(
             html! {
-                <div class="columns">
-                    {
-                        self.users
-                            .iter()
-                            .filter(|user| user.show_wallet)
-                            .map(|user| html!{
-                                <div class="column">
-                                    <isds::Wallet
-                                        full_node={ user.wallet_node }
-                                        address={ user.name.clone() }
-                                        send_whitelist={
-                                            Some(isds::SendWhitelist::new(
-                                                    self.users
-                                                        .iter()
-                                                        .filter(|u| *u != user)
-                                                        .map(|u| &u.name)
-                                                        .cloned()
-                                                        .collect(),
-                                                    wallet_send_amounts.clone()
+                <>
+                    <div class="columns">
+                        {
+                            self.users
+                                .iter()
+                                .filter(|user| user.show_wallet)
+                                .map(|user| html!{
+                                    <div class="column">
+                                        <isds::Wallet
+                                            full_node={ user.wallet_node }
+                                            address={ user.name.clone() }
+                                            send_whitelist={
+                                                Some(isds::SendWhitelist::new(
+                                                        self.users
+                                                            .iter()
+                                                            .filter(|u| *u != user)
+                                                            .map(|u| &u.name)
+                                                            .cloned()
+                                                            .collect(),
+                                                        wallet_send_amounts.clone()
+                                                    )
+                                                    .with_fees(wallet_fees.clone())
                                                 )
-                                            )
-                                        }
-                                        class="box"
-                                    />
-                                </div>
-                            }).collect::<Html>()
-                    }
-                </div>
+                                            }
+                                            class="box"
+                                        />
+                                    </div>
+                                }).collect::<Html>()
+                        }
+                    </div>
+                    <p class="has-text-centered is-size-7">
+                        { format!("{}'s wallet runs on a light client:", bob.name) }
+                    </p>
+                    <isds::LightClientStatsView light_client={ bob.wallet_node } />
+                </>
             },
             html! {
                 <LayerDescription title="Application">
@@ -155,7 +164,7 @@ impl Layers {
     fn view_consensus_layer(&self) -> Html {
         let on_button = {
             let sim = self.sim.clone();
-            Callback::from(move |_| sim.borrow_mut().do_now(isds::ForRandomNode(MINE_BLOCK)))
+            Callback::from(move |_| sim.borrow_mut().do_now(mine_on_random_full_node()))
         };
         view_layer(
             html! {
@@ -226,6 +235,9 @@ fn init_simulation() -> isds::Simulation {
     sim.add_event_handler(isds::InvokeProtocolForAllNodes(
         isds::nakamoto_consensus::NakamotoConsensus::new_with_block_limit(BLOCK_SIZE_LIMIT),
     ));
+    sim.add_event_handler(isds::InvokeProtocolForAllNodes(
+        isds::light_client::LightClient,
+    ));
 
     // init network
     sim.do_now(isds::SpawnRandomNodes(20));
@@ -235,22 +247,21 @@ fn init_simulation() -> isds::Simulation {
 
     // make some transactions so that wallet balances are not 0
     let power_node = sim.pick_random_node().unwrap();
-    sim.do_now(isds::ForSpecific(
-        power_node,
-        isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(
-            "CoinBroker25",
-            "Alice",
-            isds::blockchain_types::toshis_from(10.) as u64,
-        ),
-    ));
-    sim.do_now(isds::ForSpecific(
-        power_node,
-        isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(
-            "Roberts",
-            "Alice",
-            isds::blockchain_types::toshis_from(15.) as u64,
-        ),
-    ));
+    for (sender, value) in [("CoinBroker25", 10.), ("Roberts", 15.)] {
+        let value = isds::blockchain_types::toshis_from(value) as u64;
+        sim.do_now(isds::ForSpecific(
+            power_node,
+            isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(
+                isds::blockchain_types::FAUCET,
+                sender,
+                value,
+            ),
+        ));
+        sim.do_now(isds::ForSpecific(
+            power_node,
+            isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(sender, "Alice", value),
+        ));
+    }
     // mine a block
     sim.do_now(isds::ForSpecific(power_node, MINE_BLOCK));
 
@@ -267,13 +278,19 @@ fn init_simulation() -> isds::Simulation {
 
     // magically mine a block at random intervals centered around 10 minutes
     sim.do_now(isds::AtRandomIntervals::new(
-        isds::ForRandomNode(MINE_BLOCK),
+        mine_on_random_full_node(),
         isds::SimSeconds::from(600.),
     ));
 
     sim
 }
 
+/// Light clients can't mine.
+fn mine_on_random_full_node(
+) -> isds::ForRandomNodeWithout<isds::nakamoto_consensus::MineBlockWithLimit> {
+    isds::ForRandomNodeWithout(isds::NodeRole::LightClient, MINE_BLOCK)
+}
+
 fn view_layer(simulation_part: Html, explanation_part: Html) -> Html {
     view_layer_with_extra_part(simulation_part, Html::default(), explanation_part)
 }
@@ -16,6 +16,13 @@ pub fn network() -> Html {
                         The main principles are the same,
                         the details vary.
 
+                        Like Bitcoin nodes, the nodes here don't just push new blocks to their
+                        peers.
+                        They first announce them in small `inv` messages (the gray dots),
+                        and only peers that don't have a block yet request it with `getdata`.
+                        When two nodes connect, they first exchange `headers` of their chains
+                        and then request the blocks that they are missing.
+
                         Try creating some forks!
                         Clicking on a node causes it to mine a block.
                         Clicking on a link between two nodes will cause that link to disappear.
@@ -34,6 +41,32 @@ pub fn network() -> Html {
                     <KeyboardShortcutsList />
                 </div>
             </Section>
+            <Section>
+                <h3 class="title is-4">{ "Compact blocks" }</h3>
+                <div class="block">
+                    {
+                        indoc_markdown_content! { r#"
+                            Most transactions in a new block have already reached the other nodes
+                            before the block did.
+                            This is why Bitcoin nodes can relay *compact blocks* (BIP 152):
+                            instead of the full transactions,
+                            a compact block only contains short ids of the transactions,
+                            and the receiving node looks them up in its own mempool.
+                            Only transactions that it doesn't have yet need to be requested,
+                            which costs another round trip.
+
+                            Click on a node to let it mine a block with 20 fresh transactions
+                            that have only reached some of the other nodes so far.
+                            How does the share of nodes that already know them affect how many
+                            blocks can be reconstructed right away?
+                            "#
+                        }
+                    }
+                </div>
+                <div class="block">
+                    <CompactBlocks />
+                </div>
+            </Section>
             <Footer />
         </>
     }
@@ -41,7 +74,8 @@ pub fn network() -> Html {
 
 pub struct Standalone {
     sim: isds::SharedSimulation,
-    slowdown_handler_index: usize,
+    speed_controller_index: usize,
+    breakpoints_handler_index: usize,
     _key_listener: gloo::events::EventListener,
 }
 
@@ -52,19 +86,38 @@ impl Component for Standalone {
     fn create(_: &Context<Self>) -> Self {
         let mut sim = init_simulation();
 
-        // add handler to make time run slower when messages are in-flight
-        let slowdown_handler_index =
-            sim.add_event_handler(isds::SlowDownOnMessages::new(0.01, |_, _| true, true));
+        // add handler to make time run slower when messages are in-flight (or faster when nothing
+        // is happening)
+        let speed_controller_index = sim.add_event_handler(
+            isds::SpeedController::new()
+                .with_selected(isds::SlowMotionOnMessages::new(0.01, |_, _| true))
+                .with(isds::Eased::new(
+                    isds::SlowMotionOnMessages::new(0.01, |_, _| true),
+                    0.25,
+                ))
+                .with(isds::SkipIdlePeriods::new(1.))
+                .with(isds::TargetTimePerBlock::new(
+                    10.,
+                    isds::SimSeconds::from(600.),
+                )),
+        );
+
+        // presenters can use this to fast-forward to the next fork
+        let mut breakpoints =
+            isds::Breakpoints::new().with(isds::nakamoto_consensus::ForkOccurred::new());
+        breakpoints.set_enabled(false);
+        let breakpoints_handler_index = sim.add_event_handler(breakpoints);
 
         // switch to high speed so we can see something happening quickly
         sim.time.set_speed(100.);
 
         let sim = sim.into_shared();
-        let _key_listener = init_keyboard_listener(sim.clone(), slowdown_handler_index);
+        let _key_listener = init_keyboard_listener(sim.clone(), speed_controller_index);
 
         Self {
             sim,
-            slowdown_handler_index,
+            speed_controller_index,
+            breakpoints_handler_index,
             _key_listener,
         }
     }
@@ -80,9 +133,13 @@ impl Component for Standalone {
         html! {
             <isds::Isds sim={ self.sim.clone() }>
                 <isds::TimeUi
-                    slowdown_handler_index={
-                        Some(self.slowdown_handler_index)
+                    speed_controller_index={
+                        Some(self.speed_controller_index)
                     }
+                    breakpoints_handler_index={
+                        Some(self.breakpoints_handler_index)
+                    }
+                    show_step_controls=true
                 />
                 <isds::NetView
                     { on_node_click }
@@ -96,15 +153,109 @@ impl Component for Standalone {
 fn init_simulation() -> isds::Simulation {
     let mut sim = isds::Simulation::new();
     sim.add_event_handler(isds::InvokeProtocolForAllNodes(
-        isds::nakamoto_consensus::NakamotoConsensus::default(),
+        isds::nakamoto_consensus::NakamotoConsensus::default()
+            .with_relay(isds::nakamoto_consensus::Relay::Inventory)
+            // every node has the same hashrate, and together they find a block every 10 minutes
+            .with_difficulty_adjustment(
+                isds::difficulty::DifficultyAdjustment::for_block_interval(
+                    isds::SimSeconds::from(600.),
+                    32.,
+                ),
+            ),
     ));
-    // magically mine a block at random intervals centered around 10 minutes
-    sim.do_now(isds::AtRandomIntervals::new(
-        isds::ForRandomNode(isds::nakamoto_consensus::MineBlock),
-        isds::SimSeconds::from(600.),
+    sim.add_event_handler(isds::mining::Mining::new());
+    // so that the simulation doesn't keep growing if the page is left open
+    sim.do_now(isds::AtStaticIntervals::new(
+        isds::nakamoto_consensus::CollectGarbage::new(24),
+        isds::SimSeconds::from(3600.),
     ));
     sim.do_now(isds::SpawnRandomNodes(34));
     sim.do_now(isds::DespawnMostCrowdedNodes(2));
     sim.do_now(isds::MakeDelaunayNetwork);
+    sim.do_now(isds::ForEachNode(isds::mining::SetHashrate(1.)));
     sim
 }
+
+pub struct CompactBlocks {
+    sim: isds::SharedSimulation,
+    mempool_overlap: f64,
+}
+
+pub enum CompactBlocksMsg {
+    SetMempoolOverlap(f64),
+}
+
+impl Component for CompactBlocks {
+    type Message = CompactBlocksMsg;
+    type Properties = ();
+
+    fn create(_: &Context<Self>) -> Self {
+        let mut sim = isds::Simulation::new();
+        sim.add_event_handler(isds::InvokeProtocolForAllNodes(
+            isds::nakamoto_consensus::NakamotoConsensus::default()
+                .with_relay(isds::nakamoto_consensus::Relay::CompactBlocks),
+        ));
+        sim.add_event_handler(isds::SlowDownOnMessages::new(0.01, |_, _| true, true));
+        sim.do_now(isds::SpawnRandomNodes(16));
+        sim.do_now(isds::MakeDelaunayNetwork);
+        Self {
+            sim: sim.into_shared(),
+            mempool_overlap: 0.9,
+        }
+    }
+
+    fn update(&mut self, _: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            CompactBlocksMsg::SetMempoolOverlap(mempool_overlap) => {
+                self.mempool_overlap = mempool_overlap;
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let on_node_click = {
+            let sim = self.sim.clone();
+            let share_of_nodes = self.mempool_overlap;
+            Callback::from(move |node| {
+                let mut sim = sim.borrow_mut();
+                sim.do_now(isds::ForSpecific(
+                    node,
+                    isds::nakamoto_consensus::SpawnPartiallyRelayedTransactions {
+                        count: 20,
+                        share_of_nodes,
+                    },
+                ));
+                sim.do_now(isds::ForSpecific(node, isds::nakamoto_consensus::MineBlock));
+            })
+        };
+        let on_input = ctx.link().callback(|e: InputEvent| {
+            let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
+            CompactBlocksMsg::SetMempoolOverlap(input.value_as_number() / 100.)
+        });
+        html! {
+            <isds::Isds sim={ self.sim.clone() }>
+                <isds::TimeUi />
+                <div class="level is-mobile">
+                    <div class="level-item">
+                        <label>
+                            { format!(
+                                "Fresh transactions have reached {:.0}% of the nodes ",
+                                self.mempool_overlap * 100.
+                            ) }
+                            <input
+                                type="range"
+                                min="0"
+                                max="100"
+                                value={ (self.mempool_overlap * 100.).to_string() }
+                                oninput={ on_input }
+                            />
+                        </label>
+                    </div>
+                </div>
+                <isds::NetView { on_node_click } buffer_space=25. />
+                <isds::CompactBlockStatsView />
+            </isds::Isds>
+        }
+    }
+}
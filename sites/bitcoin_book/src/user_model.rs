@@ -1,4 +1,5 @@
-use rand::{seq::SliceRandom, thread_rng, Rng};
+use isds::Command;
+use rand::{seq::IteratorRandom, seq::SliceRandom, thread_rng, Rng};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct User {
@@ -15,26 +16,85 @@ impl User {
             show_wallet,
         }
     }
+    /// Picks a random node that isn't anybody's wallet yet and labels it with the user's name.
+    pub fn with_new_wallet_node(sim: &mut isds::Simulation, name: &str) -> Self {
+        let wallet_node = sim
+            .all_nodes()
+            .into_iter()
+            .filter(|&node| !sim.has_role(node, isds::NodeRole::Wallet))
+            .choose(&mut thread_rng());
+        if let Some(node) = wallet_node {
+            sim.set_node_label(node, name).unwrap();
+            sim.add_node_role(node, isds::NodeRole::Wallet).unwrap();
+        }
+        Self::new(name, wallet_node, true)
+    }
+    /// Spawns a light client (see `isds::light_client`) that watches the user's address, labels
+    /// it with the user's name, and connects it to the two closest nodes.
+    pub fn with_new_light_client(sim: &mut isds::Simulation, name: &str) -> Self {
+        let identity = isds::NodeIdentity::new()
+            .with_label(name)
+            .with_role(isds::NodeRole::Wallet)
+            .with_role(isds::NodeRole::LightClient);
+        let light_client = sim.spawn_node(identity).ok();
+        if let Some(node) = light_client {
+            isds::ForSpecific(node, isds::light_client::WatchAddress(name.to_string()))
+                .execute(sim)
+                .unwrap();
+            let position = *sim.world.get::<isds::UnderlayPosition>(node).unwrap();
+            let mut others = sim.all_other_nodes(node);
+            others.sort_by_key(|&other| {
+                let other_position = *sim.world.get::<isds::UnderlayPosition>(other).unwrap();
+                isds::OrderedFloat(isds::UnderlayPosition::distance(position, other_position))
+            });
+            for &peer in others.iter().take(2) {
+                sim.node_interface(node).connect(peer);
+            }
+        }
+        Self::new(name, light_client, true)
+    }
 }
 
 pub fn random_transaction(sim: &mut isds::Simulation, origin_node: isds::Entity) {
-    sim.do_now(isds::ForSpecific(origin_node, random_transaction_command()));
+    sim.do_now(isds::ForSpecific(origin_node, RandomTransaction));
 }
 
 pub fn random_transaction_from_random_node(sim: &mut isds::Simulation) {
-    sim.do_now(isds::ForRandomNode(random_transaction_command()));
+    sim.do_now(isds::ForRandomNode(RandomTransaction));
 }
 
-fn random_transaction_command() -> isds::nakamoto_consensus::BuildAndBroadcastTransaction {
-    let mut rng = thread_rng();
-    let addresses = "CDEFGHIJKLMNOPQRSTUVWXYZ"
-        .chars()
-        .map(|c| c.to_string())
-        .collect::<Vec<String>>();
+/// Pays a random amount (with a random fee) from one random address to another. If the sender
+/// can't afford it (as far as the node knows), the coins come from the faucet instead, as if the
+/// recipient had bought them.
+#[derive(Debug, Clone)]
+struct RandomTransaction;
+impl isds::EntityAction for RandomTransaction {
+    fn execute_for(
+        &self,
+        sim: &mut isds::Simulation,
+        entity: isds::Entity,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = thread_rng();
+        let addresses = "CDEFGHIJKLMNOPQRSTUVWXYZ"
+            .chars()
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>();
+        let from = addresses.choose(&mut rng).unwrap();
+        let to = addresses.choose(&mut rng).unwrap();
+        let value = isds::blockchain_types::toshis_from(rng.gen_range(1..100) as f64) as u64;
+        let fee = isds::blockchain_types::toshis_from(rng.gen_range(0..10) as f64 / 100.) as u64;
 
-    isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(
-        addresses.choose(&mut rng).unwrap(),
-        addresses.choose(&mut rng).unwrap(),
-        isds::blockchain_types::toshis_from(rng.gen_range(1..100) as f64) as u64,
-    )
+        isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(from, to, value)
+            .with_fee(fee)
+            .execute_for(sim, entity)
+            .or_else(|_| {
+                isds::nakamoto_consensus::BuildAndBroadcastTransaction::from(
+                    isds::blockchain_types::FAUCET,
+                    to,
+                    value,
+                )
+                .with_fee(fee)
+                .execute_for(sim, entity)
+            })
+    }
 }
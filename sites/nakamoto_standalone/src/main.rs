@@ -20,7 +20,7 @@ impl Component for NakamotoStandalone {
         html! {
             <isds::Isds sim={ self.sim.clone() }>
                 <div style="margin-bottom: -30px"> // chosen based on height of TimeUi level
-                    <isds::TimeUi />
+                    <isds::TimeUi show_step_controls=true />
                 </div>
                 <isds::NetView />
             </isds::Isds>
@@ -65,12 +65,21 @@ fn init_simulation() -> isds::Simulation {
     sim.add_event_handler(isds::InvokeProtocolForAllNodes(
         // simple_flooding::SimpleFlooding::<u32>::default(),
         // random_walks::RandomWalks::new(1024),
-        isds::nakamoto_consensus::NakamotoConsensus::default(),
+        isds::nakamoto_consensus::NakamotoConsensus::default().with_mempool_policy(
+            // blocks come every two seconds here, so nothing should have to wait for minutes
+            isds::mempool_policy::MempoolPolicy::default()
+                .with_max_transactions(1000)
+                .with_expiry(isds::SimSeconds::from(120.)),
+        ),
     ));
     sim.do_now(isds::AtRandomIntervals::new(
         isds::ForRandomNode(isds::PokeNode),
         isds::SimSeconds::from(2.),
     ));
+    sim.do_now(isds::AtStaticIntervals::new(
+        isds::nakamoto_consensus::CollectGarbage::new(24),
+        isds::SimSeconds::from(60.),
+    ));
     sim.do_now(isds::SpawnRandomNodes(32));
     sim.do_now(isds::MakeDelaunayNetwork);
     sim